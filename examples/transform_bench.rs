@@ -0,0 +1,23 @@
+//! Measure master key transformation speed, in rounds per second.
+//!
+//! Usage: cargo run --release --example transform_bench [ROUNDS]
+
+extern crate mpkdb;
+
+use std::env;
+use std::time::Instant;
+
+use mpkdb::db::kdb;
+
+fn main() {
+    let rounds: u32 = match env::args().nth(1) {
+        Some(r) => r.parse().expect("ROUNDS must be a number"),
+        None => 1_000_000,
+    };
+    let start = Instant::now();
+    kdb::transform_master_key([1; 32], [2; 16], [3; 32], rounds);
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    println!("{} rounds in {:.3} s", rounds, secs);
+    println!("{:.0} rounds/s", rounds as f64 / secs);
+}
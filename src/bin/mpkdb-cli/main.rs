@@ -0,0 +1,1433 @@
+//! mpkdb-cli: headless command line interface to password databases, for scripting.
+
+extern crate getopts;
+#[cfg(unix)]
+extern crate libc;
+#[macro_use]
+extern crate log;
+extern crate mpkdb;
+extern crate rpassword;
+extern crate serde_json;
+
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use getopts::Options;
+
+use mpkdb::config;
+use mpkdb::db;
+use mpkdb::db::api;
+use mpkdb::db::api::{Entry, GroupId, PasswordDatabase};
+use mpkdb::db::audit;
+use mpkdb::db::diff;
+use mpkdb::db::formats;
+use mpkdb::db::formats::Format;
+use mpkdb::db::merge;
+use mpkdb::db::report;
+use mpkdb::db::report::ReportFormat;
+use mpkdb::errors;
+use mpkdb::generator::{CharOptions, Generator};
+use mpkdb::otp;
+use mpkdb::otp::{Kind, Otp};
+
+const USAGE_COMMANDS: &str = "Commands:
+    new FILE                Create database, master key is given with global options
+    ls [GROUP]              List groups and entries
+    show PATH               Show entry
+    search TERM             Search entries by title, username, url and notes
+    get-password PATH       Print entry password (or copy it to clipboard with -c)
+    totp PATH               Print entry's one-time code (TOTP or HOTP), or set it up with --set
+    add PATH                Add entry, PATH is GROUP/TITLE
+    edit PATH               Edit entry
+    rm PATH                 Remove entry (or group, with -r)
+    mv PATH DEST            Move entry to group DEST, or to DEST as GROUP/TITLE
+    export FILE             Write entries (all or one group) to another KDB file, CSV, XML...
+    import FILE             Copy entries from another KDB file, CSV, XML...
+    report FILE             Write printable HTML or Markdown report of entries
+    audit                   Check for weak, reused, empty, old and breached passwords
+    info                    Show database file information
+    passwd                  Change master password and key file
+    rounds [N]              Show or set key transformation rounds
+    merge FILE_A FILE_B     Merge changes from FILE_B into FILE_A (or into -o FILE)
+    diff FILE_A FILE_B      Show what changed from FILE_A to FILE_B
+    generate                Print random password or passphrase (no database needed)
+
+Entries are given as GROUP/SUBGROUP/TITLE paths or UUIDs.
+Run mpkdb-cli COMMAND -h for command options.";
+
+/// Error to report to user, ends the program with non-zero exit code.
+#[derive(Debug)]
+enum CliError {
+    /// Invalid command line, exit code 2.
+    Usage(String),
+    /// Exit code 1.
+    Failed(api::Error),
+}
+
+impl CliError {
+    fn new(desc: &str) -> CliError {
+        CliError::Failed(api::Error::new(desc))
+    }
+
+    fn invalid(desc: &str) -> CliError {
+        CliError::Failed(api::Error::Invalid(String::from(desc)))
+    }
+
+    fn usage(desc: &str) -> CliError {
+        CliError::Usage(String::from(desc))
+    }
+
+    fn exit_code(&self) -> i32 {
+        match *self {
+            CliError::Usage(_) => 2,
+            CliError::Failed(_) => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CliError::Usage(ref desc) => write!(f, "{}", desc),
+            CliError::Failed(ref e) => write!(f, "{}", errors::report(e)),
+        }
+    }
+}
+
+impl From<api::Error> for CliError {
+    fn from(e: api::Error) -> CliError {
+        CliError::Failed(e)
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> CliError {
+        CliError::Failed(api::Error::from(e))
+    }
+}
+
+impl From<getopts::Fail> for CliError {
+    fn from(e: getopts::Fail) -> CliError {
+        CliError::Usage(format!("{}", e))
+    }
+}
+
+type CliResult = Result<(), CliError>;
+
+/// Where to read a secret (password) from.
+#[derive(Clone, Debug)]
+enum SecretSource {
+    /// Ask on terminal, with given prompt, confirm if needed.
+    Prompt(String, bool),
+    Stdin,
+    #[cfg(unix)]
+    Fd(i32),
+}
+
+/// Read first line from reader, without line end.
+fn read_line<R: BufRead>(mut reader: R) -> Result<String, CliError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    while line.ends_with('\n') || line.ends_with('\r') {
+        line.pop();
+    }
+    Ok(line)
+}
+
+fn read_secret(source: &SecretSource) -> Result<String, CliError> {
+    match *source {
+        SecretSource::Prompt(ref prompt, confirm) => {
+            let secret = rpassword::prompt_password_stderr(prompt)?;
+            if confirm {
+                let again = rpassword::prompt_password_stderr("Repeat: ")?;
+                if again != secret {
+                    return Err(CliError::new("Passwords do not match"));
+                }
+            }
+            Ok(secret)
+        }
+        SecretSource::Stdin => {
+            let stdin = io::stdin();
+            let lock = stdin.lock();
+            read_line(lock)
+        }
+        #[cfg(unix)]
+        SecretSource::Fd(fd) => {
+            use std::os::unix::io::FromRawFd;
+            // Read from a copy, so that closing it leaves the caller's fd alone.
+            let copy = unsafe { libc::dup(fd) };
+            if copy < 0 {
+                let e = io::Error::last_os_error();
+                return Err(CliError::invalid(&format!("Can't read file descriptor {}: {}", fd, e)));
+            }
+            let file = unsafe { File::from_raw_fd(copy) };
+            read_line(io::BufReader::new(file))
+        }
+    }
+}
+
+/// Add options selecting secret source, named "<prefix>-stdin" and "<prefix>-fd".
+fn add_secret_opts(opts: &mut Options, prefix: &str, what: &str) {
+    opts.optflag("", &format!("{}-stdin", prefix), &format!("read {} from first line of stdin", what));
+    opts.optopt(
+        "",
+        &format!("{}-fd", prefix),
+        &format!("read {} from first line of file descriptor", what),
+        "FD",
+    );
+}
+
+fn get_secret_source(
+    matches: &getopts::Matches,
+    prefix: &str,
+    prompt: &str,
+    confirm: bool,
+) -> Result<SecretSource, CliError> {
+    if matches.opt_present(&format!("{}-stdin", prefix)) {
+        return Ok(SecretSource::Stdin);
+    }
+    if let Some(fd) = matches.opt_str(&format!("{}-fd", prefix)) {
+        let fd: i32 = match fd.parse() {
+            Ok(fd) => fd,
+            Err(_) => return Err(CliError::invalid(&format!("Invalid file descriptor: {}", fd))),
+        };
+        #[cfg(unix)]
+        {
+            if fd <= 2 {
+                return Err(CliError::invalid(&format!(
+                    "Invalid file descriptor: {}, use --{}-stdin to read stdin",
+                    fd, prefix
+                )));
+            }
+            if unsafe { libc::fcntl(fd, libc::F_GETFD) } < 0 {
+                return Err(CliError::invalid(&format!("File descriptor {} is not open", fd)));
+            }
+            return Ok(SecretSource::Fd(fd));
+        }
+        #[cfg(not(unix))]
+        return Err(CliError::new(&format!("Reading from file descriptor {} is not supported", fd)));
+    }
+    Ok(SecretSource::Prompt(String::from(prompt), confirm))
+}
+
+/// Global options, given before command.
+struct GlobalOptions {
+    file: String,
+    key_file: Option<String>,
+    password: SecretSource,
+    /// Lock database file at open, even if someone else has it locked.
+    take_over_lock: bool,
+}
+
+impl GlobalOptions {
+    fn open_db(&self) -> Result<Box<PasswordDatabase>, CliError> {
+        let password = read_secret(&self.password)?;
+        let key_file = self.key_file.as_ref().map(|k| k.as_str());
+        let mut db = db::kdb::open(&self.file, &password, key_file)?;
+        if self.take_over_lock {
+            db.acquire_lock(true)?;
+        }
+        Ok(Box::new(db))
+    }
+}
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {} [options] COMMAND [command options]", program);
+    println!("{}\n\n{}", opts.usage(&brief), USAGE_COMMANDS);
+}
+
+/// Parse command options, return None if help was requested (and printed).
+fn parse_command_opts(
+    name: &str,
+    args_usage: &str,
+    opts: &mut Options,
+    args: &[String],
+) -> Result<Option<getopts::Matches>, CliError> {
+    opts.optflag("h", "help", "print this help");
+    let matches = opts.parse(args)?;
+    if matches.opt_present("h") {
+        let brief = format!("Usage: mpkdb-cli {} {}", name, args_usage);
+        println!("{}", opts.usage(&brief));
+        return Ok(None);
+    }
+    Ok(Some(matches))
+}
+
+/// Exactly one entry at path, or error.
+fn find_entry(db: &PasswordDatabase, path: &str) -> Result<Entry, CliError> {
+    let mut entries = db.find_entries(path);
+    match entries.len() {
+        0 => Err(CliError::new(&format!("No such entry: {}", path))),
+        1 => Ok(entries.remove(0)),
+        n => Err(CliError::new(&format!(
+            "{} entries match \"{}\", use entry UUID instead (see search)",
+            n, path
+        ))),
+    }
+}
+
+fn find_group(db: &PasswordDatabase, path: &str) -> Result<GroupId, CliError> {
+    match db.find_group(path) {
+        Some(id) => Ok(id),
+        None => Err(CliError::new(&format!("No such group: {}", path))),
+    }
+}
+
+/// Find group, creating missing groups along the way.
+fn find_or_create_group(db: &mut PasswordDatabase, path: &str) -> Result<GroupId, CliError> {
+    Ok(api::find_or_create_group(db, path)?)
+}
+
+/// Split "GROUP/TITLE" path into group path and title.
+fn split_entry_path(path: &str) -> Result<(String, String), CliError> {
+    let mut components = api::split_path(path);
+    match components.pop() {
+        Some(title) if !components.is_empty() => Ok((
+            components.join(&api::PATH_SEPARATOR.to_string()),
+            String::from(title),
+        )),
+        _ => Err(CliError::new(&format!(
+            "Invalid entry path \"{}\", expected GROUP/TITLE",
+            path
+        ))),
+    }
+}
+
+fn cmd_ls(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optflag("r", "recursive", "list whole subtree, with full paths");
+    let matches = match parse_command_opts("ls", "[GROUP]", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let db = g.open_db()?;
+    let root = match matches.free.get(0) {
+        Some(path) => Some(find_group(&*db, path)?),
+        None => None,
+    };
+    let groups = db.get_groups();
+    let entries = db.get_entries();
+    if matches.opt_present("r") {
+        // Groups are in tree order, so group paths come out sorted like a tree.
+        let mut in_subtree: Vec<GroupId> = Vec::new();
+        for group in &groups {
+            let include = match root {
+                None => true,
+                Some(root) => group.id == root || group.parent.map_or(false, |p| in_subtree.contains(&p)),
+            };
+            if !include {
+                continue;
+            }
+            in_subtree.push(group.id);
+            let path = db.get_group_path(group.id).join(&api::PATH_SEPARATOR.to_string());
+            println!("{}{}", path, api::PATH_SEPARATOR);
+            for e in entries.iter().filter(|e| e.group_id == group.id) {
+                println!("{}{}{}", path, api::PATH_SEPARATOR, e.title);
+            }
+        }
+    } else {
+        for group in groups.iter().filter(|gr| gr.parent == root) {
+            println!("{}{}", group.name, api::PATH_SEPARATOR);
+        }
+        if let Some(root) = root {
+            for e in entries.iter().filter(|e| e.group_id == root) {
+                println!("{}", e.title);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(db: &PasswordDatabase, e: &Entry, show_password: bool) {
+    let group_path = db.get_group_path(e.group_id).join(&api::PATH_SEPARATOR.to_string());
+    println!("Title: {}", e.title);
+    println!("Group: {}", group_path);
+    println!("Username: {}", e.username);
+    println!("URL: {}", e.url);
+    if show_password {
+        println!("Password: {}", e.password);
+    } else {
+        println!("Password: ********");
+    }
+    println!("Created: {}", e.created);
+    println!("Modified: {}", e.modified);
+    println!("Accessed: {}", e.accessed);
+    println!("Expires: {}", e.expires);
+    println!("UUID: {}", e.id.hyphenated());
+    if !e.notes.is_empty() {
+        let notes = if show_password {
+            e.notes.clone()
+        } else {
+            otp::hide_in_notes(&e.notes)
+        };
+        println!("Notes:\n{}", notes);
+    }
+}
+
+fn cmd_show(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optflag("p", "show-password", "show password (and one-time password secret) instead of asterisks");
+    let matches = match parse_command_opts("show", "PATH", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing entry path")),
+    };
+    let db = g.open_db()?;
+    let e = find_entry(&*db, path)?;
+    print_entry(&*db, &e, matches.opt_present("p"));
+    Ok(())
+}
+
+fn cmd_search(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    let matches = match parse_command_opts("search", "TERM", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let term = match matches.free.get(0) {
+        Some(t) => t,
+        None => return Err(CliError::usage("Missing search term")),
+    };
+    let db = g.open_db()?;
+    for e in db.search(term) {
+        println!("{}  {}", e.id.hyphenated(), db.get_entry_path(&e));
+    }
+    Ok(())
+}
+
+/// Put text on clipboard using whatever clipboard tool is installed.
+fn copy_to_clipboard(text: &str) -> CliResult {
+    let tools: &[(&str, &[&str])] = &[
+        ("wl-copy", &[]),
+        ("xclip", &["-selection", "clipboard"]),
+        ("xsel", &["--clipboard", "--input"]),
+        ("pbcopy", &[]),
+    ];
+    for &(tool, args) in tools {
+        let child = process::Command::new(tool)
+            .args(args)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(c) => c,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(CliError::from(e)),
+        };
+        if let Some(ref mut stdin) = child.stdin {
+            stdin.write_all(text.as_bytes())?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(CliError::new(&format!("{} failed: {}", tool, status)));
+        }
+        return Ok(());
+    }
+    Err(CliError::new(
+        "No clipboard tool found, install wl-clipboard, xclip or xsel",
+    ))
+}
+
+fn cmd_get_password(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optflag("c", "clip", "copy password to clipboard instead of printing it");
+    let matches = match parse_command_opts("get-password", "PATH", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing entry path")),
+    };
+    let db = g.open_db()?;
+    let e = find_entry(&*db, path)?;
+    if matches.opt_present("c") {
+        copy_to_clipboard(&e.password)?;
+        eprintln!("Password copied to clipboard");
+    } else {
+        println!("{}", e.password);
+    }
+    Ok(())
+}
+
+fn cmd_totp(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optflag("c", "clip", "copy code to clipboard instead of printing it");
+    opts.optopt("", "set", "set up one-time password from otpauth:// URI or base32 secret", "SECRET");
+    opts.optflag("", "uri", "print otpauth:// URI instead of code, e.g. to add to an authenticator app");
+    let matches = match parse_command_opts("totp", "PATH", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing entry path")),
+    };
+    let mut db = g.open_db()?;
+    let mut e = find_entry(&*db, path)?;
+    if let Some(secret) = matches.opt_str("set") {
+        Otp::parse(&secret)?;
+        e.notes = otp::set_in_notes(&e.notes, secret.trim());
+        e.touch();
+        db.update_entry(&e)?;
+        db.save()?;
+    }
+    let mut otp = match Otp::from_notes(&e.notes) {
+        Some(o) => o?,
+        None => {
+            return Err(CliError::new(&format!(
+                "No one-time password set up for {}, use --set",
+                path
+            )))
+        }
+    };
+    if matches.opt_present("uri") {
+        // Authenticator apps show the label.
+        if otp.issuer.is_empty() && otp.account.is_empty() {
+            otp.issuer = e.title.clone();
+            otp.account = e.username.clone();
+        }
+        println!("{}", otp.to_uri());
+        return Ok(());
+    }
+    let (_, remaining) = otp.current();
+    let code = otp.next_code();
+    if let Kind::Hotp { .. } = otp.kind {
+        // Next time the next code.
+        e.notes = otp::set_in_notes(&e.notes, &otp.to_uri());
+        e.touch();
+        db.update_entry(&e)?;
+        db.save()?;
+    }
+    if matches.opt_present("c") {
+        copy_to_clipboard(&code)?;
+        eprintln!("One-time code copied to clipboard");
+    } else {
+        println!("{}", code);
+    }
+    if remaining > 0 {
+        eprintln!("Valid for {} seconds", remaining);
+    }
+    Ok(())
+}
+
+/// Options shared by add and edit.
+fn add_entry_field_opts(opts: &mut Options) {
+    opts.optopt("u", "username", "entry username", "USERNAME");
+    opts.optopt("", "url", "entry URL", "URL");
+    opts.optopt("n", "notes", "entry notes", "NOTES");
+    add_secret_opts(opts, "entry-password", "entry password");
+}
+
+fn apply_entry_field_opts(matches: &getopts::Matches, e: &mut Entry) {
+    if let Some(username) = matches.opt_str("username") {
+        e.username = username;
+    }
+    if let Some(url) = matches.opt_str("url") {
+        e.url = url;
+    }
+    if let Some(notes) = matches.opt_str("notes") {
+        e.notes = notes;
+    }
+}
+
+/// Only one of master and entry password can come from stdin.
+fn check_stdin_use(g: &GlobalOptions, source: &SecretSource) -> CliResult {
+    if let (&SecretSource::Stdin, &SecretSource::Stdin) = (&g.password, source) {
+        return Err(CliError::new(
+            "Master password and entry password can't both be read from stdin",
+        ));
+    }
+    Ok(())
+}
+
+/// Options selecting how passwords are generated, shared by generate, add and edit.
+fn add_generator_opts(opts: &mut Options) {
+    opts.optopt("l", "length", "password length (default: 20)", "N");
+    opts.optopt(
+        "",
+        "classes",
+        "character classes: u(pper), l(ower), d(igits), s(ymbols) (default: uld)",
+        "CLASSES",
+    );
+    opts.optopt("", "custom", "additional characters", "CHARS");
+    opts.optflag("", "no-lookalikes", &format!("leave out {}", mpkdb::generator::LOOKALIKES));
+    opts.optflag("", "any", "don't require a character from each class");
+    opts.optopt("", "pattern", "generate from KeePass style pattern, e.g. \"uLLLd{4}\"", "PATTERN");
+    opts.optopt("", "words", "generate passphrase of N words instead", "N");
+    opts.optopt("", "separator", "passphrase word separator (default: space)", "SEP");
+}
+
+fn parse_count(matches: &getopts::Matches, name: &str, default: usize) -> Result<usize, CliError> {
+    match matches.opt_str(name) {
+        Some(n) => n.parse()
+            .map_err(|_| CliError::usage(&format!("Invalid --{}: {}", name, n))),
+        None => Ok(default),
+    }
+}
+
+fn get_generator(matches: &getopts::Matches) -> Result<Generator, CliError> {
+    if let Some(pattern) = matches.opt_str("pattern") {
+        return Ok(Generator::Pattern {
+            pattern: pattern,
+            exclude_lookalikes: matches.opt_present("no-lookalikes"),
+        });
+    }
+    if matches.opt_present("words") {
+        return Ok(Generator::Passphrase {
+            words: parse_count(matches, "words", 0)?,
+            separator: matches.opt_str("separator").unwrap_or_else(|| String::from(" ")),
+        });
+    }
+    let classes = matches.opt_str("classes").unwrap_or_else(|| String::from("uld"));
+    if let Some(c) = classes.chars().find(|c| !"ulds".contains(*c)) {
+        return Err(CliError::usage(&format!("Unknown character class: {}", c)));
+    }
+    Ok(Generator::Chars(CharOptions {
+        length: parse_count(matches, "length", CharOptions::default().length)?,
+        upper: classes.contains('u'),
+        lower: classes.contains('l'),
+        digits: classes.contains('d'),
+        symbols: classes.contains('s'),
+        custom: matches.opt_str("custom").unwrap_or_default(),
+        exclude_lookalikes: matches.opt_present("no-lookalikes"),
+        each_class: !matches.opt_present("any"),
+    }))
+}
+
+fn cmd_generate(args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    add_generator_opts(&mut opts);
+    opts.optopt("n", "count", "number of passwords (default: 1)", "N");
+    opts.optflag("", "bits", "print strength in bits to stderr");
+    let matches = match parse_command_opts("generate", "", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let generator = get_generator(&matches)?;
+    if matches.opt_present("bits") {
+        eprintln!("{:.0} bits", generator.entropy_bits()?);
+    }
+    for _ in 0..parse_count(&matches, "count", 1)? {
+        println!("{}", generator.generate()?);
+    }
+    Ok(())
+}
+
+fn cmd_add(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    add_entry_field_opts(&mut opts);
+    opts.optflag("", "no-password", "don't ask for entry password, leave it empty");
+    opts.optflag("G", "generate", "generate entry password, see generate -h for options");
+    add_generator_opts(&mut opts);
+    opts.optflag("p", "parents", "create missing groups");
+    let matches = match parse_command_opts("add", "GROUP/TITLE", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let (group_path, title) = match matches.free.get(0) {
+        Some(p) => split_entry_path(p)?,
+        None => return Err(CliError::usage("Missing entry path")),
+    };
+    let generator = if matches.opt_present("generate") {
+        Some(get_generator(&matches)?)
+    } else {
+        None
+    };
+    let entry_password_source = get_secret_source(&matches, "entry-password", "Entry password: ", true)?;
+    if generator.is_none() {
+        check_stdin_use(g, &entry_password_source)?;
+    }
+    let mut db = g.open_db()?;
+    let group_id = if matches.opt_present("p") {
+        find_or_create_group(&mut *db, &group_path)?
+    } else {
+        find_group(&*db, &group_path)?
+    };
+    let mut e = Entry::new(group_id, &title);
+    apply_entry_field_opts(&matches, &mut e);
+    if let Some(ref generator) = generator {
+        e.password = generator.generate()?;
+    } else if !matches.opt_present("no-password") {
+        e.password = read_secret(&entry_password_source)?;
+    }
+    db.add_entry(&e)?;
+    db.save()?;
+    println!("{}", e.id.hyphenated());
+    Ok(())
+}
+
+fn cmd_edit(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optopt("t", "title", "entry title", "TITLE");
+    add_entry_field_opts(&mut opts);
+    opts.optflag("", "password", "ask for new entry password");
+    opts.optflag("G", "generate", "generate new entry password, see generate -h for options");
+    add_generator_opts(&mut opts);
+    let matches = match parse_command_opts("edit", "PATH", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing entry path")),
+    };
+    let change_password = matches.opt_present("password") || matches.opt_present("entry-password-stdin") ||
+        matches.opt_present("entry-password-fd");
+    let generator = if matches.opt_present("generate") {
+        Some(get_generator(&matches)?)
+    } else {
+        None
+    };
+    let entry_password_source = get_secret_source(&matches, "entry-password", "New entry password: ", true)?;
+    if change_password && generator.is_none() {
+        check_stdin_use(g, &entry_password_source)?;
+    }
+    let mut db = g.open_db()?;
+    let mut e = find_entry(&*db, path)?;
+    if let Some(title) = matches.opt_str("title") {
+        e.title = title;
+    }
+    apply_entry_field_opts(&matches, &mut e);
+    if let Some(ref generator) = generator {
+        e.password = generator.generate()?;
+    } else if change_password {
+        e.password = read_secret(&entry_password_source)?;
+    }
+    e.touch();
+    db.update_entry(&e)?;
+    db.save()?;
+    Ok(())
+}
+
+fn cmd_rm(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optflag("r", "recursive", "remove group with all its subgroups and entries");
+    let matches = match parse_command_opts("rm", "PATH", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing path")),
+    };
+    let mut db = g.open_db()?;
+    if matches.opt_present("r") {
+        let group_id = find_group(&*db, path)?;
+        db.remove_group(group_id)?;
+    } else {
+        let e = find_entry(&*db, path)?;
+        db.remove_entry(&e.id)?;
+    }
+    db.save()?;
+    Ok(())
+}
+
+fn cmd_mv(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    let matches = match parse_command_opts("mv", "PATH DEST", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    if matches.free.len() != 2 {
+        return Err(CliError::usage("Expected entry path and destination"));
+    }
+    let mut db = g.open_db()?;
+    let mut e = find_entry(&*db, &matches.free[0])?;
+    let dest = &matches.free[1];
+    match db.find_group(dest) {
+        Some(group_id) => e.group_id = group_id,
+        None => {
+            let (group_path, title) = split_entry_path(dest)?;
+            e.group_id = find_group(&*db, &group_path)?;
+            e.title = title;
+        }
+    }
+    e.touch();
+    db.update_entry(&e)?;
+    db.save()?;
+    Ok(())
+}
+
+/// Format from --format, or from file contents (when importing) or name, KDB by default.
+/// Directories are pass stores.
+fn get_format(matches: &getopts::Matches, path: &str, existing: bool) -> Result<Format, CliError> {
+    if let Some(name) = matches.opt_str("format") {
+        return Format::from_name(&name).ok_or_else(|| CliError::usage(&format!("Unknown format: {}", name)));
+    }
+    if Path::new(path).is_dir() {
+        return Ok(Format::Pass);
+    }
+    if existing {
+        let mut start = [0; 1024];
+        let n = File::open(path)?.read(&mut start)?;
+        if let Some(f) = Format::sniff(&start[..n]) {
+            return Ok(f);
+        }
+    }
+    Ok(Format::from_path(Path::new(path)).unwrap_or(Format::Kdb))
+}
+
+/// Options shared by import and export of plaintext formats.
+fn add_format_opts(opts: &mut Options) {
+    opts.optopt(
+        "F",
+        "format",
+        "file format: kdb, csv, json, keepass1-xml, keepass2-xml, pass (a directory), or for \
+         import only bitwarden, 1pux, 1pif, lastpass (default: from file, or kdb)",
+        "FORMAT",
+    );
+    opts.optopt(
+        "",
+        "columns",
+        &format!("CSV columns, \"-\" skips one (default: {})", formats::csv::DEFAULT_COLUMNS),
+        "LIST",
+    );
+    opts.optflag("", "no-header", "CSV has no header row");
+    opts.optflag("n", "dry-run", "only show what would be done");
+}
+
+fn get_csv_options(matches: &getopts::Matches) -> Result<formats::csv::Options, CliError> {
+    let mapping = match matches.opt_str("columns") {
+        Some(spec) => Some(formats::csv::Mapping::parse(&spec)?),
+        None => None,
+    };
+    Ok(formats::csv::Options {
+        mapping: mapping,
+        header: !matches.opt_present("no-header"),
+    })
+}
+
+/// Plaintext files are readable by anyone who gets them, make sure user wants that.
+fn confirm_plaintext(g: &GlobalOptions, matches: &getopts::Matches, path: &str) -> CliResult {
+    if matches.opt_present("yes") {
+        return Ok(());
+    }
+    if let SecretSource::Stdin = g.password {
+        return Err(CliError::usage("Exported file is not encrypted, confirm with --yes"));
+    }
+    eprint!(
+        "{} will contain passwords in plaintext, anyone who can read it can read them. Continue? [y/N] ",
+        path
+    );
+    let stdin = io::stdin();
+    let answer = read_line(stdin.lock())?;
+    if answer.trim().eq_ignore_ascii_case("y") || answer.trim().eq_ignore_ascii_case("yes") {
+        Ok(())
+    } else {
+        Err(CliError::new("Export cancelled"))
+    }
+}
+
+fn cmd_export(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optopt("g", "group", "export only this group", "GROUP");
+    add_format_opts(&mut opts);
+    opts.optflag("y", "yes", "don't ask before writing plaintext passwords");
+    opts.optopt("", "new-key-file", "key file for exported KDB file", "FILE");
+    opts.optflag("", "force", "replace existing KDB file");
+    add_secret_opts(&mut opts, "new-password", "password for exported KDB file");
+    opts.optmulti("", "gpg-id", "GPG key to encrypt for, when creating a pass store", "KEY");
+    let matches = match parse_command_opts("export", "FILE", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing output file")),
+    };
+    let format = get_format(&matches, path, false)?;
+    if format.is_import_only() {
+        return Err(CliError::usage(&format!("Can't export to {}, only import", format.name())));
+    }
+    let csv_options = get_csv_options(&matches)?;
+    let dry_run = matches.opt_present("dry-run");
+    if format.is_plaintext() && !dry_run {
+        confirm_plaintext(g, &matches, path)?;
+    }
+    let force = matches.opt_present("force");
+    if format == Format::Kdb && !dry_run && !force && Path::new(path).exists() {
+        return Err(CliError::new(&format!("File \"{}\" already exists, use --force to replace it", path)));
+    }
+    let new_password_source = if format == Format::Kdb && !dry_run {
+        let source = get_secret_source(&matches, "new-password", "Password for exported file: ", true)?;
+        check_stdin_use(g, &source)?;
+        Some(source)
+    } else {
+        None
+    };
+    let db = g.open_db()?;
+    let group = match matches.opt_str("group") {
+        Some(gr) => Some(find_group(&*db, &gr)?),
+        None => None,
+    };
+    if dry_run {
+        let records = formats::records(&*db, group);
+        for r in &records {
+            println!("{}", r.path());
+        }
+        eprintln!("Would export {} entries to {}", records.len(), path);
+        return Ok(());
+    }
+    let count = match format {
+        Format::Kdb => {
+            let source = new_password_source.expect("Password source for KDB");
+            let new_password = read_secret(&source)?;
+            let new_key_file = matches.opt_str("new-key-file");
+            db::kdb::export(
+                &*db,
+                group,
+                path,
+                &new_password,
+                new_key_file.as_ref().map(|k| k.as_str()),
+                force,
+            )?
+        }
+        Format::Pass => {
+            let records = formats::records(&*db, group);
+            let (count, skipped) = formats::pass::write(
+                Path::new(path),
+                &records,
+                &matches.opt_strs("gpg-id"),
+                &formats::pass::gpg_encrypt,
+            )?;
+            for s in &skipped {
+                eprintln!("  skipped: {}", s);
+            }
+            count
+        }
+        Format::Json => {
+            let mut contents = Vec::new();
+            let count = formats::json::write(&mut contents, &formats::json::Dump::new(&*db, group))?;
+            db::file::write_atomic(Path::new(path), &contents, 0)?;
+            count
+        }
+        _ => {
+            let mut contents = Vec::new();
+            let count = formats::write(
+                format,
+                &mut contents,
+                &formats::group_paths(&*db, group),
+                &formats::records(&*db, group),
+                &csv_options,
+            )?;
+            db::file::write_atomic(Path::new(path), &contents, 0)?;
+            count
+        }
+    };
+    eprintln!("Exported {} entries to {}", count, path);
+    Ok(())
+}
+
+/// Records of given groups and their subgroups, all if none are given.
+fn group_records(db: &PasswordDatabase, groups: &[String]) -> Result<Vec<formats::Record>, CliError> {
+    if groups.is_empty() {
+        return Ok(formats::records(db, None));
+    }
+    let mut records: Vec<formats::Record> = Vec::new();
+    for gr in groups {
+        for r in formats::records(db, Some(find_group(db, gr)?)) {
+            // Groups given may be subgroups of each other.
+            if !records.iter().any(|other| other.entry.id == r.entry.id) {
+                records.push(r);
+            }
+        }
+    }
+    Ok(records)
+}
+
+fn cmd_report(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optmulti("g", "group", "report only this group, can be given more than once", "GROUP");
+    opts.optopt("F", "format", "html or markdown (default: from file name, or html)", "FORMAT");
+    opts.optflag("p", "passwords", "include passwords");
+    opts.optflag("", "qr", "add QR code with title, username, URL (and password) to entries");
+    opts.optflag("y", "yes", "don't ask before writing plaintext passwords");
+    let matches = match parse_command_opts("report", "FILE", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing output file")),
+    };
+    let format = match matches.opt_str("format") {
+        Some(name) => {
+            ReportFormat::from_name(&name).ok_or_else(|| CliError::usage(&format!("Unknown format: {}", name)))?
+        }
+        None => Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(ReportFormat::from_name)
+            .unwrap_or(ReportFormat::Html),
+    };
+    let passwords = matches.opt_present("passwords");
+    if passwords {
+        confirm_plaintext(g, &matches, path)?;
+    }
+    let db = g.open_db()?;
+    let records = group_records(&*db, &matches.opt_strs("group"))?;
+    let options = report::Options {
+        format: format,
+        title: Path::new(&g.file)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| g.file.clone()),
+        passwords: passwords,
+        qr_codes: matches.opt_present("qr"),
+    };
+    let mut contents = Vec::new();
+    let count = report::write(&mut contents, &records, &options)?;
+    db::file::write_atomic(Path::new(path), &contents, 0)?;
+    eprintln!("Wrote report of {} entries to {}", count, path);
+    Ok(())
+}
+
+fn cmd_audit(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optmulti("g", "group", "check only this group, can be given more than once", "GROUP");
+    opts.optopt("", "days", "report passwords not changed in N days", "N");
+    opts.optopt(
+        "",
+        "min-score",
+        "passwords scoring lower are weak, 0 (very weak) to 4 (strong) (default: 3)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "pwned",
+        "look passwords up in local Have I Been Pwned list (ordered by hash) or range files directory",
+        "PATH",
+    );
+    opts.optflag("a", "all", "list all entries with their strength, not only those with issues");
+    let matches = match parse_command_opts("audit", "", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let mut options = audit::Options::default();
+    if matches.opt_present("days") {
+        options.max_age_days = Some(parse_count(&matches, "days", 0)? as i64);
+    }
+    options.min_score = match parse_count(&matches, "min-score", options.min_score as usize)? {
+        n if n <= audit::strength::MAX_SCORE as usize => n as u8,
+        n => {
+            return Err(CliError::usage(&format!(
+                "Invalid --min-score: {}, expected 0 to {}",
+                n,
+                audit::strength::MAX_SCORE
+            )))
+        }
+    };
+    options.pwned_list = matches.opt_str("pwned").map(PathBuf::from);
+    let db = g.open_db()?;
+    let records = group_records(&*db, &matches.opt_strs("group"))?;
+    let result = audit::audit(&records, &options)?;
+    let all = matches.opt_present("all");
+    for e in &result.entries {
+        if e.issues.is_empty() && !all {
+            continue;
+        }
+        let issues: Vec<String> = e.issues.iter().map(|i| i.to_string()).collect();
+        if e.issues.contains(&audit::Issue::Empty) {
+            println!("{}: {}", e.path, issues.join("; "));
+            continue;
+        }
+        let mut strength = format!("{}, {:.0} bits", e.strength.label(), e.strength.bits);
+        if let Some(weakness) = e.strength.weakness.filter(|_| e.strength.score < options.min_score) {
+            strength = format!("{}, {}", strength, weakness);
+        }
+        if issues.is_empty() {
+            println!("{} ({})", e.path, strength);
+        } else {
+            println!("{} ({}): {}", e.path, strength, issues.join("; "));
+        }
+    }
+    let summary = result.summary();
+    if summary.is_empty() {
+        eprintln!("No issues in {} entries", result.entries.len());
+    } else {
+        eprintln!(
+            "{} of {} entries have issues: {}",
+            result.with_issues().len(),
+            result.entries.len(),
+            summary.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn cmd_import(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optopt("g", "into", "import into this group (created if missing)", "GROUP");
+    add_format_opts(&mut opts);
+    opts.optopt("", "import-key-file", "key file of imported KDB file", "FILE");
+    add_secret_opts(&mut opts, "import-password", "password of imported KDB file");
+    let matches = match parse_command_opts("import", "FILE", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing input file")),
+    };
+    let format = get_format(&matches, path, true)?;
+    let csv_options = get_csv_options(&matches)?;
+    let import_password_source = if format == Format::Kdb {
+        let source = get_secret_source(&matches, "import-password", "Password of imported file: ", false)?;
+        check_stdin_use(g, &source)?;
+        Some(source)
+    } else {
+        None
+    };
+    let mut db = g.open_db()?;
+    let into = match matches.opt_str("into") {
+        Some(gr) => Some(find_or_create_group(&mut *db, &gr)?),
+        None => None,
+    };
+    let dry_run = matches.opt_present("dry-run");
+    match format {
+        Format::Kdb => {
+            let source = import_password_source.expect("Password source for KDB");
+            let import_password = read_secret(&source)?;
+            let import_key_file = matches.opt_str("import-key-file");
+            let src = db::kdb::open(path, &import_password, import_key_file.as_ref().map(|k| k.as_str()))?;
+            let count = api::copy_into(&src, None, &mut *db, into)?;
+            if dry_run {
+                eprintln!("Would import {} entries from {}", count, path);
+                return Ok(());
+            }
+            db.save()?;
+            eprintln!("Imported {} entries from {}", count, path);
+        }
+        _ => {
+            let parsed = if format == Format::Pass {
+                formats::pass::read(Path::new(path), &formats::pass::gpg_decrypt)?
+            } else {
+                formats::read(format, File::open(path)?, &csv_options)?
+            };
+            let summary = formats::import(&mut *db, &parsed, into)?;
+            if dry_run {
+                for r in &parsed.records {
+                    println!("{}", r.path());
+                }
+                eprintln!("Would import {}", summary);
+                return Ok(());
+            }
+            db.save()?;
+            eprintln!("Imported {}", summary);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_info(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    if parse_command_opts("info", "", &mut opts, args)?.is_none() {
+        return Ok(());
+    }
+    let db = g.open_db()?;
+    for (k, v) in db.get_info() {
+        println!("{}: {}", k, v);
+    }
+    Ok(())
+}
+
+fn cmd_passwd(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optopt("", "new-key-file", "new key file (default: none)", "FILE");
+    add_secret_opts(&mut opts, "new-password", "new master password");
+    let matches = match parse_command_opts("passwd", "", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let new_password_source = get_secret_source(&matches, "new-password", "New master password: ", true)?;
+    check_stdin_use(g, &new_password_source)?;
+    let mut db = g.open_db()?;
+    let new_password = read_secret(&new_password_source)?;
+    let new_key_file = matches.opt_str("new-key-file");
+    db.change_master_key(&new_password, new_key_file.as_ref().map(|k| k.as_str()))?;
+    db.save()?;
+    eprintln!("Master key changed");
+    Ok(())
+}
+
+/// Rounds given as number, or as seconds to calibrate for, None if neither is given.
+fn parse_rounds(rounds: Option<&String>, calibrate: Option<String>) -> Result<Option<u32>, CliError> {
+    match (rounds, calibrate) {
+        (Some(_), Some(_)) => Err(CliError::usage("Give either rounds or --calibrate, not both")),
+        (Some(n), None) => match n.parse::<u32>() {
+            Ok(n) if n > 0 => Ok(Some(n)),
+            _ => Err(CliError::usage(&format!("Invalid number of rounds: {}", n))),
+        },
+        (None, Some(secs)) => match secs.parse::<f64>() {
+            Ok(secs) if secs > 0.0 && secs < 3600.0 => {
+                let target = Duration::from_millis((secs * 1000.0) as u64);
+                let rounds = db::kdb::calibrate_rounds(target);
+                eprintln!("{} rounds take about {} s on this machine", rounds, secs);
+                Ok(Some(rounds))
+            }
+            _ => Err(CliError::usage(&format!("Invalid number of seconds: {}", secs))),
+        },
+        (None, None) => Ok(None),
+    }
+}
+
+fn cmd_rounds(g: &GlobalOptions, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optopt(
+        "c",
+        "calibrate",
+        "set rounds taking about this long on this machine",
+        "SECONDS",
+    );
+    let matches = match parse_command_opts("rounds", "[N]", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let rounds = parse_rounds(matches.free.get(0), matches.opt_str("calibrate"))?;
+    let mut db = g.open_db()?;
+    match rounds {
+        Some(rounds) => {
+            db.set_key_transform_rounds(rounds)?;
+            db.save()?;
+            eprintln!("Key transformation rounds set to {}", rounds);
+        }
+        None => println!("{}", db.get_key_transform_rounds()),
+    }
+    Ok(())
+}
+
+/// Create database, file is given as argument (or with -f), key file and password with global options.
+fn cmd_new(global: &getopts::Matches, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optopt("r", "rounds", "key transformation rounds", "N");
+    opts.optopt(
+        "c",
+        "calibrate",
+        "set rounds taking about this long on this machine",
+        "SECONDS",
+    );
+    opts.optflag("", "no-groups", "don't create default groups");
+    let matches = match parse_command_opts("new", "FILE", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    let path = match matches.free.get(0).cloned().or_else(|| global.opt_str("file")) {
+        Some(p) => p,
+        None => return Err(CliError::usage("Missing database file")),
+    };
+    let rounds = parse_rounds(matches.opt_str("rounds").as_ref(), matches.opt_str("calibrate"))?
+        .unwrap_or(db::kdb::DEFAULT_KEY_ENC_ROUNDS);
+    let groups = if matches.opt_present("no-groups") {
+        &[]
+    } else {
+        db::kdb::DEFAULT_GROUPS
+    };
+    let password = read_secret(&get_secret_source(global, "password", "Master password: ", true)?)?;
+    let key_file = global.opt_str("key-file");
+    db::kdb::create_new(&path, &password, key_file.as_ref().map(|k| k.as_str()), rounds, groups)?;
+    eprintln!("Created {}", path);
+    Ok(())
+}
+
+/// Password and key file.
+type Key = (String, Option<String>);
+
+/// Options for the key of the second file of merge and diff.
+fn add_other_key_opts(opts: &mut Options, files: &str) {
+    opts.optopt("", "other-key-file", &format!("key file of {}", files), "FILE");
+    add_secret_opts(opts, "other-password", &format!("password of {}", files));
+}
+
+/// Global key, and the other key, which is the same unless given.
+fn read_keys(global: &getopts::Matches, matches: &getopts::Matches) -> Result<(Key, Key), CliError> {
+    let password_source = get_secret_source(global, "password", "Master password: ", false)?;
+    let password = read_secret(&password_source)?;
+    let key_file = global.opt_str("key-file");
+    let other_given = matches.opt_present("other-password-stdin") || matches.opt_present("other-password-fd");
+    let other_password = if other_given {
+        let source = get_secret_source(matches, "other-password", "", false)?;
+        if let (&SecretSource::Stdin, &SecretSource::Stdin) = (&password_source, &source) {
+            return Err(CliError::new("Both passwords can't be read from stdin"));
+        }
+        read_secret(&source)?
+    } else {
+        password.clone()
+    };
+    let other_key_file = matches.opt_str("other-key-file").or_else(|| key_file.clone());
+    Ok(((password, key_file), (other_password, other_key_file)))
+}
+
+fn open_with(path: &str, key: &Key) -> Result<db::kdb::KdbDatabase, CliError> {
+    Ok(db::kdb::open(path, &key.0, key.1.as_ref().map(|k| k.as_str()))?)
+}
+
+/// Merge two copies of database, passwords and key files are the global ones unless given.
+fn cmd_merge(global: &getopts::Matches, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "write result here (default: FILE_A)", "FILE");
+    opts.optopt("", "base", "common ancestor of both, so deletions are merged too", "FILE");
+    add_other_key_opts(&mut opts, "FILE_B and base");
+    opts.optflag("", "backup", "keep losing versions of entries in Backup group");
+    opts.optflag("n", "dry-run", "only show what would change");
+    let matches = match parse_command_opts("merge", "FILE_A FILE_B", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    if matches.free.len() != 2 {
+        return Err(CliError::usage("Give two files to merge"));
+    }
+    let (key, other_key) = read_keys(global, &matches)?;
+    let mut ours = open_with(&matches.free[0], &key)?;
+    let theirs = open_with(&matches.free[1], &other_key)?;
+    let base = match matches.opt_str("base") {
+        Some(b) => Some(open_with(&b, &other_key)?),
+        None => None,
+    };
+    let report = merge::merge(
+        base.as_ref().map(|b| b as &PasswordDatabase),
+        &theirs,
+        &mut ours,
+        matches.opt_present("backup"),
+    )?;
+    println!("{}", report);
+    if matches.opt_present("dry-run") {
+        return Ok(());
+    }
+    match matches.opt_str("output") {
+        Some(out) => {
+            ours.save_as(&out)?;
+            eprintln!("Merged into {}", out);
+        }
+        None => if !report.is_empty() {
+            if global.opt_present("take-over-lock") {
+                ours.acquire_lock(true)?;
+            }
+            ours.save()?;
+            eprintln!("Merged into {}", matches.free[0]);
+        },
+    }
+    Ok(())
+}
+
+/// What changed from FILE_A to FILE_B.
+fn cmd_diff(global: &getopts::Matches, args: &[String]) -> CliResult {
+    let mut opts = Options::new();
+    add_other_key_opts(&mut opts, "FILE_B");
+    opts.optflag("", "json", "JSON output");
+    let matches = match parse_command_opts("diff", "FILE_A FILE_B", &mut opts, args)? {
+        Some(m) => m,
+        None => return Ok(()),
+    };
+    if matches.free.len() != 2 {
+        return Err(CliError::usage("Give two files to compare"));
+    }
+    let (key, other_key) = read_keys(global, &matches)?;
+    let old = open_with(&matches.free[0], &key)?;
+    let new = open_with(&matches.free[1], &other_key)?;
+    let d = diff::diff(&old, &new);
+    if matches.opt_present("json") {
+        let json = serde_json::to_string_pretty(&d).map_err(|e| CliError::new(&e.to_string()))?;
+        println!("{}", json);
+    } else {
+        println!("{}", d);
+    }
+    Ok(())
+}
+
+fn run() -> CliResult {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+    let mut opts = Options::new();
+    opts.parsing_style(getopts::ParsingStyle::StopAtFirstFree);
+    opts.optopt("f", "file", "database file (default: last opened file)", "FILE");
+    opts.optopt("k", "key-file", "key file", "FILE");
+    opts.optopt("", "config", "config file", "FILE");
+    add_secret_opts(&mut opts, "password", "master password");
+    opts.optflag("", "take-over-lock", "save even if database is locked, e.g. by a crashed instance");
+    opts.optflag("h", "help", "print this help");
+    let matches = opts.parse(&args[1..])?;
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_usage(&program, &opts);
+        return Ok(());
+    }
+    // There's no database to open yet.
+    if matches.free[0] == "new" {
+        return cmd_new(&matches, &matches.free[1..]);
+    }
+    // Files are given as arguments, or there are none.
+    match matches.free[0].as_str() {
+        "merge" => return cmd_merge(&matches, &matches.free[1..]),
+        "diff" => return cmd_diff(&matches, &matches.free[1..]),
+        "generate" => return cmd_generate(&matches.free[1..]),
+        _ => {}
+    }
+    let conf = config::load_config(&config::config_file_path(
+        matches.opt_str("config").as_ref().map(|p| p.as_str()),
+    )).map_err(|e| CliError::new(&format!("Error loading config: {}", errors::report(&e))))?;
+    // Without -f use the last opened file, along with its key file.
+    let (file, key_file) = match matches.opt_str("file") {
+        Some(f) => (f, matches.opt_str("key-file")),
+        None => match conf.recent_files.first() {
+            Some(r) => (
+                r.path.clone(),
+                matches.opt_str("key-file").or_else(|| r.key_file.clone()),
+            ),
+            None => return Err(CliError::new("No database file given, use -f FILE")),
+        },
+    };
+    let g = GlobalOptions {
+        file: file,
+        key_file: key_file,
+        password: get_secret_source(&matches, "password", "Master password: ", false)?,
+        take_over_lock: matches.opt_present("take-over-lock"),
+    };
+    let command = matches.free[0].as_str();
+    let command_args = &matches.free[1..];
+    debug!("run: Command {}, file {}", command, g.file);
+    match command {
+        "ls" => cmd_ls(&g, command_args),
+        "show" => cmd_show(&g, command_args),
+        "search" => cmd_search(&g, command_args),
+        "get-password" => cmd_get_password(&g, command_args),
+        "totp" => cmd_totp(&g, command_args),
+        "add" => cmd_add(&g, command_args),
+        "edit" => cmd_edit(&g, command_args),
+        "rm" => cmd_rm(&g, command_args),
+        "mv" => cmd_mv(&g, command_args),
+        "export" => cmd_export(&g, command_args),
+        "import" => cmd_import(&g, command_args),
+        "report" => cmd_report(&g, command_args),
+        "audit" => cmd_audit(&g, command_args),
+        "info" => cmd_info(&g, command_args),
+        "passwd" => cmd_passwd(&g, command_args),
+        "rounds" => cmd_rounds(&g, command_args),
+        _ => Err(CliError::usage(&format!(
+            "Unknown command: {}, see {} -h",
+            command, program
+        ))),
+    }
+}
+
+fn main() {
+    mpkdb::logging::env_logger_init_with_level(log::LogLevelFilter::Warn);
+    if let Err(e) = run() {
+        eprintln!("mpkdb-cli: {}", e);
+        if let CliError::Failed(ref e) = e {
+            if let api::Error::Locked { .. } = *e.kind() {
+                eprintln!("mpkdb-cli: If it was left behind by a crash, use --take-over-lock");
+            }
+        }
+        process::exit(e.exit_code());
+    }
+}
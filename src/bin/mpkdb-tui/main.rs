@@ -0,0 +1,45 @@
+//! mpkdb-tui: terminal UI, for when GTK is not available.
+
+extern crate getopts;
+extern crate log;
+extern crate mpkdb;
+
+use std::env;
+use std::process;
+
+use getopts::Options;
+
+use mpkdb::config;
+use mpkdb::errors;
+
+fn main() {
+    mpkdb::logging::env_logger_init_with_level(log::LogLevelFilter::Warn);
+    let args: Vec<String> = env::args().collect();
+    let mut opts = Options::new();
+    opts.optopt("k", "key-file", "key file", "FILE");
+    opts.optopt("", "config", "config file", "FILE");
+    opts.optflag("h", "help", "print this help");
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("mpkdb-tui: {}", e);
+            process::exit(2);
+        }
+    };
+    if matches.opt_present("h") {
+        let brief = format!("Usage: {} [options] [FILE]", args[0]);
+        println!("{}", opts.usage(&brief));
+        return;
+    }
+    let config_path = config::config_file_path(matches.opt_str("config").as_ref().map(|p| p.as_str()));
+    let key_file = matches.opt_str("key-file");
+    let file = matches.free.get(0);
+    if let Err(e) = mpkdb::tui::run(
+        file.map(|f| f.as_str()),
+        key_file.as_ref().map(|k| k.as_str()),
+        &config_path,
+    ) {
+        eprintln!("mpkdb-tui: {}", errors::report(&e));
+        process::exit(1);
+    }
+}
@@ -1,33 +1,144 @@
-
 //! App config.
+//!
+//! Config lives in `$XDG_CONFIG_HOME/mpkdb/config.toml` (falling back to `~/.config`),
+//! unless overridden with `--config`.
+//! Config files carry a `version`, older files are migrated on load.
 
 use std;
+use std::env;
+use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
 
 use toml;
 
 use errors;
 use model::Config;
 
+/// Version of config file we write.
+/// Bump this when changing config schema, and add the migration to MIGRATIONS.
+pub const CONFIG_VERSION: i64 = 1;
+
+/// Migrations, element N upgrades config from version N to N + 1.
+const MIGRATIONS: &[fn(&mut toml::value::Table)] = &[migrate_v0_to_v1];
+
 /// Return default config file path on this system.
-pub fn default_config_file_path() -> String {
-    String::from("mpkdb.toml")
+pub fn default_config_file_path() -> PathBuf {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        // Spec says relative paths should be ignored.
+        Some(ref p) if Path::new(p).is_absolute() => PathBuf::from(p),
+        _ => match env::var_os("HOME") {
+            Some(home) => Path::new(&home).join(".config"),
+            None => {
+                warn!("Neither XDG_CONFIG_HOME nor HOME is set, using current dir for config");
+                PathBuf::from(".")
+            }
+        },
+    };
+    config_home.join("mpkdb").join("config.toml")
+}
+
+/// Config file path, given (optional) path from command line.
+pub fn config_file_path(override_path: Option<&str>) -> PathBuf {
+    match override_path {
+        Some(p) => PathBuf::from(p),
+        None => default_config_file_path(),
+    }
+}
+
+/// Config version 0 only had last_file, recent files list came with version 1.
+fn migrate_v0_to_v1(table: &mut toml::value::Table) {
+    if table.contains_key("recent_files") {
+        return;
+    }
+    let last_file = match table.get("last_file") {
+        Some(&toml::Value::String(ref s)) => s.clone(),
+        _ => return,
+    };
+    let mut recent_file = toml::value::Table::new();
+    recent_file.insert(String::from("path"), toml::Value::String(last_file));
+    table.insert(
+        String::from("recent_files"),
+        toml::Value::Array(vec![toml::Value::Table(recent_file)]),
+    );
+}
+
+/// Bring config table up to current version.
+fn migrate(table: &mut toml::value::Table) -> Result<(), errors::Error> {
+    let version = match table.get("version") {
+        None => 0,
+        Some(&toml::Value::Integer(v)) if v >= 0 => v,
+        Some(v) => return Err(errors::Error::InvalidConfig(format!("Invalid version: {}", v))),
+    };
+    if version > CONFIG_VERSION {
+        warn!(
+            "Config version {} is newer than supported version {}, some settings may be ignored",
+            version, CONFIG_VERSION
+        );
+        return Ok(());
+    }
+    for v in version..CONFIG_VERSION {
+        debug!("Migrating config from version {} to {}", v, v + 1);
+        MIGRATIONS[v as usize](table);
+    }
+    table.insert(String::from("version"), toml::Value::Integer(CONFIG_VERSION));
+    Ok(())
+}
+
+/// Collect keys present in `read` but not in `known`, descending into tables and arrays of tables.
+fn find_unknown_keys(prefix: &str, read: &toml::Value, known: &toml::Value, unknown: &mut Vec<String>) {
+    match (read, known) {
+        (&toml::Value::Table(ref read), &toml::Value::Table(ref known)) => for (k, v) in read {
+            let name = if prefix.is_empty() {
+                k.clone()
+            } else {
+                format!("{}.{}", prefix, k)
+            };
+            match known.get(k) {
+                Some(known_v) => find_unknown_keys(&name, v, known_v, unknown),
+                None => unknown.push(name),
+            }
+        },
+        (&toml::Value::Array(ref read), &toml::Value::Array(ref known)) => for (i, (v, known_v)) in
+            read.iter().zip(known.iter()).enumerate()
+        {
+            find_unknown_keys(&format!("{}[{}]", prefix, i), v, known_v, unknown);
+        },
+        _ => {}
+    }
 }
 
-pub fn load_config() -> Result<Config, errors::Error> {
-    let path = default_config_file_path();
-    let conf: Config = match File::open(&path) {
+/// Parse config file contents, migrating it if needed.
+/// Keys we don't know about are reported as warnings.
+pub fn parse_config(contents: &str) -> Result<Config, errors::Error> {
+    let mut table: toml::value::Table = toml::from_str(contents)?;
+    migrate(&mut table)?;
+    let value = toml::Value::Table(table);
+    let conf: Config = value.clone().try_into()?;
+    // Whatever doesn't survive the round trip through Config was not understood.
+    let known = toml::Value::try_from(&conf)?;
+    let mut unknown = Vec::new();
+    find_unknown_keys("", &value, &known, &mut unknown);
+    for k in unknown {
+        warn!("Unknown config key \"{}\", ignoring", k);
+    }
+    Ok(conf)
+}
+
+pub fn load_config(path: &Path) -> Result<Config, errors::Error> {
+    let conf: Config = match File::open(path) {
         Ok(mut file) => {
             let mut contents = String::new();
             file.read_to_string(&mut contents)?;
-            let conf: Config = toml::from_str(&contents)?;
-            conf
+            parse_config(&contents)?
         }
         Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
             debug!(
                 "Config file \"{}\" not found, returning default config",
-                path
+                path.display()
             );
             Default::default()
         }
@@ -35,3 +146,84 @@ pub fn load_config() -> Result<Config, errors::Error> {
     };
     Ok(conf)
 }
+
+/// Write config file.
+/// Config is written to temp file in the same dir first, and then renamed over the old one,
+/// so we never leave half-written config behind.
+pub fn save_config(conf: &Config, path: &Path) -> Result<(), errors::Error> {
+    let contents = toml::to_string(conf)?;
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    let mut tmp_path = path.as_os_str().to_os_string();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+    }
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(errors::Error::from(e));
+    }
+    debug!("Saved config to \"{}\"", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use model::RecentFile;
+
+    use super::*;
+
+    fn table(contents: &str) -> toml::value::Table {
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn test_migrate() {
+        let conf = parse_config("last_file = \"/home/bob/passwords.kdb\"\n").unwrap();
+        assert_eq!(conf.version, CONFIG_VERSION);
+        assert_eq!(
+            conf.recent_files,
+            vec![
+                RecentFile {
+                    path: String::from("/home/bob/passwords.kdb"),
+                    key_file: None,
+                },
+            ]
+        );
+        // Current config stays as it is.
+        let contents = toml::to_string(&conf).unwrap();
+        let mut current = table(&contents);
+        migrate(&mut current).unwrap();
+        assert_eq!(current, table(&contents));
+        assert_eq!(parse_config(&contents).unwrap().recent_files, conf.recent_files);
+        // Newer config is left alone, it may be read by a newer version again.
+        let newer = "version = 99\nlast_file = \"a.kdb\"\n";
+        let mut t = table(newer);
+        migrate(&mut t).unwrap();
+        assert_eq!(t, table(newer));
+        for invalid in &["version = -1", "version = 1.5", "version = \"1\""] {
+            match migrate(&mut table(invalid)) {
+                Err(errors::Error::InvalidConfig(_)) => {}
+                r => panic!("{}: {:?}", invalid, r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_unknown_keys() {
+        let contents = "version = 1\ncolour = \"red\"\n\n[[recent_files]]\npath = \"a.kdb\"\n\n\
+                        [[recent_files]]\npath = \"b.kdb\"\nread_only = true\n\n[ui]\nwidth = 800\n";
+        let value = toml::Value::Table(table(contents));
+        let conf: Config = value.clone().try_into().unwrap();
+        let known = toml::Value::try_from(&conf).unwrap();
+        let mut unknown = Vec::new();
+        find_unknown_keys("", &value, &known, &mut unknown);
+        assert_eq!(unknown, vec!["colour", "recent_files[1].read_only", "ui"]);
+    }
+}
@@ -0,0 +1,1157 @@
+//! Application controller, shared by all frontends.
+//!
+//! Frontends send `Command`s (open, lock, search, select, save...) and react to the `Event`s
+//! returned, and query controller for what to show. Controller owns `model::State`,
+//! so frontends don't touch the database directly, and it can be tested without display.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+
+use config;
+use db;
+use db::api;
+use db::api::{Entry, Group, GroupId, PasswordDatabase, Uuid};
+use db::formats;
+use db::merge;
+use errors;
+use model::{Config, SharedDatabase, State, Transition};
+use otp;
+use otp::{Kind, Otp};
+
+/// Opens database given path, password and key file.
+pub type Opener = Box<Fn(&str, &str, Option<&str>) -> Result<SharedDatabase, api::Error>>;
+
+/// Entry field that can be copied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Username,
+    Password,
+    Url,
+    /// Current one-time code, see `otp`.
+    Totp,
+}
+
+impl Field {
+    /// Name for messages, like "Copied password to clipboard".
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Field::Username => "username",
+            Field::Password => "password",
+            Field::Url => "URL",
+            Field::Totp => "one-time code",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Command {
+    Open {
+        path: String,
+        password: String,
+        key_file: Option<String>,
+    },
+    /// Create new database file with default groups, and open it.
+    Create {
+        path: String,
+        password: String,
+        key_file: Option<String>,
+        rounds: u32,
+    },
+    /// Close database, but remember which file it was, so it can be unlocked.
+    Lock,
+    /// Reopen locked database.
+    Unlock { password: String },
+    /// Empty term ends search.
+    Search(String),
+    SelectGroup(Option<GroupId>),
+    SelectEntry(Option<Uuid>),
+    /// Copy field of selected entry.
+    Copy(Field),
+    AddEntry(Entry),
+    UpdateEntry(Entry),
+    RemoveEntry(Uuid),
+    /// New password and key file, used from next save on.
+    ChangeMasterKey {
+        password: String,
+        key_file: Option<String>,
+    },
+    SetKeyTransformRounds(u32),
+    /// Fails with `Event::ChangedOnDisk` if someone else changed the file.
+    Save,
+    /// File may have changed (e.g. file watcher noticed), check it.
+    CheckDisk,
+    /// Read file again, dropping our unsaved changes.
+    Reload,
+    /// Save even though file was changed by someone else.
+    Overwrite,
+    /// Take changes from file on disk, and save the result.
+    Merge,
+    /// Lock database file although someone else has it locked (see `Event::LockedByOther`).
+    TakeOverLock,
+    /// Add records read from a file (CSV...), groups are created as needed.
+    Import(formats::Parsed),
+}
+
+/// Passwords are hidden, commands end up in logs.
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Command::Open {
+                ref path,
+                ref password,
+                ref key_file,
+            } => f.debug_struct("Open")
+                .field("path", path)
+                .field("password", &api::hidden(password))
+                .field("key_file", key_file)
+                .finish(),
+            Command::Create {
+                ref path,
+                ref password,
+                ref key_file,
+                rounds,
+            } => f.debug_struct("Create")
+                .field("path", path)
+                .field("password", &api::hidden(password))
+                .field("key_file", key_file)
+                .field("rounds", &rounds)
+                .finish(),
+            Command::Unlock { ref password } => f.debug_struct("Unlock")
+                .field("password", &api::hidden(password))
+                .finish(),
+            Command::ChangeMasterKey {
+                ref password,
+                ref key_file,
+            } => f.debug_struct("ChangeMasterKey")
+                .field("password", &api::hidden(password))
+                .field("key_file", key_file)
+                .finish(),
+            Command::Search(ref term) => f.debug_tuple("Search").field(term).finish(),
+            Command::SelectGroup(ref id) => f.debug_tuple("SelectGroup").field(id).finish(),
+            Command::SelectEntry(ref id) => f.debug_tuple("SelectEntry").field(id).finish(),
+            Command::Copy(ref field) => f.debug_tuple("Copy").field(field).finish(),
+            Command::AddEntry(ref e) => f.debug_tuple("AddEntry").field(e).finish(),
+            Command::UpdateEntry(ref e) => f.debug_tuple("UpdateEntry").field(e).finish(),
+            Command::RemoveEntry(ref id) => f.debug_tuple("RemoveEntry").field(id).finish(),
+            Command::SetKeyTransformRounds(rounds) => f.debug_tuple("SetKeyTransformRounds").field(&rounds).finish(),
+            Command::Import(ref parsed) => f.debug_tuple("Import").field(parsed).finish(),
+            Command::Lock |
+            Command::Save |
+            Command::CheckDisk |
+            Command::Reload |
+            Command::Overwrite |
+            Command::Merge |
+            Command::TakeOverLock => write!(f, "{}", command_name(self)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// Database opened or unlocked, main view should be shown.
+    Unlocked,
+    /// Database closed, unlock view should be shown.
+    Locked,
+    OpenFailed(String),
+    /// Listed entries changed (search, selected group, entry edits).
+    ListChanged,
+    SelectionChanged,
+    /// Frontend should put text on clipboard.
+    CopyToClipboard { field: Field, text: String },
+    Saved,
+    DirtyChanged(bool),
+    /// File was changed by someone else, frontend should offer reload, overwrite or merge.
+    ChangedOnDisk,
+    /// Database file is locked by someone else, who may be editing it, or whose lock was left
+    /// behind by a crash. Saving fails until frontend sends `Command::TakeOverLock`.
+    LockedByOther(String),
+    /// Records were imported.
+    Imported(formats::ImportSummary),
+    /// Command failed, message for user.
+    Error(String),
+}
+
+fn default_opener() -> Opener {
+    Box::new(|path, password, key_file| {
+        let db = db::kdb::open(path, password, key_file)?;
+        Ok(Arc::new(RwLock::new(db)) as SharedDatabase)
+    })
+}
+
+pub struct Controller {
+    state: State,
+    opener: Opener,
+    /// Config and where to save it, successfully opened files are remembered there.
+    config: Option<(Config, PathBuf)>,
+    search: String,
+    selected_group: Option<GroupId>,
+    selected_entry: Option<Uuid>,
+}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller::with_opener(default_opener())
+    }
+
+    /// Controller using custom way of opening databases.
+    pub fn with_opener(opener: Opener) -> Controller {
+        Controller {
+            state: State::NoFile,
+            opener: opener,
+            config: None,
+            search: String::new(),
+            selected_group: None,
+            selected_entry: None,
+        }
+    }
+
+    /// Remember opened files in this config, and save it to given path.
+    pub fn set_config(&mut self, conf: Config, path: PathBuf) {
+        self.config = Some((conf, path));
+    }
+
+    pub fn get_config(&self) -> Option<&Config> {
+        self.config.as_ref().map(|&(ref c, _)| c)
+    }
+
+    pub fn get_state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.state.is_unlocked()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.state.is_dirty()
+    }
+
+    pub fn get_path(&self) -> Option<&str> {
+        self.state.path()
+    }
+
+    pub fn get_key_file(&self) -> Option<&str> {
+        self.state.key_file()
+    }
+
+    pub fn get_search(&self) -> &str {
+        &self.search
+    }
+
+    pub fn get_selected_group(&self) -> Option<GroupId> {
+        self.selected_group
+    }
+
+    /// Lock is poisoned only if we panicked while writing, reading whatever is there is fine then.
+    fn db(&self) -> Option<RwLockReadGuard<PasswordDatabase + 'static>> {
+        self.state
+            .db()
+            .map(|db| db.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// All groups, in tree order.
+    pub fn get_groups(&self) -> Vec<Group> {
+        self.db().map(|db| db.get_groups()).unwrap_or_default()
+    }
+
+    /// Entries to list: search results, or entries of selected group.
+    pub fn get_entries(&self) -> Vec<Entry> {
+        let db = match self.db() {
+            Some(db) => db,
+            None => return Vec::new(),
+        };
+        if !self.search.is_empty() {
+            return db.search(&self.search);
+        }
+        match self.selected_group {
+            Some(group) => db.get_entries()
+                .into_iter()
+                .filter(|e| e.group_id == group)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn get_selected_entry(&self) -> Option<Entry> {
+        match (self.db(), self.selected_entry) {
+            (Some(db), Some(id)) => db.get_entry(&id),
+            _ => None,
+        }
+    }
+
+    pub fn get_key_transform_rounds(&self) -> Option<u32> {
+        self.db().map(|db| db.get_key_transform_rounds())
+    }
+
+    pub fn get_group_path(&self, id: GroupId) -> Vec<String> {
+        self.db().map(|db| db.get_group_path(id)).unwrap_or_default()
+    }
+
+    /// Entries to export, all or of group and its subgroups.
+    pub fn get_records(&self, group: Option<GroupId>) -> Vec<formats::Record> {
+        self.db().map(|db| formats::records(&*db, group)).unwrap_or_default()
+    }
+
+    /// Paths of groups to export, for formats which keep empty groups.
+    pub fn get_group_paths(&self, group: Option<GroupId>) -> Vec<String> {
+        self.db().map(|db| formats::group_paths(&*db, group)).unwrap_or_default()
+    }
+
+    /// Run command, return what happened.
+    pub fn handle(&mut self, command: Command) -> Vec<Event> {
+        debug!("handle: {}", command_name(&command));
+        match command {
+            Command::Open {
+                path,
+                password,
+                key_file,
+            } => self.open(path, &password, key_file),
+            Command::Create {
+                path,
+                password,
+                key_file,
+                rounds,
+            } => self.create(path, &password, key_file, rounds),
+            Command::Lock => self.lock(),
+            Command::Unlock { password } => match self.state.path().map(String::from) {
+                Some(path) => {
+                    let key_file = self.state.key_file().map(String::from);
+                    self.open(path, &password, key_file)
+                }
+                None => vec![Event::Error(String::from("No database to unlock"))],
+            },
+            Command::Search(term) => {
+                if term == self.search {
+                    return Vec::new();
+                }
+                self.search = term;
+                self.after_list_change()
+            }
+            Command::SelectGroup(group) => {
+                if group == self.selected_group {
+                    return Vec::new();
+                }
+                self.selected_group = group;
+                self.after_list_change()
+            }
+            Command::SelectEntry(entry) => {
+                if entry == self.selected_entry {
+                    return Vec::new();
+                }
+                self.selected_entry = entry;
+                vec![Event::SelectionChanged]
+            }
+            Command::Copy(Field::Totp) => match self.get_selected_entry() {
+                Some(e) => self.copy_otp(e),
+                None => vec![Event::Error(String::from("No entry selected"))],
+            },
+            Command::Copy(field) => match self.get_selected_entry() {
+                Some(e) => {
+                    let text = match field {
+                        Field::Username => e.username,
+                        Field::Password => e.password,
+                        Field::Url => e.url,
+                        Field::Totp => unreachable!(),
+                    };
+                    vec![
+                        Event::CopyToClipboard {
+                            field: field,
+                            text: text,
+                        },
+                    ]
+                }
+                None => vec![Event::Error(String::from("No entry selected"))],
+            },
+            Command::AddEntry(e) => {
+                let id = e.id;
+                let r = self.modify(|db| db.add_entry(&e));
+                self.after_modify(r, Some(id))
+            }
+            Command::UpdateEntry(e) => {
+                let id = e.id;
+                let r = self.modify(|db| db.update_entry(&e));
+                self.after_modify(r, Some(id))
+            }
+            Command::RemoveEntry(id) => {
+                let r = self.modify(|db| db.remove_entry(&id));
+                self.after_modify(r, None)
+            }
+            Command::ChangeMasterKey { password, key_file } => {
+                let r = self.modify(|db| db.change_master_key(&password, key_file.as_ref().map(|k| k.as_str())));
+                if let Err(events) = r {
+                    return events;
+                }
+                let was_dirty = self.state.is_dirty();
+                if let Some(e) = self.transition(Transition::KeyChanged { key_file: key_file }) {
+                    return e;
+                }
+                if was_dirty {
+                    Vec::new()
+                } else {
+                    vec![Event::DirtyChanged(true)]
+                }
+            }
+            Command::SetKeyTransformRounds(rounds) => {
+                let r = self.modify(|db| db.set_key_transform_rounds(rounds));
+                let selected = self.selected_entry;
+                self.after_modify(r, selected)
+            }
+            Command::Save => self.save(false),
+            Command::CheckDisk => match self.db().map(|db| db.changed_on_disk()) {
+                Some(Ok(true)) => vec![Event::ChangedOnDisk],
+                Some(Ok(false)) | None => Vec::new(),
+                Some(Err(e)) => vec![Event::Error(errors::report(&e))],
+            },
+            Command::Reload => self.reload(),
+            Command::Overwrite => self.save(true),
+            Command::Merge => self.merge(),
+            Command::TakeOverLock => self.acquire_lock(true),
+            Command::Import(parsed) => {
+                let mut summary = formats::ImportSummary::default();
+                let r = self.modify(|db| {
+                    summary = formats::import(db, &parsed, None)?;
+                    Ok(())
+                });
+                let selected = self.selected_entry;
+                let mut events = self.after_modify(r, selected);
+                if self.state.is_unlocked() && events.contains(&Event::ListChanged) {
+                    events.push(Event::Imported(summary));
+                }
+                events
+            }
+        }
+    }
+
+    fn reload(&mut self) -> Vec<Event> {
+        if let Err(events) = self.modify(|db| db.reload()) {
+            return events;
+        }
+        let was_dirty = self.state.is_dirty();
+        if let Some(e) = self.transition(Transition::Reloaded) {
+            return e;
+        }
+        let mut events = Vec::new();
+        if was_dirty {
+            events.push(Event::DirtyChanged(false));
+        }
+        let groups = self.get_groups();
+        if !groups.iter().any(|g| Some(g.id) == self.selected_group) {
+            self.selected_group = groups.first().map(|g| g.id);
+        }
+        events.extend(self.after_list_change());
+        events
+    }
+
+    fn merge(&mut self) -> Vec<Event> {
+        let (base, theirs) = match self.db().map(|db| (db.load_base_version(), db.load_disk_version())) {
+            Some((base, Ok(db))) => (base, db),
+            Some((_, Err(e))) => return vec![Event::Error(format!("Merge failed: {}", errors::report(&e)))],
+            None => return vec![Event::Error(String::from("No database open"))],
+        };
+        let r = self.modify(|db| {
+            let report = merge::merge(base.as_ref().map(|b| &**b), &*theirs, db, true)?;
+            info!("Merged changes from disk:\n{}", report);
+            Ok(())
+        });
+        let selected = self.selected_entry;
+        let mut events = self.after_modify(r, selected);
+        if self.state.is_dirty() {
+            events.extend(self.save(true));
+        }
+        events
+    }
+
+    fn open(&mut self, path: String, password: &str, key_file: Option<String>) -> Vec<Event> {
+        if self.state.is_dirty() {
+            return vec![Event::Error(String::from("Save or discard changes first"))];
+        }
+        let opened = (self.opener)(&path, password, key_file.as_ref().map(|k| k.as_str()));
+        let db = match opened {
+            Ok(db) => db,
+            Err(e) => {
+                let desc = errors::report(&e);
+                return self.transition(Transition::OpenFailed {
+                    path: path,
+                    key_file: key_file,
+                    desc: desc.clone(),
+                }).unwrap_or_else(|| vec![Event::OpenFailed(desc)]);
+            }
+        };
+        self.opened(path, key_file, db)
+    }
+
+    fn create(&mut self, path: String, password: &str, key_file: Option<String>, rounds: u32) -> Vec<Event> {
+        if self.state.is_dirty() {
+            return vec![Event::Error(String::from("Save or discard changes first"))];
+        }
+        let created = db::kdb::create_new(
+            &path,
+            password,
+            key_file.as_ref().map(|k| k.as_str()),
+            rounds,
+            db::kdb::DEFAULT_GROUPS,
+        );
+        match created {
+            Ok(db) => self.opened(path, key_file, Arc::new(RwLock::new(db))),
+            Err(e) => vec![Event::Error(format!("Failed to create database: {}", errors::report(&e)))],
+        }
+    }
+
+    /// Show newly opened (or created) database.
+    fn opened(&mut self, path: String, key_file: Option<String>, db: SharedDatabase) -> Vec<Event> {
+        // Keep selection when unlocking the same file again.
+        if self.state.path() != Some(&path) {
+            self.search = String::new();
+            self.selected_group = None;
+            self.selected_entry = None;
+        }
+        let mut events = self.transition(Transition::Opened {
+            path: path,
+            key_file: key_file,
+            db: db,
+        }).unwrap_or_else(|| vec![Event::Unlocked, Event::ListChanged]);
+        if !self.state.is_unlocked() {
+            return events;
+        }
+        if let Some(e) = self.remember_file() {
+            events.push(e);
+        }
+        events.extend(self.acquire_lock(false));
+        if self.selected_group.is_none() {
+            self.selected_group = self.get_groups().first().map(|g| g.id);
+        }
+        events
+    }
+
+    /// Lock database file, no events unless it's locked by someone else, or locking failed.
+    fn acquire_lock(&mut self, take_over: bool) -> Vec<Event> {
+        let db = match self.state.db() {
+            Some(db) => Arc::clone(db),
+            None => return vec![Event::Error(String::from("No database open"))],
+        };
+        let r = match db.write() {
+            Ok(mut db) => db.acquire_lock(take_over),
+            Err(_) => return self.fail("Database lock poisoned"),
+        };
+        match r {
+            Ok(()) => Vec::new(),
+            Err(e) => match *e.kind() {
+                api::Error::Locked { ref owner, .. } => vec![Event::LockedByOther(owner.clone())],
+                _ => vec![Event::Error(format!("Can't lock database file: {}", errors::report(&e)))],
+            },
+        }
+    }
+
+    /// Remember current file in config, returns error event if config can't be saved.
+    fn remember_file(&mut self) -> Option<Event> {
+        let (conf, conf_path) = match self.config {
+            Some((ref mut conf, ref conf_path)) => (conf, conf_path),
+            None => return None,
+        };
+        let path = match self.state.path() {
+            Some(p) => p,
+            None => return None,
+        };
+        conf.remember_file(path, self.state.key_file());
+        match config::save_config(conf, conf_path) {
+            Ok(()) => None,
+            Err(e) => {
+                // Not fatal, we just won't remember this file.
+                warn!("Failed to save config: {}", errors::report(&e));
+                Some(Event::Error(format!("Failed to save config: {}", errors::report(&e))))
+            }
+        }
+    }
+
+    /// Apply state transition, returns events for failed transition, None if it succeeded.
+    fn transition(&mut self, t: Transition) -> Option<Vec<Event>> {
+        match self.state.apply(t) {
+            Ok(()) => None,
+            Err(e) => Some(vec![Event::Error(errors::report(&e))]),
+        }
+    }
+
+    fn lock(&mut self) -> Vec<Event> {
+        if !self.state.is_unlocked() {
+            return Vec::new();
+        }
+        self.transition(Transition::Lock)
+            .unwrap_or_else(|| vec![Event::Locked])
+    }
+
+    /// Run f on database for writing.
+    fn modify<F>(&mut self, f: F) -> Result<(), Vec<Event>>
+    where
+        F: FnOnce(&mut PasswordDatabase) -> Result<(), api::Error>,
+    {
+        let db = match self.state.db() {
+            Some(db) => Arc::clone(db),
+            None => return Err(vec![Event::Error(String::from("No database open"))]),
+        };
+        let r = match db.write() {
+            Ok(mut db) => f(&mut *db),
+            Err(_) => return Err(self.fail("Database lock poisoned")),
+        };
+        r.map_err(|e| vec![Event::Error(errors::report(&e))])
+    }
+
+    /// Database can't be trusted anymore (we panicked while changing it), close it.
+    fn fail(&mut self, desc: &str) -> Vec<Event> {
+        error!("Closing database: {}", desc);
+        self.state
+            .apply(Transition::Fail(String::from(desc)))
+            .expect("Failing is always allowed");
+        vec![Event::Locked, Event::Error(String::from(desc))]
+    }
+
+    fn after_modify(&mut self, r: Result<(), Vec<Event>>, select: Option<Uuid>) -> Vec<Event> {
+        if let Err(events) = r {
+            return events;
+        }
+        let mut events = Vec::new();
+        if !self.state.is_dirty() {
+            if let Some(e) = self.transition(Transition::Modified) {
+                return e;
+            }
+            events.push(Event::DirtyChanged(true));
+        }
+        self.selected_entry = select;
+        events.push(Event::ListChanged);
+        events.push(Event::SelectionChanged);
+        events
+    }
+
+    /// Code of entry's OTP. HOTP counter is saved in entry, so the next code is different.
+    fn copy_otp(&mut self, mut e: Entry) -> Vec<Event> {
+        let mut otp = match Otp::from_notes(&e.notes) {
+            Some(Ok(otp)) => otp,
+            Some(Err(err)) => return vec![Event::Error(errors::report(&err))],
+            None => return vec![Event::Error(format!("No one-time password set up for {}", e.title))],
+        };
+        let code = otp.next_code();
+        let copy = Event::CopyToClipboard {
+            field: Field::Totp,
+            text: code,
+        };
+        if let Kind::Totp { .. } = otp.kind {
+            return vec![copy];
+        }
+        e.notes = otp::set_in_notes(&e.notes, &otp.to_uri());
+        e.touch();
+        let id = e.id;
+        let r = self.modify(|db| db.update_entry(&e));
+        let mut events = self.after_modify(r, Some(id));
+        if !events.iter().any(|e| match *e {
+            Event::Error(_) => true,
+            _ => false,
+        }) {
+            events.insert(0, copy);
+        }
+        events
+    }
+
+    /// Selected entry may disappear from list, selection follows list.
+    fn after_list_change(&mut self) -> Vec<Event> {
+        let mut events = vec![Event::ListChanged];
+        if let Some(id) = self.selected_entry {
+            if !self.get_entries().iter().any(|e| e.id == id) {
+                self.selected_entry = None;
+                events.push(Event::SelectionChanged);
+            }
+        }
+        events
+    }
+
+    /// Save, overwriting changes made by others if asked to.
+    fn save(&mut self, overwrite: bool) -> Vec<Event> {
+        if !self.state.is_unlocked() {
+            return vec![Event::Error(String::from("No database open"))];
+        }
+        let was_dirty = self.state.is_dirty();
+        if let Some(e) = self.transition(Transition::StartSave) {
+            return e;
+        }
+        let mut changed_on_disk = false;
+        let mut locked_by = None;
+        let r = self.modify(|db| {
+            let r = if overwrite { db.overwrite() } else { db.save() };
+            if let Err(ref e) = r {
+                match *e.kind() {
+                    api::Error::ChangedOnDisk(_) => changed_on_disk = true,
+                    api::Error::Locked { ref owner, .. } => locked_by = Some(owner.clone()),
+                    _ => {}
+                }
+            }
+            r
+        });
+        if !self.state.is_unlocked() {
+            // Database closed by modify.
+            return r.err().unwrap_or_default();
+        }
+        match r {
+            Ok(()) => {
+                self.state.apply(Transition::SaveDone).expect("Saving state");
+                let mut events = vec![Event::Saved];
+                // Key file may have changed.
+                if let Some(e) = self.remember_file() {
+                    events.push(e);
+                }
+                if was_dirty {
+                    events.push(Event::DirtyChanged(false));
+                }
+                events
+            }
+            Err(_) if changed_on_disk => {
+                self.state.apply(Transition::SaveFailed).expect("Saving state");
+                vec![Event::ChangedOnDisk]
+            }
+            Err(_) if locked_by.is_some() => {
+                self.state.apply(Transition::SaveFailed).expect("Saving state");
+                vec![Event::LockedByOther(locked_by.unwrap_or_default())]
+            }
+            Err(events) => {
+                self.state.apply(Transition::SaveFailed).expect("Saving state");
+                events
+                    .into_iter()
+                    .map(|e| match e {
+                        Event::Error(desc) => Event::Error(format!("Save failed: {}", desc)),
+                        e => e,
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// For logs, without secrets.
+fn command_name(command: &Command) -> &'static str {
+    match *command {
+        Command::Open { .. } => "Open",
+        Command::Create { .. } => "Create",
+        Command::Lock => "Lock",
+        Command::Unlock { .. } => "Unlock",
+        Command::Search(_) => "Search",
+        Command::SelectGroup(_) => "SelectGroup",
+        Command::SelectEntry(_) => "SelectEntry",
+        Command::Copy(_) => "Copy",
+        Command::AddEntry(_) => "AddEntry",
+        Command::UpdateEntry(_) => "UpdateEntry",
+        Command::RemoveEntry(_) => "RemoveEntry",
+        Command::ChangeMasterKey { .. } => "ChangeMasterKey",
+        Command::SetKeyTransformRounds(_) => "SetKeyTransformRounds",
+        Command::Save => "Save",
+        Command::CheckDisk => "CheckDisk",
+        Command::Reload => "Reload",
+        Command::Overwrite => "Overwrite",
+        Command::Merge => "Merge",
+        Command::TakeOverLock => "TakeOverLock",
+        Command::Import(_) => "Import",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// In-memory database, counts saves.
+    #[derive(Debug)]
+    struct MemoryDatabase {
+        groups: Vec<Group>,
+        entries: Vec<Entry>,
+        saves: Arc<AtomicUsize>,
+    }
+
+    impl PasswordDatabase for MemoryDatabase {
+        fn get_entry_count(&self) -> u32 {
+            self.entries.len() as u32
+        }
+        fn get_groups(&self) -> Vec<Group> {
+            self.groups.clone()
+        }
+        fn get_entries(&self) -> Vec<Entry> {
+            self.entries.clone()
+        }
+        fn add_group(&mut self, name: &str, parent: Option<GroupId>) -> Result<GroupId, api::Error> {
+            let id = self.groups.len() as GroupId + 1;
+            self.groups.push(Group {
+                id: id,
+                parent: parent,
+                name: String::from(name),
+                created: api::now(),
+                modified: api::now(),
+                accessed: api::now(),
+                expires: String::new(),
+            });
+            Ok(id)
+        }
+        fn update_group(&mut self, _group: &Group) -> Result<(), api::Error> {
+            Ok(())
+        }
+        fn remove_group(&mut self, id: GroupId) -> Result<(), api::Error> {
+            self.groups.retain(|g| g.id != id);
+            Ok(())
+        }
+        fn add_entry(&mut self, entry: &Entry) -> Result<(), api::Error> {
+            self.entries.push(entry.clone());
+            Ok(())
+        }
+        fn update_entry(&mut self, entry: &Entry) -> Result<(), api::Error> {
+            match self.entries.iter_mut().find(|e| e.id == entry.id) {
+                Some(e) => {
+                    *e = entry.clone();
+                    Ok(())
+                }
+                None => Err(api::Error::NoSuchEntry(entry.id)),
+            }
+        }
+        fn remove_entry(&mut self, id: &Uuid) -> Result<(), api::Error> {
+            self.entries.retain(|e| e.id != *id);
+            Ok(())
+        }
+        fn save(&mut self) -> Result<(), api::Error> {
+            self.saves.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+        fn get_info(&self) -> Vec<(String, String)> {
+            Vec::new()
+        }
+        fn change_master_key(&mut self, _password: &str, _key_file: Option<&str>) -> Result<(), api::Error> {
+            Ok(())
+        }
+        fn get_key_transform_rounds(&self) -> u32 {
+            1
+        }
+        fn set_key_transform_rounds(&mut self, _rounds: u32) -> Result<(), api::Error> {
+            Ok(())
+        }
+    }
+
+    /// Controller opening test databases with two groups, password is "secret".
+    fn controller(saves: Arc<AtomicUsize>) -> Controller {
+        Controller::with_opener(Box::new(move |_path, password, _key_file| {
+            if password != "secret" {
+                return Err(api::Error::WrongKey);
+            }
+            let mut db = MemoryDatabase {
+                groups: Vec::new(),
+                entries: Vec::new(),
+                saves: Arc::clone(&saves),
+            };
+            let internet = db.add_group("Internet", None)?;
+            let email = db.add_group("EMail", None)?;
+            let mut e = Entry::new(internet, "Forum");
+            e.username = String::from("bob");
+            e.password = String::from("forum-pw");
+            db.add_entry(&e)?;
+            let mut e = Entry::new(email, "Gmail");
+            e.password = String::from("gmail-pw");
+            db.add_entry(&e)?;
+            Ok(Arc::new(RwLock::new(db)) as SharedDatabase)
+        }))
+    }
+
+    fn open(c: &mut Controller) -> Vec<Event> {
+        c.handle(Command::Open {
+            path: String::from("test.kdb"),
+            password: String::from("secret"),
+            key_file: None,
+        })
+    }
+
+    #[test]
+    fn test_open_wrong_password() {
+        let mut c = controller(Arc::new(AtomicUsize::new(0)));
+        let events = c.handle(Command::Open {
+            path: String::from("test.kdb"),
+            password: String::from("wrong"),
+            key_file: None,
+        });
+        assert_eq!(events, vec![Event::OpenFailed(String::from("Wrong password or key file"))]);
+        assert!(!c.is_unlocked());
+        assert!(c.get_entries().is_empty());
+    }
+
+    #[test]
+    fn test_open_selects_first_group() {
+        let mut c = controller(Arc::new(AtomicUsize::new(0)));
+        assert_eq!(open(&mut c), vec![Event::Unlocked, Event::ListChanged]);
+        assert!(c.is_unlocked());
+        let entries = c.get_entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title, "Forum");
+    }
+
+    #[test]
+    fn test_search_and_copy() {
+        let mut c = controller(Arc::new(AtomicUsize::new(0)));
+        open(&mut c);
+        assert_eq!(c.handle(Command::Search(String::from("gmail"))), vec![Event::ListChanged]);
+        let entries = c.get_entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(c.handle(Command::Copy(Field::Password)), vec![Event::Error(String::from("No entry selected"))]);
+        c.handle(Command::SelectEntry(Some(entries[0].id)));
+        assert_eq!(
+            c.handle(Command::Copy(Field::Password)),
+            vec![
+                Event::CopyToClipboard {
+                    field: Field::Password,
+                    text: String::from("gmail-pw"),
+                },
+            ]
+        );
+        // Ending search brings back selected group, where Gmail isn't, so selection is cleared.
+        assert_eq!(
+            c.handle(Command::Search(String::new())),
+            vec![Event::ListChanged, Event::SelectionChanged]
+        );
+        assert!(c.get_selected_entry().is_none());
+    }
+
+    #[test]
+    fn test_copy_one_time_code() {
+        let mut c = controller(Arc::new(AtomicUsize::new(0)));
+        open(&mut c);
+        let forum = c.get_entries()[0].clone();
+        c.handle(Command::SelectEntry(Some(forum.id)));
+        assert_eq!(
+            c.handle(Command::Copy(Field::Totp)),
+            vec![Event::Error(String::from("No one-time password set up for Forum"))]
+        );
+        let mut e = forum.clone();
+        e.notes = String::from("otpauth://hotp/Forum?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=0");
+        c.handle(Command::UpdateEntry(e));
+        for code in &["755224", "287082"] {
+            let events = c.handle(Command::Copy(Field::Totp));
+            assert_eq!(
+                events[0],
+                Event::CopyToClipboard {
+                    field: Field::Totp,
+                    text: String::from(*code),
+                }
+            );
+        }
+        let notes = c.get_selected_entry().unwrap().notes;
+        assert_eq!(Otp::from_notes(&notes).unwrap().unwrap().kind, Kind::Hotp { counter: 2 });
+    }
+
+    #[test]
+    fn test_edit_marks_dirty_until_saved() {
+        let saves = Arc::new(AtomicUsize::new(0));
+        let mut c = controller(Arc::clone(&saves));
+        open(&mut c);
+        let group = c.get_selected_group().unwrap();
+        let e = Entry::new(group, "New");
+        let events = c.handle(Command::AddEntry(e.clone()));
+        assert_eq!(
+            events,
+            vec![Event::DirtyChanged(true), Event::ListChanged, Event::SelectionChanged]
+        );
+        assert!(c.is_dirty());
+        assert_eq!(c.get_selected_entry(), Some(e.clone()));
+        assert_eq!(c.get_entries().len(), 2);
+        // Can't lock with unsaved changes.
+        assert_eq!(c.handle(Command::Lock), vec![Event::Error(String::from("Save or discard changes first"))]);
+        assert!(c.is_unlocked());
+        assert_eq!(c.handle(Command::Save), vec![Event::Saved, Event::DirtyChanged(false)]);
+        assert_eq!(saves.load(Ordering::SeqCst), 1);
+        assert!(!c.is_dirty());
+    }
+
+    #[test]
+    fn test_lock_and_unlock_keeps_selection() {
+        let mut c = controller(Arc::new(AtomicUsize::new(0)));
+        open(&mut c);
+        let id = c.get_entries()[0].id;
+        c.handle(Command::SelectEntry(Some(id)));
+        assert_eq!(c.handle(Command::Lock), vec![Event::Locked]);
+        assert!(!c.is_unlocked());
+        assert!(c.get_selected_entry().is_none());
+        assert_eq!(
+            c.handle(Command::Unlock {
+                password: String::from("wrong"),
+            }),
+            vec![Event::OpenFailed(String::from("Wrong password or key file"))]
+        );
+        assert_eq!(
+            c.handle(Command::Unlock {
+                password: String::from("secret"),
+            }),
+            vec![Event::Unlocked, Event::ListChanged]
+        );
+        assert_eq!(c.get_path(), Some("test.kdb"));
+        // Test opener creates new entry ids every time, so entry is gone, group is found by id though.
+        assert!(c.get_selected_group().is_some());
+    }
+
+    #[test]
+    fn test_change_master_key() {
+        let mut c = controller(Arc::new(AtomicUsize::new(0)));
+        open(&mut c);
+        let events = c.handle(Command::ChangeMasterKey {
+            password: String::from("new"),
+            key_file: Some(String::from("new.key")),
+        });
+        assert_eq!(events, vec![Event::DirtyChanged(true)]);
+        assert_eq!(c.get_key_file(), Some("new.key"));
+        assert_eq!(c.handle(Command::Save), vec![Event::Saved, Event::DirtyChanged(false)]);
+    }
+
+    #[test]
+    fn test_failed_open_can_be_retried() {
+        let mut c = controller(Arc::new(AtomicUsize::new(0)));
+        c.handle(Command::Open {
+            path: String::from("test.kdb"),
+            password: String::from("wrong"),
+            key_file: Some(String::from("test.key")),
+        });
+        match *c.get_state() {
+            State::Error { ref desc, .. } => assert_eq!(desc, "Wrong password or key file"),
+            ref s => panic!("Unexpected state {}", s.name()),
+        }
+        assert_eq!(
+            c.handle(Command::Unlock {
+                password: String::from("secret"),
+            }),
+            vec![Event::Unlocked, Event::ListChanged]
+        );
+        assert_eq!(c.get_key_file(), Some("test.key"));
+        // Failing to open another file keeps current database.
+        c.handle(Command::Open {
+            path: String::from("other.kdb"),
+            password: String::from("wrong"),
+            key_file: None,
+        });
+        assert!(c.is_unlocked());
+        assert_eq!(c.get_path(), Some("test.kdb"));
+    }
+
+    #[test]
+    fn test_debug_hides_passwords() {
+        let open = Command::Open {
+            path: String::from("test.kdb"),
+            password: String::from("hunter2"),
+            key_file: None,
+        };
+        let mut e = Entry::new(1, "Gmail");
+        e.password = String::from("hunter2");
+        for command in &[open, Command::Unlock { password: String::from("hunter2") }, Command::AddEntry(e)] {
+            let text = format!("{:?}", command);
+            assert!(!text.contains("hunter2"), "{}", text);
+        }
+        assert_eq!(format!("{:?}", Command::Save), "Save");
+    }
+
+    #[test]
+    fn test_create_new_database() {
+        let path = std::env::temp_dir().join(format!("mpkdb-test-create-{}.kdb", Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let mut c = Controller::new();
+        let create = Command::Create {
+            path: path.clone(),
+            password: String::from("secret"),
+            key_file: None,
+            rounds: 10,
+        };
+        assert_eq!(c.handle(create.clone()), vec![Event::Unlocked, Event::ListChanged]);
+        let names: Vec<String> = c.get_groups().into_iter().map(|g| g.name).collect();
+        assert_eq!(names, db::kdb::DEFAULT_GROUPS);
+        assert_eq!(c.get_key_transform_rounds(), Some(10));
+        assert!(!c.is_dirty());
+        // Written file opens with the same key, and isn't overwritten.
+        assert_eq!(c.handle(Command::Lock), vec![Event::Locked]);
+        assert_eq!(
+            c.handle(Command::Unlock {
+                password: String::from("secret"),
+            }),
+            vec![Event::Unlocked, Event::ListChanged]
+        );
+        match c.handle(create).as_slice() {
+            [Event::Error(desc)] => assert!(desc.contains("already exists"), "{}", desc),
+            events => panic!("Unexpected events {:?}", events),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_locked_by_other() {
+        let path = std::env::temp_dir().join(format!("mpkdb-test-lock-{}.kdb", Uuid::new_v4()));
+        let path = path.to_str().unwrap().to_string();
+        let mut c = Controller::new();
+        c.handle(Command::Create {
+            path: path.clone(),
+            password: String::from("secret"),
+            key_file: None,
+            rounds: 10,
+        });
+        assert!(db::file::lock_path(std::path::Path::new(&path)).exists());
+        drop(c);
+        // Lock left behind by a crashed instance.
+        std::fs::write(db::file::lock_path(std::path::Path::new(&path)), b"alice").unwrap();
+        let mut c = Controller::new();
+        let events = c.handle(Command::Open {
+            path: path.clone(),
+            password: String::from("secret"),
+            key_file: None,
+        });
+        assert_eq!(events.last(), Some(&Event::LockedByOther(String::from("alice"))));
+        assert!(c.is_unlocked());
+        let group = c.get_selected_group().unwrap();
+        c.handle(Command::AddEntry(Entry::new(group, "Ours")));
+        assert_eq!(c.handle(Command::Save), vec![Event::LockedByOther(String::from("alice"))]);
+        assert!(c.is_dirty());
+        assert_eq!(c.handle(Command::TakeOverLock), Vec::new());
+        assert_eq!(c.handle(Command::Save), vec![Event::Saved, Event::DirtyChanged(false)]);
+        drop(c);
+        assert!(!db::file::lock_path(std::path::Path::new(&path)).exists());
+        db::file::remove_test_files(std::path::Path::new(&path));
+    }
+
+    #[test]
+    fn test_changed_on_disk_merge() {
+        let dir = std::env::temp_dir();
+        let id = Uuid::new_v4();
+        let path = dir.join(format!("mpkdb-test-merge-{}.kdb", id)).to_str().unwrap().to_string();
+        let other = dir.join(format!("mpkdb-test-merge-{}-other.kdb", id)).to_str().unwrap().to_string();
+        let mut c = Controller::new();
+        c.handle(Command::Create {
+            path: path.clone(),
+            password: String::from("secret"),
+            key_file: None,
+            rounds: 10,
+        });
+        let group = c.get_selected_group().unwrap();
+        // Someone else adds an entry to their copy, which sync puts in place of ours.
+        std::fs::copy(&path, &other).unwrap();
+        {
+            let mut db = db::kdb::open(&other, "secret", None).unwrap();
+            db.add_entry(&Entry::new(group, "Theirs")).unwrap();
+            db.save().unwrap();
+        }
+        c.handle(Command::AddEntry(Entry::new(group, "Ours")));
+        assert_eq!(c.handle(Command::CheckDisk), Vec::new());
+        std::fs::copy(&other, &path).unwrap();
+        assert_eq!(c.handle(Command::CheckDisk), vec![Event::ChangedOnDisk]);
+        assert_eq!(c.handle(Command::Save), vec![Event::ChangedOnDisk]);
+        assert!(c.is_dirty());
+        let events = c.handle(Command::Merge);
+        assert!(events.contains(&Event::Saved), "{:?}", events);
+        assert!(!c.is_dirty());
+        let mut titles: Vec<String> = c.get_entries().into_iter().map(|e| e.title).collect();
+        titles.sort();
+        assert_eq!(titles, vec!["Ours", "Theirs"]);
+        // Reload gives what was saved.
+        assert_eq!(c.handle(Command::Reload), vec![Event::ListChanged]);
+        assert_eq!(c.get_entries().len(), 2);
+        drop(c);
+        for p in &[&path, &other] {
+            db::file::remove_test_files(std::path::Path::new(p));
+        }
+    }
+}
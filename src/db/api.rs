@@ -1,26 +1,482 @@
-
 //! DB Abstraction.
 
 use std;
 
-#[derive(Debug)]
-pub struct Error {
-    pub desc: String,
+use time;
+
+pub use uuid::Uuid;
+
+pub use errors::Error;
+use otp;
+
+/// Separates group names (and entry title) in paths like "Internet/Mail/Gmail".
+pub const PATH_SEPARATOR: char = '/';
+
+pub type GroupId = u32;
+
+/// Expiry time of entries and groups that never expire, KeePass uses this too.
+pub const NEVER_EXPIRES: &str = "2999-12-28 23:59:59";
+
+/// Group of entries, groups form a tree.
+/// Serialized with ISO-8601 UTC times, see `db::formats::json`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Group {
+    pub id: GroupId,
+    /// None for top level groups.
+    pub parent: Option<GroupId>,
+    pub name: String,
+    /// Format: 2017-10-23 00:21:00
+    #[serde(with = "serde_fields::time")]
+    pub created: String,
+    #[serde(with = "serde_fields::time")]
+    pub modified: String,
+    #[serde(with = "serde_fields::time")]
+    pub accessed: String,
+    /// Never expires is null.
+    #[serde(with = "serde_fields::expires")]
+    pub expires: String,
+}
+
+/// File attached to entry, KDB entries can have one.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+pub struct Attachment {
+    pub name: String,
+    /// Base64 when serialized.
+    #[serde(with = "serde_fields::base64_data")]
+    pub data: Vec<u8>,
+}
+
+/// Contents may be secret too.
+impl std::fmt::Debug for Attachment {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Attachment {{ name: {:?}, size: {} }}", self.name, self.data.len())
+    }
+}
+
+/// Password entry.
+/// Serialized with ISO-8601 UTC times, see `db::formats::json`.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    #[serde(with = "serde_fields::uuid")]
+    pub id: Uuid,
+    pub group_id: GroupId,
+    pub title: String,
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub notes: String,
+    /// Format: 2017-10-23 00:21:00
+    #[serde(with = "serde_fields::time")]
+    pub created: String,
+    #[serde(with = "serde_fields::time")]
+    pub modified: String,
+    #[serde(with = "serde_fields::time")]
+    pub accessed: String,
+    /// Never expires is null.
+    #[serde(with = "serde_fields::expires")]
+    pub expires: String,
+    pub attachment: Option<Attachment>,
 }
 
-impl std::fmt::Display for Error {
+/// Password and one-time password secrets are hidden, entries end up in logs.
+impl std::fmt::Debug for Entry {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        f.debug_struct("Entry")
+            .field("id", &self.id)
+            .field("group_id", &self.group_id)
+            .field("title", &self.title)
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &hidden(&self.password))
+            .field("notes", &otp::hide_in_notes(&self.notes))
+            .field("created", &self.created)
+            .field("modified", &self.modified)
+            .field("accessed", &self.accessed)
+            .field("expires", &self.expires)
+            .field("attachment", &self.attachment)
+            .finish()
     }
 }
 
-impl std::error::Error for Error {
-    fn description(&self) -> &str {
-        &self.desc
+/// For `Debug`, secret is hidden, but whether it's set shows.
+pub fn hidden(secret: &str) -> &'static str {
+    if secret.is_empty() {
+        ""
+    } else {
+        otp::HIDDEN_SECRET
+    }
+}
+
+/// Current local time, formatted the way we keep date/time.
+pub fn now() -> String {
+    let t = time::now();
+    time::strftime("%Y-%m-%d %H:%M:%S", &t).expect("Failed to format time")
+}
+
+impl Entry {
+    /// New empty entry in given group, with fresh id.
+    pub fn new(group_id: GroupId, title: &str) -> Entry {
+        let now = now();
+        Entry {
+            id: Uuid::new_v4(),
+            group_id: group_id,
+            title: String::from(title),
+            url: String::new(),
+            username: String::new(),
+            password: String::new(),
+            notes: String::new(),
+            created: now.clone(),
+            modified: now.clone(),
+            accessed: now,
+            expires: String::new(),
+            attachment: None,
+        }
     }
+
+    /// Mark entry as modified now.
+    pub fn touch(&mut self) {
+        let now = now();
+        self.modified = now.clone();
+        self.accessed = now;
+    }
+
+    /// Case insensitive match against title, username, url and notes.
+    pub fn matches(&self, term: &str) -> bool {
+        let term = term.to_lowercase();
+        self.title.to_lowercase().contains(&term) || self.username.to_lowercase().contains(&term) ||
+            self.url.to_lowercase().contains(&term) || self.notes.to_lowercase().contains(&term)
+    }
+}
+
+/// Split "a/b/c" into non-empty components.
+pub fn split_path(path: &str) -> Vec<&str> {
+    path.split(PATH_SEPARATOR).filter(|s| !s.is_empty()).collect()
 }
 
 /// What our app can handle as the password db.
 pub trait PasswordDatabase: std::fmt::Debug {
     fn get_entry_count(&self) -> u32;
+
+    /// All groups, in tree order (parents come before their children).
+    fn get_groups(&self) -> Vec<Group>;
+
+    /// All entries visible to user.
+    fn get_entries(&self) -> Vec<Entry>;
+
+    /// Add new group as the last child of parent (or as the last top level group).
+    fn add_group(&mut self, name: &str, parent: Option<GroupId>) -> Result<GroupId, Error>;
+
+    /// Update group name and timestamps, group can't change its parent this way.
+    fn update_group(&mut self, group: &Group) -> Result<(), Error>;
+
+    /// Remove group, with all its subgroups and entries.
+    fn remove_group(&mut self, id: GroupId) -> Result<(), Error>;
+
+    /// Add new entry, entry id must not be used yet.
+    fn add_entry(&mut self, entry: &Entry) -> Result<(), Error>;
+
+    /// Store entry as given, use Entry::touch to update modification time.
+    fn update_entry(&mut self, entry: &Entry) -> Result<(), Error>;
+
+    fn remove_entry(&mut self, id: &Uuid) -> Result<(), Error>;
+
+    /// Write database back to the file it was opened from.
+    /// Fails with `Error::ChangedOnDisk` if someone else changed the file meanwhile.
+    fn save(&mut self) -> Result<(), Error>;
+
+    /// Human readable description of database file: format, cipher etc.
+    fn get_info(&self) -> Vec<(String, String)>;
+
+    /// Use new password and/or key file from next save on.
+    fn change_master_key(&mut self, password: &str, key_file: Option<&str>) -> Result<(), Error>;
+
+    /// How many times master key is transformed before it's used, more is slower to brute force.
+    fn get_key_transform_rounds(&self) -> u32;
+
+    fn set_key_transform_rounds(&mut self, rounds: u32) -> Result<(), Error>;
+
+    /// Add group with given id, parent and times, e.g. when merging.
+    fn insert_group(&mut self, _group: &Group) -> Result<(), Error> {
+        Err(Error::Unsupported(String::from("Inserting groups is not supported")))
+    }
+
+    /// Whether file was changed by someone else since we read or saved it.
+    fn changed_on_disk(&self) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// Read file again with the same key, dropping unsaved changes.
+    fn reload(&mut self) -> Result<(), Error> {
+        Err(Error::Unsupported(String::from("Reloading is not supported")))
+    }
+
+    /// Database as it is on disk now, opened with the same key.
+    fn load_disk_version(&self) -> Result<Box<PasswordDatabase>, Error> {
+        Err(Error::Unsupported(String::from("Reloading is not supported")))
+    }
+
+    /// Database as we last read or saved it, base for merging with the version on disk.
+    fn load_base_version(&self) -> Option<Box<PasswordDatabase>> {
+        None
+    }
+
+    /// Save even if file was changed by someone else, their changes are lost.
+    fn overwrite(&mut self) -> Result<(), Error> {
+        self.save()
+    }
+
+    /// Lock database file, so that others (KeePass too) know it's being edited, see
+    /// `db::file::LockFile`. Fails with `Error::Locked` if someone else has it locked, unless
+    /// `take_over` is set. Saving takes the lock too, if it's not taken yet.
+    fn acquire_lock(&mut self, _take_over: bool) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn get_group(&self, id: GroupId) -> Option<Group> {
+        self.get_groups().into_iter().find(|g| g.id == id)
+    }
+
+    fn get_entry(&self, id: &Uuid) -> Option<Entry> {
+        self.get_entries().into_iter().find(|e| e.id == *id)
+    }
+
+    /// Names of groups from top level group down to given one.
+    fn get_group_path(&self, id: GroupId) -> Vec<String> {
+        let groups = self.get_groups();
+        let mut path = Vec::new();
+        let mut current = Some(id);
+        while let Some(id) = current {
+            match groups.iter().find(|g| g.id == id) {
+                Some(g) => {
+                    path.insert(0, g.name.clone());
+                    current = g.parent;
+                }
+                None => break,
+            }
+        }
+        path
+    }
+
+    /// Path of entry, like "Internet/Mail/Gmail".
+    fn get_entry_path(&self, entry: &Entry) -> String {
+        let mut path = self.get_group_path(entry.group_id);
+        path.push(entry.title.clone());
+        path.join(&PATH_SEPARATOR.to_string())
+    }
+
+    /// Find group by path like "Internet/Mail". If there are many groups with the same path, first one wins.
+    fn find_group(&self, path: &str) -> Option<GroupId> {
+        let groups = self.get_groups();
+        let mut parent: Option<GroupId> = None;
+        for name in split_path(path) {
+            match groups.iter().find(|g| g.parent == parent && g.name == name) {
+                Some(g) => parent = Some(g.id),
+                None => return None,
+            }
+        }
+        parent
+    }
+
+    /// Find entries by path like "Internet/Mail/Gmail", or by entry UUID.
+    /// Titles don't have to be unique, so there may be more than one.
+    fn find_entries(&self, path: &str) -> Vec<Entry> {
+        if let Ok(id) = Uuid::parse_str(path) {
+            return self.get_entry(&id).into_iter().collect();
+        }
+        let mut components = split_path(path);
+        let title = match components.pop() {
+            Some(t) => t,
+            None => return Vec::new(),
+        };
+        let group_id = match self.find_group(&components.join(&PATH_SEPARATOR.to_string())) {
+            Some(g) => g,
+            None => return Vec::new(),
+        };
+        self.get_entries()
+            .into_iter()
+            .filter(|e| e.group_id == group_id && e.title == title)
+            .collect()
+    }
+
+    /// Entries matching search term, see Entry::matches.
+    fn search(&self, term: &str) -> Vec<Entry> {
+        self.get_entries().into_iter().filter(|e| e.matches(term)).collect()
+    }
+}
+
+/// Find group by path like "Internet/Mail", creating missing groups along the way.
+pub fn find_or_create_group(db: &mut PasswordDatabase, path: &str) -> Result<GroupId, Error> {
+    let mut parent: Option<GroupId> = None;
+    for name in split_path(path) {
+        let existing = db.get_groups()
+            .into_iter()
+            .find(|g| g.parent == parent && g.name == name)
+            .map(|g| g.id);
+        parent = Some(match existing {
+            Some(id) => id,
+            None => db.add_group(name, parent)?,
+        });
+    }
+    match parent {
+        Some(id) => Ok(id),
+        None => Err(Error::Invalid(String::from("Empty group path"))),
+    }
+}
+
+/// Copy groups and entries of `src` (all of them, or the given group subtree) into `dst`,
+/// under `dst_parent` group. Groups with the same path are reused, entries already present
+/// in `dst` (by id) are skipped.
+/// Returns number of copied entries.
+pub fn copy_into(
+    src: &PasswordDatabase,
+    src_group: Option<GroupId>,
+    dst: &mut PasswordDatabase,
+    dst_parent: Option<GroupId>,
+) -> Result<usize, Error> {
+    let src_groups = src.get_groups();
+    let dst_groups = dst.get_groups();
+    // Source group id -> destination group id.
+    let mut group_map: Vec<(GroupId, GroupId)> = Vec::new();
+    for g in &src_groups {
+        let in_subtree = match src_group {
+            None => true,
+            Some(root) => g.id == root || group_map.iter().any(|&(s, _)| Some(s) == g.parent),
+        };
+        if !in_subtree {
+            continue;
+        }
+        let parent = if Some(g.id) == src_group {
+            dst_parent
+        } else {
+            match g.parent {
+                Some(p) => group_map.iter().find(|&&(s, _)| s == p).map(|&(_, d)| d),
+                None => dst_parent,
+            }
+        };
+        let existing = dst_groups
+            .iter()
+            .find(|d| d.parent == parent && d.name == g.name)
+            .map(|d| d.id);
+        let id = match existing {
+            Some(id) => id,
+            None => dst.add_group(&g.name, parent)?,
+        };
+        group_map.push((g.id, id));
+    }
+    let mut count = 0;
+    for e in src.get_entries() {
+        let dst_group = match group_map.iter().find(|&&(s, _)| s == e.group_id) {
+            Some(&(_, d)) => d,
+            None => continue,
+        };
+        if dst.get_entry(&e.id).is_some() {
+            debug!("copy_into: Entry {} already present, skipping", e.id);
+            continue;
+        }
+        let mut e = e;
+        e.group_id = dst_group;
+        dst.add_entry(&e)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// How fields of `Group` and `Entry` are serialized, where it differs from how they're kept.
+mod serde_fields {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use db::formats::{from_iso8601, never_expires, to_iso8601};
+
+    fn parse_time<E: Error>(t: &str) -> Result<String, E> {
+        if t.is_empty() {
+            return Ok(String::new());
+        }
+        from_iso8601(t).ok_or_else(|| E::custom(format!("invalid time \"{}\", expected ISO-8601", t)))
+    }
+
+    /// Local time as ISO-8601 UTC time.
+    pub mod time {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(t: &str, serializer: S) -> Result<S::Ok, S::Error> {
+            match to_iso8601(t) {
+                Some(t) => serializer.serialize_str(&t),
+                None => serializer.serialize_str(t),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+            parse_time(&String::deserialize(deserializer)?)
+        }
+    }
+
+    /// Like `time`, null for never.
+    pub mod expires {
+        use super::*;
+
+        use db::api::NEVER_EXPIRES;
+
+        pub fn serialize<S: Serializer>(t: &str, serializer: S) -> Result<S::Ok, S::Error> {
+            if never_expires(t) {
+                serializer.serialize_none()
+            } else {
+                time::serialize(t, serializer)
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(t) => parse_time(&t),
+                None => Ok(String::from(NEVER_EXPIRES)),
+            }
+        }
+    }
+
+    /// Hyphenated, like "0b7b1ab6-1c58-4e4a-93d6-3f6b1b1c9e10".
+    pub mod uuid {
+        use super::*;
+
+        use db::api::Uuid;
+
+        pub fn serialize<S: Serializer>(id: &Uuid, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&id.hyphenated().to_string())
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+            let id = String::deserialize(deserializer)?;
+            Uuid::parse_str(&id).map_err(|_| D::Error::custom(format!("invalid UUID \"{}\"", id)))
+        }
+    }
+
+    /// Base64.
+    pub mod base64_data {
+        use super::*;
+
+        use base64;
+
+        pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&base64::encode(data))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+            base64::decode(&String::deserialize(deserializer)?).map_err(|e| D::Error::custom(e.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_hides_secrets() {
+        let mut e = Entry::new(1, "Gmail");
+        e.username = String::from("alice");
+        e.password = String::from("hunter2");
+        e.notes = String::from("Recovery email set\nTOTP: JBSWY3DPEHPK3PXP");
+        let text = format!("{:?}", e);
+        assert!(text.contains("alice") && text.contains("Recovery email set"), "{}", text);
+        assert!(!text.contains("hunter2") && !text.contains("JBSWY3DP"), "{}", text);
+    }
 }
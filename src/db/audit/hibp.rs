@@ -0,0 +1,194 @@
+//! Offline check of passwords against Have I Been Pwned's list of breached passwords.
+//!
+//! Nothing is sent anywhere: passwords are hashed (SHA-1) in memory and looked up in a local
+//! copy of the list, either
+//!
+//! - the whole list as one file, "HASH:COUNT" lines ordered by hash, as written by the
+//!   haveibeenpwned-downloader or the older "ordered by hash" downloads, searched by bisection
+//!   without reading the whole file, or
+//! - a directory of range files, "<first 5 hash digits>.txt" with "REST OF HASH:COUNT" lines,
+//!   as returned by api.pwnedpasswords.com/range/.
+//!
+//! COUNT is how many times the password was seen in breaches.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+
+use errors::Error;
+
+const FORMAT: &str = "Pwned Passwords";
+
+/// Length of SHA-1 in hex.
+const HASH_LENGTH: usize = 40;
+
+/// Length of hash prefix in range file names.
+const PREFIX_LENGTH: usize = 5;
+
+/// Bisection stops when this many bytes are left, the rest is read line by line.
+const SCAN_SIZE: u64 = 4096;
+
+enum Source {
+    /// Whole list, and its length.
+    File(File, u64),
+    Dir(PathBuf),
+}
+
+/// Local copy of the Pwned Passwords list.
+pub struct PwnedList {
+    source: Source,
+}
+
+/// Upper case hex SHA-1 of password, as in the list.
+fn hash(password: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.input_str(password);
+    sha.result_str().to_uppercase()
+}
+
+/// Hash and count of list line, None if line doesn't look like one.
+fn parse_line(line: &str) -> Option<(&str, u64)> {
+    let line = line.trim_end();
+    let pos = line.find(':')?;
+    let count = line[pos + 1..].trim().parse().ok()?;
+    Some((&line[..pos], count))
+}
+
+impl PwnedList {
+    /// List file, or directory of range files.
+    pub fn open(path: &Path) -> Result<PwnedList, Error> {
+        let context = |e: Error| Error::Context {
+            desc: format!("Can't open {}", path.display()),
+            source: Box::new(e),
+        };
+        let source = if path.is_dir() {
+            Source::Dir(path.to_path_buf())
+        } else {
+            let file = File::open(path).map_err(|e| context(Error::Io(e)))?;
+            let length = file.metadata().map_err(|e| context(Error::Io(e)))?.len();
+            Source::File(file, length)
+        };
+        Ok(PwnedList { source: source })
+    }
+
+    /// How many times password was seen in breaches, 0 if never.
+    pub fn count(&mut self, password: &str) -> Result<u64, Error> {
+        let hash = hash(password);
+        match self.source {
+            Source::File(ref mut file, length) => search_file(file, length, &hash),
+            Source::Dir(ref dir) => {
+                let (prefix, rest) = hash.split_at(PREFIX_LENGTH);
+                let path = dir.join(format!("{}.txt", prefix));
+                let file = match File::open(&path) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        return Err(Error::Context {
+                            desc: format!("No range file {} in {}", path.display(), dir.display()),
+                            source: Box::new(Error::Io(e)),
+                        })
+                    }
+                };
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    match parse_line(&line) {
+                        Some((suffix, count)) if suffix.eq_ignore_ascii_case(rest) => return Ok(count),
+                        _ => {}
+                    }
+                }
+                Ok(0)
+            }
+        }
+    }
+}
+
+/// Find hash in ordered list file by bisection.
+fn search_file(file: &mut File, length: u64, hash: &str) -> Result<u64, Error> {
+    // Lines starting before lo have smaller hashes, lo is always at a line start.
+    let mut lo = 0;
+    let mut hi = length;
+    let mut line = String::new();
+    while hi - lo > SCAN_SIZE {
+        let mid = lo + (hi - lo) / 2;
+        file.seek(SeekFrom::Start(mid))?;
+        let mut reader = BufReader::new(&mut *file);
+        // Rest of line at mid, then first whole line after it.
+        line.clear();
+        let skipped = reader.read_line(&mut line)? as u64;
+        line.clear();
+        let read = reader.read_line(&mut line)? as u64;
+        if read == 0 {
+            hi = mid;
+            continue;
+        }
+        let line_hash = match parse_line(&line) {
+            Some((h, _)) if h.len() == HASH_LENGTH => h.to_uppercase(),
+            _ => return Err(Error::format(FORMAT, &format!("Invalid line at {}: {}", mid + skipped, line.trim()))),
+        };
+        if line_hash.as_str() < hash {
+            lo = mid + skipped + read;
+        } else {
+            hi = mid;
+        }
+    }
+    file.seek(SeekFrom::Start(lo))?;
+    for line in BufReader::new(&mut *file).lines() {
+        let line = line?;
+        let (line_hash, count) = match parse_line(&line) {
+            Some(p) => p,
+            None if line.trim().is_empty() => continue,
+            None => return Err(Error::format(FORMAT, &format!("Invalid line: {}", line.trim()))),
+        };
+        let line_hash = line_hash.to_uppercase();
+        if line_hash.as_str() == hash {
+            return Ok(count);
+        }
+        if line_hash.as_str() > hash {
+            break;
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+    use std::fs;
+    use std::io::Write;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        let dir = std::env::temp_dir().join(format!("mpkdb-test-hibp-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut lines: Vec<String> = (0..3000)
+            .map(|i| format!("{}:{}", hash(&format!("password-{}", i)), i + 1))
+            .collect();
+        lines.push(format!("{}:3861493", hash("password")));
+        lines.sort();
+        let path = dir.join("pwned-passwords-sha1-ordered-by-hash.txt");
+        fs::write(&path, lines.join("\r\n")).unwrap();
+        let mut range = File::create(dir.join("5BAA6.txt")).unwrap();
+        write!(range, "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:3861493\r\n").unwrap();
+        let mut list = PwnedList::open(&path).unwrap();
+        assert_eq!(list.count("password").unwrap(), 3861493);
+        assert_eq!(list.count("password-0").unwrap(), 1);
+        assert_eq!(list.count("password-2999").unwrap(), 3000);
+        assert_eq!(list.count("not in the list").unwrap(), 0);
+        let length = fs::metadata(&path).unwrap().len();
+        let mut file = File::open(&path).unwrap();
+        for l in &lines {
+            let (h, count) = parse_line(l).unwrap();
+            assert_eq!(search_file(&mut file, length, h).unwrap(), count, "{}", l);
+        }
+        let mut ranges = PwnedList::open(&dir).unwrap();
+        assert_eq!(ranges.count("password").unwrap(), 3861493);
+        assert!(ranges.count("password-0").is_err(), "no range file");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
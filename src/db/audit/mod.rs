@@ -0,0 +1,253 @@
+//! Database health check: weak, empty, reused, old and breached passwords, and passwords
+//! that are the same as the entry's username or title.
+
+pub mod hibp;
+pub mod strength;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+
+use db::api::{now, Uuid};
+use db::formats::{days_between, Record};
+use errors::Error;
+
+use self::hibp::PwnedList;
+use self::strength::{Estimator, Strength};
+
+/// What to check.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// Passwords scoring lower than this (0 to 4, see `Strength`) are weak.
+    pub min_score: u8,
+    /// Passwords not changed in this many days are old, None to not check.
+    pub max_age_days: Option<i64>,
+    /// Local Pwned Passwords list (see `hibp`) to look passwords up in, None to not check.
+    pub pwned_list: Option<PathBuf>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            min_score: 3,
+            max_age_days: None,
+            pwned_list: None,
+        }
+    }
+}
+
+/// Problem with an entry's password.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Issue {
+    Empty,
+    /// Scores lower than `Options::min_score`.
+    Weak,
+    /// Same password as these other entries (paths).
+    Reused(Vec<String>),
+    SameAsUsername,
+    SameAsTitle,
+    /// Not changed in this many days.
+    Old(i64),
+    /// Seen this many times in data breaches.
+    Breached(u64),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Issue::Empty => write!(f, "empty password"),
+            Issue::Weak => write!(f, "weak password"),
+            Issue::Reused(ref paths) => write!(f, "also used by {}", paths.join(", ")),
+            Issue::SameAsUsername => write!(f, "same as username"),
+            Issue::SameAsTitle => write!(f, "same as title"),
+            Issue::Old(days) => write!(f, "not changed in {} days", days),
+            Issue::Breached(count) => write!(f, "seen {} times in data breaches", count),
+        }
+    }
+}
+
+/// Result for one entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntryAudit {
+    pub id: Uuid,
+    /// Path like "Internet/Mail/Gmail".
+    pub path: String,
+    pub strength: Strength,
+    pub issues: Vec<Issue>,
+}
+
+/// Results for all entries, in the order they were given.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Audit {
+    pub entries: Vec<EntryAudit>,
+}
+
+impl Audit {
+    /// Entries with any issues.
+    pub fn with_issues(&self) -> Vec<&EntryAudit> {
+        self.entries.iter().filter(|e| !e.issues.is_empty()).collect()
+    }
+
+    /// Number of entries with issue matching f, e.g. `|i| *i == Issue::Weak`.
+    pub fn count<F: Fn(&Issue) -> bool>(&self, f: F) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| e.issues.iter().any(|i| f(i)))
+            .count()
+    }
+
+    /// Counts of entries with each kind of issue, e.g. "3 weak", for a summary.
+    pub fn summary(&self) -> Vec<String> {
+        let kinds: [(&str, &Fn(&Issue) -> bool); 7] = [
+            ("empty", &|i| *i == Issue::Empty),
+            ("weak", &|i| *i == Issue::Weak),
+            ("reused", &|i| match *i {
+                Issue::Reused(_) => true,
+                _ => false,
+            }),
+            ("same as username", &|i| *i == Issue::SameAsUsername),
+            ("same as title", &|i| *i == Issue::SameAsTitle),
+            ("old", &|i| match *i {
+                Issue::Old(_) => true,
+                _ => false,
+            }),
+            ("breached", &|i| match *i {
+                Issue::Breached(_) => true,
+                _ => false,
+            }),
+        ];
+        kinds
+            .iter()
+            .map(|&(name, f)| (name, self.count(f)))
+            .filter(|&(_, count)| count > 0)
+            .map(|(name, count)| format!("{} {}", count, name))
+            .collect()
+    }
+}
+
+/// Check passwords of records, fails only if Pwned Passwords list can't be read.
+pub fn audit(records: &[Record], options: &Options) -> Result<Audit, Error> {
+    let mut pwned_list = match options.pwned_list {
+        Some(ref path) => Some(PwnedList::open(path)?),
+        None => None,
+    };
+    let mut breaches: HashMap<&str, u64> = HashMap::new();
+    let estimator = Estimator::new();
+    let today = now();
+    // Entries can have the same path, so they are told apart by id.
+    let mut by_password: HashMap<&str, Vec<(Uuid, String)>> = HashMap::new();
+    for r in records.iter().filter(|r| !r.entry.password.is_empty()) {
+        by_password
+            .entry(r.entry.password.as_str())
+            .or_insert_with(Vec::new)
+            .push((r.entry.id, r.path()));
+    }
+    let mut audit = Audit::default();
+    for r in records {
+        let e = &r.entry;
+        let path = r.path();
+        let strength = estimator.estimate(&e.password, &[&e.username, &e.title]);
+        let mut issues = Vec::new();
+        if e.password.is_empty() {
+            issues.push(Issue::Empty);
+        } else {
+            if strength.score < options.min_score {
+                issues.push(Issue::Weak);
+            }
+            let others: Vec<String> = by_password[e.password.as_str()]
+                .iter()
+                .filter(|&&(id, _)| id != e.id)
+                .map(|&(_, ref p)| p.clone())
+                .collect();
+            if !others.is_empty() {
+                issues.push(Issue::Reused(others));
+            }
+            let password = e.password.trim().to_lowercase();
+            if password == e.username.trim().to_lowercase() {
+                issues.push(Issue::SameAsUsername);
+            }
+            if password == e.title.trim().to_lowercase() {
+                issues.push(Issue::SameAsTitle);
+            }
+            if let Some(ref mut list) = pwned_list {
+                // Reused passwords are looked up once.
+                let count = match breaches.get(e.password.as_str()) {
+                    Some(&count) => count,
+                    None => list.count(&e.password)?,
+                };
+                breaches.insert(&e.password, count);
+                if count > 0 {
+                    issues.push(Issue::Breached(count));
+                }
+            }
+        }
+        if let Some(max) = options.max_age_days {
+            match days_between(&e.modified, &today) {
+                Some(days) if days > max => issues.push(Issue::Old(days)),
+                _ => {}
+            }
+        }
+        debug!("audit: {}: {} issues", path, issues.len());
+        audit.entries.push(EntryAudit {
+            id: e.id,
+            path: path,
+            strength: strength,
+            issues: issues,
+        });
+    }
+    Ok(audit)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::api::Entry;
+
+    use super::*;
+
+    fn record(title: &str, username: &str, password: &str, modified: &str) -> Record {
+        let mut e = Entry::new(0, title);
+        e.username = String::from(username);
+        e.password = String::from(password);
+        e.modified = String::from(modified);
+        Record {
+            group: String::from("Internet"),
+            entry: e,
+        }
+    }
+
+    #[test]
+    fn test_audit() {
+        let records = vec![
+            record("Forum", "bob", "x7#Kq9!vLm2$Wp4z", "2000-01-01 00:00:00"),
+            record("Mail", "alice", "x7#Kq9!vLm2$Wp4z", &now()),
+            record("Bank", "Bank", "bank", &now()),
+            record("Shop", "carol", "", &now()),
+            record("Gmail", "dave", "m4R!zQ8#vT2&kW6p", &now()),
+            record("Gmail", "erin", "m4R!zQ8#vT2&kW6p", &now()),
+        ];
+        let options = Options {
+            max_age_days: Some(365),
+            ..Options::default()
+        };
+        let audit = audit(&records, &options).unwrap();
+        let issues: Vec<&[Issue]> = audit.entries.iter().map(|e| &e.issues[..]).collect();
+        match issues[0] {
+            [Issue::Reused(ref others), Issue::Old(days)] => {
+                assert_eq!(others, &vec![String::from("Internet/Mail")]);
+                assert!(*days > 365 * 10);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(issues[1], &[Issue::Reused(vec![String::from("Internet/Forum")])]);
+        assert_eq!(issues[2], &[Issue::Weak, Issue::SameAsUsername, Issue::SameAsTitle]);
+        assert_eq!(issues[3], &[Issue::Empty]);
+        // Same path, still two entries.
+        assert_eq!(issues[4], &[Issue::Reused(vec![String::from("Internet/Gmail")])]);
+        assert_eq!(issues[5], &[Issue::Reused(vec![String::from("Internet/Gmail")])]);
+        assert_eq!(audit.with_issues().len(), 6);
+        assert_eq!(
+            audit.summary(),
+            vec!["1 empty", "1 weak", "4 reused", "1 same as username", "1 same as title", "1 old"]
+        );
+    }
+}
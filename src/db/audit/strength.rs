@@ -0,0 +1,421 @@
+//! Password strength estimate, in the spirit of zxcvbn: passwords are split into guessable
+//! parts (common passwords, words, the entry's own username or title, sequences, repeats,
+//! keyboard runs, years), the rest counts as random characters, and the estimate is the
+//! number of guesses of the cheapest split.
+
+use std::collections::HashMap;
+
+use time;
+
+use generator;
+
+/// Most common passwords, most common first.
+const COMMON_PASSWORDS: &str = include_str!("common.txt");
+
+/// Rows and columns of a US keyboard, runs of these are easy to type and to guess.
+const KEYBOARD_RUNS: &[&str] = &[
+    "`1234567890-=",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+    "1qaz2wsx3edc4rfv5tgb6yhn7ujm8ik,9ol.0p;/-['=]",
+    "qazwsxedcrfvtgbyhnujmikolp",
+    "789456123",
+    "147258369",
+];
+
+/// Substitutions of l33t speak, "1" is tried both as "i" and "l".
+const LEET: &[&[(char, char)]] = &[
+    &[('4', 'a'), ('@', 'a'), ('3', 'e'), ('1', 'i'), ('!', 'i'), ('0', 'o'), ('$', 's'), ('5', 's'), ('7', 't'), ('+', 't')],
+    &[('4', 'a'), ('@', 'a'), ('3', 'e'), ('1', 'l'), ('|', 'l'), ('0', 'o'), ('$', 's'), ('5', 's'), ('7', 't'), ('+', 't')],
+];
+
+/// Longest password looked at in detail, rest is counted as random characters.
+const MAX_LENGTH: usize = 64;
+
+/// Fewest guesses for a part of several characters, so that splitting into many cheap parts
+/// doesn't look weaker than it is.
+const MIN_PART_GUESSES: f64 = 50.0;
+
+/// Highest `Strength::score`.
+pub const MAX_SCORE: u8 = 4;
+
+/// How guessable a password is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Strength {
+    /// log2 of estimated number of guesses.
+    pub bits: f64,
+    /// 0 (guessed in under a thousand tries) to 4 (over ten billion).
+    pub score: u8,
+    /// What makes it guessable, e.g. "common password", None if nothing does.
+    pub weakness: Option<&'static str>,
+}
+
+impl Strength {
+    pub fn label(&self) -> &'static str {
+        match self.score {
+            0 => "very weak",
+            1 => "weak",
+            2 => "fair",
+            3 => "good",
+            _ => "strong",
+        }
+    }
+}
+
+/// Guessable part of password, end is exclusive, in chars.
+#[derive(Clone, Debug)]
+struct Part {
+    start: usize,
+    end: usize,
+    guesses: f64,
+    kind: &'static str,
+}
+
+/// Estimates strength, built once since loading the word lists takes a while.
+pub struct Estimator {
+    common: HashMap<String, usize>,
+    words: HashMap<String, usize>,
+}
+
+impl Default for Estimator {
+    fn default() -> Estimator {
+        Estimator::new()
+    }
+}
+
+fn ranked<'a, I: Iterator<Item = &'a str>>(words: I) -> HashMap<String, usize> {
+    let mut ranks = HashMap::new();
+    for (i, w) in words.filter(|w| !w.is_empty()).enumerate() {
+        ranks.entry(w.to_lowercase()).or_insert(i + 1);
+    }
+    ranks
+}
+
+/// Number of possible characters, from the classes used in password.
+fn cardinality(chars: &[char]) -> f64 {
+    let has = |f: &Fn(char) -> bool| chars.iter().any(|&c| f(c));
+    let mut n = 0;
+    if has(&|c| c.is_ascii_lowercase()) {
+        n += 26;
+    }
+    if has(&|c| c.is_ascii_uppercase()) {
+        n += 26;
+    }
+    if has(&|c| c.is_ascii_digit()) {
+        n += 10;
+    }
+    if has(&|c| c.is_ascii_punctuation() || c == ' ') {
+        n += 33;
+    }
+    if has(&|c| !c.is_ascii()) {
+        n += 100;
+    }
+    n.max(10) as f64
+}
+
+/// Ways to capitalize a word like this one.
+fn case_variations(chars: &[char]) -> f64 {
+    let upper = chars.iter().filter(|c| c.is_uppercase()).count();
+    let lower = chars.iter().filter(|c| c.is_lowercase()).count();
+    if upper == 0 {
+        return 1.0;
+    }
+    let first_only = upper == 1 && chars[0].is_uppercase();
+    let last_only = upper == 1 && chars[chars.len() - 1].is_uppercase();
+    if lower == 0 || first_only || last_only {
+        return 2.0;
+    }
+    2f64.powi(upper.min(lower) as i32)
+}
+
+impl Estimator {
+    pub fn new() -> Estimator {
+        Estimator {
+            common: ranked(COMMON_PASSWORDS.lines()),
+            words: ranked(generator::wordlist().into_iter()),
+        }
+    }
+
+    /// Strength of password, user inputs are what attackers know, e.g. username and title.
+    pub fn estimate(&self, password: &str, user_inputs: &[&str]) -> Strength {
+        let chars: Vec<char> = password.chars().collect();
+        if chars.is_empty() {
+            return Strength {
+                bits: 0.0,
+                score: 0,
+                weakness: Some("empty"),
+            };
+        }
+        let (head, tail) = chars.split_at(chars.len().min(MAX_LENGTH));
+        let inputs = ranked(
+            user_inputs
+                .iter()
+                .flat_map(|i| {
+                    let words = i.split(|c: char| !c.is_alphanumeric()).filter(|w| w.chars().count() >= 3);
+                    Some(*i).into_iter().chain(words)
+                })
+                .filter(|i| i.chars().count() >= 3),
+        );
+        let (mut bits, kind) = self.min_guesses(head, &inputs);
+        bits += tail.len() as f64 * cardinality(&chars).log2();
+        let guesses = 2f64.powf(bits);
+        let score = if guesses < 1e3 {
+            0
+        } else if guesses < 1e6 {
+            1
+        } else if guesses < 1e8 {
+            2
+        } else if guesses < 1e10 {
+            3
+        } else {
+            MAX_SCORE
+        };
+        Strength {
+            bits: bits,
+            score: score,
+            weakness: kind,
+        }
+    }
+
+    /// Dictionary parts: common passwords, words and user inputs, also reversed and in l33t.
+    fn dictionary_parts(&self, chars: &[char], inputs: &HashMap<String, usize>, parts: &mut Vec<Part>) {
+        let lower: Vec<char> = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+        if lower.len() != chars.len() {
+            return;
+        }
+        let mut variants: Vec<(Vec<char>, f64)> = vec![(lower.clone(), 1.0)];
+        for table in LEET {
+            let leet: Vec<char> = lower
+                .iter()
+                .map(|&c| table.iter().find(|&&(from, _)| from == c).map_or(c, |&(_, to)| to))
+                .collect();
+            if leet != lower {
+                variants.push((leet, 2.0));
+            }
+        }
+        let dictionaries: [(&HashMap<String, usize>, &'static str); 3] = [
+            (inputs, "contains username or title"),
+            (&self.common, "common password"),
+            (&self.words, "dictionary word"),
+        ];
+        for i in 0..chars.len() {
+            for j in i + 3..chars.len() + 1 {
+                let case = case_variations(&chars[i..j]);
+                for &(ref variant, leet) in &variants {
+                    let word: String = variant[i..j].iter().collect();
+                    let reversed: String = variant[i..j].iter().rev().collect();
+                    for &(dictionary, kind) in &dictionaries {
+                        let found = dictionary
+                            .get(&word)
+                            .map(|&rank| rank as f64)
+                            .or_else(|| dictionary.get(&reversed).map(|&rank| rank as f64 * 2.0));
+                        if let Some(rank) = found {
+                            parts.push(Part {
+                                start: i,
+                                end: j,
+                                guesses: rank * case * leet,
+                                kind: kind,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sequences like "abc", "97531", repeats like "aaa" or "abcabc", keyboard runs and years.
+    fn pattern_parts(&self, chars: &[char], inputs: &HashMap<String, usize>, parts: &mut Vec<Part>) {
+        let n = chars.len();
+        // Sequences, with the same step all the way.
+        let mut i = 0;
+        while i + 2 < n {
+            let step = chars[i + 1] as i64 - chars[i] as i64;
+            let mut j = i + 1;
+            while j + 1 < n && chars[j + 1] as i64 - chars[j] as i64 == step {
+                j += 1;
+            }
+            if j - i >= 2 && step != 0 && step.abs() <= 5 {
+                let first = chars[i];
+                let base = if "aAzZ019".contains(first) {
+                    4.0
+                } else if first.is_ascii_digit() {
+                    10.0
+                } else {
+                    26.0
+                };
+                let direction = if step < 0 { 2.0 } else { 1.0 };
+                parts.push(Part {
+                    start: i,
+                    end: j + 1,
+                    guesses: base * direction * (j + 1 - i) as f64 * step.abs() as f64,
+                    kind: "sequence",
+                });
+            }
+            i = if j > i + 1 { j } else { i + 1 };
+        }
+        // Repeated blocks, block guesses as estimated on their own.
+        for start in 0..n {
+            for len in 1..(n - start) / 2 + 1 {
+                let block = &chars[start..start + len];
+                // Only whole runs, of blocks that are not repeats themselves ("ab", not "abab").
+                if start >= len && &chars[start - len..start] == block {
+                    continue;
+                }
+                if (1..len).any(|d| len % d == 0 && (d..len).all(|k| block[k] == block[k - d])) {
+                    continue;
+                }
+                let mut count = 1;
+                while start + (count + 1) * len <= n && &chars[start + count * len..start + (count + 1) * len] == block {
+                    count += 1;
+                }
+                if count >= 2 && count * len >= 3 {
+                    let block_guesses = if len == 1 {
+                        cardinality(block)
+                    } else {
+                        2f64.powf(self.min_guesses(block, inputs).0)
+                    };
+                    parts.push(Part {
+                        start: start,
+                        end: start + count * len,
+                        guesses: block_guesses * count as f64,
+                        kind: "repeated characters",
+                    });
+                }
+            }
+        }
+        // Keyboard runs, forwards or backwards.
+        let lower: String = chars.iter().flat_map(|c| c.to_lowercase()).collect();
+        if lower.chars().count() == n {
+            let lower: Vec<char> = lower.chars().collect();
+            for i in 0..n {
+                for j in i + 4..n + 1 {
+                    let run: String = lower[i..j].iter().collect();
+                    let reversed: String = lower[i..j].iter().rev().collect();
+                    if KEYBOARD_RUNS.iter().any(|r| r.contains(&run) || r.contains(&reversed)) {
+                        parts.push(Part {
+                            start: i,
+                            end: j,
+                            guesses: KEYBOARD_RUNS.len() as f64 * 12.0 * (j - i) as f64 * 2.0,
+                            kind: "keyboard pattern",
+                        });
+                    }
+                }
+            }
+        }
+        // Years, recent ones are likelier.
+        let this_year = time::now().tm_year as i64 + 1900;
+        for i in 0..n.saturating_sub(3) {
+            let digits: String = chars[i..i + 4].iter().collect();
+            if let Ok(year) = digits.parse::<i64>() {
+                if digits.chars().all(|c| c.is_ascii_digit()) && year >= 1900 && year <= 2099 {
+                    parts.push(Part {
+                        start: i,
+                        end: i + 4,
+                        guesses: ((year - this_year).abs() as f64).max(20.0),
+                        kind: "year",
+                    });
+                }
+            }
+        }
+    }
+
+    /// log2 of guesses for cheapest split of chars into parts and random characters, and the
+    /// kind of the longest guessable part in it.
+    fn min_guesses(&self, chars: &[char], inputs: &HashMap<String, usize>) -> (f64, Option<&'static str>) {
+        let n = chars.len();
+        let mut parts = Vec::new();
+        self.dictionary_parts(chars, inputs, &mut parts);
+        self.pattern_parts(chars, inputs, &mut parts);
+        let random = cardinality(chars).log2();
+        // best[i][k]: log2 guesses of chars[..i] in k parts, and how it got there.
+        let mut best: Vec<Vec<Option<(f64, usize, Option<usize>)>>> = vec![vec![None; n + 1]; n + 1];
+        best[0][0] = Some((0.0, 0, None));
+        for end in 1..n + 1 {
+            for k in 1..end + 1 {
+                let mut candidate: Option<(f64, usize, Option<usize>)> = None;
+                let mut consider = |bits: f64, start: usize, part: Option<usize>| {
+                    if let Some((prev, _, _)) = best[start][k - 1] {
+                        let total = prev + bits;
+                        if candidate.map_or(true, |(b, _, _)| total < b) {
+                            candidate = Some((total, start, part));
+                        }
+                    }
+                };
+                for start in 0..end {
+                    let len = end - start;
+                    let bits = if len > 1 {
+                        (len as f64 * random).max(MIN_PART_GUESSES.log2())
+                    } else {
+                        random
+                    };
+                    consider(bits, start, None);
+                }
+                for (p, part) in parts.iter().enumerate().filter(|&(_, p)| p.end == end) {
+                    let min = if part.end - part.start > 1 { MIN_PART_GUESSES } else { 1.0 };
+                    consider(part.guesses.max(min).log2(), part.start, Some(p));
+                }
+                best[end][k] = candidate;
+            }
+        }
+        // More parts, more ways to combine them.
+        let mut result = (n as f64 * random, None);
+        let mut best_k = 0;
+        let mut factorial = 0.0;
+        for k in 1..n + 1 {
+            factorial += (k as f64).log2();
+            if let Some((bits, _, _)) = best[n][k] {
+                if bits + factorial < result.0 {
+                    result.0 = bits + factorial;
+                    best_k = k;
+                }
+            }
+        }
+        let mut longest = 0;
+        let (mut end, mut k) = (n, best_k);
+        while k > 0 {
+            let (_, start, part) = best[end][k].expect("path exists");
+            if let Some(p) = part {
+                if end - start > longest {
+                    longest = end - start;
+                    result.1 = Some(parts[p].kind);
+                }
+            }
+            end = start;
+            k -= 1;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let estimator = Estimator::new();
+        let weak = [
+            ("password", "common password"),
+            ("P@ssw0rd", "common password"),
+            ("abcdefgh", "sequence"),
+            ("aaaaaaaaaaaa", "repeated characters"),
+            ("qwertyuiop", "common password"),
+            ("zxcvbnm,./", "keyboard pattern"),
+            ("Bobby!", "contains username or title"),
+        ];
+        for &(password, weakness) in &weak {
+            let s = estimator.estimate(password, &["bobby"]);
+            assert!(s.score <= 1, "{}: {:?}", password, s);
+            assert_eq!(s.weakness, Some(weakness), "{}: {:?}", password, s);
+        }
+        // Words are easier to guess than as many random letters.
+        let s = estimator.estimate("correcthorseoceanwindow", &[]);
+        assert_eq!(s.weakness, Some("dictionary word"));
+        assert!(s.score >= 3 && s.bits < 22.0 * 26f64.log2() / 2.0, "{:?}", s);
+        let s = estimator.estimate("x7#Kq9!vLm2$Wp4z", &[]);
+        assert_eq!(s.score, 4);
+        assert!(s.bits > 90.0, "{:?}", s);
+        assert_eq!(estimator.estimate("", &[]).label(), "very weak");
+    }
+}
@@ -0,0 +1,258 @@
+//! Structural diff of two databases, e.g. before merging, or against a backup.
+//!
+//! Groups and entries are matched by id, like in `db::merge`. Secret fields (and notes with
+//! one-time password secrets) are only reported as changed, their values never end up in the diff.
+
+use std::fmt;
+
+use db::api::{Attachment, Entry, Group, PasswordDatabase};
+use otp;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Group,
+    Entry,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Changed field, values are None for secrets.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+    pub secret: bool,
+}
+
+/// Added, removed or modified group or entry.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RecordDiff {
+    pub kind: Kind,
+    pub change: Change,
+    pub id: String,
+    /// Path in the new database (old one for removed records), like "Internet/Gmail".
+    pub path: String,
+    /// Only for modified records.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldChange>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Diff {
+    pub records: Vec<RecordDiff>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn count(&self, change: Change) -> usize {
+        self.records.iter().filter(|r| r.change == change).count()
+    }
+}
+
+impl fmt::Display for RecordDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = match self.change {
+            Change::Added => '+',
+            Change::Removed => '-',
+            Change::Modified => '~',
+        };
+        let kind = match self.kind {
+            Kind::Group => "group",
+            Kind::Entry => "entry",
+        };
+        write!(f, "{} {} {}", sign, kind, self.path)?;
+        for c in &self.fields {
+            match (&c.old, &c.new) {
+                (&Some(ref old), &Some(ref new)) => write!(f, "\n    {}: {:?} -> {:?}", c.field, old, new)?,
+                _ => write!(f, "\n    {}: changed", c.field)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for r in &self.records {
+            writeln!(f, "{}", r)?;
+        }
+        write!(
+            f,
+            "{} added, {} removed, {} modified",
+            self.count(Change::Added),
+            self.count(Change::Removed),
+            self.count(Change::Modified)
+        )
+    }
+}
+
+fn field(changes: &mut Vec<FieldChange>, name: &'static str, old: &str, new: &str, secret: bool) {
+    if old == new {
+        return;
+    }
+    changes.push(FieldChange {
+        field: name,
+        old: if secret { None } else { Some(String::from(old)) },
+        new: if secret { None } else { Some(String::from(new)) },
+        secret: secret,
+    });
+}
+
+fn attachment_desc(a: &Option<Attachment>) -> String {
+    match *a {
+        Some(ref a) => format!("{} ({} bytes)", a.name, a.data.len()),
+        None => String::new(),
+    }
+}
+
+fn group_path(db: &PasswordDatabase, g: &Group) -> String {
+    db.get_group_path(g.id).join("/")
+}
+
+fn parent_path(db: &PasswordDatabase, g: &Group) -> String {
+    g.parent.map(|p| db.get_group_path(p).join("/")).unwrap_or_default()
+}
+
+fn entry_path(db: &PasswordDatabase, e: &Entry) -> String {
+    db.get_entry_path(e)
+}
+
+fn record(kind: Kind, change: Change, id: String, path: String, fields: Vec<FieldChange>) -> RecordDiff {
+    RecordDiff {
+        kind: kind,
+        change: change,
+        id: id,
+        path: path,
+        fields: fields,
+    }
+}
+
+/// What changed from `old` to `new`, groups first, both sorted by path.
+/// Times (other than expiry) are not compared, they change on every edit anyway.
+pub fn diff(old: &PasswordDatabase, new: &PasswordDatabase) -> Diff {
+    let mut groups = Vec::new();
+    for n in new.get_groups() {
+        match old.get_group(n.id) {
+            None => groups.push(record(Kind::Group, Change::Added, n.id.to_string(), group_path(new, &n), Vec::new())),
+            Some(o) => {
+                let mut fields = Vec::new();
+                field(&mut fields, "name", &o.name, &n.name, false);
+                field(&mut fields, "parent", &parent_path(old, &o), &parent_path(new, &n), false);
+                field(&mut fields, "expires", &o.expires, &n.expires, false);
+                if !fields.is_empty() {
+                    groups.push(record(Kind::Group, Change::Modified, n.id.to_string(), group_path(new, &n), fields));
+                }
+            }
+        }
+    }
+    for o in old.get_groups() {
+        if new.get_group(o.id).is_none() {
+            groups.push(record(Kind::Group, Change::Removed, o.id.to_string(), group_path(old, &o), Vec::new()));
+        }
+    }
+    let mut entries = Vec::new();
+    for n in new.get_entries() {
+        match old.get_entry(&n.id) {
+            None => entries.push(record(Kind::Entry, Change::Added, n.id.to_string(), entry_path(new, &n), Vec::new())),
+            Some(o) => {
+                let mut fields = Vec::new();
+                field(&mut fields, "title", &o.title, &n.title, false);
+                field(
+                    &mut fields,
+                    "group",
+                    &old.get_group_path(o.group_id).join("/"),
+                    &new.get_group_path(n.group_id).join("/"),
+                    false,
+                );
+                field(&mut fields, "username", &o.username, &n.username, false);
+                field(&mut fields, "password", &o.password, &n.password, true);
+                field(&mut fields, "url", &o.url, &n.url, false);
+                // Notes may hold a one-time password secret.
+                let notes_secret = otp::has_secret(&o.notes) || otp::has_secret(&n.notes);
+                field(&mut fields, "notes", &o.notes, &n.notes, notes_secret);
+                field(&mut fields, "expires", &o.expires, &n.expires, false);
+                if o.attachment != n.attachment {
+                    let (old, new) = (attachment_desc(&o.attachment), attachment_desc(&n.attachment));
+                    // Same name and size, only contents differ.
+                    let secret = old == new;
+                    fields.push(FieldChange {
+                        field: "attachment",
+                        old: if secret { None } else { Some(old) },
+                        new: if secret { None } else { Some(new) },
+                        secret: secret,
+                    });
+                }
+                if !fields.is_empty() {
+                    entries.push(record(Kind::Entry, Change::Modified, n.id.to_string(), entry_path(new, &n), fields));
+                }
+            }
+        }
+    }
+    for o in old.get_entries() {
+        if new.get_entry(&o.id).is_none() {
+            entries.push(record(Kind::Entry, Change::Removed, o.id.to_string(), entry_path(old, &o), Vec::new()));
+        }
+    }
+    groups.sort_by(|a, b| a.path.cmp(&b.path));
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    groups.extend(entries);
+    Diff { records: groups }
+}
+
+#[cfg(test)]
+mod tests {
+    use db::api::{Entry, PasswordDatabase};
+    use db::kdb;
+
+    use super::*;
+
+    #[test]
+    fn test_diff_hides_secrets() {
+        let mut old = kdb::create("unused.kdb", "secret", None).unwrap();
+        let internet = old.add_group("Internet", None).unwrap();
+        let mut e = Entry::new(internet, "Forum");
+        e.username = String::from("bob");
+        e.password = String::from("old-pw");
+        old.add_entry(&e).unwrap();
+        let gone = Entry::new(internet, "Old");
+        old.add_entry(&gone).unwrap();
+        let mut new = kdb::create("unused.kdb", "secret", None).unwrap();
+        new.insert_group(&old.get_group(internet).unwrap()).unwrap();
+        e.username = String::from("alice");
+        e.password = String::from("new-pw");
+        new.add_entry(&e).unwrap();
+        new.add_group("Email", None).unwrap();
+        let d = diff(&old, &new);
+        assert_eq!(d.count(Change::Added), 1);
+        assert_eq!(d.count(Change::Removed), 1);
+        assert_eq!(d.count(Change::Modified), 1);
+        let text = d.to_string();
+        assert!(text.contains("+ group Email"), "{}", text);
+        assert!(text.contains("- entry Internet/Old"), "{}", text);
+        assert!(text.contains("username: \"bob\" -> \"alice\""), "{}", text);
+        assert!(text.contains("password: changed"), "{}", text);
+        assert!(!text.contains("-pw"), "{}", text);
+        let mut with_otp = e.clone();
+        with_otp.notes = String::from("PIN: 1234\nTOTP: JBSWY3DPEHPK3PXP");
+        let mut newer = kdb::create("unused.kdb", "secret", None).unwrap();
+        newer.insert_group(&old.get_group(internet).unwrap()).unwrap();
+        newer.add_entry(&with_otp).unwrap();
+        let text = diff(&new, &newer).to_string();
+        assert!(text.contains("notes: changed"), "{}", text);
+        assert!(!text.contains("JBSWY3DP") && !text.contains("1234"), "{}", text);
+        assert!(diff(&new, &new).is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+//! Safe writing of database files: atomic replace, rotating backups and lock files.
+//!
+//! Not KDB specific, any file based database can use these.
+
+use std;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use errors::Error;
+
+/// Backups kept by default, `<file>.bak.1` is the newest.
+pub const DEFAULT_BACKUP_COUNT: u32 = 3;
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut p = path.as_os_str().to_os_string();
+    p.push(suffix);
+    PathBuf::from(p)
+}
+
+/// Path of nth backup of file, counting from 1.
+pub fn backup_path(path: &Path, n: u32) -> PathBuf {
+    with_suffix(path, &format!(".bak.{}", n))
+}
+
+/// Remove database written by a test, along with its backups and lock file.
+#[cfg(test)]
+pub fn remove_test_files(path: &Path) {
+    let _ = fs::remove_file(path);
+    for n in 1..DEFAULT_BACKUP_COUNT + 1 {
+        let _ = fs::remove_file(backup_path(path, n));
+    }
+    let _ = fs::remove_file(lock_path(path));
+}
+
+/// KeePass puts lock file next to database, named `<file>.lock`.
+pub fn lock_path(path: &Path) -> PathBuf {
+    with_suffix(path, ".lock")
+}
+
+/// Shift backups by one, oldest falls off, and make current file the newest backup.
+fn rotate_backups(path: &Path, count: u32) -> io::Result<()> {
+    if count == 0 || !path.exists() {
+        return Ok(());
+    }
+    for n in (1..count).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, n + 1))?;
+        }
+    }
+    let newest = backup_path(path, 1);
+    // Hard link is cheap, and the original stays in place until it's replaced.
+    if fs::hard_link(path, &newest).is_err() {
+        fs::copy(path, &newest)?;
+    }
+    Ok(())
+}
+
+/// New file only readable by us, passwords are in there.
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Make rename durable, directory entry is only written when directory is synced.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn write_temp(tmp_path: &Path, original: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = create_private(tmp_path)?;
+    if let Ok(meta) = fs::metadata(original) {
+        file.set_permissions(meta.permissions())?;
+    }
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+/// Replace file contents so that file is either old or new, never half-written.
+/// Contents go to a temp file in the same directory (rename only works within a file system),
+/// which is synced and renamed over the original, after the original is kept as backup.
+pub fn write_atomic(path: &Path, contents: &[u8], backups: u32) -> io::Result<()> {
+    let tmp_path = with_suffix(path, &format!(".{}.tmp", std::process::id()));
+    // Left behind by a crashed process, which had the same pid (e.g. before reboot).
+    match fs::remove_file(&tmp_path) {
+        Ok(()) => warn!("write_atomic: Removed stale \"{}\"", tmp_path.display()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    if let Err(e) = write_temp(&tmp_path, path, contents) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    let r = rotate_backups(path, backups).and_then(|_| fs::rename(&tmp_path, path));
+    if let Err(e) = r {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    sync_dir(dir)?;
+    debug!("write_atomic: Wrote {} bytes to \"{}\"", contents.len(), path.display());
+    Ok(())
+}
+
+/// What file looked like when we last read or wrote it, to notice changes by others.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiskState {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: [u8; 32],
+}
+
+fn sha256(contents: &[u8]) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    sha.input(contents);
+    let mut hash = [0; 32];
+    sha.result(&mut hash);
+    hash
+}
+
+impl DiskState {
+    /// State of file at path, which we just read or wrote contents from/to.
+    pub fn new(path: &Path, contents: &[u8]) -> io::Result<DiskState> {
+        let meta = fs::metadata(path)?;
+        Ok(DiskState {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            hash: sha256(contents),
+        })
+    }
+
+    /// Whether file is different now, deleted file counts as changed.
+    /// Content is only hashed if mtime or size changed, e.g. sync tools touch files without changing them.
+    pub fn changed(&self, path: &Path) -> io::Result<bool> {
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+        if meta.len() == self.len && meta.modified().ok() == self.modified && self.modified.is_some() {
+            return Ok(false);
+        }
+        let mut contents = Vec::new();
+        File::open(path)?.read_to_end(&mut contents)?;
+        Ok(sha256(&contents) != self.hash)
+    }
+}
+
+/// Whoever holds the lock, for other users to see.
+/// KeePass 1.x shows lock file contents as the user name.
+fn lock_owner() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| String::from("mpkdb"))
+}
+
+/// Advisory lock, other programs can only see it if they look for it (KeePass does).
+/// Lock file is removed when dropped.
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    /// Lock database file, fails with `Error::Locked` if someone else has it.
+    pub fn acquire(db_path: &Path) -> Result<LockFile, Error> {
+        let path = lock_path(db_path);
+        match create_private(&path) {
+            Ok(mut file) => {
+                let r = file.write_all(lock_owner().as_bytes());
+                let lock = LockFile { path: path };
+                r?;
+                debug!("LockFile::acquire: Locked \"{}\"", db_path.display());
+                Ok(lock)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                let mut owner = String::new();
+                File::open(&path).and_then(|mut f| f.read_to_string(&mut owner))?;
+                Err(Error::Locked {
+                    path: path.to_string_lossy().into_owned(),
+                    owner: String::from(owner.trim_matches('\0').trim()),
+                })
+            }
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+
+    /// Lock database file even if someone else has it locked, e.g. when their lock was left
+    /// behind by a crash. If they are still editing, one of us loses changes.
+    pub fn take_over(db_path: &Path) -> Result<LockFile, Error> {
+        let path = lock_path(db_path);
+        match fs::remove_file(&path) {
+            Ok(()) => warn!("LockFile::take_over: Removed lock \"{}\"", path.display()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::from(e)),
+        }
+        LockFile::acquire(db_path)
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove lock file \"{}\": {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("mpkdb-test-file-{}.kdb", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_write_atomic_rotates_backups() {
+        let path = temp_path();
+        for i in 0..4 {
+            write_atomic(&path, format!("v{}", i).as_bytes(), 2).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v3");
+        assert_eq!(fs::read_to_string(backup_path(&path, 1)).unwrap(), "v2");
+        assert_eq!(fs::read_to_string(backup_path(&path, 2)).unwrap(), "v1");
+        assert!(!backup_path(&path, 3).exists());
+        for p in &[path.clone(), backup_path(&path, 1), backup_path(&path, 2)] {
+            fs::remove_file(p).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_write_atomic_stale_temp_file() {
+        let path = temp_path();
+        let tmp_path = with_suffix(&path, &format!(".{}.tmp", std::process::id()));
+        fs::write(&tmp_path, b"half-written").unwrap();
+        write_atomic(&path, b"new", 0).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert!(!tmp_path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_disk_state() {
+        let path = temp_path();
+        write_atomic(&path, b"ours", 0).unwrap();
+        let state = DiskState::new(&path, b"ours").unwrap();
+        assert!(!state.changed(&path).unwrap());
+        // Same contents, written again, is not a change.
+        write_atomic(&path, b"ours", 0).unwrap();
+        assert!(!state.changed(&path).unwrap());
+        write_atomic(&path, b"theirs", 0).unwrap();
+        assert!(state.changed(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(state.changed(&path).unwrap());
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let path = temp_path();
+        let lock = LockFile::acquire(&path).unwrap();
+        match LockFile::acquire(&path) {
+            Err(Error::Locked { ref owner, .. }) => assert_eq!(*owner, lock_owner()),
+            r => panic!("Unexpected result {:?}", r),
+        }
+        drop(lock);
+        assert!(!lock_path(&path).exists());
+        LockFile::acquire(&path).unwrap();
+        // Stale lock of a crashed instance.
+        fs::write(lock_path(&path), b"alice").unwrap();
+        assert!(LockFile::acquire(&path).is_err());
+        let lock = LockFile::take_over(&path).unwrap();
+        assert_eq!(fs::read_to_string(lock_path(&path)).unwrap(), lock_owner());
+        drop(lock);
+        assert!(!lock_path(&path).exists());
+    }
+}
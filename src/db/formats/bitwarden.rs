@@ -0,0 +1,246 @@
+//! Bitwarden unencrypted JSON, what Bitwarden writes with Tools / Export vault / .json.
+//!
+//! Folders (or collections, in organization exports) become groups, "Social/Forums" ones
+//! nested too. Custom fields, the TOTP secret, extra URLs, cards and identities are added to
+//! notes. Old passwords (password history) are skipped.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use serde_json;
+use serde_json::Value;
+use uuid::Uuid;
+
+use db::api::Entry;
+use db::formats::{add_to_notes, from_iso8601, Parsed, Record, TOTP_FIELD};
+use errors::Error;
+
+const FORMAT: &str = "Bitwarden JSON";
+
+const LOGIN: u32 = 1;
+const SECURE_NOTE: u32 = 2;
+const CARD: u32 = 3;
+const IDENTITY: u32 = 4;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Export {
+    encrypted: bool,
+    folders: Vec<Folder>,
+    collections: Vec<Folder>,
+    items: Vec<Item>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Item {
+    id: String,
+    folder_id: Option<String>,
+    collection_ids: Option<Vec<String>>,
+    #[serde(rename = "type")]
+    item_type: u32,
+    name: Option<String>,
+    notes: Option<String>,
+    fields: Option<Vec<Field>>,
+    login: Option<Login>,
+    card: Option<Value>,
+    identity: Option<Value>,
+    password_history: Option<Vec<Value>>,
+    creation_date: Option<String>,
+    revision_date: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Field {
+    name: Option<String>,
+    value: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Login {
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    uris: Option<Vec<Uri>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Uri {
+    uri: Option<String>,
+}
+
+/// "expMonth" as "Exp month".
+fn label(key: &str) -> String {
+    let mut label = String::new();
+    for c in key.chars() {
+        if label.is_empty() {
+            label.extend(c.to_uppercase());
+        } else if c.is_uppercase() {
+            label.push(' ');
+            label.extend(c.to_lowercase());
+        } else {
+            label.push(c);
+        }
+    }
+    label
+}
+
+/// Non-empty string fields of a card or identity, as note lines.
+fn object_fields(object: &Value) -> Vec<String> {
+    match *object {
+        Value::Object(ref map) => map.iter()
+            .filter_map(|(key, value)| match *value {
+                Value::String(ref s) if !s.is_empty() => Some(format!("{}: {}", label(key), s)),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn read_item(item: Item, groups: &HashMap<String, String>, parsed: &mut Parsed) -> usize {
+    let name = item.name.unwrap_or_default();
+    if name.trim().is_empty() {
+        parsed.skipped.push(format!("Item {}: no name", item.id));
+        return 0;
+    }
+    let collection = item.collection_ids.as_ref().and_then(|c| c.first());
+    let group = item.folder_id
+        .as_ref()
+        .or(collection)
+        .and_then(|id| groups.get(id))
+        .cloned()
+        .unwrap_or_default();
+    let mut entry = Entry::new(0, &name);
+    if let Ok(id) = Uuid::parse_str(&item.id) {
+        entry.id = id;
+    }
+    entry.notes = item.notes.unwrap_or_default();
+    if let Some(t) = item.creation_date.as_ref().and_then(|t| from_iso8601(t)) {
+        entry.created = t;
+    }
+    if let Some(t) = item.revision_date.as_ref().and_then(|t| from_iso8601(t)) {
+        entry.modified = t;
+    }
+    let mut fields = Vec::new();
+    match item.item_type {
+        LOGIN => if let Some(login) = item.login {
+            entry.username = login.username.unwrap_or_default();
+            entry.password = login.password.unwrap_or_default();
+            let mut uris = login.uris
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|u| u.uri)
+                .filter(|u| !u.is_empty());
+            entry.url = uris.next().unwrap_or_default();
+            for (i, uri) in uris.enumerate() {
+                fields.push(format!("URL {}: {}", i + 2, uri));
+            }
+            if let Some(totp) = login.totp.filter(|t| !t.is_empty()) {
+                fields.push(format!("{}: {}", TOTP_FIELD, totp));
+            }
+        },
+        SECURE_NOTE => {}
+        CARD => fields.extend(item.card.as_ref().map(object_fields).unwrap_or_default()),
+        IDENTITY => fields.extend(item.identity.as_ref().map(object_fields).unwrap_or_default()),
+        other => {
+            parsed
+                .skipped
+                .push(format!("{}: unknown item type {}", name, other));
+            return 0;
+        }
+    }
+    for f in item.fields.unwrap_or_default() {
+        if let (Some(name), Some(value)) = (f.name, f.value) {
+            if !value.is_empty() {
+                fields.push(format!("{}: {}", name, value));
+            }
+        }
+    }
+    add_to_notes(&mut entry.notes, &fields);
+    parsed.records.push(Record {
+        group: group,
+        entry: entry,
+    });
+    item.password_history.map(|h| h.len()).unwrap_or(0)
+}
+
+/// Read Bitwarden JSON export, encrypted exports can't be read.
+pub fn read<R: Read>(reader: R) -> Result<Parsed, Error> {
+    let export: Export = serde_json::from_reader(reader).map_err(|e| Error::format(FORMAT, &e.to_string()))?;
+    if export.encrypted {
+        return Err(Error::format(
+            FORMAT,
+            "Export is encrypted, export the vault as unencrypted JSON",
+        ));
+    }
+    let mut parsed = Parsed::default();
+    let mut groups = HashMap::new();
+    for f in export.folders.into_iter().chain(export.collections) {
+        parsed.groups.push(f.name.clone());
+        groups.insert(f.id, f.name);
+    }
+    let mut history = 0;
+    for item in export.items {
+        history += read_item(item, &groups, &mut parsed);
+    }
+    if history > 0 {
+        parsed
+            .skipped
+            .push(format!("{} old passwords (password history)", history));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let json = r#"{
+          "encrypted": false,
+          "folders": [{"id": "f1", "name": "Social/Forums"}],
+          "items": [
+            {"id": "0b7b1ab6-1c58-4e4a-93d6-3f6b1b1c9e10", "folderId": "f1", "type": 1,
+             "name": "Forum", "notes": null, "favorite": false,
+             "fields": [{"name": "PIN", "value": "1234", "type": 1}],
+             "login": {"uris": [{"match": null, "uri": "https://forum.example"},
+                                {"match": null, "uri": "https://m.forum.example"}],
+                       "username": "bob", "password": "pw", "totp": "JBSWY3DPEHPK3PXP"},
+             "passwordHistory": [{"lastUsedDate": "2020-01-01T00:00:00.000Z", "password": "old"}],
+             "revisionDate": "2020-02-03T04:05:06.789Z", "creationDate": "2020-01-01T00:00:00.000Z"},
+            {"id": "x", "folderId": null, "type": 3, "name": "Visa",
+             "card": {"cardholderName": "Bob", "number": "4111", "code": null}},
+            {"id": "y", "type": 2, "name": "", "notes": "lost"}
+          ]
+        }"#;
+        let parsed = read(json.as_bytes()).unwrap();
+        assert_eq!(parsed.groups, vec![String::from("Social/Forums")]);
+        assert_eq!(parsed.records.len(), 2);
+        let r = &parsed.records[0];
+        assert_eq!(r.group, "Social/Forums");
+        assert_eq!(r.entry.id.hyphenated().to_string(), "0b7b1ab6-1c58-4e4a-93d6-3f6b1b1c9e10");
+        assert_eq!(r.entry.url, "https://forum.example");
+        assert_eq!(
+            r.entry.notes,
+            "URL 2: https://m.forum.example\nTOTP: JBSWY3DPEHPK3PXP\nPIN: 1234"
+        );
+        assert_eq!(from_iso8601("2020-02-03T04:05:06Z").unwrap(), r.entry.modified);
+        let card = &parsed.records[1];
+        assert_eq!(card.group, "");
+        assert_eq!(card.entry.notes, "Cardholder name: Bob\nNumber: 4111");
+        assert_eq!(parsed.skipped.len(), 2, "{:?}", parsed.skipped);
+        assert!(read(r#"{"encrypted": true, "items": []}"#.as_bytes()).is_err());
+    }
+}
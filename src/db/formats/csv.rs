@@ -0,0 +1,265 @@
+//! CSV import and export.
+//!
+//! Columns are given by a mapping like "title,username,password,url,notes,group", or
+//! guessed from the header row, which most password managers and spreadsheets write.
+
+use std::fmt;
+use std::io::{Read, Write};
+
+use csv;
+
+use db::api::Entry;
+use db::formats::{Parsed, Record};
+use errors::Error;
+
+/// Columns KeePass 1.x writes, plus group.
+pub const DEFAULT_COLUMNS: &str = "title,username,password,url,notes,group";
+
+/// How to read and write CSV files.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    /// None to guess columns from header when reading, and to use default columns when writing.
+    pub mapping: Option<Mapping>,
+    /// Whether first row is header.
+    pub header: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            mapping: None,
+            header: true,
+        }
+    }
+}
+
+/// What a CSV column holds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Title,
+    Username,
+    Password,
+    Url,
+    Notes,
+    /// Group path like "Internet/Mail".
+    Group,
+    /// Not imported, empty in exported files.
+    Skip,
+}
+
+impl Column {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Column::Title => "title",
+            Column::Username => "username",
+            Column::Password => "password",
+            Column::Url => "url",
+            Column::Notes => "notes",
+            Column::Group => "group",
+            Column::Skip => "-",
+        }
+    }
+
+    /// Column by name, also recognizes header names used by other apps, like "Login Name".
+    pub fn from_name(name: &str) -> Option<Column> {
+        let name: String = name.chars()
+            .filter(|c| c.is_alphanumeric() || *c == '-')
+            .flat_map(|c| c.to_lowercase())
+            .collect();
+        match name.as_str() {
+            "title" | "name" | "account" => Some(Column::Title),
+            "username" | "user" | "login" | "loginname" | "loginusername" => Some(Column::Username),
+            "password" | "pass" | "loginpassword" => Some(Column::Password),
+            "url" | "website" | "web" | "site" | "uri" | "loginuri" => Some(Column::Url),
+            "notes" | "note" | "comment" | "comments" | "extra" => Some(Column::Notes),
+            "group" | "folder" | "grouping" | "category" | "path" => Some(Column::Group),
+            "-" | "skip" => Some(Column::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// Column of each CSV field, in order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mapping {
+    pub columns: Vec<Column>,
+}
+
+impl Mapping {
+    /// Parse comma separated column names, "-" skips a column.
+    pub fn parse(spec: &str) -> Result<Mapping, Error> {
+        let mut columns = Vec::new();
+        for name in spec.split(',').map(|s| s.trim()) {
+            match Column::from_name(name) {
+                Some(c) => columns.push(c),
+                None => {
+                    return Err(Error::Invalid(format!(
+                        "Unknown CSV column \"{}\", expected title, username, password, url, notes, group or -",
+                        name
+                    )))
+                }
+            }
+        }
+        Ok(Mapping { columns: columns })
+    }
+
+    /// Guess columns from header row, unknown ones are skipped.
+    /// None if there's no title column, then header is probably not a header.
+    pub fn from_header(header: &[&str]) -> Option<Mapping> {
+        let columns: Vec<Column> = header
+            .iter()
+            .map(|h| Column::from_name(h).unwrap_or(Column::Skip))
+            .collect();
+        if columns.contains(&Column::Title) {
+            Some(Mapping { columns: columns })
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Mapping {
+    fn default() -> Mapping {
+        Mapping::parse(DEFAULT_COLUMNS).expect("Valid default columns")
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.columns.iter().map(|c| c.name()).collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+fn csv_error(e: csv::Error) -> Error {
+    Error::format("CSV", &e.to_string())
+}
+
+/// Read CSV file. With header, mapping (if not given) is guessed from it, otherwise
+/// the default mapping is used. Rows without title are skipped.
+pub fn read<R: Read>(reader: R, mapping: Option<&Mapping>, header: bool) -> Result<Parsed, Error> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    let mut rows = reader.records();
+    let mut mapping = mapping.cloned();
+    if header {
+        if let Some(row) = rows.next() {
+            let row = row.map_err(csv_error)?;
+            if mapping.is_none() {
+                let names: Vec<&str> = row.iter().collect();
+                mapping = Some(Mapping::from_header(&names).ok_or_else(|| {
+                    Error::format("CSV", "No title column in header, give columns explicitly")
+                })?);
+            }
+        }
+    }
+    let mapping = mapping.unwrap_or_default();
+    debug!("read: Columns {}", mapping);
+    let mut parsed = Parsed::default();
+    for (i, row) in rows.enumerate() {
+        let row = row.map_err(csv_error)?;
+        let line = row.position().map(|p| p.line()).unwrap_or(i as u64 + 1);
+        if row.iter().all(|f| f.trim().is_empty()) {
+            continue;
+        }
+        let mut group = String::new();
+        let mut e = Entry::new(0, "");
+        for (column, value) in mapping.columns.iter().zip(row.iter()) {
+            // Notes written on Windows.
+            let value = value.replace("\r\n", "\n");
+            match *column {
+                Column::Title => e.title = value,
+                Column::Username => e.username = value,
+                Column::Password => e.password = value,
+                Column::Url => e.url = value,
+                Column::Notes => e.notes = value,
+                Column::Group => group = value,
+                Column::Skip => {}
+            }
+        }
+        if e.title.trim().is_empty() {
+            parsed.skipped.push(format!("Line {}: no title", line));
+            continue;
+        }
+        parsed.records.push(Record { group: group, entry: e });
+    }
+    Ok(parsed)
+}
+
+/// Write records as CSV, quoting fields where needed (multiline notes are kept as they are).
+/// Returns number of records written.
+pub fn write<W: Write>(writer: W, records: &[Record], mapping: &Mapping, header: bool) -> Result<usize, Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+    if header {
+        let names: Vec<&str> = mapping.columns.iter().map(|c| c.name()).collect();
+        writer.write_record(&names).map_err(csv_error)?;
+    }
+    for r in records {
+        let fields: Vec<&str> = mapping
+            .columns
+            .iter()
+            .map(|c| match *c {
+                Column::Title => r.entry.title.as_str(),
+                Column::Username => r.entry.username.as_str(),
+                Column::Password => r.entry.password.as_str(),
+                Column::Url => r.entry.url.as_str(),
+                Column::Notes => r.entry.notes.as_str(),
+                Column::Group => r.group.as_str(),
+                Column::Skip => "",
+            })
+            .collect();
+        writer.write_record(&fields).map_err(csv_error)?;
+    }
+    writer.flush()?;
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use db::api::Entry;
+    use db::formats::Record;
+
+    use super::*;
+
+    #[test]
+    fn test_write_and_read() {
+        let mut e = Entry::new(0, "Forum, \"old\"");
+        e.username = String::from("bob");
+        e.password = String::from("p,w\"");
+        e.notes = String::from("line 1\nline 2");
+        let records = vec![
+            Record {
+                group: String::from("Internet/Forums"),
+                entry: e.clone(),
+            },
+        ];
+        let mut out = Vec::new();
+        write(&mut out, &records, &Mapping::default(), true).unwrap();
+        let parsed = read(&out[..], None, true).unwrap();
+        assert_eq!(parsed.records.len(), 1);
+        let r = &parsed.records[0];
+        assert_eq!(r.group, "Internet/Forums");
+        assert_eq!(r.entry.title, e.title);
+        assert_eq!(r.entry.password, e.password);
+        assert_eq!(r.entry.notes, e.notes);
+    }
+
+    #[test]
+    fn test_read_guesses_columns() {
+        let csv = "\"Account\",\"Login Name\",\"Password\",\"Web Site\",\"Comments\"\n\
+                   \"Gmail\",\"bob\",\"secret\",\"https://mail.google.com\",\"a\r\nb\"\n\
+                   \"\",\"nobody\",\"\",\"\",\"\"\n";
+        let parsed = read(csv.as_bytes(), None, true).unwrap();
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].entry.username, "bob");
+        assert_eq!(parsed.records[0].entry.url, "https://mail.google.com");
+        assert_eq!(parsed.records[0].entry.notes, "a\nb");
+        assert_eq!(parsed.skipped, vec![String::from("Line 4: no title")]);
+        let mapping = Mapping::parse("username,-,title").unwrap();
+        let parsed = read("bob,x,Gmail".as_bytes(), Some(&mapping), false).unwrap();
+        assert_eq!(parsed.records[0].entry.title, "Gmail");
+        assert!(Mapping::parse("title,bogus").is_err());
+    }
+}
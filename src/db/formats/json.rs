@@ -0,0 +1,189 @@
+//! JSON dump of a database, for scripts. Groups and entries are serialized as they are in
+//! `db::api`, except that times are ISO-8601 UTC (null when it never expires), ids are
+//! hyphenated UUIDs and attachment contents base64:
+//!
+//! ```json
+//! {
+//!   "format": "mpkdb",
+//!   "version": 1,
+//!   "groups": [
+//!     {"id": 1, "parent": null, "name": "Internet", "created": "2017-10-22T22:21:00Z",
+//!      "modified": "...", "accessed": "...", "expires": null}
+//!   ],
+//!   "entries": [
+//!     {"id": "0b7b1ab6-1c58-4e4a-93d6-3f6b1b1c9e10", "group_id": 1, "title": "Gmail",
+//!      "url": "", "username": "bob", "password": "secret", "notes": "",
+//!      "created": "...", "modified": "...", "accessed": "...", "expires": null,
+//!      "attachment": {"name": "key.txt", "data": "c2VjcmV0"}}
+//!   ]
+//! }
+//! ```
+//!
+//! Groups are in tree order, parents before their subgroups. Group ids only link entries and
+//! groups within the dump, importing creates groups by path.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use serde_json;
+
+use db::api::{Entry, Group, GroupId, PasswordDatabase, PATH_SEPARATOR};
+use db::formats::{subtree, Parsed, Record};
+use errors::Error;
+
+const FORMAT: &str = "JSON";
+
+/// Value of "format", to tell dumps from other JSON.
+pub const FORMAT_NAME: &str = "mpkdb";
+
+/// Version of dump layout, increased when it changes incompatibly.
+pub const VERSION: u32 = 1;
+
+/// Whole database, or a group and its subgroups.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Dump {
+    /// Always "mpkdb".
+    pub format: String,
+    pub version: u32,
+    pub groups: Vec<Group>,
+    pub entries: Vec<Entry>,
+}
+
+impl Dump {
+    /// Groups of db (all, or given one, which becomes top level, and its subgroups) and their entries.
+    pub fn new(db: &PasswordDatabase, group: Option<GroupId>) -> Dump {
+        let ids = subtree(db, group);
+        let groups: Vec<Group> = ids.iter()
+            .filter_map(|&id| db.get_group(id))
+            .map(|mut g| {
+                if Some(g.id) == group {
+                    g.parent = None;
+                }
+                g
+            })
+            .collect();
+        let entries = db.get_entries()
+            .into_iter()
+            .filter(|e| ids.contains(&e.group_id))
+            .collect();
+        Dump {
+            format: String::from(FORMAT_NAME),
+            version: VERSION,
+            groups: groups,
+            entries: entries,
+        }
+    }
+
+    /// Path of group, empty if it's not in dump.
+    fn group_path(&self, by_id: &HashMap<GroupId, &Group>, id: GroupId) -> String {
+        let mut names = Vec::new();
+        let mut next = Some(id);
+        while let Some(g) = next.and_then(|id| by_id.get(&id)) {
+            names.insert(0, g.name.as_str());
+            // Parents loop in damaged dumps.
+            if names.len() > by_id.len() {
+                break;
+            }
+            next = g.parent;
+        }
+        names.join(&PATH_SEPARATOR.to_string())
+    }
+
+    /// Groups (by path) and entries to import.
+    pub fn to_parsed(&self) -> Parsed {
+        let by_id: HashMap<GroupId, &Group> = self.groups.iter().map(|g| (g.id, g)).collect();
+        let mut parsed = Parsed::default();
+        parsed.groups = self.groups
+            .iter()
+            .map(|g| self.group_path(&by_id, g.id))
+            .collect();
+        for e in &self.entries {
+            let group = self.group_path(&by_id, e.group_id);
+            if group.is_empty() {
+                parsed
+                    .skipped
+                    .push(format!("{}: no group {} in dump", e.title, e.group_id));
+                continue;
+            }
+            parsed.records.push(Record {
+                group: group,
+                entry: e.clone(),
+            });
+        }
+        parsed
+    }
+}
+
+/// Read dump, newer versions can't be read.
+pub fn read<R: Read>(reader: R) -> Result<Dump, Error> {
+    let dump: Dump = serde_json::from_reader(reader).map_err(|e| Error::format(FORMAT, &e.to_string()))?;
+    if dump.format != FORMAT_NAME {
+        return Err(Error::format(FORMAT, &format!("Not an {} dump", FORMAT_NAME)));
+    }
+    if dump.version > VERSION {
+        return Err(Error::Unsupported(format!(
+            "JSON dump version {} is newer than {}, upgrade mpkdb",
+            dump.version, VERSION
+        )));
+    }
+    Ok(dump)
+}
+
+/// Write dump, pretty printed. Returns number of entries written.
+pub fn write<W: Write>(mut writer: W, dump: &Dump) -> Result<usize, Error> {
+    serde_json::to_writer_pretty(&mut writer, dump).map_err(|e| Error::format(FORMAT, &e.to_string()))?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(dump.entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use db::api::{Attachment, NEVER_EXPIRES};
+    use db::formats::from_iso8601;
+
+    use super::*;
+
+    fn group(id: GroupId, parent: Option<GroupId>, name: &str) -> Group {
+        let t = from_iso8601("2017-10-22T22:21:00Z").unwrap();
+        Group {
+            id: id,
+            parent: parent,
+            name: String::from(name),
+            created: t.clone(),
+            modified: t.clone(),
+            accessed: t,
+            expires: String::from(NEVER_EXPIRES),
+        }
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let mut e = Entry::new(2, "Gmail");
+        e.password = String::from("secret \"quoted\"");
+        e.modified = from_iso8601("2017-10-22T22:21:00Z").unwrap();
+        e.expires = from_iso8601("2030-01-01T00:00:00Z").unwrap();
+        e.attachment = Some(Attachment {
+            name: String::from("key.txt"),
+            data: b"secret".to_vec(),
+        });
+        let dump = Dump {
+            format: String::from(FORMAT_NAME),
+            version: VERSION,
+            groups: vec![group(1, None, "Internet"), group(2, Some(1), "Mail")],
+            entries: vec![e.clone()],
+        };
+        let mut out = Vec::new();
+        write(&mut out, &dump).unwrap();
+        let json = String::from_utf8(out.clone()).unwrap();
+        assert!(json.contains("\"modified\": \"2017-10-22T22:21:00Z\""), "{}", json);
+        assert!(json.contains("\"expires\": null"), "{}", json);
+        assert!(json.contains("\"data\": \"c2VjcmV0\""), "{}", json);
+        let again = read(&out[..]).unwrap();
+        assert_eq!(again, dump);
+        let parsed = again.to_parsed();
+        assert_eq!(parsed.groups, vec![String::from("Internet"), String::from("Internet/Mail")]);
+        assert_eq!(parsed.records[0].path(), "Internet/Mail/Gmail");
+        assert!(read("{\"format\": \"mpkdb\", \"version\": 99, \"groups\": [], \"entries\": []}".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! KeePass 1.x XML (pwlist), what KeePass 1.x writes with File / Export To / XML File.
+//!
+//! Each entry names its group, with parent groups in the "tree" attribute, separated by "\".
+//! Times are local, like "2017-10-23T00:21:00", attachments are base64.
+
+use std::io::{Read, Write};
+
+use base64;
+use uuid::Uuid;
+
+use db::api::{split_path, Attachment, Entry, NEVER_EXPIRES, PATH_SEPARATOR};
+use db::formats::xml::{escape, escape_attr, Element};
+use db::formats::{never_expires, xml, Parsed, Record};
+use errors::Error;
+
+const FORMAT: &str = "KeePass 1.x XML";
+
+/// "2017-10-23T00:21:00" as "2017-10-23 00:21:00", None if it's not a time.
+fn parse_time(t: &str) -> Option<String> {
+    let t = t.trim();
+    if t.len() == 19 && t.as_bytes()[10] == b'T' {
+        Some(t.replacen('T', " ", 1))
+    } else {
+        None
+    }
+}
+
+fn read_entry(e: &Element, n: usize, parsed: &mut Parsed) {
+    let group = match e.child("group") {
+        Some(g) => {
+            let tree = g.attr("tree").unwrap_or("");
+            let mut path: Vec<&str> = tree.split('\\').filter(|s| !s.is_empty()).collect();
+            path.push(&g.text);
+            path.join(&PATH_SEPARATOR.to_string())
+        }
+        None => String::new(),
+    };
+    let title = e.child_text("title");
+    if title.trim().is_empty() {
+        parsed.skipped.push(format!("Entry {}: no title", n));
+        return;
+    }
+    let mut entry = Entry::new(0, title);
+    entry.username = String::from(e.child_text("username"));
+    entry.url = String::from(e.child_text("url"));
+    entry.password = String::from(e.child_text("password"));
+    entry.notes = String::from(e.child_text("notes"));
+    if let Ok(id) = Uuid::parse_str(e.child_text("uuid").trim()) {
+        entry.id = id;
+    }
+    if let Some(t) = parse_time(e.child_text("creationtime")) {
+        entry.created = t;
+    }
+    if let Some(t) = parse_time(e.child_text("lastmodtime")) {
+        entry.modified = t;
+    }
+    if let Some(t) = parse_time(e.child_text("lastaccesstime")) {
+        entry.accessed = t;
+    }
+    entry.expires = match e.child("expiretime") {
+        Some(x) if x.attr("expires") != Some("false") => parse_time(&x.text).unwrap_or_default(),
+        _ => String::from(NEVER_EXPIRES),
+    };
+    let name = e.child_text("attachdesc");
+    let data = e.child_text("attachment");
+    if !name.is_empty() || !data.trim().is_empty() {
+        let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+        match base64::decode(&data) {
+            Ok(data) => {
+                entry.attachment = Some(Attachment {
+                    name: String::from(name),
+                    data: data,
+                })
+            }
+            Err(_) => parsed
+                .skipped
+                .push(format!("Entry {}: invalid attachment \"{}\"", n, name)),
+        }
+    }
+    parsed.records.push(Record {
+        group: group,
+        entry: entry,
+    });
+}
+
+/// Read KeePass 1.x XML file.
+pub fn read<R: Read>(reader: R) -> Result<Parsed, Error> {
+    let root = xml::parse(reader, FORMAT)?;
+    if root.name != "pwlist" {
+        return Err(Error::format(FORMAT, &format!("Unexpected root element {}", root.name)));
+    }
+    let mut parsed = Parsed::default();
+    for (i, e) in root.children_named("pwentry").into_iter().enumerate() {
+        read_entry(e, i + 1, &mut parsed);
+    }
+    Ok(parsed)
+}
+
+fn write_time<W: Write>(w: &mut W, name: &str, t: &str) -> Result<(), Error> {
+    if !t.is_empty() {
+        writeln!(w, "\t<{}>{}</{}>", name, escape(&t.replacen(' ', "T", 1)), name)?;
+    }
+    Ok(())
+}
+
+/// Write records as KeePass 1.x XML, returns number of records written.
+pub fn write<W: Write>(mut w: W, records: &[Record]) -> Result<usize, Error> {
+    writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>")?;
+    writeln!(w, "<pwlist>")?;
+    for r in records {
+        let e = &r.entry;
+        let mut path = split_path(&r.group);
+        let group = path.pop().unwrap_or("");
+        writeln!(w, "<pwentry>")?;
+        if path.is_empty() {
+            writeln!(w, "\t<group>{}</group>", escape(group))?;
+        } else {
+            writeln!(w, "\t<group tree=\"{}\">{}</group>", escape_attr(&path.join("\\")), escape(group))?;
+        }
+        for &(name, value) in &[
+            ("title", &e.title),
+            ("username", &e.username),
+            ("url", &e.url),
+            ("password", &e.password),
+            ("notes", &e.notes),
+        ] {
+            writeln!(w, "\t<{}>{}</{}>", name, escape(value), name)?;
+        }
+        writeln!(w, "\t<uuid>{}</uuid>", e.id.simple())?;
+        writeln!(w, "\t<image>0</image>")?;
+        write_time(&mut w, "creationtime", &e.created)?;
+        write_time(&mut w, "lastmodtime", &e.modified)?;
+        write_time(&mut w, "lastaccesstime", &e.accessed)?;
+        if never_expires(&e.expires) {
+            writeln!(w, "\t<expiretime expires=\"false\">2999-12-28T23:59:59</expiretime>")?;
+        } else {
+            writeln!(
+                w,
+                "\t<expiretime expires=\"true\">{}</expiretime>",
+                escape(&e.expires.replacen(' ', "T", 1))
+            )?;
+        }
+        if let Some(ref a) = e.attachment {
+            writeln!(w, "\t<attachdesc>{}</attachdesc>", escape(&a.name))?;
+            writeln!(w, "\t<attachment>{}</attachment>", base64::encode(&a.data))?;
+        }
+        writeln!(w, "</pwentry>")?;
+    }
+    writeln!(w, "</pwlist>")?;
+    w.flush()?;
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use db::api::{Attachment, Entry, NEVER_EXPIRES};
+    use db::formats::Record;
+
+    use super::*;
+
+    #[test]
+    fn test_write_and_read() {
+        let mut gmail = Entry::new(0, "Gmail <personal>");
+        gmail.username = String::from("bob");
+        gmail.password = String::from("p&w\"");
+        gmail.notes = String::from("line 1\nline 2");
+        gmail.expires = String::from(NEVER_EXPIRES);
+        gmail.attachment = Some(Attachment {
+            name: String::from("recovery codes.txt"),
+            data: vec![0, 1, 2, 255],
+        });
+        let mut forum = Entry::new(0, "Forum");
+        forum.expires = String::from("2030-01-31 12:00:00");
+        let records = vec![
+            Record {
+                group: String::from("Internet/Mail/Personal"),
+                entry: gmail,
+            },
+            Record {
+                group: String::from("General"),
+                entry: forum,
+            },
+        ];
+        let mut out = Vec::new();
+        assert_eq!(write(&mut out, &records).unwrap(), 2);
+        let text = String::from_utf8(out.clone()).unwrap();
+        assert!(text.contains("<group tree=\"Internet\\Mail\">Personal</group>"), "{}", text);
+        assert!(text.contains("<expiretime expires=\"false\">"), "{}", text);
+        let parsed = read(&out[..]).unwrap();
+        assert!(parsed.skipped.is_empty(), "{:?}", parsed.skipped);
+        assert_eq!(parsed.records, records);
+    }
+}
@@ -0,0 +1,445 @@
+//! KeePass 2.x XML (KeePassFile), what KeePass 2.x writes with File / Export / KeePass XML (2.x).
+//!
+//! KDB can't hold all of it: custom fields are added to notes, only the first attachment of
+//! an entry is kept, and history (old versions of entries) and the recycle bin are skipped.
+//! Times are UTC, ids are base64.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use base64;
+use flate2::read::GzDecoder;
+use uuid::Uuid;
+
+use db::api::{now, Attachment, Entry, NEVER_EXPIRES, PATH_SEPARATOR};
+use db::formats::xml::{escape, Element};
+use db::formats::{add_to_notes, from_iso8601, never_expires, to_iso8601, xml, Parsed, Record};
+use errors::Error;
+
+const FORMAT: &str = "KeePass 2.x XML";
+
+/// Icon of exported groups (folder), same as in KDB.
+const GROUP_ICON: u32 = 48;
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    base64::decode(&text).ok()
+}
+
+fn decode_uuid(text: &str) -> Option<Uuid> {
+    decode_base64(text).and_then(|b| Uuid::from_bytes(&b).ok())
+}
+
+/// Binary pool of KeePass 2.x XML, by id, contents are base64 and may be gzipped.
+fn read_binaries(meta: Option<&Element>) -> Result<HashMap<String, Vec<u8>>, Error> {
+    let mut binaries = HashMap::new();
+    let pool = match meta.and_then(|m| m.child("Binaries")) {
+        Some(p) => p,
+        None => return Ok(binaries),
+    };
+    for b in pool.children_named("Binary") {
+        let id = b.attr("ID").unwrap_or("");
+        let mut data = decode_base64(&b.text)
+            .ok_or_else(|| Error::format(FORMAT, &format!("Invalid binary {}", id)))?;
+        if b.attr("Compressed") == Some("True") {
+            let mut unpacked = Vec::new();
+            GzDecoder::new(&data[..])
+                .read_to_end(&mut unpacked)
+                .map_err(|e| Error::format(FORMAT, &format!("Invalid compressed binary {}: {}", id, e)))?;
+            data = unpacked;
+        }
+        binaries.insert(String::from(id), data);
+    }
+    Ok(binaries)
+}
+
+struct Reader {
+    binaries: HashMap<String, Vec<u8>>,
+    recycle_bin: Option<Uuid>,
+    history: usize,
+    parsed: Parsed,
+}
+
+impl Reader {
+    fn read_group(&mut self, group: &Element, path: &str) {
+        for e in group.children_named("Entry") {
+            self.read_entry(e, path);
+        }
+        for g in group.children_named("Group") {
+            if self.recycle_bin.is_some() && decode_uuid(g.child_text("UUID")) == self.recycle_bin {
+                let count = count_entries(g);
+                if count > 0 {
+                    self.parsed
+                        .skipped
+                        .push(format!("{} deleted entries in recycle bin", count));
+                }
+                continue;
+            }
+            let name = g.child_text("Name");
+            let sub_path = if path.is_empty() {
+                String::from(name)
+            } else {
+                format!("{}{}{}", path, PATH_SEPARATOR, name)
+            };
+            self.parsed.groups.push(sub_path.clone());
+            self.read_group(g, &sub_path);
+        }
+    }
+
+    fn read_entry(&mut self, e: &Element, path: &str) {
+        let mut entry = Entry::new(0, "");
+        if let Some(id) = decode_uuid(e.child_text("UUID")) {
+            entry.id = id;
+        }
+        let mut custom = Vec::new();
+        for s in e.children_named("String") {
+            let value = String::from(s.child_text("Value"));
+            match s.child_text("Key") {
+                "Title" => entry.title = value,
+                "UserName" => entry.username = value,
+                "Password" => entry.password = value,
+                "URL" => entry.url = value,
+                "Notes" => entry.notes = value,
+                key if !value.is_empty() => custom.push(format!("{}: {}", key, value)),
+                _ => {}
+            }
+        }
+        add_to_notes(&mut entry.notes, &custom);
+        if entry.title.trim().is_empty() {
+            self.parsed
+                .skipped
+                .push(format!("Entry without title in \"{}\"", path));
+            return;
+        }
+        let entry_path = if path.is_empty() {
+            entry.title.clone()
+        } else {
+            format!("{}{}{}", path, PATH_SEPARATOR, entry.title)
+        };
+        if let Some(times) = e.child("Times") {
+            if let Some(t) = from_iso8601(times.child_text("CreationTime")) {
+                entry.created = t;
+            }
+            if let Some(t) = from_iso8601(times.child_text("LastModificationTime")) {
+                entry.modified = t;
+            }
+            if let Some(t) = from_iso8601(times.child_text("LastAccessTime")) {
+                entry.accessed = t;
+            }
+            entry.expires = if times.child_text("Expires") == "True" {
+                from_iso8601(times.child_text("ExpiryTime")).unwrap_or_default()
+            } else {
+                String::from(NEVER_EXPIRES)
+            };
+        }
+        for b in e.children_named("Binary") {
+            let name = b.child_text("Key");
+            let data = b.child("Value").and_then(|v| match v.attr("Ref") {
+                Some(r) => self.binaries.get(r).cloned(),
+                None => decode_base64(&v.text),
+            });
+            match data {
+                Some(data) if entry.attachment.is_none() => {
+                    entry.attachment = Some(Attachment {
+                        name: String::from(name),
+                        data: data,
+                    })
+                }
+                Some(_) => self.parsed.skipped.push(format!(
+                    "{}: attachment \"{}\", KDB entries can have only one",
+                    entry_path, name
+                )),
+                None => self.parsed
+                    .skipped
+                    .push(format!("{}: invalid attachment \"{}\"", entry_path, name)),
+            }
+        }
+        self.history += e.child("History")
+            .map(|h| h.children_named("Entry").len())
+            .unwrap_or(0);
+        self.parsed.records.push(Record {
+            group: String::from(path),
+            entry: entry,
+        });
+    }
+}
+
+fn count_entries(group: &Element) -> usize {
+    group.children_named("Entry").len() + group.children_named("Group").into_iter().map(count_entries).sum::<usize>()
+}
+
+/// Read KeePass 2.x XML file. Groups under the root group become top level groups.
+pub fn read<R: Read>(reader: R) -> Result<Parsed, Error> {
+    let root = xml::parse(reader, FORMAT)?;
+    if root.name != "KeePassFile" {
+        return Err(Error::format(FORMAT, &format!("Unexpected root element {}", root.name)));
+    }
+    let meta = root.child("Meta");
+    let recycle_bin = match meta {
+        Some(m) if m.child_text("RecycleBinEnabled") != "False" => decode_uuid(m.child_text("RecycleBinUUID")),
+        _ => None,
+    };
+    let mut reader = Reader {
+        binaries: read_binaries(meta)?,
+        recycle_bin: recycle_bin,
+        history: 0,
+        parsed: Parsed::default(),
+    };
+    let top = root.child("Root")
+        .and_then(|r| r.child("Group"))
+        .ok_or_else(|| Error::format(FORMAT, "No root group"))?;
+    reader.read_group(top, "");
+    if reader.history > 0 {
+        reader.parsed.skipped.push(format!(
+            "{} old versions of entries (history)",
+            reader.history
+        ));
+    }
+    Ok(reader.parsed)
+}
+
+/// Group tree built from paths, for writing nested groups.
+#[derive(Default)]
+struct Node<'a> {
+    name: String,
+    groups: Vec<Node<'a>>,
+    entries: Vec<&'a Entry>,
+}
+
+impl<'a> Node<'a> {
+    fn find_or_add(&mut self, path: &str) -> &mut Node<'a> {
+        let mut node = self;
+        for name in path.split(PATH_SEPARATOR).filter(|s| !s.is_empty()) {
+            let index = match node.groups.iter().position(|g| g.name == name) {
+                Some(i) => i,
+                None => {
+                    node.groups.push(Node {
+                        name: String::from(name),
+                        ..Node::default()
+                    });
+                    node.groups.len() - 1
+                }
+            };
+            node = &mut node.groups[index];
+        }
+        node
+    }
+}
+
+fn encode_uuid(id: &Uuid) -> String {
+    base64::encode(id.as_bytes())
+}
+
+fn write_string<W: Write>(w: &mut W, indent: &str, key: &str, value: &str, protect: bool) -> Result<(), Error> {
+    writeln!(w, "{}<String>", indent)?;
+    writeln!(w, "{}\t<Key>{}</Key>", indent, escape(key))?;
+    if protect {
+        writeln!(w, "{}\t<Value ProtectInMemory=\"True\">{}</Value>", indent, escape(value))?;
+    } else {
+        writeln!(w, "{}\t<Value>{}</Value>", indent, escape(value))?;
+    }
+    writeln!(w, "{}</String>", indent)?;
+    Ok(())
+}
+
+struct Writer<'a, W: Write> {
+    w: W,
+    /// Entries with attachments, binary id is the index.
+    attachments: Vec<&'a Entry>,
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    fn time(t: &str) -> String {
+        to_iso8601(t).or_else(|| to_iso8601(&now())).unwrap_or_default()
+    }
+
+    fn write_entry(&mut self, e: &Entry, indent: &str) -> Result<(), Error> {
+        let w = &mut self.w;
+        writeln!(w, "{}<Entry>", indent)?;
+        writeln!(w, "{}\t<UUID>{}</UUID>", indent, encode_uuid(&e.id))?;
+        writeln!(w, "{}\t<IconID>0</IconID>", indent)?;
+        writeln!(w, "{}\t<Times>", indent)?;
+        writeln!(w, "{}\t\t<CreationTime>{}</CreationTime>", indent, Self::time(&e.created))?;
+        writeln!(w, "{}\t\t<LastModificationTime>{}</LastModificationTime>", indent, Self::time(&e.modified))?;
+        writeln!(w, "{}\t\t<LastAccessTime>{}</LastAccessTime>", indent, Self::time(&e.accessed))?;
+        if never_expires(&e.expires) {
+            writeln!(w, "{}\t\t<Expires>False</Expires>", indent)?;
+        } else {
+            writeln!(w, "{}\t\t<ExpiryTime>{}</ExpiryTime>", indent, Self::time(&e.expires))?;
+            writeln!(w, "{}\t\t<Expires>True</Expires>", indent)?;
+        }
+        writeln!(w, "{}\t</Times>", indent)?;
+        let field_indent = format!("{}\t", indent);
+        write_string(w, &field_indent, "Title", &e.title, false)?;
+        write_string(w, &field_indent, "UserName", &e.username, false)?;
+        write_string(w, &field_indent, "Password", &e.password, true)?;
+        write_string(w, &field_indent, "URL", &e.url, false)?;
+        write_string(w, &field_indent, "Notes", &e.notes, false)?;
+        if let Some(ref a) = e.attachment {
+            let id = self.attachments.iter().position(|x| x.id == e.id).unwrap_or(0);
+            writeln!(w, "{}\t<Binary>", indent)?;
+            writeln!(w, "{}\t\t<Key>{}</Key>", indent, escape(&a.name))?;
+            writeln!(w, "{}\t\t<Value Ref=\"{}\" />", indent, id)?;
+            writeln!(w, "{}\t</Binary>", indent)?;
+        }
+        writeln!(w, "{}</Entry>", indent)?;
+        Ok(())
+    }
+
+    fn write_group(&mut self, node: &Node, indent: &str) -> Result<(), Error> {
+        writeln!(self.w, "{}<Group>", indent)?;
+        writeln!(self.w, "{}\t<UUID>{}</UUID>", indent, encode_uuid(&Uuid::new_v4()))?;
+        writeln!(self.w, "{}\t<Name>{}</Name>", indent, escape(&node.name))?;
+        writeln!(self.w, "{}\t<IconID>{}</IconID>", indent, GROUP_ICON)?;
+        let inner = format!("{}\t", indent);
+        for e in &node.entries {
+            self.write_entry(e, &inner)?;
+        }
+        for g in &node.groups {
+            self.write_group(g, &inner)?;
+        }
+        writeln!(self.w, "{}</Group>", indent)?;
+        Ok(())
+    }
+}
+
+/// Write groups (given by path, so empty ones are kept too) and records as KeePass 2.x XML.
+/// Returns number of records written.
+pub fn write<W: Write>(w: W, groups: &[String], records: &[Record]) -> Result<usize, Error> {
+    let mut root = Node {
+        name: String::from("Root"),
+        ..Node::default()
+    };
+    for g in groups {
+        root.find_or_add(g);
+    }
+    for r in records {
+        root.find_or_add(&r.group).entries.push(&r.entry);
+    }
+    let mut writer = Writer {
+        w: w,
+        attachments: records
+            .iter()
+            .map(|r| &r.entry)
+            .filter(|e| e.attachment.is_some())
+            .collect(),
+    };
+    {
+        let w = &mut writer.w;
+        writeln!(w, "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>")?;
+        writeln!(w, "<KeePassFile>")?;
+        writeln!(w, "\t<Meta>")?;
+        writeln!(w, "\t\t<Generator>mpkdb</Generator>")?;
+        writeln!(w, "\t\t<RecycleBinEnabled>False</RecycleBinEnabled>")?;
+        writeln!(w, "\t\t<Binaries>")?;
+        for (id, e) in writer.attachments.iter().enumerate() {
+            let data = e.attachment.as_ref().map(|a| base64::encode(&a.data)).unwrap_or_default();
+            writeln!(w, "\t\t\t<Binary ID=\"{}\">{}</Binary>", id, data)?;
+        }
+        writeln!(w, "\t\t</Binaries>")?;
+        writeln!(w, "\t</Meta>")?;
+        writeln!(w, "\t<Root>")?;
+    }
+    writer.write_group(&root, "\t\t")?;
+    writeln!(writer.w, "\t</Root>")?;
+    writeln!(writer.w, "</KeePassFile>")?;
+    writer.w.flush()?;
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::*;
+
+    #[test]
+    fn test_read_keepass_export() {
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(b"key file").unwrap();
+        let binary = base64::encode(&gz.finish().unwrap());
+        let id = Uuid::new_v4();
+        let bin = Uuid::new_v4();
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+  <Meta>
+    <RecycleBinEnabled>True</RecycleBinEnabled>
+    <RecycleBinUUID>{bin}</RecycleBinUUID>
+    <Binaries><Binary ID="0" Compressed="True">{binary}</Binary></Binaries>
+  </Meta>
+  <Root>
+    <Group>
+      <Name>Database</Name>
+      <Group>
+        <Name>Internet</Name>
+        <Entry>
+          <UUID>{id}</UUID>
+          <Times>
+            <LastModificationTime>2018-05-01T10:00:00Z</LastModificationTime>
+            <Expires>False</Expires>
+          </Times>
+          <String><Key>Title</Key><Value>Forum</Value></String>
+          <String><Key>Password</Key><Value ProtectInMemory="True">a&lt;b</Value></String>
+          <String><Key>PIN</Key><Value>1234</Value></String>
+          <Binary><Key>key.txt</Key><Value Ref="0" /></Binary>
+          <History><Entry><String><Key>Title</Key><Value>Old</Value></String></Entry></History>
+        </Entry>
+        <Group><Name>Empty</Name></Group>
+      </Group>
+      <Group>
+        <UUID>{bin}</UUID>
+        <Name>Recycle Bin</Name>
+        <Entry><String><Key>Title</Key><Value>Deleted</Value></String></Entry>
+      </Group>
+    </Group>
+  </Root>
+</KeePassFile>"#,
+            bin = base64::encode(bin.as_bytes()),
+            binary = binary,
+            id = base64::encode(id.as_bytes())
+        );
+        let parsed = read(xml.as_bytes()).unwrap();
+        assert_eq!(parsed.groups, vec!["Internet", "Internet/Empty"]);
+        assert_eq!(parsed.records.len(), 1);
+        let e = &parsed.records[0].entry;
+        assert_eq!(e.id, id);
+        assert_eq!(e.password, "a<b");
+        assert_eq!(e.notes, "PIN: 1234");
+        assert_eq!(e.expires, NEVER_EXPIRES);
+        assert_eq!(e.modified, from_iso8601("2018-05-01T10:00:00Z").unwrap());
+        assert_eq!(e.attachment.as_ref().unwrap().data, b"key file");
+        assert_eq!(parsed.skipped.len(), 2, "{:?}", parsed.skipped);
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let mut e = Entry::new(0, "Mail & more");
+        e.notes = String::from("line 1\n  line 2 ");
+        e.password = String::from("\"quoted\" <pw>");
+        e.expires = String::from("2030-01-01 12:00:00");
+        e.attachment = Some(Attachment {
+            name: String::from("a.bin"),
+            data: vec![0, 1, 2, 255],
+        });
+        let records = vec![Record {
+            group: String::from("Internet/Mail"),
+            entry: e.clone(),
+        }];
+        let groups = vec![String::from("Internet"), String::from("Internet/Mail"), String::from("Empty")];
+        let mut out = Vec::new();
+        write(&mut out, &groups, &records).unwrap();
+        let parsed = read(&out[..]).unwrap();
+        assert_eq!(parsed.groups, vec!["Internet", "Internet/Mail", "Empty"]);
+        assert_eq!(parsed.records, records);
+        assert!(parsed.skipped.is_empty());
+        // KeePass 1.x XML has no empty groups, but the rest is the same.
+        let mut out = Vec::new();
+        ::db::formats::keepass1::write(&mut out, &records).unwrap();
+        let parsed = ::db::formats::keepass1::read(&out[..]).unwrap();
+        assert_eq!(parsed.records, records);
+    }
+}
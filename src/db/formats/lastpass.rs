@@ -0,0 +1,93 @@
+//! LastPass CSV, what LastPass writes with Advanced Options / Export.
+//!
+//! Columns are "url,username,password,totp,extra,name,grouping,fav". Folders like
+//! "Work\Mail" become groups, "extra" becomes notes and the TOTP secret is added to them.
+//! Secure notes have "http://sn" as URL, which is dropped.
+
+use std::io::Read;
+
+use csv;
+
+use db::api::{Entry, PATH_SEPARATOR};
+use db::formats::{add_to_notes, Parsed, Record, TOTP_FIELD};
+use errors::Error;
+
+const FORMAT: &str = "LastPass CSV";
+
+/// URL of secure notes.
+const SECURE_NOTE_URL: &str = "http://sn";
+
+fn csv_error(e: csv::Error) -> Error {
+    Error::format(FORMAT, &e.to_string())
+}
+
+/// Read LastPass CSV export.
+pub fn read<R: Read>(reader: R) -> Result<Parsed, Error> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+    let header = reader.headers().map_err(csv_error)?.clone();
+    let column = |name: &str| header.iter().position(|h| h.trim() == name);
+    let title_column = column("name").ok_or_else(|| Error::format(FORMAT, "No name column in header"))?;
+    let columns = [
+        column("url"),
+        column("username"),
+        column("password"),
+        column("totp"),
+        column("extra"),
+        column("grouping"),
+    ];
+    let mut parsed = Parsed::default();
+    for (i, row) in reader.records().enumerate() {
+        let row = row.map_err(csv_error)?;
+        let line = row.position().map(|p| p.line()).unwrap_or(i as u64 + 2);
+        let field = |c: Option<usize>| {
+            c.and_then(|c| row.get(c))
+                .unwrap_or("")
+                .replace("\r\n", "\n")
+        };
+        let title = field(Some(title_column));
+        if title.trim().is_empty() {
+            if row.iter().any(|f| !f.trim().is_empty()) {
+                parsed.skipped.push(format!("Line {}: no name", line));
+            }
+            continue;
+        }
+        let mut e = Entry::new(0, &title);
+        e.url = field(columns[0]);
+        if e.url == SECURE_NOTE_URL {
+            e.url.clear();
+        }
+        e.username = field(columns[1]);
+        e.password = field(columns[2]);
+        e.notes = field(columns[4]);
+        let totp = field(columns[3]);
+        if !totp.is_empty() {
+            add_to_notes(&mut e.notes, &[format!("{}: {}", TOTP_FIELD, totp)]);
+        }
+        let group = match field(columns[5]).as_str() {
+            "(none)" => String::new(),
+            g => g.replace('\\', &PATH_SEPARATOR.to_string()),
+        };
+        parsed.records.push(Record { group: group, entry: e });
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let csv = "url,username,password,totp,extra,name,grouping,fav\n\
+                   https://mail.example,bob,pw,JBSWY3DPEHPK3PXP,,Mail,Work\\Mail,0\n\
+                   http://sn,,,,\"NoteType:Server\nHostname:x\",Server,,0\n\
+                   https://x,alice,,,,,,0\n";
+        let parsed = read(csv.as_bytes()).unwrap();
+        assert_eq!(parsed.records.len(), 2);
+        assert_eq!(parsed.records[0].path(), "Work/Mail/Mail");
+        assert_eq!(parsed.records[0].entry.notes, "TOTP: JBSWY3DPEHPK3PXP");
+        assert_eq!(parsed.records[1].entry.url, "");
+        assert_eq!(parsed.records[1].entry.notes, "NoteType:Server\nHostname:x");
+        assert_eq!(parsed.skipped, vec![String::from("Line 5: no name")]);
+    }
+}
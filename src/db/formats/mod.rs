@@ -0,0 +1,401 @@
+//! Plaintext interchange formats, for moving data from and to other password managers.
+//!
+//! Importers parse a file into `Record`s, which `import` adds to a database, so all formats
+//! share group handling and the summary of what was (or wasn't) imported. Exporters write
+//! `Record`s taken from a database with `records`.
+//!
+//! Files written here are NOT encrypted, frontends should make sure user knows that.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use time;
+
+use db::api::{find_or_create_group, Entry, GroupId, PasswordDatabase, NEVER_EXPIRES, PATH_SEPARATOR};
+use errors::Error;
+
+pub mod bitwarden;
+pub mod csv;
+pub mod json;
+pub mod keepass1;
+pub mod keepass2;
+pub mod lastpass;
+pub mod onepassword;
+pub mod pass;
+pub mod xml;
+mod zip;
+
+/// Top level group for imported records without group.
+pub const IMPORT_GROUP: &str = "Imported";
+
+/// Name of notes field holding TOTP secret, otpauth:// URI or base32 key.
+pub const TOTP_FIELD: &str = "TOTP";
+
+/// File formats for import and export.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// KeePass 1.x database, the only encrypted one.
+    Kdb,
+    Csv,
+    /// KeePass 1.x XML export (pwlist).
+    KeePass1Xml,
+    /// KeePass 2.x XML export (KeePassFile).
+    KeePass2Xml,
+    /// Bitwarden unencrypted JSON export, import only.
+    Bitwarden,
+    /// 1Password 8 export (zip with export.data), import only.
+    OnePux,
+    /// 1Password 7 interchange format, import only.
+    OnePif,
+    /// LastPass CSV export, import only.
+    LastPass,
+    /// pass (password-store) directory, see `pass`.
+    Pass,
+    /// JSON dump of database, see `json`.
+    Json,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::Kdb => "kdb",
+            Format::Csv => "csv",
+            Format::KeePass1Xml => "keepass1-xml",
+            Format::KeePass2Xml => "keepass2-xml",
+            Format::Bitwarden => "bitwarden",
+            Format::OnePux => "1pux",
+            Format::OnePif => "1pif",
+            Format::LastPass => "lastpass",
+            Format::Pass => "pass",
+            Format::Json => "json",
+        }
+    }
+
+    /// Format by name, as given on command line.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "kdb" => Some(Format::Kdb),
+            "csv" => Some(Format::Csv),
+            "keepass1-xml" => Some(Format::KeePass1Xml),
+            "keepass2-xml" => Some(Format::KeePass2Xml),
+            "bitwarden" => Some(Format::Bitwarden),
+            "1pux" => Some(Format::OnePux),
+            "1pif" => Some(Format::OnePif),
+            "lastpass" => Some(Format::LastPass),
+            "pass" => Some(Format::Pass),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    /// Guess format from file name extension, XML is taken as KeePass 2.x XML.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("xml") => Some(Format::KeePass2Xml),
+            Some(e) => Format::from_name(e),
+            None => None,
+        }
+    }
+
+    /// Guess format from the start of file, for formats that can be recognized.
+    pub fn sniff(start: &[u8]) -> Option<Format> {
+        if start.starts_with(zip::SIGNATURE) {
+            return Some(Format::OnePux);
+        }
+        let start = String::from_utf8_lossy(start);
+        if start.contains("<pwlist") {
+            Some(Format::KeePass1Xml)
+        } else if start.contains("<KeePassFile") {
+            Some(Format::KeePass2Xml)
+        } else if start.starts_with("url,username,password,") {
+            Some(Format::LastPass)
+        } else if start.trim_start().starts_with('{') && start.contains(&format!("\"{}\"", json::FORMAT_NAME)) {
+            Some(Format::Json)
+        } else if start.trim_start().starts_with('{') && start.contains("\"typeName\"") {
+            Some(Format::OnePif)
+        } else if start.trim_start().starts_with('{') && start.contains("\"encrypted\"") {
+            Some(Format::Bitwarden)
+        } else {
+            None
+        }
+    }
+
+    /// Whether passwords end up unencrypted in exported file.
+    pub fn is_plaintext(&self) -> bool {
+        *self != Format::Kdb && *self != Format::Pass
+    }
+
+    /// Whether files can only be imported, not written.
+    pub fn is_import_only(&self) -> bool {
+        match *self {
+            Format::Bitwarden | Format::OnePux | Format::OnePif | Format::LastPass => true,
+            _ => false,
+        }
+    }
+}
+
+/// Entry read from a file, or to be written to one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// Group path like "Internet/Mail", empty if file has no groups.
+    pub group: String,
+    /// Entry, group_id is not used.
+    pub entry: Entry,
+}
+
+impl Record {
+    /// Path like "Internet/Mail/Gmail", for messages.
+    pub fn path(&self) -> String {
+        if self.group.is_empty() {
+            self.entry.title.clone()
+        } else {
+            format!("{}{}{}", self.group, PATH_SEPARATOR, self.entry.title)
+        }
+    }
+}
+
+/// Records parsed from a file, with descriptions of items that had to be skipped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Parsed {
+    /// Paths of groups, for formats which keep empty groups too.
+    pub groups: Vec<String>,
+    pub records: Vec<Record>,
+    /// Like "Row 5: no title".
+    pub skipped: Vec<String>,
+}
+
+/// What import did (or would do, for dry run).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub entries: usize,
+    /// Groups created.
+    pub groups: usize,
+    pub skipped: Vec<String>,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} entries, {} new groups, {} skipped",
+            self.entries,
+            self.groups,
+            self.skipped.len()
+        )?;
+        for s in &self.skipped {
+            write!(f, "\n  skipped: {}", s)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add parsed records to db, with their groups under `into` (or at top level).
+/// Records without group go to `into`, or to "Imported" group at top level.
+/// Records with id already in db are skipped, e.g. when importing own export again.
+pub fn import(db: &mut PasswordDatabase, parsed: &Parsed, into: Option<GroupId>) -> Result<ImportSummary, Error> {
+    let groups_before = db.get_groups().len();
+    let base = into.map(|id| db.get_group_path(id).join(&PATH_SEPARATOR.to_string()));
+    let mut summary = ImportSummary::default();
+    summary.skipped = parsed.skipped.clone();
+    for g in parsed.groups.iter().filter(|g| !g.is_empty()) {
+        match base {
+            Some(ref base) => find_or_create_group(db, &format!("{}{}{}", base, PATH_SEPARATOR, g))?,
+            None => find_or_create_group(db, g)?,
+        };
+    }
+    for r in &parsed.records {
+        if db.get_entry(&r.entry.id).is_some() {
+            summary.skipped.push(format!("{}: already present", r.path()));
+            continue;
+        }
+        let group_id = match (&base, r.group.is_empty()) {
+            (&Some(_), true) => into.unwrap(),
+            (&Some(ref base), false) => find_or_create_group(db, &format!("{}{}{}", base, PATH_SEPARATOR, r.group))?,
+            (&None, true) => find_or_create_group(db, IMPORT_GROUP)?,
+            (&None, false) => find_or_create_group(db, &r.group)?,
+        };
+        let mut e = r.entry.clone();
+        e.group_id = group_id;
+        db.add_entry(&e)?;
+        summary.entries += 1;
+    }
+    summary.groups = db.get_groups().len() - groups_before;
+    debug!("import: {}", summary);
+    Ok(summary)
+}
+
+/// Groups of db, all or given one and its subgroups, in tree order.
+fn subtree(db: &PasswordDatabase, group: Option<GroupId>) -> Vec<GroupId> {
+    let mut groups: Vec<GroupId> = Vec::new();
+    for g in db.get_groups() {
+        let in_subtree = match group {
+            None => true,
+            Some(root) => g.id == root || g.parent.map_or(false, |p| groups.contains(&p)),
+        };
+        if in_subtree {
+            groups.push(g.id);
+        }
+    }
+    groups
+}
+
+/// Paths of groups to export (all, or group and its subgroups), in tree order.
+pub fn group_paths(db: &PasswordDatabase, group: Option<GroupId>) -> Vec<String> {
+    subtree(db, group)
+        .into_iter()
+        .map(|id| db.get_group_path(id).join(&PATH_SEPARATOR.to_string()))
+        .collect()
+}
+
+/// Entries of db (all, or of group and its subgroups) as records to export, in tree order.
+pub fn records(db: &PasswordDatabase, group: Option<GroupId>) -> Vec<Record> {
+    let entries = db.get_entries();
+    let mut records = Vec::new();
+    for id in subtree(db, group) {
+        let path = db.get_group_path(id).join(&PATH_SEPARATOR.to_string());
+        for e in entries.iter().filter(|e| e.group_id == id) {
+            records.push(Record {
+                group: path.clone(),
+                entry: e.clone(),
+            });
+        }
+    }
+    records
+}
+
+/// Read file in given plaintext format, CSV options are used for CSV only.
+pub fn read<R: Read>(format: Format, reader: R, csv: &csv::Options) -> Result<Parsed, Error> {
+    match format {
+        Format::Kdb => Err(Error::Unsupported(String::from(
+            "KDB files are not plaintext, open them instead",
+        ))),
+        Format::Csv => csv::read(reader, csv.mapping.as_ref(), csv.header),
+        Format::KeePass1Xml => keepass1::read(reader),
+        Format::KeePass2Xml => keepass2::read(reader),
+        Format::Bitwarden => bitwarden::read(reader),
+        Format::OnePux => onepassword::read_1pux(reader),
+        Format::OnePif => onepassword::read_1pif(reader),
+        Format::LastPass => lastpass::read(reader),
+        Format::Pass => Err(Error::Unsupported(String::from(
+            "pass stores are directories, use formats::pass::read",
+        ))),
+        Format::Json => json::read(reader).map(|d| d.to_parsed()),
+    }
+}
+
+/// Write groups (by path) and records in given plaintext format, returns number of records written.
+/// Formats which can't keep empty groups ignore groups.
+pub fn write<W: Write>(
+    format: Format,
+    writer: W,
+    groups: &[String],
+    records: &[Record],
+    csv: &csv::Options,
+) -> Result<usize, Error> {
+    match format {
+        Format::Kdb => Err(Error::Unsupported(String::from(
+            "KDB files are not plaintext, use db::api::copy_into",
+        ))),
+        Format::Csv => csv::write(writer, records, &csv.mapping.clone().unwrap_or_default(), csv.header),
+        Format::KeePass1Xml => keepass1::write(writer, records),
+        Format::KeePass2Xml => keepass2::write(writer, groups, records),
+        Format::Pass => Err(Error::Unsupported(String::from(
+            "pass stores are directories, use formats::pass::write",
+        ))),
+        Format::Json => Err(Error::Unsupported(String::from(
+            "JSON dump needs whole groups, use formats::json::Dump",
+        ))),
+        f => Err(Error::Unsupported(format!("Can't export to {}, only import", f.name()))),
+    }
+}
+
+/// Add fields KDB has no place for to notes, as "Name: value" lines after a blank line.
+pub fn add_to_notes(notes: &mut String, fields: &[String]) {
+    if fields.is_empty() {
+        return;
+    }
+    if !notes.is_empty() {
+        notes.push_str("\n\n");
+    }
+    notes.push_str(&fields.join("\n"));
+}
+
+/// Format of times in `Entry` and `Group`, local time.
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Whether expiry time means "never".
+pub fn never_expires(t: &str) -> bool {
+    t.is_empty() || t == NEVER_EXPIRES
+}
+
+/// Local time like "2017-10-23 00:21:00" as ISO-8601 UTC time, like "2017-10-22T22:21:00Z".
+pub fn to_iso8601(t: &str) -> Option<String> {
+    let tm = time::strptime(t, TIME_FORMAT).ok()?;
+    // Parsed as UTC, shift by local offset at that time.
+    let naive = tm.to_timespec();
+    let offset = time::at(naive).tm_utcoff as i64;
+    let utc = time::at_utc(time::Timespec::new(naive.sec - offset, 0));
+    time::strftime("%Y-%m-%dT%H:%M:%SZ", &utc).ok()
+}
+
+/// Whole days from one local time to another, negative if `to` is earlier.
+pub fn days_between(from: &str, to: &str) -> Option<i64> {
+    let from = time::strptime(from, TIME_FORMAT).ok()?.to_timespec();
+    let to = time::strptime(to, TIME_FORMAT).ok()?.to_timespec();
+    Some((to.sec - from.sec) / (24 * 60 * 60))
+}
+
+/// Seconds since 1970 as local time.
+pub fn from_unix(seconds: i64) -> Option<String> {
+    time::strftime(TIME_FORMAT, &time::at(time::Timespec::new(seconds, 0))).ok()
+}
+
+/// ISO-8601 time, like "2017-10-22T22:21:00Z" or "2017-10-23T00:21:00+02:00", as local time.
+/// Times without zone are taken as local already, fractions of second are dropped.
+pub fn from_iso8601(t: &str) -> Option<String> {
+    let t = t.trim();
+    if t.len() < 19 || !t.is_char_boundary(19) {
+        return None;
+    }
+    let (datetime, mut zone) = t.split_at(19);
+    let tm = time::strptime(datetime, "%Y-%m-%dT%H:%M:%S").ok()?;
+    if zone.starts_with('.') {
+        zone = zone.trim_start_matches(|c: char| c == '.' || c.is_digit(10));
+    }
+    let offset: i64 = match zone {
+        "" => return time::strftime(TIME_FORMAT, &tm).ok(),
+        "Z" => 0,
+        _ => {
+            let sign = match zone.chars().next() {
+                Some('+') => 1,
+                Some('-') => -1,
+                _ => return None,
+            };
+            let hhmm = zone[1..].replace(':', "");
+            if hhmm.len() != 4 || !hhmm.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let hours: i64 = hhmm[..2].parse().ok()?;
+            let minutes: i64 = hhmm[2..].parse().ok()?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+    let utc = tm.to_timespec().sec - offset;
+    time::strftime(TIME_FORMAT, &time::at(time::Timespec::new(utc, 0))).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_iso8601_zones() {
+        for t in &["2021-03-04T05:06:07Z", "2021-03-04T05:06:07.123+01:00", "2021-03-04T05:06:07-0130"] {
+            assert!(from_iso8601(t).is_some(), "{}", t);
+        }
+        for t in &["2021-03-04T05:06:07+1é1", "2021-03-04T05:06:07+-100", "2021-03-04T05:06:07+1", "2021-03-04"] {
+            assert_eq!(from_iso8601(t), None, "{}", t);
+        }
+    }
+}
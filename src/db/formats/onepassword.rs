@@ -0,0 +1,336 @@
+//! 1Password exports: 1PUX (1Password 8, a zip with export.data JSON and attached files)
+//! and 1PIF (1Password 7, JSON items separated by "***...***" lines).
+//!
+//! Vaults (1PUX) and folders (1PIF) become groups. Section fields, tags and the TOTP secret
+//! are added to notes, one attached document is kept. Archived and trashed items and
+//! old passwords are skipped.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use serde_json;
+use serde_json::Value;
+use uuid::Uuid;
+
+use db::api::{Attachment, Entry, PATH_SEPARATOR};
+use db::formats::{add_to_notes, from_unix, zip, Parsed, Record, TOTP_FIELD};
+use errors;
+use errors::Error;
+
+const FORMAT_1PUX: &str = "1PUX";
+const FORMAT_1PIF: &str = "1PIF";
+
+/// Folders nested deeper than this are taken as a loop.
+const MAX_FOLDER_DEPTH: usize = 32;
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(|a| a.as_slice()).unwrap_or(&[])
+}
+
+fn text(value: &Value) -> &str {
+    value.as_str().unwrap_or("")
+}
+
+/// Field value as text, parts of addresses and the like joined with ", ".
+fn value_text(value: &Value) -> Option<String> {
+    match *value {
+        Value::String(ref s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(ref n) => Some(n.to_string()),
+        Value::Object(ref map) => {
+            if let Some(email) = map.get("email_address").and_then(Value::as_str) {
+                return Some(String::from(email));
+            }
+            let parts: Vec<&str> = map.values()
+                .filter_map(Value::as_str)
+                .filter(|s| !s.is_empty())
+                .collect();
+            if parts.is_empty() {
+                None
+            } else {
+                Some(parts.join(", "))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Add URLs other than the entry URL to fields.
+fn add_urls<'a, I: Iterator<Item = &'a str>>(entry: &Entry, urls: I, fields: &mut Vec<String>) {
+    let others = urls.filter(|u| !u.is_empty() && *u != entry.url);
+    for (i, url) in others.enumerate() {
+        fields.push(format!("URL {}: {}", i + 2, url));
+    }
+}
+
+/// Set username and password from login fields marked as such, "designation" in both formats.
+fn read_login_fields(fields: &Value, entry: &mut Entry) {
+    for f in array(fields) {
+        match text(&f["designation"]) {
+            "username" => entry.username = String::from(text(&f["value"])),
+            "password" => entry.password = String::from(text(&f["value"])),
+            _ => {}
+        }
+    }
+}
+
+fn set_times(entry: &mut Entry, item: &Value) {
+    if let Some(t) = item["createdAt"].as_i64().and_then(from_unix) {
+        entry.created = t;
+    }
+    if let Some(t) = item["updatedAt"].as_i64().and_then(from_unix) {
+        entry.modified = t;
+    }
+}
+
+/// Field value of 1PUX section, an object like {"concealed": "..."}, as text.
+fn field_1pux(value: &Value) -> Option<(&str, String)> {
+    let (kind, v) = value.as_object()?.iter().next()?;
+    let text = match (kind.as_str(), v.as_i64()) {
+        ("date", Some(t)) => from_unix(t).map(|t| String::from(&t[..10])),
+        ("monthYear", Some(my)) => Some(format!("{}/{:02}", my / 100, my % 100)),
+        _ => value_text(v),
+    };
+    text.map(|t| (kind.as_str(), t))
+}
+
+/// Attached document of 1PUX item, stored in archive as "files/<id>__<name>".
+fn read_document(archive: &[u8], doc: &Value, path: &str, entry: &mut Entry, parsed: &mut Parsed) {
+    let name = text(&doc["fileName"]);
+    let file = format!("files/{}__{}", text(&doc["documentId"]), name);
+    match zip::read_file(archive, &file) {
+        Ok(Some(data)) => if entry.attachment.is_none() {
+            entry.attachment = Some(Attachment {
+                name: String::from(name),
+                data: data,
+            });
+        } else {
+            parsed.skipped.push(format!(
+                "{}: attachment \"{}\", KDB entries can have only one",
+                path, name
+            ));
+        },
+        Ok(None) => parsed
+            .skipped
+            .push(format!("{}: attachment \"{}\" not in export", path, name)),
+        Err(e) => parsed
+            .skipped
+            .push(format!("{}: attachment \"{}\": {}", path, name, errors::report(&e))),
+    }
+}
+
+fn read_1pux_item(archive: &[u8], item: &Value, group: &str, parsed: &mut Parsed) -> usize {
+    let overview = &item["overview"];
+    let details = &item["details"];
+    let title = text(&overview["title"]);
+    if title.trim().is_empty() {
+        parsed
+            .skipped
+            .push(format!("Item {}: no title", text(&item["uuid"])));
+        return 0;
+    }
+    let path = format!("{}{}{}", group, PATH_SEPARATOR, title);
+    let mut entry = Entry::new(0, title);
+    set_times(&mut entry, item);
+    entry.url = String::from(text(&overview["url"]));
+    entry.notes = String::from(text(&details["notesPlain"]));
+    read_login_fields(&details["loginFields"], &mut entry);
+    if entry.password.is_empty() {
+        entry.password = String::from(text(&details["password"]));
+    }
+    let mut fields = Vec::new();
+    add_urls(&entry, array(&overview["urls"]).iter().map(|u| text(&u["url"])), &mut fields);
+    for section in array(&details["sections"]) {
+        for f in array(&section["fields"]) {
+            let label = match text(&f["title"]) {
+                "" => text(&f["id"]),
+                t => t,
+            };
+            match field_1pux(&f["value"]) {
+                Some(("totp", v)) => fields.push(format!("{}: {}", TOTP_FIELD, v)),
+                Some(("file", _)) => read_document(archive, &f["value"]["file"], &path, &mut entry, parsed),
+                Some((_, v)) => fields.push(format!("{}: {}", label, v)),
+                None => {}
+            }
+        }
+    }
+    let tags: Vec<&str> = array(&overview["tags"]).iter().map(text).collect();
+    if !tags.is_empty() {
+        fields.push(format!("Tags: {}", tags.join(", ")));
+    }
+    add_to_notes(&mut entry.notes, &fields);
+    if details["documentAttributes"].is_object() {
+        read_document(archive, &details["documentAttributes"], &path, &mut entry, parsed);
+    }
+    parsed.records.push(Record {
+        group: String::from(group),
+        entry: entry,
+    });
+    array(&details["passwordHistory"]).len()
+}
+
+/// Read 1PUX file, vaults become groups.
+pub fn read_1pux<R: Read>(mut reader: R) -> Result<Parsed, Error> {
+    let mut archive = Vec::new();
+    reader.read_to_end(&mut archive)?;
+    let data = zip::read_file(&archive, "export.data")?
+        .ok_or_else(|| Error::format(FORMAT_1PUX, "No export.data in archive"))?;
+    let export: Value = serde_json::from_slice(&data).map_err(|e| Error::format(FORMAT_1PUX, &e.to_string()))?;
+    let mut parsed = Parsed::default();
+    let (mut archived, mut history) = (0, 0);
+    for account in array(&export["accounts"]) {
+        for vault in array(&account["vaults"]) {
+            let group = text(&vault["attrs"]["name"]).replace(PATH_SEPARATOR, "-");
+            if !parsed.groups.contains(&group) {
+                parsed.groups.push(group.clone());
+            }
+            for item in array(&vault["items"]) {
+                if text(&item["state"]) == "archived" {
+                    archived += 1;
+                } else {
+                    history += read_1pux_item(&archive, item, &group, &mut parsed);
+                }
+            }
+        }
+    }
+    if archived > 0 {
+        parsed.skipped.push(format!("{} archived items", archived));
+    }
+    if history > 0 {
+        parsed
+            .skipped
+            .push(format!("{} old passwords (password history)", history));
+    }
+    Ok(parsed)
+}
+
+/// Path of 1PIF folder, folders are (uuid, (title, parent uuid)).
+fn folder_path(folders: &HashMap<String, (String, String)>, uuid: &str) -> String {
+    let mut names = Vec::new();
+    let mut uuid = uuid;
+    while let Some(&(ref title, ref parent)) = folders.get(uuid) {
+        names.insert(0, title.replace(PATH_SEPARATOR, "-"));
+        if names.len() >= MAX_FOLDER_DEPTH {
+            break;
+        }
+        uuid = parent;
+    }
+    names.join(&PATH_SEPARATOR.to_string())
+}
+
+fn read_1pif_item(item: &Value, group: String, parsed: &mut Parsed) {
+    let title = text(&item["title"]);
+    if title.trim().is_empty() {
+        parsed
+            .skipped
+            .push(format!("Item {}: no title", text(&item["uuid"])));
+        return;
+    }
+    let contents = &item["secureContents"];
+    let mut entry = Entry::new(0, title);
+    if let Ok(id) = Uuid::parse_str(text(&item["uuid"])) {
+        entry.id = id;
+    }
+    set_times(&mut entry, item);
+    entry.url = String::from(text(&item["location"]));
+    entry.notes = String::from(text(&contents["notesPlain"]));
+    read_login_fields(&contents["fields"], &mut entry);
+    if entry.password.is_empty() {
+        entry.password = String::from(text(&contents["password"]));
+    }
+    let mut fields = Vec::new();
+    add_urls(&entry, array(&contents["URLs"]).iter().map(|u| text(&u["url"])), &mut fields);
+    for section in array(&contents["sections"]) {
+        for f in array(&section["fields"]) {
+            let name = text(&f["n"]);
+            let label = match text(&f["t"]) {
+                "" => name,
+                t => t,
+            };
+            match value_text(&f["v"]) {
+                Some(ref v) if name.starts_with("TOTP_") => fields.push(format!("{}: {}", TOTP_FIELD, v)),
+                Some(v) => fields.push(format!("{}: {}", label, v)),
+                None => {}
+            }
+        }
+    }
+    add_to_notes(&mut entry.notes, &fields);
+    parsed.records.push(Record {
+        group: group,
+        entry: entry,
+    });
+}
+
+/// Read 1PIF file (data.1pif in the .1pif directory), folders become groups.
+pub fn read_1pif<R: Read>(mut reader: R) -> Result<Parsed, Error> {
+    let mut data = String::new();
+    reader.read_to_string(&mut data)?;
+    let mut items = Vec::new();
+    for (i, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("***") {
+            continue;
+        }
+        let item: Value = serde_json::from_str(line)
+            .map_err(|e| Error::format(FORMAT_1PIF, &format!("Line {}: {}", i + 1, e)))?;
+        items.push(item);
+    }
+    let mut folders = HashMap::new();
+    for item in items.iter().filter(|i| text(&i["typeName"]) == "system.folder.Regular") {
+        folders.insert(
+            String::from(text(&item["uuid"])),
+            (String::from(text(&item["title"])), String::from(text(&item["folderUuid"]))),
+        );
+    }
+    let mut parsed = Parsed::default();
+    parsed.groups = folders.keys().map(|uuid| folder_path(&folders, uuid)).collect();
+    parsed.groups.sort();
+    let mut trashed = 0;
+    for item in &items {
+        if text(&item["typeName"]).starts_with("system.") {
+            continue;
+        }
+        if item["trashed"].as_bool() == Some(true) {
+            trashed += 1;
+            continue;
+        }
+        read_1pif_item(item, folder_path(&folders, text(&item["folderUuid"])), &mut parsed);
+    }
+    if trashed > 0 {
+        parsed.skipped.push(format!("{} items in trash", trashed));
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_1pif() {
+        let data = concat!(
+            r#"{"uuid":"F1","typeName":"system.folder.Regular","title":"Work","folderUuid":""}"#,
+            "\n***5642bee8-a5ff-11dc-8314-0800200c9a66***\n",
+            r#"{"uuid":"F2","typeName":"system.folder.Regular","title":"Mail","folderUuid":"F1"}"#,
+            "\n***5642bee8-a5ff-11dc-8314-0800200c9a66***\n",
+            r#"{"uuid":"0B7B1AB61C584E4A93D63F6B1B1C9E10","typeName":"webforms.WebForm","title":"Gmail","#,
+            r#""location":"https://mail.google.com","folderUuid":"F2","createdAt":1500000000,"#,
+            r#""secureContents":{"fields":[{"designation":"username","value":"bob"},"#,
+            r#"{"designation":"password","value":"pw"}],"notesPlain":"hi","#,
+            r#""sections":[{"fields":[{"k":"concealed","n":"TOTP_1","t":"","v":"otpauth://totp/x?secret=AB"},"#,
+            r#"{"k":"string","n":"pin","t":"PIN","v":"1234"}]}]}}"#,
+            "\n***5642bee8-a5ff-11dc-8314-0800200c9a66***\n",
+            r#"{"uuid":"X","typeName":"passwords.Password","title":"Old","trashed":true}"#,
+            "\n***5642bee8-a5ff-11dc-8314-0800200c9a66***\n"
+        );
+        let parsed = read_1pif(data.as_bytes()).unwrap();
+        assert_eq!(parsed.groups, vec![String::from("Work"), String::from("Work/Mail")]);
+        assert_eq!(parsed.records.len(), 1);
+        let r = &parsed.records[0];
+        assert_eq!(r.path(), "Work/Mail/Gmail");
+        assert_eq!(r.entry.username, "bob");
+        assert_eq!(r.entry.password, "pw");
+        assert_eq!(r.entry.created, from_unix(1_500_000_000).unwrap());
+        assert_eq!(r.entry.notes, "hi\n\nTOTP: otpauth://totp/x?secret=AB\nPIN: 1234");
+        assert_eq!(parsed.skipped, vec![String::from("1 items in trash")]);
+    }
+}
@@ -0,0 +1,324 @@
+//! pass (password-store) directories: each entry is a GPG encrypted "<group>/<title>.gpg" file,
+//! with the password on the first line, then lines like "login: bob" or "url: ...".
+//!
+//! Files are decrypted and encrypted with `gpg` by default, tests (and frontends) can pass
+//! their own functions. Login and URL lines become username and URL, other lines are kept in
+//! notes, "otpauth://" lines (pass-otp) are the TOTP secret. Attachments can't be exported.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::UNIX_EPOCH;
+
+use db::api::{split_path, Entry, PATH_SEPARATOR};
+use db::file::write_atomic;
+use db::formats::{from_unix, Parsed, Record, TOTP_FIELD};
+use errors;
+use errors::Error;
+
+/// File listing GPG key ids to encrypt for, in store root and optionally in subdirectories.
+pub const GPG_ID_FILE: &str = ".gpg-id";
+
+const EXTENSION: &str = "gpg";
+
+/// Decrypts a file of the store.
+pub type Decrypt = Fn(&Path) -> Result<Vec<u8>, Error>;
+
+/// Encrypts file contents for GPG key ids.
+pub type Encrypt = Fn(&[u8], &[String]) -> Result<Vec<u8>, Error>;
+
+/// Run gpg with contents on stdin, return its output.
+fn run_gpg(args: &[&OsStr], input: &[u8]) -> Result<Vec<u8>, Error> {
+    debug!("run_gpg: {:?}", args);
+    let mut child = Command::new("gpg")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Context {
+            desc: String::from("Can't run gpg"),
+            source: Box::new(Error::Io(e)),
+        })?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(input)?;
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(Error::Other(format!(
+            "gpg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+/// Decrypt with gpg, which asks for passphrase (or has gpg-agent do it) if needed.
+pub fn gpg_decrypt(path: &Path) -> Result<Vec<u8>, Error> {
+    run_gpg(&["--quiet".as_ref(), "--decrypt".as_ref(), path.as_os_str()], &[])
+}
+
+/// Encrypt with gpg, like pass does.
+pub fn gpg_encrypt(contents: &[u8], recipients: &[String]) -> Result<Vec<u8>, Error> {
+    let mut args: Vec<&OsStr> = vec!["--quiet".as_ref(), "--batch".as_ref(), "--encrypt".as_ref()];
+    for r in recipients {
+        args.push("--recipient".as_ref());
+        args.push(r.as_ref());
+    }
+    run_gpg(&args, contents)
+}
+
+/// Whether directory looks like a password store.
+pub fn is_store(dir: &Path) -> bool {
+    dir.join(GPG_ID_FILE).is_file()
+}
+
+/// Entry from decrypted file contents.
+fn parse_entry(title: &str, contents: &str) -> Entry {
+    let contents = contents.replace("\r\n", "\n");
+    let mut lines = contents.lines();
+    let mut e = Entry::new(0, title);
+    e.password = String::from(lines.next().unwrap_or(""));
+    let mut notes = Vec::new();
+    for line in lines {
+        if line.starts_with("otpauth://") {
+            notes.push(format!("{}: {}", TOTP_FIELD, line));
+            continue;
+        }
+        if let Some(pos) = line.find(':') {
+            let value = line[pos + 1..].trim();
+            match line[..pos].trim().to_lowercase().as_str() {
+                "login" | "username" | "user" if e.username.is_empty() => {
+                    e.username = String::from(value);
+                    continue;
+                }
+                "url" | "website" | "site" if e.url.is_empty() => {
+                    e.url = String::from(value);
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        notes.push(String::from(line));
+    }
+    e.notes = notes.join("\n").trim_matches('\n').to_string();
+    e
+}
+
+/// File contents for entry, in the layout `parse_entry` reads.
+fn format_entry(e: &Entry) -> String {
+    let mut lines = vec![e.password.clone()];
+    if !e.username.is_empty() {
+        lines.push(format!("login: {}", e.username));
+    }
+    if !e.url.is_empty() {
+        lines.push(format!("url: {}", e.url));
+    }
+    let totp_prefix = format!("{}: otpauth://", TOTP_FIELD);
+    for line in e.notes.lines() {
+        if line.starts_with(&totp_prefix) {
+            lines.push(String::from(&line[TOTP_FIELD.len() + 2..]));
+        } else {
+            lines.push(String::from(line));
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+/// File modification time as local time.
+fn modified(path: &Path) -> Option<String> {
+    let mtime = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let seconds = mtime.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    from_unix(seconds as i64)
+}
+
+fn read_dir(dir: &Path, group: &str, decrypt: &Decrypt, parsed: &mut Parsed) -> Result<(), Error> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+    for path in paths {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            // Hidden: .gpg-id, .git, .extensions...
+            Some(n) if !n.starts_with('.') => String::from(n),
+            _ => continue,
+        };
+        if path.is_dir() {
+            let sub_group = if group.is_empty() {
+                name
+            } else {
+                format!("{}{}{}", group, PATH_SEPARATOR, name)
+            };
+            parsed.groups.push(sub_group.clone());
+            read_dir(&path, &sub_group, decrypt, parsed)?;
+            continue;
+        }
+        if path.extension() != Some(EXTENSION.as_ref()) {
+            continue;
+        }
+        let title = &name[..name.len() - EXTENSION.len() - 1];
+        let contents = match decrypt(&path) {
+            Ok(c) => c,
+            // No gpg, or the like, every other file would fail too.
+            Err(e @ Error::Io(_)) | Err(e @ Error::Context { .. }) => return Err(e),
+            Err(e) => {
+                parsed.skipped.push(format!("{}: {}", path.display(), errors::report(&e)));
+                continue;
+            }
+        };
+        let mut entry = parse_entry(title, &String::from_utf8_lossy(&contents));
+        if let Some(t) = modified(&path) {
+            entry.created = t.clone();
+            entry.modified = t;
+        }
+        parsed.records.push(Record {
+            group: String::from(group),
+            entry: entry,
+        });
+    }
+    Ok(())
+}
+
+/// Read password store in directory, subdirectories become groups.
+pub fn read(dir: &Path, decrypt: &Decrypt) -> Result<Parsed, Error> {
+    if !dir.is_dir() {
+        return Err(Error::Invalid(format!("{} is not a directory", dir.display())));
+    }
+    let mut parsed = Parsed::default();
+    read_dir(dir, "", decrypt, &mut parsed)?;
+    Ok(parsed)
+}
+
+/// Group or title as file name.
+fn file_name(name: &str) -> String {
+    let name = name.replace('/', "-");
+    if name.starts_with('.') {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// Key ids from nearest .gpg-id, looking from dir up to store root.
+fn find_recipients(root: &Path, dir: &Path) -> Result<Vec<String>, Error> {
+    for d in dir.ancestors().take_while(|d| d.starts_with(root)) {
+        let path = d.join(GPG_ID_FILE);
+        if path.is_file() {
+            return Ok(fs::read_to_string(path)?
+                .lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty())
+                .map(String::from)
+                .collect());
+        }
+    }
+    Err(Error::Invalid(format!("No {} in {}", GPG_ID_FILE, root.display())))
+}
+
+/// Write records to password store in directory, which is created (with .gpg-id listing
+/// recipients) if needed. Existing files are not replaced.
+/// Returns number of records written and descriptions of what was skipped.
+pub fn write(dir: &Path, records: &[Record], recipients: &[String], encrypt: &Encrypt) -> Result<(usize, Vec<String>), Error> {
+    if !is_store(dir) {
+        if recipients.is_empty() {
+            return Err(Error::Invalid(format!(
+                "{} is not a password store, give GPG key ids to create one",
+                dir.display()
+            )));
+        }
+        fs::create_dir_all(dir)?;
+        write_atomic(&dir.join(GPG_ID_FILE), format!("{}\n", recipients.join("\n")).as_bytes(), 0)?;
+    }
+    let mut written = 0;
+    let mut skipped = Vec::new();
+    for r in records {
+        let mut file_dir = dir.to_path_buf();
+        for g in split_path(&r.group) {
+            file_dir.push(file_name(g));
+        }
+        let path = file_dir.join(format!("{}.{}", file_name(&r.entry.title), EXTENSION));
+        if path.exists() {
+            skipped.push(format!("{}: {} already exists", r.path(), path.display()));
+            continue;
+        }
+        if let Some(ref a) = r.entry.attachment {
+            skipped.push(format!("{}: attachment \"{}\"", r.path(), a.name));
+        }
+        fs::create_dir_all(&file_dir)?;
+        let contents = encrypt(format_entry(&r.entry).as_bytes(), &find_recipients(dir, &file_dir)?)?;
+        write_atomic(&path, &contents, 0)?;
+        written += 1;
+    }
+    Ok((written, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// "Encryption" for tests, files are plaintext prefixed with recipients.
+    fn encrypt(contents: &[u8], recipients: &[String]) -> Result<Vec<u8>, Error> {
+        let mut data = format!("{}\n", recipients.join(",")).into_bytes();
+        data.extend_from_slice(contents);
+        Ok(data)
+    }
+
+    fn decrypt(path: &Path) -> Result<Vec<u8>, Error> {
+        let data = fs::read(path)?;
+        let start = data.iter().position(|&b| b == b'\n').map_or(0, |p| p + 1);
+        Ok(data[start..].to_vec())
+    }
+
+    #[test]
+    fn test_parse_entry() {
+        let e = parse_entry(
+            "Gmail",
+            "pw\nlogin: bob\nURL: https://mail.google.com\notpauth://totp/x?secret=AB\nPIN: 1234\n",
+        );
+        assert_eq!(e.password, "pw");
+        assert_eq!(e.username, "bob");
+        assert_eq!(e.url, "https://mail.google.com");
+        assert_eq!(e.notes, "TOTP: otpauth://totp/x?secret=AB\nPIN: 1234");
+        let again = parse_entry("Gmail", &format_entry(&e));
+        assert_eq!(
+            (again.password, again.username, again.url, again.notes),
+            (e.password, e.username, e.url, e.notes)
+        );
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let dir = std::env::temp_dir().join(format!("mpkdb-test-pass-{}", Uuid::new_v4()));
+        let mut e = Entry::new(0, "a/b");
+        e.password = String::from("secret");
+        let records = vec![
+            Record {
+                group: String::from("Work/Mail"),
+                entry: e,
+            },
+        ];
+        assert!(write(&dir, &records, &[], &encrypt).is_err());
+        let ids = vec![String::from("ABCD1234")];
+        assert_eq!(write(&dir, &records, &ids, &encrypt).unwrap().0, 1);
+        let (written, skipped) = write(&dir, &records, &ids, &encrypt).unwrap();
+        assert_eq!((written, skipped.len()), (0, 1));
+        let data = fs::read(dir.join("Work/Mail/a-b.gpg")).unwrap();
+        assert!(data.starts_with(b"ABCD1234\nsecret\n"));
+        let parsed = read(&dir, &decrypt).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(parsed.groups, vec![String::from("Work"), String::from("Work/Mail")]);
+        assert_eq!(parsed.records.len(), 1);
+        assert_eq!(parsed.records[0].path(), "Work/Mail/a-b");
+        assert_eq!(parsed.records[0].entry.password, "secret");
+    }
+}
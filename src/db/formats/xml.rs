@@ -0,0 +1,90 @@
+//! Minimal XML tree for importers, exported files are small enough to read whole,
+//! and escaping for exporters, which write XML directly.
+
+use std::borrow::Cow;
+use std::io::Read;
+
+use xml;
+use xml::reader::{EventReader, ParserConfig, XmlEvent};
+
+use errors::Error;
+
+/// Element with its attributes, child elements and text.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    /// Text directly in element, whitespace included.
+    pub text: String,
+}
+
+impl Element {
+    pub fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref v)| v.as_str())
+    }
+
+    /// First child element with given name.
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> Vec<&'a Element> {
+        self.children.iter().filter(|c| c.name == name).collect()
+    }
+
+    /// Text of first child with given name, empty if there's none.
+    pub fn child_text(&self, name: &str) -> &str {
+        self.child(name).map(|c| c.text.as_str()).unwrap_or("")
+    }
+}
+
+/// Read whole document, format is for error messages, e.g. "KeePass XML".
+pub fn parse<R: Read>(reader: R, format: &str) -> Result<Element, Error> {
+    let config = ParserConfig::new()
+        .trim_whitespace(false)
+        .whitespace_to_characters(true)
+        .cdata_to_characters(true);
+    // Stack of open elements, root is the last one closed.
+    let mut stack: Vec<Element> = Vec::new();
+    for event in EventReader::new_with_config(reader, config) {
+        match event.map_err(|e| Error::format(format, &e.to_string()))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => stack.push(Element {
+                name: name.local_name,
+                attributes: attributes
+                    .into_iter()
+                    .map(|a| (a.name.local_name, a.value))
+                    .collect(),
+                children: Vec::new(),
+                text: String::new(),
+            }),
+            XmlEvent::EndElement { .. } => {
+                let element = stack.pop().expect("Parser checks element nesting");
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            XmlEvent::Characters(text) => if let Some(e) = stack.last_mut() {
+                e.text.push_str(&text);
+            },
+            _ => {}
+        }
+    }
+    Err(Error::format(format, "No root element"))
+}
+
+/// Escape text for element content.
+pub fn escape(text: &str) -> Cow<str> {
+    xml::escape::escape_str_pcdata(text)
+}
+
+/// Escape text for attribute value.
+pub fn escape_attr(text: &str) -> Cow<str> {
+    xml::escape::escape_str_attribute(text)
+}
@@ -0,0 +1,90 @@
+//! Just enough of zip to take a file out of an archive, for 1PUX exports.
+//! Files are found in the central directory, so sizes written after data don't matter.
+
+use std::io::Read;
+
+use flate2::read::DeflateDecoder;
+
+use errors::Error;
+
+/// Start of a zip file (first local file header).
+pub const SIGNATURE: &[u8] = b"PK\x03\x04";
+
+const END_SIGNATURE: u32 = 0x0605_4b50;
+const CENTRAL_SIGNATURE: u32 = 0x0201_4b50;
+const LOCAL_SIGNATURE: u32 = 0x0403_4b50;
+const END_SIZE: usize = 22;
+
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from(b[0]) | u16::from(b[1]) << 8)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos + 4).map(|b| {
+        u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16 | u32::from(b[3]) << 24
+    })
+}
+
+fn invalid(desc: &str) -> Error {
+    Error::format("zip", desc)
+}
+
+/// Position of end of central directory record, it's followed by a comment up to 64 KiB.
+fn find_end(data: &[u8]) -> Option<usize> {
+    let last = data.len().checked_sub(END_SIZE)?;
+    let first = last.saturating_sub(0xffff);
+    (first..last + 1)
+        .rev()
+        .find(|&pos| u32_at(data, pos) == Some(END_SIGNATURE))
+}
+
+/// Contents of file with given name, None if it's not in archive.
+pub fn read_file(data: &[u8], name: &str) -> Result<Option<Vec<u8>>, Error> {
+    let end = find_end(data).ok_or_else(|| invalid("No end of central directory"))?;
+    let count = u16_at(data, end + 10).unwrap_or(0);
+    let mut pos = u32_at(data, end + 16).unwrap_or(0) as usize;
+    for _ in 0..count {
+        if u32_at(data, pos) != Some(CENTRAL_SIGNATURE) {
+            return Err(invalid("Invalid central directory"));
+        }
+        let header = || invalid("Truncated central directory");
+        let method = u16_at(data, pos + 10).ok_or_else(header)?;
+        let compressed = u32_at(data, pos + 20).ok_or_else(header)? as usize;
+        let name_len = u16_at(data, pos + 28).ok_or_else(header)? as usize;
+        let extra_len = u16_at(data, pos + 30).ok_or_else(header)? as usize;
+        let comment_len = u16_at(data, pos + 32).ok_or_else(header)? as usize;
+        let offset = u32_at(data, pos + 42).ok_or_else(header)? as usize;
+        let entry_name = data.get(pos + 46..pos + 46 + name_len).ok_or_else(header)?;
+        pos += 46 + name_len + extra_len + comment_len;
+        if entry_name != name.as_bytes() {
+            continue;
+        }
+        debug!("read_file: {} at {}, method {}, {} bytes", name, offset, method, compressed);
+        if u32_at(data, offset) != Some(LOCAL_SIGNATURE) {
+            return Err(invalid(&format!("Invalid header of {}", name)));
+        }
+        let local = || invalid(&format!("Truncated {}", name));
+        let start = offset + 30 + u16_at(data, offset + 26).ok_or_else(local)? as usize
+            + u16_at(data, offset + 28).ok_or_else(local)? as usize;
+        let stored = data.get(start..start + compressed).ok_or_else(local)?;
+        return match method {
+            STORED => Ok(Some(stored.to_vec())),
+            DEFLATED => {
+                let mut contents = Vec::new();
+                DeflateDecoder::new(stored)
+                    .read_to_end(&mut contents)
+                    .map_err(|e| invalid(&format!("Invalid compressed {}: {}", name, e)))?;
+                Ok(Some(contents))
+            }
+            _ => Err(Error::Unsupported(format!(
+                "Compression method {} of {} in zip file",
+                method, name
+            ))),
+        };
+    }
+    Ok(None)
+}
@@ -1,52 +1,622 @@
-
 //! The KDB implementation of Database.
 
 use std;
 use std::io::Read;
+use std::path::Path;
+
+use rand;
+use rand::Rng;
+use uuid::Uuid;
 
 use db::api;
+use db::api::{Attachment, Entry, Group, GroupId, PasswordDatabase};
+use db::file;
+use db::file::{DiskState, LockFile};
 use db::kdb;
+use db::kdb::parser::{KdbEntry, KdbFile, KdbGroup, KdbHeader};
+
+/// Key transformation rounds for new databases.
+pub const DEFAULT_KEY_ENC_ROUNDS: u32 = 50000;
+
+/// Groups of a new database, same as KeePass creates.
+pub const DEFAULT_GROUPS: &[&str] = &["General", "Windows", "Network", "Internet", "eMail", "Homebanking"];
+
+/// KeePass folder icon.
+const GROUP_IMAGE_ID: u32 = 48;
 
-#[derive(Debug)]
 pub struct KdbDatabase {
-    file: kdb::parser::KdbFile,
+    /// File we were opened from, and we save to.
+    path: String,
+    /// Composite key (from password and key file), before transformation.
+    master_key: [u8; 32],
+    file: KdbFile,
+    /// Backups of previous versions kept on save.
+    backups: u32,
+    /// Taken on first save, and held until database is closed.
+    lock: Option<LockFile>,
+    /// File as we last read or wrote it, None if we haven't yet.
+    disk: Option<DiskState>,
+    /// Contents as we last read or wrote them, for merging.
+    base: Option<KdbFile>,
+}
+
+/// Don't dump secrets to logs.
+impl std::fmt::Debug for KdbDatabase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "KdbDatabase {{ path: {:?}, groups: {}, entries: {} }}",
+            self.path,
+            self.file.groups.len(),
+            self.file.entries.len()
+        )
+    }
 }
 
-impl api::PasswordDatabase for KdbDatabase {
+fn to_api_entry(e: &KdbEntry) -> Entry {
+    Entry {
+        id: e.entry_id,
+        group_id: e.group_id,
+        title: e.title.clone(),
+        url: e.url.clone(),
+        username: e.username.clone(),
+        password: e.password.clone(),
+        notes: e.notes.clone(),
+        created: e.created.clone(),
+        modified: e.modified.clone(),
+        accessed: e.accessed.clone(),
+        expires: e.expires.clone(),
+        attachment: if e.binary_desc.is_empty() && e.binary_data.is_empty() {
+            None
+        } else {
+            Some(Attachment {
+                name: e.binary_desc.clone(),
+                data: e.binary_data.clone(),
+            })
+        },
+    }
+}
+
+/// Copy API entry fields into KDB entry, leaving KDB-only fields (icon) as they are.
+fn update_kdb_entry(kdb_entry: &mut KdbEntry, e: &Entry) {
+    kdb_entry.entry_id = e.id;
+    kdb_entry.group_id = e.group_id;
+    kdb_entry.title = e.title.clone();
+    kdb_entry.url = e.url.clone();
+    kdb_entry.username = e.username.clone();
+    kdb_entry.password = e.password.clone();
+    kdb_entry.notes = e.notes.clone();
+    kdb_entry.created = e.created.clone();
+    kdb_entry.modified = e.modified.clone();
+    kdb_entry.accessed = e.accessed.clone();
+    kdb_entry.expires = e.expires.clone();
+    match e.attachment {
+        Some(ref a) => {
+            kdb_entry.binary_desc = a.name.clone();
+            kdb_entry.binary_data = a.data.clone();
+        }
+        None => {
+            kdb_entry.binary_desc = String::new();
+            kdb_entry.binary_data = Vec::new();
+        }
+    }
+}
+
+impl KdbDatabase {
+    fn group_index(&self, id: GroupId) -> Option<usize> {
+        self.file.groups.iter().position(|g| g.group_id == id)
+    }
+
+    /// Index one past the last group in subtree starting at given index.
+    fn subtree_end(&self, index: usize) -> usize {
+        let level = self.file.groups[index].level;
+        let mut end = index + 1;
+        while end < self.file.groups.len() && self.file.groups[end].level > level {
+            end += 1;
+        }
+        end
+    }
+
+    /// Index of visible (non meta-stream) entry.
+    fn entry_index(&self, id: &Uuid) -> Option<usize> {
+        self.file
+            .entries
+            .iter()
+            .position(|e| e.entry_id == *id && !e.is_meta_stream())
+    }
+
+    /// Random unused group id, 0 and 0xFFFFFFFF are reserved by KeePass.
+    fn new_group_id(&self) -> Result<GroupId, api::Error> {
+        let mut rng = match rand::OsRng::new() {
+            Ok(rng) => rng,
+            Err(e) => return Err(api::Error::from(e).context("Failed to init RNG")),
+        };
+        loop {
+            let id: u32 = rng.gen();
+            if id != 0 && id != 0xFFFFFFFF && self.group_index(id).is_none() {
+                return Ok(id);
+            }
+        }
+    }
+
+    /// Write database to given file, atomically, keeping backups.
+    /// Fails if someone else has the file locked.
+    fn write_to(&mut self, path: &str) -> Result<(), api::Error> {
+        if self.lock.is_none() {
+            self.lock = Some(LockFile::acquire(Path::new(path))?);
+        }
+        let bytes = kdb::writer::write_kdb_file(&mut self.file, &self.master_key)?;
+        if let Err(e) = file::write_atomic(Path::new(path), &bytes, self.backups) {
+            return Err(api::Error::from(e).context(&format!("Error writing \"{}\"", path)));
+        }
+        self.disk = DiskState::new(Path::new(path), &bytes).ok();
+        self.base = Some(self.file.clone());
+        debug!("write_to: Saved \"{}\"", path);
+        Ok(())
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    /// Write to another file, which is used from now on. Existing file is replaced.
+    pub fn save_as(&mut self, path: &str) -> Result<(), api::Error> {
+        self.path = String::from(path);
+        self.lock = None;
+        self.disk = None;
+        self.overwrite()
+    }
+
+    /// How many previous versions to keep on save, 0 for none.
+    pub fn set_backup_count(&mut self, count: u32) {
+        self.backups = count;
+    }
+}
+
+impl PasswordDatabase for KdbDatabase {
     fn get_entry_count(&self) -> u32 {
-        let s = self.file.entries.len();
+        let s = self.file.entries.iter().filter(|e| !e.is_meta_stream()).count();
         if s > (u32::max_value() as usize) {
             // Shouldn't happen, we should not support files that big.
             panic!("Too many entries")
         }
-        self.file.entries.len() as u32
+        s as u32
     }
-}
 
-pub fn open(filename: &str, password: &str) -> Result<KdbDatabase, api::Error> {
-    debug!("open: About to open \"{}\"...", filename);
-    let file = match std::fs::File::open(filename) {
-        Ok(file) => file,
-        Err(e) => {
-            return Err(api::Error {
-                desc: format!("Error opening file: {}", e),
-            })
+    fn get_groups(&self) -> Vec<Group> {
+        // Last seen group id at each level, parent of a group is the last seen one level up.
+        let mut ancestors: Vec<GroupId> = Vec::new();
+        let mut groups = Vec::with_capacity(self.file.groups.len());
+        for g in &self.file.groups {
+            ancestors.truncate(g.level as usize);
+            groups.push(Group {
+                id: g.group_id,
+                parent: ancestors.last().cloned(),
+                name: g.group_name.clone(),
+                created: g.created.clone(),
+                modified: g.modified.clone(),
+                accessed: g.accessed.clone(),
+                expires: g.expires.clone(),
+            });
+            ancestors.push(g.group_id);
         }
-    };
-    let mut reader = std::io::BufReader::new(file);
-    let mut buffer = Vec::new();
-    if let Err(e) = reader.read_to_end(&mut buffer) {
-        return Err(api::Error {
-            desc: format!("Error reading file: {}", e),
+        groups
+    }
+
+    fn get_entries(&self) -> Vec<Entry> {
+        self.file
+            .entries
+            .iter()
+            .filter(|e| !e.is_meta_stream())
+            .map(to_api_entry)
+            .collect()
+    }
+
+    fn add_group(&mut self, name: &str, parent: Option<GroupId>) -> Result<GroupId, api::Error> {
+        let id = self.new_group_id()?;
+        let now = api::now();
+        self.insert_group(&Group {
+            id: id,
+            parent: parent,
+            name: String::from(name),
+            created: now.clone(),
+            modified: now.clone(),
+            accessed: now,
+            expires: String::from(kdb::parser::NEVER_EXPIRES),
+        })?;
+        Ok(id)
+    }
+
+    fn update_group(&mut self, group: &Group) -> Result<(), api::Error> {
+        let current_parent = match self.get_group(group.id) {
+            Some(g) => g.parent,
+            None => return Err(api::Error::NoSuchGroup(group.id)),
+        };
+        if current_parent != group.parent {
+            return Err(api::Error::Unsupported(String::from("Moving groups is not supported")));
+        }
+        let index = self.group_index(group.id).expect("Group disappeared");
+        let g = &mut self.file.groups[index];
+        g.group_name = group.name.clone();
+        g.created = group.created.clone();
+        g.modified = group.modified.clone();
+        g.accessed = group.accessed.clone();
+        g.expires = group.expires.clone();
+        Ok(())
+    }
+
+    fn remove_group(&mut self, id: GroupId) -> Result<(), api::Error> {
+        let index = match self.group_index(id) {
+            Some(i) => i,
+            None => return Err(api::Error::NoSuchGroup(id)),
+        };
+        let end = self.subtree_end(index);
+        let removed: Vec<GroupId> = self.file
+            .groups
+            .drain(index..end)
+            .map(|g| g.group_id)
+            .collect();
+        self.file
+            .entries
+            .retain(|e| e.is_meta_stream() || !removed.contains(&e.group_id));
+        Ok(())
+    }
+
+    fn add_entry(&mut self, entry: &Entry) -> Result<(), api::Error> {
+        if self.group_index(entry.group_id).is_none() {
+            return Err(api::Error::NoSuchGroup(entry.group_id));
+        }
+        if self.file.entries.iter().any(|e| e.entry_id == entry.id) {
+            return Err(api::Error::EntryExists(entry.id));
+        }
+        let mut kdb_entry = KdbEntry {
+            entry_id: entry.id,
+            group_id: entry.group_id,
+            image_id: 0,
+            title: String::new(),
+            url: String::new(),
+            username: String::new(),
+            password: String::new(),
+            notes: String::new(),
+            created: String::new(),
+            modified: String::new(),
+            accessed: String::new(),
+            expires: String::new(),
+            binary_desc: String::new(),
+            binary_data: Vec::new(),
+        };
+        update_kdb_entry(&mut kdb_entry, entry);
+        if kdb_entry.expires.is_empty() {
+            kdb_entry.expires = String::from(kdb::parser::NEVER_EXPIRES);
+        }
+        // KeePass keeps meta-streams at the end.
+        let index = self.file
+            .entries
+            .iter()
+            .position(|e| e.is_meta_stream())
+            .unwrap_or(self.file.entries.len());
+        self.file.entries.insert(index, kdb_entry);
+        Ok(())
+    }
+
+    fn update_entry(&mut self, entry: &Entry) -> Result<(), api::Error> {
+        if self.group_index(entry.group_id).is_none() {
+            return Err(api::Error::NoSuchGroup(entry.group_id));
+        }
+        match self.entry_index(&entry.id) {
+            Some(i) => {
+                update_kdb_entry(&mut self.file.entries[i], entry);
+                Ok(())
+            }
+            None => Err(api::Error::NoSuchEntry(entry.id)),
+        }
+    }
+
+    fn remove_entry(&mut self, id: &Uuid) -> Result<(), api::Error> {
+        match self.entry_index(id) {
+            Some(i) => {
+                self.file.entries.remove(i);
+                Ok(())
+            }
+            None => Err(api::Error::NoSuchEntry(*id)),
+        }
+    }
+
+    fn save(&mut self) -> Result<(), api::Error> {
+        if self.changed_on_disk()? {
+            return Err(api::Error::ChangedOnDisk(self.path.clone()));
+        }
+        self.overwrite()
+    }
+
+    fn overwrite(&mut self) -> Result<(), api::Error> {
+        let path = self.path.clone();
+        self.write_to(&path)
+    }
+
+    fn insert_group(&mut self, group: &Group) -> Result<(), api::Error> {
+        if self.group_index(group.id).is_some() {
+            return Err(api::Error::Invalid(format!("Group {} already exists", group.id)));
+        }
+        let (index, level) = match group.parent {
+            Some(parent) => match self.group_index(parent) {
+                Some(i) => (self.subtree_end(i), self.file.groups[i].level + 1),
+                None => return Err(api::Error::NoSuchGroup(parent)),
+            },
+            None => (self.file.groups.len(), 0),
+        };
+        self.file.groups.insert(
+            index,
+            KdbGroup {
+                group_id: group.id,
+                group_name: group.name.clone(),
+                created: group.created.clone(),
+                modified: group.modified.clone(),
+                accessed: group.accessed.clone(),
+                expires: group.expires.clone(),
+                image_id: GROUP_IMAGE_ID,
+                level: level,
+                flags: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn acquire_lock(&mut self, take_over: bool) -> Result<(), api::Error> {
+        if self.lock.is_some() {
+            return Ok(());
+        }
+        let path = Path::new(&self.path);
+        self.lock = Some(if take_over {
+            LockFile::take_over(path)?
+        } else {
+            LockFile::acquire(path)?
         });
+        Ok(())
     }
-    let kdb_file = match kdb::parser::parse_kdb_file(&buffer, password) {
-        Ok(f) => f,
-        Err(e) => {
-            return Err(api::Error {
-                desc: format!("Failed to parse file: {}", e),
-            })
+
+    /// New database which was never saved has changed if someone created the file.
+    fn changed_on_disk(&self) -> Result<bool, api::Error> {
+        let path = Path::new(&self.path);
+        let r = match self.disk {
+            Some(ref disk) => disk.changed(path),
+            None => Ok(path.exists()),
+        };
+        r.map_err(|e| api::Error::from(e).context(&format!("Error checking \"{}\"", self.path)))
+    }
+
+    fn reload(&mut self) -> Result<(), api::Error> {
+        let (file, disk) = read_kdb_file(&self.path, &self.master_key)?;
+        self.base = Some(file.clone());
+        self.file = file;
+        self.disk = Some(disk);
+        debug!("reload: Reloaded \"{}\"", self.path);
+        Ok(())
+    }
+
+    fn load_disk_version(&self) -> Result<Box<PasswordDatabase>, api::Error> {
+        let (file, disk) = read_kdb_file(&self.path, &self.master_key)?;
+        Ok(Box::new(KdbDatabase {
+            path: self.path.clone(),
+            master_key: self.master_key,
+            file: file,
+            backups: self.backups,
+            lock: None,
+            disk: Some(disk),
+            base: None,
+        }))
+    }
+
+    fn load_base_version(&self) -> Option<Box<PasswordDatabase>> {
+        self.base.as_ref().map(|base| {
+            Box::new(KdbDatabase {
+                path: self.path.clone(),
+                master_key: self.master_key,
+                file: base.clone(),
+                backups: self.backups,
+                lock: None,
+                disk: None,
+                base: None,
+            }) as Box<PasswordDatabase>
+        })
+    }
+
+    /// Seeds are regenerated on every save anyway, so there's nothing else to do.
+    fn change_master_key(&mut self, password: &str, key_file: Option<&str>) -> Result<(), api::Error> {
+        if password.is_empty() && key_file.is_none() {
+            return Err(api::Error::Invalid(String::from("Password or key file is needed")));
         }
+        self.master_key = master_key(password, key_file)?;
+        Ok(())
+    }
+
+    fn get_key_transform_rounds(&self) -> u32 {
+        self.file.header.key_enc_rounds
+    }
+
+    fn set_key_transform_rounds(&mut self, rounds: u32) -> Result<(), api::Error> {
+        if rounds == 0 {
+            return Err(api::Error::Invalid(String::from("Key transformation rounds must be positive")));
+        }
+        self.file.header.key_enc_rounds = rounds;
+        Ok(())
+    }
+
+    fn get_info(&self) -> Vec<(String, String)> {
+        let h = &self.file.header;
+        vec![
+            (String::from("Path"), self.path.clone()),
+            (
+                String::from("Format"),
+                format!("KDB {}.{}", h.version >> 16, h.version & 0xFFFF),
+            ),
+            (String::from("Cipher"), String::from("AES-256")),
+            (String::from("Key transformation rounds"), format!("{}", h.key_enc_rounds)),
+            (String::from("Groups"), format!("{}", self.file.groups.len())),
+            (String::from("Entries"), format!("{}", self.get_entry_count())),
+        ]
+    }
+}
+
+/// Read the whole file into memory.
+fn read_file(filename: &str) -> Result<Vec<u8>, std::io::Error> {
+    let file = std::fs::File::open(filename)?;
+    let mut reader = std::io::BufReader::new(file);
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Composite master key from password and optional key file path.
+fn master_key(password: &str, key_file: Option<&str>) -> Result<[u8; 32], api::Error> {
+    let key_file_data = match key_file {
+        Some(key_file) => match read_file(key_file) {
+            Ok(data) => Some(data),
+            Err(e) => return Err(api::Error::from(e).context("Error reading key file")),
+        },
+        None => None,
+    };
+    Ok(kdb::key::composite_master_key(
+        password,
+        key_file_data.as_ref().map(|d| d.as_slice()),
+    ))
+}
+
+/// Read and decrypt KDB file, along with its state for noticing changes later.
+fn read_kdb_file(filename: &str, master_key: &[u8; 32]) -> Result<(KdbFile, DiskState), api::Error> {
+    let r = read_file(filename).and_then(|buffer| {
+        let disk = DiskState::new(Path::new(filename), &buffer)?;
+        Ok((buffer, disk))
+    });
+    let (buffer, disk) = match r {
+        Ok(r) => r,
+        Err(e) => return Err(api::Error::from(e).context(&format!("Error reading \"{}\"", filename))),
+    };
+    let kdb_file = kdb::parser::parse_kdb_file(&buffer, master_key)?;
+    Ok((kdb_file, disk))
+}
+
+/// Open KDB file, key_file is the path to optional key file.
+/// Opening doesn't lock the file, so that just reading it works while someone else is editing it.
+/// Callers that edit call `acquire_lock` after opening (the controller does, for the apps), otherwise
+/// the lock is taken by the first save, which fails if someone else has the file locked by then.
+pub fn open(filename: &str, password: &str, key_file: Option<&str>) -> Result<KdbDatabase, api::Error> {
+    debug!("open: About to open \"{}\"...", filename);
+    let master_key = master_key(password, key_file)?;
+    let (kdb_file, disk) = read_kdb_file(filename, &master_key)?;
+    Ok(KdbDatabase {
+        path: String::from(filename),
+        master_key: master_key,
+        base: Some(kdb_file.clone()),
+        file: kdb_file,
+        backups: file::DEFAULT_BACKUP_COUNT,
+        lock: None,
+        disk: Some(disk),
+    })
+}
+
+/// Create empty database, nothing is written until save.
+/// Seeds and IV are random, and generated again each time the file is written.
+pub fn create(filename: &str, password: &str, key_file: Option<&str>) -> Result<KdbDatabase, api::Error> {
+    let master_key = master_key(password, key_file)?;
+    let mut header = KdbHeader {
+        signature_1: kdb::parser::PWM_DBSIG_1,
+        signature_2: kdb::parser::PWM_DBSIG_2,
+        flags: kdb::parser::PWM_FLAG_SHA2 | kdb::parser::PWM_FLAG_RIJNDAEL,
+        version: kdb::parser::PWM_DBVER_DW,
+        master_seed: [0; 16],
+        enc_iv: [0; 16],
+        group_count: 0,
+        entry_count: 0,
+        contents_hash: [0; 32],
+        master_seed_2: [0; 32],
+        key_enc_rounds: DEFAULT_KEY_ENC_ROUNDS,
     };
-    Ok(KdbDatabase { file: kdb_file })
+    kdb::writer::random_bytes(&mut header.master_seed)?;
+    kdb::writer::random_bytes(&mut header.enc_iv)?;
+    kdb::writer::random_bytes(&mut header.master_seed_2)?;
+    Ok(KdbDatabase {
+        path: String::from(filename),
+        master_key: master_key,
+        file: KdbFile {
+            header: header,
+            groups: Vec::new(),
+            entries: Vec::new(),
+        },
+        backups: file::DEFAULT_BACKUP_COUNT,
+        lock: None,
+        disk: None,
+        base: None,
+    })
+}
+
+/// Create new database file with given rounds and top level groups, and write it.
+/// Existing file is not overwritten.
+pub fn create_new(
+    filename: &str,
+    password: &str,
+    key_file: Option<&str>,
+    rounds: u32,
+    groups: &[&str],
+) -> Result<KdbDatabase, api::Error> {
+    if std::path::Path::new(filename).exists() {
+        return Err(api::Error::Invalid(format!("File \"{}\" already exists", filename)));
+    }
+    if password.is_empty() && key_file.is_none() {
+        return Err(api::Error::Invalid(String::from("Password or key file is needed")));
+    }
+    let mut db = create(filename, password, key_file)?;
+    db.set_key_transform_rounds(rounds)?;
+    for name in groups {
+        db.add_group(name, None)?;
+    }
+    db.save()?;
+    debug!("create_new: Created \"{}\" with {} groups", filename, groups.len());
+    Ok(db)
+}
+
+/// Copy entries of src (all, or group and its subgroups) to new database file.
+/// Existing file is only replaced if overwrite is set.
+/// Returns number of copied entries.
+pub fn export(
+    src: &PasswordDatabase,
+    group: Option<GroupId>,
+    filename: &str,
+    password: &str,
+    key_file: Option<&str>,
+    overwrite: bool,
+) -> Result<usize, api::Error> {
+    if !overwrite && std::path::Path::new(filename).exists() {
+        return Err(api::Error::Invalid(format!("File \"{}\" already exists", filename)));
+    }
+    let mut out = create(filename, password, key_file)?;
+    let count = api::copy_into(src, group, &mut out, None)?;
+    // Never saved database would take any existing file as changed by someone else.
+    out.overwrite()?;
+    debug!("export: Wrote {} entries to \"{}\"", count, filename);
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_existing_file() {
+        let path = std::env::temp_dir().join(format!("mpkdb-test-export-{}.kdb", Uuid::new_v4()));
+        let path = path.to_str().unwrap();
+        let mut src = create("unused.kdb", "secret", None).unwrap();
+        src.set_key_transform_rounds(10).unwrap();
+        let internet = src.add_group("Internet", None).unwrap();
+        src.add_entry(&Entry::new(internet, "Forum")).unwrap();
+        assert_eq!(export(&src, None, path, "other", None, false).unwrap(), 1);
+        match export(&src, None, path, "other", None, false) {
+            Err(api::Error::Invalid(desc)) => assert!(desc.contains("already exists"), "{}", desc),
+            r => panic!("{:?}", r),
+        }
+        src.add_entry(&Entry::new(internet, "Mail")).unwrap();
+        assert_eq!(export(&src, None, path, "other", None, true).unwrap(), 2);
+        assert_eq!(open(path, "other", None).unwrap().get_entry_count(), 2);
+        file::remove_test_files(Path::new(path));
+    }
 }
@@ -0,0 +1,207 @@
+//! KDB master key handling: turning password and key file into the key,
+//! and the key transformation done before decrypting/encrypting the file.
+
+use std;
+use std::time::{Duration, Instant};
+
+use crypto;
+use crypto::digest::Digest;
+use crypto::symmetriccipher::BlockEncryptor;
+
+/// Hash password string to 32-byte master key used in KDB.
+/// I think original KDB uses local windows ANSI encoding for this.
+/// But most passwords do not contain "national" characters...
+pub fn password_to_master_key(password: &str) -> [u8; 32] {
+    let mut sha = crypto::sha2::Sha256::new();
+    let sb: Vec<u8> = password.as_bytes().iter().map(|b| *b).collect();
+    sha.input(&sb);
+    let mut rb: [u8; 32] = [0; 32];
+    sha.result(&mut rb);
+    rb
+}
+
+/// Turn key file contents into 32-byte key, the way KeePass 1.x does:
+/// 32 bytes are used as is, 64 hex digits are decoded, anything else is hashed.
+pub fn key_file_to_key(data: &[u8]) -> [u8; 32] {
+    let mut rb: [u8; 32] = [0; 32];
+    if data.len() == 32 {
+        rb.copy_from_slice(data);
+        return rb;
+    }
+    if data.len() == 64 && data.iter().all(|b| b.is_ascii_hexdigit()) {
+        for (i, pair) in data.chunks(2).enumerate() {
+            let digits = std::str::from_utf8(pair).expect("hex digits are ASCII");
+            rb[i] = u8::from_str_radix(digits, 16).expect("checked hex digits");
+        }
+        return rb;
+    }
+    let mut sha = crypto::sha2::Sha256::new();
+    sha.input(data);
+    sha.result(&mut rb);
+    rb
+}
+
+/// Master key from password and/or key file.
+/// With both present, KDB hashes the password hash together with the key file key.
+pub fn composite_master_key(password: &str, key_file: Option<&[u8]>) -> [u8; 32] {
+    match key_file {
+        None => password_to_master_key(password),
+        Some(data) if password.is_empty() => key_file_to_key(data),
+        Some(data) => {
+            let mut sha = crypto::sha2::Sha256::new();
+            sha.input(&password_to_master_key(password));
+            sha.input(&key_file_to_key(data));
+            let mut rb: [u8; 32] = [0; 32];
+            sha.result(&mut rb);
+            rb
+        }
+    }
+}
+
+/// Encrypt 16-byte block with AES `rounds` times.
+fn transform_half<E: BlockEncryptor>(encryptor: &E, block: [u8; 16], rounds: u32) -> [u8; 16] {
+    let mut block = block;
+    let mut out: [u8; 16] = [0; 16];
+    for _ in 0..rounds {
+        encryptor.encrypt_block(&block, &mut out);
+        block = out;
+    }
+    block
+}
+
+/// Both halves are independent, so right one is done on another thread.
+fn transform_halves<E>(encryptor: E, left: [u8; 16], right: [u8; 16], rounds: u32) -> ([u8; 16], [u8; 16])
+where
+    E: BlockEncryptor + Copy + Send + 'static,
+{
+    let right_thread = std::thread::spawn(move || transform_half(&encryptor, right, rounds));
+    let left = transform_half(&encryptor, left, rounds);
+    let right = right_thread.join().expect("Key transformation thread panicked");
+    (left, right)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn transform_key_halves(seed: &[u8; 32], left: [u8; 16], right: [u8; 16], rounds: u32) -> ([u8; 16], [u8; 16]) {
+    if crypto::util::supports_aesni() {
+        debug!("transform_key_halves: Using AES-NI");
+        let encryptor = crypto::aesni::AesNiEncryptor::new(crypto::aes::KeySize::KeySize256, seed);
+        return transform_halves(encryptor, left, right, rounds);
+    }
+    let encryptor = crypto::aessafe::AesSafe256Encryptor::new(seed);
+    transform_halves(encryptor, left, right, rounds)
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+fn transform_key_halves(seed: &[u8; 32], left: [u8; 16], right: [u8; 16], rounds: u32) -> ([u8; 16], [u8; 16]) {
+    let encryptor = crypto::aessafe::AesSafe256Encryptor::new(seed);
+    transform_halves(encryptor, left, right, rounds)
+}
+
+/// Encrypt master key (provided by user) with master key seed (loaded from file).
+/// Left and right halves of the key are each encrypted `key_enc_rounds` times with AES-256,
+/// keyed by master_seed_2, then hashed together with master_seed.
+pub fn transform_master_key(
+    master_key: [u8; 32],
+    master_seed: [u8; 16],
+    master_seed_2: [u8; 32],
+    key_enc_rounds: u32,
+) -> [u8; 32] {
+    let mut left: [u8; 16] = [0; 16];
+    left.copy_from_slice(&master_key[..16]);
+    let mut right: [u8; 16] = [0; 16];
+    right.copy_from_slice(&master_key[16..]);
+    let (left, right) = transform_key_halves(&master_seed_2, left, right, key_enc_rounds);
+    let mut encrypted_master_key: [u8; 32] = [0; 32];
+    encrypted_master_key[..16].copy_from_slice(&left);
+    encrypted_master_key[16..].copy_from_slice(&right);
+    // Now sha256 hash it..
+    let mut sha = crypto::sha2::Sha256::new();
+    sha.input(&encrypted_master_key);
+    let mut transformed_master_key: [u8; 32] = [0; 32];
+    sha.result(&mut transformed_master_key);
+    // And again hash it, but this time with master_seed.
+    let mut sha = crypto::sha2::Sha256::new();
+    sha.input(&master_seed);
+    sha.input(&transformed_master_key);
+    sha.result(&mut transformed_master_key);
+    transformed_master_key
+}
+
+fn as_secs_f64(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+/// Number of key transformation rounds taking about `target` time on this machine,
+/// like "1 second delay" in KeePass.
+pub fn calibrate_rounds(target: Duration) -> u32 {
+    // Short runs are too noisy, measure for at least 100 ms.
+    let mut rounds: u32 = 10000;
+    loop {
+        let start = Instant::now();
+        transform_master_key([0; 32], [0; 16], [0; 32], rounds);
+        let elapsed = as_secs_f64(start.elapsed());
+        if elapsed >= 0.1 || rounds > u32::max_value() / 2 {
+            let r = rounds as f64 * as_secs_f64(target) / elapsed;
+            debug!("calibrate_rounds: {} rounds in {:.3} s, {:.0} rounds for target", rounds, elapsed, r);
+            return r.max(1.0).min(u32::max_value() as f64) as u32;
+        }
+        rounds *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crypto;
+
+    use super::*;
+
+    /// Straightforward ECB transformation, the way it was done before.
+    fn reference_transform_half(block: &[u8; 16], seed: &[u8; 32], rounds: u32) -> [u8; 16] {
+        let mut block = *block;
+        for _ in 0..rounds {
+            // Encryptor is done after eof, so new one is needed every round.
+            let mut encryptor = crypto::aes::ecb_encryptor(
+                crypto::aes::KeySize::KeySize256,
+                seed,
+                crypto::blockmodes::NoPadding,
+            );
+            let mut out: [u8; 16] = [0; 16];
+            {
+                let mut reader = crypto::buffer::RefReadBuffer::new(&block);
+                let mut writer = crypto::buffer::RefWriteBuffer::new(&mut out);
+                encryptor.encrypt(&mut reader, &mut writer, true).unwrap();
+            }
+            block = out;
+        }
+        block
+    }
+
+    #[test]
+    fn test_key_file_to_key() {
+        let hex = b"000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F";
+        let expected: Vec<u8> = (0..32).collect();
+        assert_eq!(key_file_to_key(hex).to_vec(), expected);
+        // 64 bytes of text that isn't hex, with multi-byte characters, is hashed.
+        let text = "la clé secrète à ne pas perdre, écrite à la main dans un fi";
+        assert_eq!(text.len(), 64);
+        let mut sha = crypto::sha2::Sha256::new();
+        sha.input(text.as_bytes());
+        let mut hashed: [u8; 32] = [0; 32];
+        sha.result(&mut hashed);
+        assert_eq!(key_file_to_key(text.as_bytes()), hashed);
+    }
+
+    #[test]
+    fn test_transform_matches_ecb() {
+        let seed: [u8; 32] = [7; 32];
+        let left: [u8; 16] = [1; 16];
+        let right: [u8; 16] = [2; 16];
+        let expected = (
+            reference_transform_half(&left, &seed, 1000),
+            reference_transform_half(&right, &seed, 1000),
+        );
+        assert_eq!(transform_key_halves(&seed, left, right, 1000), expected);
+        let safe = crypto::aessafe::AesSafe256Encryptor::new(&seed);
+        assert_eq!(transform_halves(safe, left, right, 1000), expected);
+    }
+}
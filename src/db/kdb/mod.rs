@@ -1,6 +1,12 @@
-
-//! KDB file support.
-//! TODO: export to library.
+//! KDB (KeePass 1.x) file support.
+//!
+//! Use `open` to read existing file and `create` for a new one, both return `KdbDatabase`,
+//! which implements `db::api::PasswordDatabase`.
 
 pub mod db;
+mod key;
 mod parser;
+mod writer;
+
+pub use self::db::{create, create_new, export, open, KdbDatabase, DEFAULT_GROUPS, DEFAULT_KEY_ENC_ROUNDS};
+pub use self::key::{calibrate_rounds, transform_master_key};
@@ -3,61 +3,36 @@
 //! TODO: Don't need Nom I think, KDB format is too small.
 
 use std;
-use std::io::BufRead;
+use std::io::Cursor;
+use std::io::Read;
 
 use crypto;
+use crypto::buffer::{ReadBuffer, WriteBuffer};
 use crypto::digest::Digest;
 use nom;
-use uuid;
+use time;
 use uuid::Uuid;
 
-const PWM_DBSIG_1: u32 = 0x9AA2D903;
-const PWM_DBSIG_2: u32 = 0xB54BFB65;
+use db::kdb::key;
+use errors::Error;
+
+pub const PWM_DBSIG_1: u32 = 0x9AA2D903;
+pub const PWM_DBSIG_2: u32 = 0xB54BFB65;
 // const PWM_DBSIG_1_KDBX_P: u32 = 0x9AA2D903;
 // const PWM_DBSIG_1_KDBX_R: u32 = 0x9AA2D903;
-// const PWM_DBVER_DW: u32 = 0x00030004;
-const PWM_FLAG_RIJNDAEL: u32 = 2;
+pub const PWM_DBVER_DW: u32 = 0x00030004;
+pub const PWM_FLAG_SHA2: u32 = 1;
+pub const PWM_FLAG_RIJNDAEL: u32 = 2;
 const PWM_FLAG_TWOFISH: u32 = 8;
 
-type DateTuple = (u16, u8, u8);
-type TimeTuple = (u8, u8, u8);
-type DateTimeTuple = (DateTuple, TimeTuple);
-
-/// Header size in file.
-const HEADER_SIZE: usize = 4 + 4 + 4 + 4 + 16 + 16 + 4 + 4 + 32 + 32 + 4;
+pub type DateTuple = (u16, u8, u8);
+pub type TimeTuple = (u8, u8, u8);
+pub type DateTimeTuple = (DateTuple, TimeTuple);
 
-#[derive(Debug)]
-pub struct Error {
-    pub desc: String,
-}
+pub use db::api::NEVER_EXPIRES;
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "KDB Parse Error: {}", self.desc)
-    }
-}
-
-impl std::error::Error for Error {
-    fn description(&self) -> &str {
-        &self.desc
-    }
-}
-
-impl std::convert::From<std::io::Error> for Error {
-    fn from(e: std::io::Error) -> Error {
-        Error {
-            desc: format!("IO Error: {}", e),
-        }
-    }
-}
-
-impl std::convert::From<uuid::ParseError> for Error {
-    fn from(e: uuid::ParseError) -> Error {
-        Error {
-            desc: format!("UUID Parse Error: {}", e),
-        }
-    }
-}
+/// Header size in file.
+pub const HEADER_SIZE: usize = 4 + 4 + 4 + 4 + 16 + 16 + 4 + 4 + 32 + 32 + 4;
 
 /// KDB file encryption algo, currently only AES is supported.
 #[derive(Debug)]
@@ -68,57 +43,72 @@ enum EncryptionAlgorithm {
 
 /// KDB password file header structure.
 /// Based on PwStructs.h.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct KdbHeader {
-    signature_1: u32,
-    signature_2: u32,
-    flags: u32,
-    version: u32,
-    master_seed: [u8; 16],
-    enc_iv: [u8; 16],
-    group_count: u32,
-    entry_count: u32,
+    pub signature_1: u32,
+    pub signature_2: u32,
+    pub flags: u32,
+    pub version: u32,
+    pub master_seed: [u8; 16],
+    pub enc_iv: [u8; 16],
+    pub group_count: u32,
+    pub entry_count: u32,
     /// Hash of the encrypted file contents.
     /// TODO: Actually check the hash.
-    contents_hash: [u8; 32],
+    pub contents_hash: [u8; 32],
     /// Used to transform password into decryption key.
-    master_seed_2: [u8; 32],
-    key_enc_rounds: u32,
+    pub master_seed_2: [u8; 32],
+    pub key_enc_rounds: u32,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct KdbGroup {
-    group_id: u32,
-    group_name: String,
+    pub group_id: u32,
+    pub group_name: String,
     /// Format: 2017-10-23 00:21:00
-    created: String,
-    modified: String,
-    accessed: String,
-    expires: String,
+    pub created: String,
+    pub modified: String,
+    pub accessed: String,
+    pub expires: String,
+    pub image_id: u32,
+    /// Depth in group tree, groups are stored in tree order, so parent of a group
+    /// is the closest preceding group with level one less.
+    pub level: u16,
+    pub flags: u32,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct KdbEntry {
     /// UUID
-    entry_id: Uuid,
-    group_id: u32,
-    title: String,
-    url: String,
-    username: String,
-    password: String,
-    notes: String,
-    created: String,
-    modified: String,
-    accessed: String,
-    expires: String,
-    // binary_description
-    // binary_data
+    pub entry_id: Uuid,
+    pub group_id: u32,
+    pub image_id: u32,
+    pub title: String,
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    pub notes: String,
+    pub created: String,
+    pub modified: String,
+    pub accessed: String,
+    pub expires: String,
+    pub binary_desc: String,
+    pub binary_data: Vec<u8>,
 }
 
-#[derive(Debug)]
+impl KdbEntry {
+    /// KeePass stores some of its own data (custom icons, UI state etc) as special entries,
+    /// these should be kept in file, but not shown to user.
+    pub fn is_meta_stream(&self) -> bool {
+        self.binary_desc == "bin-stream" && self.title == "Meta-Info" && self.username == "SYSTEM" &&
+            self.url == "$" && !self.notes.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct KdbFile {
-    header: KdbHeader,
-    groups: Vec<KdbGroup>,
+    pub header: KdbHeader,
+    pub groups: Vec<KdbGroup>,
     pub entries: Vec<KdbEntry>,
 }
 
@@ -182,100 +172,8 @@ fn format_bytes(bytes: &[u8]) -> String {
     s
 }
 
-/// Hash password string to 32-byte master key used in KDB.
-/// I think original KDB uses local windows ANSI encoding for this.
-/// But most passwords do not contain "national" characters...
-fn password_to_master_key(password: &str) -> [u8; 32] {
-    let mut sha = crypto::sha2::Sha256::new();
-    let sb: Vec<u8> = password.as_bytes().iter().map(|b| *b).collect();
-    sha.input(&sb);
-    let mut rb: [u8; 32] = [0; 32];
-    sha.result(&mut rb);
-    rb
-}
-
-/// Encrypt master key (provided by user) with master key seed (loaded from file).
-/// Probably could be cleaned up a litte...
-fn transform_master_key(
-    master_key: [u8; 32],
-    master_seed: [u8; 16],
-    master_seed_2: [u8; 32],
-    key_enc_rounds: u32,
-) -> [u8; 32] {
-    // Transform256
-    let master_key_left: [u8; 16] = {
-        let mut b: [u8; 16] = [0; 16];
-        b.copy_from_slice(&master_key[..16]);
-        b
-    };
-    let master_key_right: [u8; 16] = {
-        let mut b: [u8; 16] = [0; 16];
-        b.copy_from_slice(&master_key[16..]);
-        b
-    };
-    let mut transformed_key_left: [u8; 16] = [0; 16];
-    let mut transformed_key_right: [u8; 16] = [0; 16];
-    // both "left" and "right" are independently crypted using aes
-    // each of them key_enc_rounds-rounds
-    transformed_key_left.copy_from_slice(&master_key_left);
-    for _ in 0..key_enc_rounds {
-        let mut outbuf: [u8; 16] = [0; 16];
-        {
-            let mut outwrite = crypto::buffer::RefWriteBuffer::new(&mut outbuf);
-            let mut reader = crypto::buffer::RefReadBuffer::new(&transformed_key_left);
-            let mut encryptor = crypto::aes::ecb_encryptor(
-                crypto::aes::KeySize::KeySize256,
-                &master_seed_2,
-                crypto::blockmodes::NoPadding,
-            );
-            if let Err(e) = encryptor.encrypt(&mut reader, &mut outwrite, true) {
-                panic!("Failed to encrypt master key, error: {:?}", e);
-            }
-        }
-        transformed_key_left.copy_from_slice(&outbuf);
-    }
-    transformed_key_right.copy_from_slice(&master_key_right);
-    for _ in 0..key_enc_rounds {
-        let mut outbuf: [u8; 16] = [0; 16];
-        {
-            let mut outwrite = crypto::buffer::RefWriteBuffer::new(&mut outbuf);
-            let mut reader = crypto::buffer::RefReadBuffer::new(&transformed_key_right);
-            let mut encryptor = crypto::aes::ecb_encryptor(
-                crypto::aes::KeySize::KeySize256,
-                &master_seed_2,
-                crypto::blockmodes::NoPadding,
-            );
-            if let Err(e) = encryptor.encrypt(&mut reader, &mut outwrite, true) {
-                panic!("Failed to encrypt master key, error: {:?}", e);
-            }
-        }
-        transformed_key_right.copy_from_slice(&outbuf);
-    }
-    let encrypted_master_key: [u8; 32] = {
-        let mut t: [u8; 32] = [0; 32];
-        for i in 0..16 {
-            t[i] = transformed_key_left[i];
-        }
-        for i in 0..16 {
-            t[i + 16] = transformed_key_right[i];
-        }
-        t
-    };
-    // Now sha256 hash it..
-    let mut sha = crypto::sha2::Sha256::new();
-    sha.input(&encrypted_master_key);
-    let mut transformed_master_key: [u8; 32] = [0; 32];
-    sha.result(&mut transformed_master_key);
-    // And again hash it, but this time with master_seed.
-    let mut sha = crypto::sha2::Sha256::new();
-    sha.input(&master_seed);
-    sha.input(&transformed_master_key);
-    sha.result(&mut transformed_master_key);
-    transformed_master_key
-}
-
 /// Read little endian 16 bit unsigned int.
-fn read_u16<R: BufRead>(src: &mut R) -> Result<u16, Error> {
+fn read_u16<R: Read>(src: &mut R) -> std::io::Result<u16> {
     let mut buf: [u8; 2] = [0; 2];
     src.read_exact(&mut buf)?;
     // Note: Plus is stronger than bit-shift.
@@ -289,9 +187,9 @@ fn bytes_to_u32(buf: [u8; 4]) -> u32 {
     r
 }
 
-fn slice_to_u32(buf: &[u8]) -> Result<u32, Error> {
+fn slice_to_u32(buf: &[u8]) -> Result<u32, String> {
     if buf.len() != 4 {
-        return Err(Error{desc: format!("Can't convert to u32, expected 4 bytes, got {}", buf.len())});
+        return Err(format!("Can't convert to u32, expected 4 bytes, got {}", buf.len()));
     }
     let mut arr: [u8; 4] = [0; 4];
     arr.copy_from_slice(buf);
@@ -299,7 +197,7 @@ fn slice_to_u32(buf: &[u8]) -> Result<u32, Error> {
 }
 
 /// Read little endian (least significant byte first).
-fn read_u32<R: BufRead>(src: &mut R) -> Result<u32, Error> {
+fn read_u32<R: Read>(src: &mut R) -> std::io::Result<u32> {
     let mut buf: [u8; 4] = [0; 4];
     src.read_exact(&mut buf)?;
     let r = bytes_to_u32(buf);
@@ -325,9 +223,9 @@ fn parse_datetime(b: [u8; 5]) -> DateTimeTuple {
     ((year, month, day), (hour, minute, sec))
 }
 
-fn parse_datetime_slice(b: &[u8]) -> Result<DateTimeTuple, Error> {
+fn parse_datetime_slice(b: &[u8]) -> Result<DateTimeTuple, String> {
     if b.len() != 5 {
-        return Err(Error{desc: format!("Failed to parse date-time: expected 5 bytes, got {}", b.len())});
+        return Err(format!("Failed to parse date-time: expected 5 bytes, got {}", b.len()));
     }
     // Again: copying but it's just too small to bother with refs.
     let mut byte_arr: [u8; 5] = [0; 5];
@@ -341,7 +239,7 @@ fn format_date_time(dt: &DateTimeTuple) -> String {
     let t = dt.1;
     format!(
         "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
-        d.1,
+        d.0,
         d.1,
         d.2,
         t.0,
@@ -349,19 +247,61 @@ fn format_date_time(dt: &DateTimeTuple) -> String {
         t.2)
 }
 
-/// Read group from stream.
-fn read_group<R: BufRead>(src: &mut R) -> Result<KdbGroup, Error> {
+/// Parse date/time string, as formatted by format_date_time.
+pub fn parse_date_time(s: &str) -> Option<DateTimeTuple> {
+    let t = match time::strptime(s, "%Y-%m-%d %H:%M:%S") {
+        Ok(t) => t,
+        Err(_) => return None,
+    };
+    Some((
+        ((t.tm_year + 1900) as u16, (t.tm_mon + 1) as u8, t.tm_mday as u8),
+        (t.tm_hour as u8, t.tm_min as u8, t.tm_sec as u8),
+    ))
+}
+
+/// Field string data is NUL terminated.
+fn field_to_string(field_data: &[u8]) -> String {
+    let end = match field_data.last() {
+        Some(&0) => field_data.len() - 1,
+        _ => field_data.len(),
+    };
+    String::from(String::from_utf8_lossy(&field_data[0..end]))
+}
+
+/// Read field type and data.
+fn read_field(src: &mut Cursor<&[u8]>) -> Result<(u16, Vec<u8>), String> {
+    let eof = |_| String::from("Unexpected end of data");
+    let field_type = read_u16(src).map_err(&eof)?;
+    let field_size = read_u32(src).map_err(&eof)?;
+    let remaining = src.get_ref().len() as u64 - src.position();
+    if field_size as u64 > remaining {
+        return Err(format!("Field size {} exceeds remaining {} bytes", field_size, remaining));
+    }
+    let mut field_data: Vec<u8> = vec![0; field_size as usize];
+    src.read_exact(&mut field_data).map_err(&eof)?;
+    Ok((field_type, field_data))
+}
+
+/// Read group from decrypted contents.
+fn read_group(src: &mut Cursor<&[u8]>) -> Result<KdbGroup, Error> {
     let mut group_id = 0;
     let mut group_name = String::new();
     let mut created = String::new();
     let mut modified = String::new();
     let mut accessed = String::new();
     let mut expires = String::new();
+    let mut image_id = 0;
+    let mut level = 0;
+    let mut flags = 0;
     loop {
-        let field_type: u16 = read_u16(src)?;
-        let field_size: u32 = read_u32(src)?;
-        let mut field_data: Vec<u8> = vec![0; field_size as usize];
-        src.read_exact(&mut field_data)?;
+        // Contents are encrypted with block cipher, so offsets in contents are offsets in file.
+        let offset = (HEADER_SIZE as u64) + src.position();
+        let corrupt = |desc: String| Error::Corrupted {
+            offset: offset,
+            desc: desc,
+        };
+        let (field_type, field_data) = read_field(src).map_err(&corrupt)?;
+        let field_size = field_data.len();
         match field_type {
             // End of record
             0xFFFF => {
@@ -374,12 +314,10 @@ fn read_group<R: BufRead>(src: &mut R) -> Result<KdbGroup, Error> {
             // Group ID
             0x0001 => {
                 if field_size != 4 {
-                    return Err(Error {
-                        desc: format!(
+                    return Err(corrupt(format!(
                             "Invalid field size for Group ID: {}, expected 4 bytes",
                             field_size
-                        ),
-                    });
+                        )));
                 }
                 let mut bytes: [u8; 4] = [0; 4];
                 for i in 0..4 {
@@ -389,34 +327,35 @@ fn read_group<R: BufRead>(src: &mut R) -> Result<KdbGroup, Error> {
             }
             // Group Name
             0x0002 => {
-                group_name = String::from(String::from_utf8_lossy(&field_data));
+                group_name = field_to_string(&field_data);
             }
             // Creation Time
             0x0003 => {
-                created = format_date_time(&parse_datetime_slice(&field_data)?);
+                created = format_date_time(&parse_datetime_slice(&field_data).map_err(&corrupt)?);
             }
             0x0004 => {
-                modified = format_date_time(&parse_datetime_slice(&field_data)?);
+                modified = format_date_time(&parse_datetime_slice(&field_data).map_err(&corrupt)?);
             }
             0x0005 => {
-                accessed = format_date_time(&parse_datetime_slice(&field_data)?);
+                accessed = format_date_time(&parse_datetime_slice(&field_data).map_err(&corrupt)?);
             }
             0x0006 => {
-                expires = format_date_time(&parse_datetime_slice(&field_data)?);
+                expires = format_date_time(&parse_datetime_slice(&field_data).map_err(&corrupt)?);
             }
             0x0007 => {
-                // TODO: icon
+                image_id = slice_to_u32(&field_data).map_err(&corrupt)?;
             }
             0x0008 => {
-                // TODO: level
+                if field_size != 2 {
+                    return Err(corrupt(format!("Invalid field size for Group Level: {}, expected 2 bytes", field_size)));
+                }
+                level = field_data[0] as u16 + ((field_data[1] as u16) << 8);
             }
             0x0009 => {
-                // TODO: flags
+                flags = slice_to_u32(&field_data).map_err(&corrupt)?;
             }
             _ => {
-                return Err(Error {
-                    desc: format!("Unknown field type: {} ({:0x})", field_type, field_type),
-                });
+                return Err(corrupt(format!("Unknown field type: {} ({:0x})", field_type, field_type)));
             }
         }
     }
@@ -427,11 +366,14 @@ fn read_group<R: BufRead>(src: &mut R) -> Result<KdbGroup, Error> {
         modified: modified,
         accessed: accessed,
         expires: expires,
+        image_id: image_id,
+        level: level,
+        flags: flags,
     })
 }
 
-/// Read entry from stream.
-fn read_entry<R: BufRead>(src: &mut R) -> Result<KdbEntry, Error> {
+/// Read entry from decrypted contents.
+fn read_entry(src: &mut Cursor<&[u8]>) -> Result<KdbEntry, Error> {
     let mut entry_id: Uuid = Uuid::nil();
     let mut group_id: u32 = 0;
     let mut title: String = String::new();
@@ -443,11 +385,17 @@ fn read_entry<R: BufRead>(src: &mut R) -> Result<KdbEntry, Error> {
     let mut modified: String = String::new();
     let mut accessed: String = String::new();
     let mut expires: String = String::new();
+    let mut image_id: u32 = 0;
+    let mut binary_desc: String = String::new();
+    let mut binary_data: Vec<u8> = Vec::new();
     loop {
-        let field_type: u16 = read_u16(src)?;
-        let field_size: u32 = read_u32(src)?;
-        let mut field_data: Vec<u8> = vec![0; field_size as usize];
-        src.read_exact(&mut field_data)?;
+        // Contents are encrypted with block cipher, so offsets in contents are offsets in file.
+        let offset = (HEADER_SIZE as u64) + src.position();
+        let corrupt = |desc: String| Error::Corrupted {
+            offset: offset,
+            desc: desc,
+        };
+        let (field_type, field_data) = read_field(src).map_err(&corrupt)?;
         match field_type {
             0xFFFF => break,
             0x0000 => {
@@ -455,52 +403,50 @@ fn read_entry<R: BufRead>(src: &mut R) -> Result<KdbEntry, Error> {
             }
             0x0001 => {
                 // Entry ID
-                entry_id = Uuid::from_bytes(&field_data)?;
+                entry_id = Uuid::from_bytes(&field_data).map_err(|e| corrupt(format!("Invalid UUID: {}", e)))?;
             }
             0x0002 => {
                 // Group ID
-                group_id = slice_to_u32(&field_data)?;
+                group_id = slice_to_u32(&field_data).map_err(&corrupt)?;
             }
             0x0003 => {
-                // TODO: Icon
+                image_id = slice_to_u32(&field_data).map_err(&corrupt)?;
             }
             0x0004 => {
-                title = String::from(String::from_utf8_lossy(&field_data[0..field_data.len()-1]));
+                title = field_to_string(&field_data);
             }
             0x0005 => {
-                url = String::from(String::from_utf8_lossy(&field_data[0..field_data.len()-1]));
+                url = field_to_string(&field_data);
             }
             0x0006 => {
-                username = String::from(String::from_utf8_lossy(&field_data[0..field_data.len()-1]));
+                username = field_to_string(&field_data);
             }
             0x0007 => {
-                password = String::from(String::from_utf8_lossy(&field_data[0..field_data.len()-1]));
+                password = field_to_string(&field_data);
             }
             0x0008 => {
-                notes = String::from(String::from_utf8_lossy(&field_data[0..field_data.len()-1]));
+                notes = field_to_string(&field_data);
             }
             0x0009 => {
-                created = format_date_time(&parse_datetime_slice(&field_data)?);
+                created = format_date_time(&parse_datetime_slice(&field_data).map_err(&corrupt)?);
             }
             0x000a => {
-                modified = format_date_time(&parse_datetime_slice(&field_data)?);
+                modified = format_date_time(&parse_datetime_slice(&field_data).map_err(&corrupt)?);
             }
             0x000b => {
-                accessed = format_date_time(&parse_datetime_slice(&field_data)?);
+                accessed = format_date_time(&parse_datetime_slice(&field_data).map_err(&corrupt)?);
             }
             0x000c => {
-                expires = format_date_time(&parse_datetime_slice(&field_data)?);
+                expires = format_date_time(&parse_datetime_slice(&field_data).map_err(&corrupt)?);
             }
             0x000d => {
-                // TODO: Binary Description
+                binary_desc = field_to_string(&field_data);
             }
             0x000e => {
-                // TODO: Binary Data
+                binary_data = field_data;
             }
             _ => {
-                return Err(Error {
-                    desc: format!("Unknown field type: {} ({:0x})", field_type, field_type),
-                });
+                return Err(corrupt(format!("Unknown field type: {} ({:0x})", field_type, field_type)));
             }
         }
     }
@@ -516,26 +462,64 @@ fn read_entry<R: BufRead>(src: &mut R) -> Result<KdbEntry, Error> {
         accessed: accessed,
         expires: expires,
         notes: notes,
+        image_id: image_id,
+        binary_desc: binary_desc,
+        binary_data: binary_data,
     })
 }
 
+/// Decrypt file contents (everything after header).
+fn decrypt_contents(header: &KdbHeader, master_key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let transformed_master_key = key::transform_master_key(
+        *master_key,
+        header.master_seed,
+        header.master_seed_2,
+        header.key_enc_rounds,
+    );
+    let mut decryptor = crypto::aes::cbc_decryptor(
+        crypto::aes::KeySize::KeySize256,
+        &transformed_master_key,
+        &header.enc_iv,
+        crypto::blockmodes::PkcsPadding,
+    );
+    let mut out: Vec<u8> = Vec::with_capacity(data.len());
+    let mut decryptor_input = crypto::buffer::RefReadBuffer::new(data);
+    let mut buf: [u8; 4096] = [0; 4096];
+    loop {
+        let mut decryptor_output = crypto::buffer::RefWriteBuffer::new(&mut buf);
+        let r = match decryptor.decrypt(&mut decryptor_input, &mut decryptor_output, true) {
+            Ok(r) => r,
+            Err(e) => {
+                // Bad padding is what we get with wrong key most of the time.
+                debug!("decrypt_contents: Decrypt error: {:?}", e);
+                return Err(Error::WrongKey);
+            }
+        };
+        out.extend_from_slice(decryptor_output.take_read_buffer().take_remaining());
+        if let crypto::buffer::BufferResult::BufferUnderflow = r {
+            break;
+        }
+    }
+    Ok(out)
+}
+
 /// Parse KDB file.
+/// Master key is the composite key from password and/or key file, see key::composite_master_key.
 /// TODO: Split into smaller functions.
-pub fn parse_kdb_file(bytes: &[u8], password: &str) -> Result<KdbFile, Error> {
+pub fn parse_kdb_file(bytes: &[u8], master_key: &[u8; 32]) -> Result<KdbFile, Error> {
     let r = kdb_header(bytes);
     match r {
         nom::IResult::Done(_, header) => {
             // first check the "signature"
             if header.signature_1 != PWM_DBSIG_1 || header.signature_2 != PWM_DBSIG_2 {
-                return Err(Error {
-                    desc: format!(
-                        "Invalid file signature: {:x} {:x} (expected {:x} {:x})",
-                        header.signature_1,
-                        header.signature_2,
-                        PWM_DBSIG_1,
-                        PWM_DBSIG_2
-                    ),
-                });
+                debug!(
+                    "Invalid file signature: {:x} {:x} (expected {:x} {:x})",
+                    header.signature_1,
+                    header.signature_2,
+                    PWM_DBSIG_1,
+                    PWM_DBSIG_2
+                );
+                return Err(Error::InvalidSignature);
             }
             // KDB has three versions, and we support newest one for now...
             // 0x00020000 -> v2
@@ -544,57 +528,43 @@ pub fn parse_kdb_file(bytes: &[u8], password: &str) -> Result<KdbFile, Error> {
             // 0x00010002 -> v1
             // ...
             let file_ver_major = header.version >> 16;
-            let file_ver_minor = header.version & 0x0000FFFF;
             if file_ver_major < 3 {
-                return Err(Error {
-                    desc: format!(
-                        "Unsupported DB version {}.{}",
-                        file_ver_major,
-                        file_ver_minor
-                    ),
-                });
+                return Err(Error::UnsupportedVersion(header.version));
             }
-            let _enc_algo = {
+            let enc_algo = {
                 if header.flags & PWM_FLAG_RIJNDAEL != 0 {
                     EncryptionAlgorithm::AES
                 } else if header.flags & PWM_FLAG_TWOFISH != 0 {
                     EncryptionAlgorithm::TwoFish
                 } else {
-                    return Err(Error {
-                        desc: format!("Unknown encryption algorithm, flags: {:x}", header.flags),
-                    });
+                    return Err(Error::UnsupportedCipher(format!("unknown, flags: {:x}", header.flags)));
                 }
             };
-            let master_key = password_to_master_key(password);
-            let transformed_master_key = transform_master_key(
-                master_key,
-                header.master_seed,
-                header.master_seed_2,
-                header.key_enc_rounds,
-            );
-            let mut decryptor = crypto::aes::cbc_decryptor(
-                crypto::aes::KeySize::KeySize256,
-                &transformed_master_key,
-                &header.enc_iv,
-                crypto::blockmodes::NoPadding,
-            );
-            let out: Vec<u8> = {
-                let mut mout: Vec<u8> = Vec::new();
-                mout.resize(bytes.len() - HEADER_SIZE, 0);
-                {
-                    let mut decryptor_input = crypto::buffer::RefReadBuffer::new(&bytes[HEADER_SIZE..]);
-                    let mut decryptor_output = crypto::buffer::RefWriteBuffer::new(&mut mout);
-                    if let Err(e) =  decryptor.decrypt(&mut decryptor_input, &mut decryptor_output, true) {
-                        return Err(Error {
-                            desc: format!("Decrypt error: {:?}", e),
-                        });
-                    }
-                }
-                mout
+            if let EncryptionAlgorithm::TwoFish = enc_algo {
+                return Err(Error::UnsupportedCipher(String::from("Twofish")));
+            }
+            let encrypted = &bytes[HEADER_SIZE..];
+            if encrypted.len() % 16 != 0 {
+                // Truncated file, decryption would fail as if the key was wrong.
+                return Err(Error::Corrupted {
+                    offset: bytes.len() as u64,
+                    desc: String::from("Encrypted data is incomplete"),
+                });
+            }
+            let out = decrypt_contents(&header, master_key, encrypted)?;
+            let contents_hash: [u8; 32] = {
+                let mut sha = crypto::sha2::Sha256::new();
+                sha.input(&out);
+                let mut h: [u8; 32] = [0; 32];
+                sha.result(&mut h);
+                h
             };
-            // TODO: Check hash.
+            if contents_hash != header.contents_hash {
+                // Decryption with wrong key can still end with valid padding.
+                return Err(Error::WrongKey);
+            }
             let (groups, entries) = {
-                let mut curs = std::io::Cursor::new(&out);
+                let mut curs = Cursor::new(&out[..]);
                 let mut groups = Vec::new();
                 for i in 0..header.group_count {
                     debug!("Reading group {}", i);
@@ -621,12 +591,14 @@ pub fn parse_kdb_file(bytes: &[u8], password: &str) -> Result<KdbFile, Error> {
         }
         nom::IResult::Error(e) => {
             error!("Failed to parse: {}", e);
-            Err(Error {
-                desc: format!("Parse error: {}", e),
+            Err(Error::Corrupted {
+                offset: 0,
+                desc: format!("Invalid header: {}", e),
             })
         }
-        nom::IResult::Incomplete(i) => Err(Error {
-            desc: format!("Parse error - incomplete input: {:?}", i),
+        nom::IResult::Incomplete(_) => Err(Error::Corrupted {
+            offset: bytes.len() as u64,
+            desc: String::from("File is too short"),
         }),
     }
 }
@@ -0,0 +1,194 @@
+//! Write KDB files.
+
+use crypto;
+use crypto::buffer::{ReadBuffer, WriteBuffer};
+use crypto::digest::Digest;
+use rand;
+use rand::Rng;
+
+use db::kdb::key;
+use db::kdb::parser;
+use db::kdb::parser::{KdbEntry, KdbFile, KdbGroup};
+use errors::Error;
+
+/// Fill buffer with random bytes from OS CSPRNG.
+pub fn random_bytes(buf: &mut [u8]) -> Result<(), Error> {
+    let mut rng = rand::OsRng::new()?;
+    rng.fill_bytes(buf);
+    Ok(())
+}
+
+fn write_u16(dst: &mut Vec<u8>, v: u16) {
+    dst.push((v & 0xFF) as u8);
+    dst.push((v >> 8) as u8);
+}
+
+/// Write little endian (least significant byte first).
+fn write_u32(dst: &mut Vec<u8>, v: u32) {
+    for i in 0..4 {
+        dst.push(((v >> (i * 8)) & 0xFF) as u8);
+    }
+}
+
+fn write_field(dst: &mut Vec<u8>, field_type: u16, data: &[u8]) {
+    write_u16(dst, field_type);
+    write_u32(dst, data.len() as u32);
+    dst.extend_from_slice(data);
+}
+
+fn write_u32_field(dst: &mut Vec<u8>, field_type: u16, v: u32) {
+    let mut data = Vec::with_capacity(4);
+    write_u32(&mut data, v);
+    write_field(dst, field_type, &data);
+}
+
+/// Strings are stored NUL terminated.
+fn write_string_field(dst: &mut Vec<u8>, field_type: u16, s: &str) {
+    let mut data = Vec::with_capacity(s.len() + 1);
+    data.extend_from_slice(s.as_bytes());
+    data.push(0);
+    write_field(dst, field_type, &data);
+}
+
+/// Pack date/time into 5 bytes, reverse of parser::parse_datetime.
+fn pack_datetime(dt: &parser::DateTimeTuple) -> [u8; 5] {
+    let ((year, month, day), (hour, minute, sec)) = *dt;
+    [
+        (year >> 6) as u8,
+        (((year & 0b111111) as u8) << 2) + (month >> 2),
+        ((month & 0b11) << 6) + (day << 1) + (hour >> 4),
+        ((hour & 0b1111) << 4) + (minute >> 2),
+        ((minute & 0b11) << 6) + sec,
+    ]
+}
+
+/// Date/time missing in the original file is written as "never".
+fn write_date_time_field(dst: &mut Vec<u8>, field_type: u16, s: &str) -> Result<(), Error> {
+    let s = if s.is_empty() { parser::NEVER_EXPIRES } else { s };
+    let dt = match parser::parse_date_time(s) {
+        Some(dt) => dt,
+        None => {
+            return Err(Error::Invalid(format!("Invalid date/time: \"{}\"", s)))
+        }
+    };
+    write_field(dst, field_type, &pack_datetime(&dt));
+    Ok(())
+}
+
+fn write_group(dst: &mut Vec<u8>, group: &KdbGroup) -> Result<(), Error> {
+    write_u32_field(dst, 0x0001, group.group_id);
+    write_string_field(dst, 0x0002, &group.group_name);
+    write_date_time_field(dst, 0x0003, &group.created)?;
+    write_date_time_field(dst, 0x0004, &group.modified)?;
+    write_date_time_field(dst, 0x0005, &group.accessed)?;
+    write_date_time_field(dst, 0x0006, &group.expires)?;
+    write_u32_field(dst, 0x0007, group.image_id);
+    let mut level = Vec::with_capacity(2);
+    write_u16(&mut level, group.level);
+    write_field(dst, 0x0008, &level);
+    write_u32_field(dst, 0x0009, group.flags);
+    write_field(dst, 0xFFFF, &[]);
+    Ok(())
+}
+
+fn write_entry(dst: &mut Vec<u8>, entry: &KdbEntry) -> Result<(), Error> {
+    write_field(dst, 0x0001, entry.entry_id.as_bytes());
+    write_u32_field(dst, 0x0002, entry.group_id);
+    write_u32_field(dst, 0x0003, entry.image_id);
+    write_string_field(dst, 0x0004, &entry.title);
+    write_string_field(dst, 0x0005, &entry.url);
+    write_string_field(dst, 0x0006, &entry.username);
+    write_string_field(dst, 0x0007, &entry.password);
+    write_string_field(dst, 0x0008, &entry.notes);
+    write_date_time_field(dst, 0x0009, &entry.created)?;
+    write_date_time_field(dst, 0x000a, &entry.modified)?;
+    write_date_time_field(dst, 0x000b, &entry.accessed)?;
+    write_date_time_field(dst, 0x000c, &entry.expires)?;
+    write_string_field(dst, 0x000d, &entry.binary_desc);
+    write_field(dst, 0x000e, &entry.binary_data);
+    write_field(dst, 0xFFFF, &[]);
+    Ok(())
+}
+
+fn encrypt_contents(key: &[u8; 32], iv: &[u8; 16], data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut encryptor = crypto::aes::cbc_encryptor(
+        crypto::aes::KeySize::KeySize256,
+        key,
+        iv,
+        crypto::blockmodes::PkcsPadding,
+    );
+    let mut out: Vec<u8> = Vec::with_capacity(data.len() + 16);
+    let mut encryptor_input = crypto::buffer::RefReadBuffer::new(data);
+    let mut buf: [u8; 4096] = [0; 4096];
+    loop {
+        let mut encryptor_output = crypto::buffer::RefWriteBuffer::new(&mut buf);
+        let r = match encryptor.encrypt(&mut encryptor_input, &mut encryptor_output, true) {
+            Ok(r) => r,
+            Err(e) => {
+                return Err(Error::Other(format!("Encrypt error: {:?}", e)))
+            }
+        };
+        out.extend_from_slice(encryptor_output.take_read_buffer().take_remaining());
+        if let crypto::buffer::BufferResult::BufferUnderflow = r {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+fn write_header(dst: &mut Vec<u8>, file: &KdbFile) {
+    let h = &file.header;
+    write_u32(dst, h.signature_1);
+    write_u32(dst, h.signature_2);
+    write_u32(dst, h.flags);
+    write_u32(dst, h.version);
+    dst.extend_from_slice(&h.master_seed);
+    dst.extend_from_slice(&h.enc_iv);
+    write_u32(dst, h.group_count);
+    write_u32(dst, h.entry_count);
+    dst.extend_from_slice(&h.contents_hash);
+    dst.extend_from_slice(&h.master_seed_2);
+    write_u32(dst, h.key_enc_rounds);
+}
+
+/// Serialize and encrypt KDB file.
+/// Seeds and IV are regenerated on every write, header is updated to match what was written.
+/// Always writes current KDB version, AES encrypted.
+pub fn write_kdb_file(file: &mut KdbFile, master_key: &[u8; 32]) -> Result<Vec<u8>, Error> {
+    if file.groups.len() > (u32::max_value() as usize) || file.entries.len() > (u32::max_value() as usize) {
+        return Err(Error::Invalid(String::from("Too many groups or entries")));
+    }
+    let mut contents: Vec<u8> = Vec::new();
+    for group in &file.groups {
+        write_group(&mut contents, group)?;
+    }
+    for entry in &file.entries {
+        write_entry(&mut contents, entry)?;
+    }
+    {
+        let h = &mut file.header;
+        h.signature_1 = parser::PWM_DBSIG_1;
+        h.signature_2 = parser::PWM_DBSIG_2;
+        h.flags = parser::PWM_FLAG_SHA2 | parser::PWM_FLAG_RIJNDAEL;
+        h.version = parser::PWM_DBVER_DW;
+        random_bytes(&mut h.master_seed)?;
+        random_bytes(&mut h.enc_iv)?;
+        random_bytes(&mut h.master_seed_2)?;
+        h.group_count = file.groups.len() as u32;
+        h.entry_count = file.entries.len() as u32;
+        let mut sha = crypto::sha2::Sha256::new();
+        sha.input(&contents);
+        sha.result(&mut h.contents_hash);
+    }
+    let transformed_master_key = key::transform_master_key(
+        *master_key,
+        file.header.master_seed,
+        file.header.master_seed_2,
+        file.header.key_enc_rounds,
+    );
+    let encrypted = encrypt_contents(&transformed_master_key, &file.header.enc_iv, &contents)?;
+    let mut out: Vec<u8> = Vec::with_capacity(parser::HEADER_SIZE + encrypted.len());
+    write_header(&mut out, file);
+    out.extend_from_slice(&encrypted);
+    Ok(out)
+}
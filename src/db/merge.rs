@@ -0,0 +1,494 @@
+//! Merging changes of two copies of the same database.
+//!
+//! Groups and entries are matched by id, so renamed or moved records are still the same record.
+//! With a common base version (what both copies started from) deletions can be told apart
+//! from additions, without it records deleted on one side come back.
+
+use std::fmt;
+
+use db::api::{Entry, Error, Group, GroupId, PasswordDatabase, Uuid};
+
+/// Group where KeePass 1.x keeps old versions of entries.
+pub const BACKUP_GROUP: &str = "Backup";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Added,
+    Updated,
+    Deleted,
+    /// Our version kept, though the other side changed (or deleted) it too.
+    Kept,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Group,
+    Entry,
+}
+
+/// One change made to our database.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub action: Action,
+    pub kind: Kind,
+    /// Like "Internet/Gmail".
+    pub path: String,
+    /// Why, when both sides changed the record.
+    pub conflict: Option<String>,
+}
+
+/// What merge did.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MergeReport {
+    pub changes: Vec<Change>,
+}
+
+impl MergeReport {
+    pub fn count(&self, action: Action) -> usize {
+        self.changes.iter().filter(|c| c.action == action).count()
+    }
+
+    pub fn conflicts(&self) -> usize {
+        self.changes.iter().filter(|c| c.conflict.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = match self.action {
+            Action::Added => '+',
+            Action::Updated => '~',
+            Action::Deleted => '-',
+            Action::Kept => '=',
+        };
+        let kind = match self.kind {
+            Kind::Group => "group",
+            Kind::Entry => "entry",
+        };
+        write!(f, "{} {} {}", sign, kind, self.path)?;
+        if let Some(ref c) = self.conflict {
+            write!(f, " ({})", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for MergeReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in &self.changes {
+            writeln!(f, "{}", c)?;
+        }
+        write!(
+            f,
+            "{} added, {} updated, {} deleted, {} conflicts",
+            self.count(Action::Added),
+            self.count(Action::Updated),
+            self.count(Action::Deleted),
+            self.conflicts()
+        )
+    }
+}
+
+/// Same entry contents, access time doesn't count as a change.
+fn same_entry(a: &Entry, b: &Entry) -> bool {
+    a.group_id == b.group_id && a.title == b.title && a.url == b.url && a.username == b.username &&
+        a.password == b.password && a.notes == b.notes && a.expires == b.expires && a.attachment == b.attachment
+}
+
+fn same_group(a: &Group, b: &Group) -> bool {
+    a.name == b.name && a.expires == b.expires
+}
+
+/// Merges `theirs` into `ours`, see `merge`.
+struct Merger<'a> {
+    base: Option<&'a PasswordDatabase>,
+    theirs: &'a PasswordDatabase,
+    ours: &'a mut PasswordDatabase,
+    /// Keep losing versions of entries in the Backup group.
+    backup: bool,
+    backup_group: Option<GroupId>,
+    report: MergeReport,
+}
+
+impl<'a> Merger<'a> {
+    fn change(&mut self, action: Action, kind: Kind, path: String, conflict: Option<&str>) {
+        self.report.changes.push(Change {
+            action: action,
+            kind: kind,
+            path: path,
+            conflict: conflict.map(String::from),
+        });
+    }
+
+    fn entry_path(&self, e: &Entry) -> String {
+        let mut path = self.ours.get_group_path(e.group_id);
+        path.push(e.title.clone());
+        path.join("/")
+    }
+
+    /// Add group from theirs, with any missing parents.
+    fn ensure_group(&mut self, id: GroupId) -> Result<(), Error> {
+        if self.ours.get_group(id).is_some() {
+            return Ok(());
+        }
+        let g = match self.theirs.get_group(id) {
+            Some(g) => g,
+            None => return Err(Error::NoSuchGroup(id)),
+        };
+        if let Some(parent) = g.parent {
+            self.ensure_group(parent)?;
+        }
+        self.ours.insert_group(&g)?;
+        let path = self.ours.get_group_path(id).join("/");
+        self.change(Action::Added, Kind::Group, path, None);
+        Ok(())
+    }
+
+    /// Copy of the losing version, with new id, in the Backup group, unless it's already there
+    /// (e.g. from merging the same file before).
+    fn keep_backup(&mut self, e: &Entry) -> Result<(), Error> {
+        if !self.backup {
+            return Ok(());
+        }
+        let group = match self.backup_group {
+            Some(g) => g,
+            None => {
+                let existing = self.ours
+                    .get_groups()
+                    .into_iter()
+                    .find(|g| g.parent.is_none() && g.name == BACKUP_GROUP);
+                let g = match existing {
+                    Some(g) => g.id,
+                    None => self.ours.add_group(BACKUP_GROUP, None)?,
+                };
+                self.backup_group = Some(g);
+                g
+            }
+        };
+        let mut copy = e.clone();
+        copy.id = Uuid::new_v4();
+        copy.group_id = group;
+        if self.ours.get_entries().iter().any(|b| same_entry(b, &copy)) {
+            return Ok(());
+        }
+        self.ours.add_entry(&copy)
+    }
+
+    fn merge_groups(&mut self) -> Result<(), Error> {
+        // Tree order, parents come before their children.
+        for g in self.theirs.get_groups() {
+            let base = self.base.and_then(|b| b.get_group(g.id));
+            match self.ours.get_group(g.id) {
+                Some(o) => {
+                    let theirs_changed = base.as_ref().map_or(true, |b| !same_group(b, &g));
+                    let ours_changed = base.as_ref().map_or(true, |b| !same_group(b, &o));
+                    if same_group(&g, &o) || !theirs_changed || (ours_changed && o.modified >= g.modified) {
+                        continue;
+                    }
+                    let mut g = g;
+                    // Moving groups is not supported, changes stay in the group's current place.
+                    g.parent = o.parent;
+                    self.ours.update_group(&g)?;
+                    let path = self.ours.get_group_path(g.id).join("/");
+                    let conflict = if ours_changed { Some("changed on both sides, newer kept") } else { None };
+                    self.change(Action::Updated, Kind::Group, path, conflict);
+                }
+                // We deleted it, it stays deleted unless they changed it, or put entries in it (see merge_entries).
+                None => match base {
+                    Some(ref b) if same_group(b, &g) => {}
+                    _ => self.ensure_group(g.id)?,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn merge_entries(&mut self) -> Result<(), Error> {
+        for e in self.theirs.get_entries() {
+            let base = self.base.and_then(|b| b.get_entry(&e.id));
+            match self.ours.get_entry(&e.id) {
+                Some(o) => {
+                    if same_entry(&e, &o) {
+                        continue;
+                    }
+                    let theirs_changed = base.as_ref().map_or(true, |b| !same_entry(b, &e));
+                    let ours_changed = base.as_ref().map_or(true, |b| !same_entry(b, &o));
+                    if !theirs_changed {
+                        continue;
+                    }
+                    let both = ours_changed && base.is_some();
+                    if ours_changed && o.modified >= e.modified {
+                        self.keep_backup(&e)?;
+                        let path = self.entry_path(&o);
+                        let conflict = if both { "changed on both sides, ours is newer" } else { "differs, ours is newer" };
+                        self.change(Action::Kept, Kind::Entry, path, Some(conflict));
+                        continue;
+                    }
+                    if ours_changed {
+                        self.keep_backup(&o)?;
+                    }
+                    let mut e = e;
+                    if self.ours.get_group(e.group_id).is_none() {
+                        // They moved it to a group we deleted.
+                        e.group_id = o.group_id;
+                    }
+                    self.ours.update_entry(&e)?;
+                    let path = self.entry_path(&e);
+                    let conflict = if both {
+                        Some("changed on both sides, theirs is newer")
+                    } else if ours_changed {
+                        Some("differs, theirs is newer")
+                    } else {
+                        None
+                    };
+                    self.change(Action::Updated, Kind::Entry, path, conflict);
+                }
+                None => {
+                    let conflict = match base {
+                        // We deleted it, and they didn't touch it.
+                        Some(ref b) if same_entry(b, &e) => continue,
+                        Some(_) => Some("deleted here, changed there"),
+                        None => None,
+                    };
+                    self.ensure_group(e.group_id)?;
+                    self.ours.add_entry(&e)?;
+                    let path = self.entry_path(&e);
+                    self.change(Action::Added, Kind::Entry, path, conflict);
+                }
+            }
+        }
+        // Deleted by them.
+        if let Some(base) = self.base {
+            for o in self.ours.get_entries() {
+                if self.theirs.get_entry(&o.id).is_some() {
+                    continue;
+                }
+                let path = self.entry_path(&o);
+                match base.get_entry(&o.id) {
+                    Some(ref b) if same_entry(b, &o) => {
+                        self.ours.remove_entry(&o.id)?;
+                        self.change(Action::Deleted, Kind::Entry, path, None);
+                    }
+                    Some(_) => self.change(Action::Kept, Kind::Entry, path, Some("changed here, deleted there")),
+                    None => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Groups they deleted, only if nothing is left in them, deepest first.
+    fn remove_deleted_groups(&mut self) -> Result<(), Error> {
+        let base = match self.base {
+            Some(b) => b,
+            None => return Ok(()),
+        };
+        let mut groups = self.ours.get_groups();
+        groups.reverse();
+        for o in groups {
+            if self.theirs.get_group(o.id).is_some() {
+                continue;
+            }
+            let b = match base.get_group(o.id) {
+                Some(b) => b,
+                None => continue,
+            };
+            let path = self.ours.get_group_path(o.id).join("/");
+            let ours = self.ours.get_groups();
+            let empty = !ours.iter().any(|g| g.parent == Some(o.id)) &&
+                !self.ours.get_entries().iter().any(|e| e.group_id == o.id);
+            if !same_group(&b, &o) {
+                self.change(Action::Kept, Kind::Group, path, Some("changed here, deleted there"));
+            } else if !empty {
+                self.change(Action::Kept, Kind::Group, path, Some("deleted there, but not empty"));
+            } else {
+                self.ours.remove_group(o.id)?;
+                self.change(Action::Deleted, Kind::Group, path, None);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Bring changes from `theirs` into `ours`.
+///
+/// `base` is the version both started from, if known. Records changed on one side only take
+/// that change, records changed on both sides take the newer version (by modification time),
+/// and a record deleted on one side is deleted, unless the other side changed it.
+/// Without base, every difference counts as a change on both sides, and nothing is deleted.
+///
+/// If `backup` is set, losing versions of entries changed on both sides are kept in the
+/// Backup group, like KeePass 1.x keeps old versions.
+pub fn merge(
+    base: Option<&PasswordDatabase>,
+    theirs: &PasswordDatabase,
+    ours: &mut PasswordDatabase,
+    backup: bool,
+) -> Result<MergeReport, Error> {
+    let report = {
+        let mut m = Merger {
+            base: base,
+            theirs: theirs,
+            ours: ours,
+            backup: backup,
+            backup_group: None,
+            report: MergeReport::default(),
+        };
+        m.merge_groups()?;
+        m.merge_entries()?;
+        m.remove_deleted_groups()?;
+        m.report
+    };
+    debug!(
+        "merge: {} changes, {} conflicts",
+        report.changes.len(),
+        report.conflicts()
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use db::api::{Attachment, Entry, Group, PasswordDatabase, Uuid};
+    use db::kdb;
+
+    use super::*;
+
+    const GROUP: GroupId = 1;
+    const OLD: &str = "2020-01-01 00:00:00";
+
+    fn uuid(n: u8) -> Uuid {
+        Uuid::from_bytes(&[n; 16]).unwrap()
+    }
+
+    fn entry(n: u8, title: &str) -> Entry {
+        let mut e = Entry::new(GROUP, title);
+        e.id = uuid(n);
+        e.modified = String::from(OLD);
+        e
+    }
+
+    /// Same database every time, like copies of one file.
+    fn copy() -> kdb::KdbDatabase {
+        let mut db = kdb::create("unused.kdb", "secret", None).unwrap();
+        db.insert_group(&Group {
+            id: GROUP,
+            parent: None,
+            name: String::from("Internet"),
+            created: String::from(OLD),
+            modified: String::from(OLD),
+            accessed: String::from(OLD),
+            expires: String::from(OLD),
+        }).unwrap();
+        db.add_entry(&entry(1, "Forum")).unwrap();
+        db.add_entry(&entry(2, "Gmail")).unwrap();
+        db
+    }
+
+    fn titles(db: &PasswordDatabase) -> Vec<String> {
+        let mut t: Vec<String> = db.get_entries().into_iter().map(|e| e.title).collect();
+        t.sort();
+        t
+    }
+
+    #[test]
+    fn test_attachment_change() {
+        let base = copy();
+        let mut theirs = copy();
+        let mut ours = copy();
+        let attachment = Some(Attachment {
+            name: String::from("recovery-codes.txt"),
+            data: b"1234 5678".to_vec(),
+        });
+        for n in 1..3 {
+            let mut e = theirs.get_entry(&uuid(n)).unwrap();
+            e.attachment = attachment.clone();
+            e.touch();
+            theirs.update_entry(&e).unwrap();
+        }
+        ours.remove_entry(&uuid(1)).unwrap();
+        let report = merge(Some(&base), &theirs, &mut ours, false).unwrap();
+        assert_eq!(titles(&ours), vec!["Forum", "Gmail"]);
+        for n in 1..3 {
+            assert_eq!(ours.get_entry(&uuid(n)).unwrap().attachment, attachment);
+        }
+        assert_eq!(report.count(Action::Updated), 1);
+        assert_eq!(report.conflicts(), 1);
+    }
+
+    #[test]
+    fn test_three_way() {
+        let base = copy();
+        let mut theirs = copy();
+        let mut ours = copy();
+        let mut forum = theirs.get_entry(&uuid(1)).unwrap();
+        forum.password = String::from("new");
+        forum.touch();
+        theirs.update_entry(&forum).unwrap();
+        theirs.add_entry(&entry(3, "Bank")).unwrap();
+        ours.remove_entry(&uuid(2)).unwrap();
+        let report = merge(Some(&base), &theirs, &mut ours, false).unwrap();
+        assert_eq!(titles(&ours), vec!["Bank", "Forum"]);
+        assert_eq!(ours.get_entry(&uuid(1)).unwrap().password, "new");
+        assert_eq!(report.count(Action::Added), 1);
+        assert_eq!(report.count(Action::Updated), 1);
+        assert_eq!(report.conflicts(), 0);
+        // Merging again changes nothing.
+        let report = merge(Some(&base), &theirs, &mut ours, false).unwrap();
+        assert!(report.is_empty(), "{}", report);
+    }
+
+    #[test]
+    fn test_deleted_there_changed_here() {
+        let base = copy();
+        let mut theirs = copy();
+        let mut ours = copy();
+        theirs.remove_entry(&uuid(1)).unwrap();
+        theirs.remove_entry(&uuid(2)).unwrap();
+        let mut gmail = ours.get_entry(&uuid(2)).unwrap();
+        gmail.username = String::from("bob");
+        gmail.touch();
+        ours.update_entry(&gmail).unwrap();
+        let report = merge(Some(&base), &theirs, &mut ours, false).unwrap();
+        assert_eq!(titles(&ours), vec!["Gmail"]);
+        assert_eq!(report.count(Action::Deleted), 1);
+        assert_eq!(report.count(Action::Kept), 1);
+        assert_eq!(report.conflicts(), 1);
+    }
+
+    #[test]
+    fn test_changed_on_both_sides_newer_wins() {
+        let base = copy();
+        let mut theirs = copy();
+        let mut ours = copy();
+        let mut e = theirs.get_entry(&uuid(1)).unwrap();
+        e.password = String::from("theirs");
+        e.modified = String::from("2021-01-01 00:00:00");
+        theirs.update_entry(&e).unwrap();
+        e.password = String::from("ours");
+        e.modified = String::from("2022-01-01 00:00:00");
+        ours.update_entry(&e).unwrap();
+        let report = merge(Some(&base), &theirs, &mut ours, true).unwrap();
+        assert_eq!(ours.get_entry(&uuid(1)).unwrap().password, "ours");
+        assert_eq!(report.count(Action::Kept), 1);
+        // Their version is kept in Backup group.
+        let backup = ours.find_group(BACKUP_GROUP).unwrap();
+        let backups: Vec<Entry> = ours.get_entries().into_iter().filter(|e| e.group_id == backup).collect();
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].password, "theirs");
+        // Merging again doesn't add another copy.
+        merge(Some(&base), &theirs, &mut ours, true).unwrap();
+        let backups: Vec<Entry> = ours.get_entries().into_iter().filter(|e| e.group_id == backup).collect();
+        assert_eq!(backups.len(), 1);
+        // Without base, records only in ours are kept.
+        let mut ours = copy();
+        ours.add_entry(&entry(4, "Ours only")).unwrap();
+        merge(None, &theirs, &mut ours, false).unwrap();
+        assert_eq!(titles(&ours), vec!["Forum", "Gmail", "Ours only"]);
+        assert_eq!(ours.get_entry(&uuid(1)).unwrap().password, "theirs");
+    }
+}
@@ -3,4 +3,10 @@
 //! Generally in future it would be nice to support many DB implementations.
 
 pub mod api;
+pub mod audit;
+pub mod diff;
+pub mod file;
+pub mod formats;
 pub mod kdb;
+pub mod merge;
+pub mod report;
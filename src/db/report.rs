@@ -0,0 +1,300 @@
+//! Printable report of entries, HTML or Markdown, e.g. for an emergency kit kept on paper.
+//!
+//! Reports are self-contained: styles are inline and QR codes are SVG (HTML) or text
+//! (Markdown), nothing is loaded from the network. Passwords, and one-time password secrets
+//! in notes, are only included on request.
+
+use std::fmt;
+use std::io::Write;
+
+use qrcode::render::{svg, unicode};
+use qrcode::QrCode;
+
+use db::api::{now, Entry};
+use db::formats::xml::escape;
+use db::formats::{never_expires, Record};
+use errors::Error;
+use otp;
+
+/// Shown instead of passwords that are not included.
+const HIDDEN_PASSWORD: &str = "(not included)";
+
+/// Size of QR codes in HTML reports, in pixels (printed at 96 dpi, about 3 cm).
+const QR_SIZE: u32 = 120;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+}
+
+impl ReportFormat {
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name.to_lowercase().as_str() {
+            "html" => Some(ReportFormat::Html),
+            "markdown" | "md" => Some(ReportFormat::Markdown),
+            _ => None,
+        }
+    }
+
+    /// File name extension.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ReportFormat::Html => "html",
+            ReportFormat::Markdown => "md",
+        }
+    }
+}
+
+/// What goes into report.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub format: ReportFormat,
+    /// Heading, e.g. database file name.
+    pub title: String,
+    pub passwords: bool,
+    /// QR code per entry, with title, username, URL, and password if included.
+    pub qr_codes: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            format: ReportFormat::Html,
+            title: String::from("Passwords"),
+            passwords: false,
+            qr_codes: false,
+        }
+    }
+}
+
+/// Entry fields to show, in order, without empty ones.
+fn fields<'a>(e: &'a Entry, options: &Options) -> Vec<(&'static str, &'a str)> {
+    let password = if options.passwords {
+        e.password.as_str()
+    } else {
+        HIDDEN_PASSWORD
+    };
+    let mut fields = vec![
+        ("Username", e.username.as_str()),
+        ("Password", password),
+        ("URL", e.url.as_str()),
+    ];
+    if !never_expires(&e.expires) {
+        fields.push(("Expires", e.expires.as_str()));
+    }
+    if let Some(ref a) = e.attachment {
+        fields.push(("Attachment", a.name.as_str()));
+    }
+    fields.retain(|&(_, value)| !value.is_empty());
+    fields
+}
+
+/// QR code of entry, if they are wanted.
+fn qr_code(e: &Entry, options: &Options) -> Option<QrCode> {
+    if !options.qr_codes {
+        return None;
+    }
+    let mut lines = vec![e.title.as_str(), e.username.as_str()];
+    if options.passwords {
+        lines.push(&e.password);
+    }
+    lines.push(&e.url);
+    match QrCode::new(lines.join("\n").as_bytes()) {
+        Ok(code) => Some(code),
+        Err(err) => {
+            warn!("qr_code: {}: {:?}", e.title, err);
+            None
+        }
+    }
+}
+
+/// Notes, one-time password secrets only with passwords.
+fn notes(e: &Entry, options: &Options) -> String {
+    if options.passwords {
+        e.notes.clone()
+    } else {
+        otp::hide_in_notes(&e.notes)
+    }
+}
+
+/// Records grouped by group path, in order.
+fn by_group(records: &[Record]) -> Vec<(&str, Vec<&Entry>)> {
+    let mut groups: Vec<(&str, Vec<&Entry>)> = Vec::new();
+    for r in records {
+        match groups.last_mut() {
+            Some(&mut (group, ref mut entries)) if group == r.group => {
+                entries.push(&r.entry);
+                continue;
+            }
+            _ => {}
+        }
+        groups.push((&r.group, vec![&r.entry]));
+    }
+    groups
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; font-size: 11pt; margin: 2em; }
+h2 { border-bottom: 1px solid #888; margin-top: 1.5em; }
+.entry { page-break-inside: avoid; break-inside: avoid; margin: 1em 0; overflow: auto; }
+.entry h3 { margin: 0 0 0.3em 0; }
+.entry table { border-collapse: collapse; }
+.entry th { text-align: left; padding-right: 1em; font-weight: normal; color: #555; }
+.entry td { font-family: monospace; font-size: 12pt; word-break: break-all; }
+.notes { white-space: pre-wrap; font-family: monospace; margin: 0.3em 0; }
+.qr { float: right; margin-left: 1em; }
+";
+
+fn write_html<W: Write>(w: &mut W, records: &[Record], options: &Options) -> Result<(), Error> {
+    writeln!(w, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(w, "<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>", escape(&options.title), HTML_STYLE)?;
+    writeln!(w, "<h1>{}</h1>", escape(&options.title))?;
+    writeln!(w, "<p>{} entries, printed {}.</p>", records.len(), now())?;
+    for (group, entries) in by_group(records) {
+        writeln!(w, "<h2>{}</h2>", escape(group))?;
+        for e in entries {
+            writeln!(w, "<div class=\"entry\">")?;
+            if let Some(code) = qr_code(e, options) {
+                let image = code.render::<svg::Color>()
+                    .min_dimensions(QR_SIZE, QR_SIZE)
+                    .build();
+                // Without the XML declaration, to inline it.
+                let start = image.find("<svg").unwrap_or(0);
+                writeln!(w, "<div class=\"qr\">{}</div>", &image[start..])?;
+            }
+            writeln!(w, "<h3>{}</h3>\n<table>", escape(&e.title))?;
+            for (name, value) in fields(e, options) {
+                writeln!(w, "<tr><th>{}</th><td>{}</td></tr>", name, escape(value))?;
+            }
+            writeln!(w, "</table>")?;
+            if !e.notes.is_empty() {
+                writeln!(w, "<div class=\"notes\">{}</div>", escape(&notes(e, options)))?;
+            }
+            writeln!(w, "</div>")?;
+        }
+    }
+    writeln!(w, "</body>\n</html>")?;
+    Ok(())
+}
+
+/// Markdown text with special characters escaped.
+struct MdText<'a>(&'a str);
+
+impl<'a> fmt::Display for MdText<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' => write!(f, "\\{}", c)?,
+                '\n' => write!(f, " ")?,
+                _ => write!(f, "{}", c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Markdown code span, which can hold any text, backticks too.
+fn md_code(text: &str) -> String {
+    let text = text.replace('\n', " ");
+    let mut fence = String::from("`");
+    while text.contains(fence.as_str()) {
+        fence.push('`');
+    }
+    format!("{} {} {}", fence, text, fence)
+}
+
+/// Markdown code block, which keeps line breaks and any text as it is.
+fn md_block(text: &str) -> String {
+    let mut fence = String::from("```");
+    while text.contains(fence.as_str()) {
+        fence.push('`');
+    }
+    format!("{}\n{}\n{}", fence, text, fence)
+}
+
+fn write_markdown<W: Write>(w: &mut W, records: &[Record], options: &Options) -> Result<(), Error> {
+    writeln!(w, "# {}\n", MdText(&options.title))?;
+    writeln!(w, "{} entries, printed {}.", records.len(), now())?;
+    for (group, entries) in by_group(records) {
+        writeln!(w, "\n## {}", MdText(group))?;
+        for e in entries {
+            writeln!(w, "\n### {}\n", MdText(&e.title))?;
+            for (name, value) in fields(e, options) {
+                if name == "Password" && !options.passwords {
+                    writeln!(w, "- {}: {}", name, value)?;
+                } else {
+                    writeln!(w, "- {}: {}", name, md_code(value))?;
+                }
+            }
+            if !e.notes.is_empty() {
+                writeln!(w, "\n{}", md_block(&notes(e, options)))?;
+            }
+            if let Some(code) = qr_code(e, options) {
+                let image = code.render::<unicode::Dense1x2>()
+                    .dark_color(unicode::Dense1x2::Light)
+                    .light_color(unicode::Dense1x2::Dark)
+                    .build();
+                writeln!(w, "\n{}", md_block(&image))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write report of records, which are listed by group, in order.
+/// Returns number of entries written.
+pub fn write<W: Write>(mut w: W, records: &[Record], options: &Options) -> Result<usize, Error> {
+    match options.format {
+        ReportFormat::Html => write_html(&mut w, records, options)?,
+        ReportFormat::Markdown => write_markdown(&mut w, records, options)?,
+    }
+    w.flush()?;
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record> {
+        let mut e = Entry::new(0, "Mail <work>");
+        e.username = String::from("bob");
+        e.password = String::from("p`w");
+        e.notes = String::from("line 1\nline 2\nTOTP: JBSWY3DPEHPK3PXP");
+        vec![
+            Record {
+                group: String::from("Internet"),
+                entry: e,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_html() {
+        let mut options = Options::default();
+        options.qr_codes = true;
+        let mut out = Vec::new();
+        assert_eq!(write(&mut out, &records(), &options).unwrap(), 1);
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("<h3>Mail &lt;work&gt;</h3>"), "{}", html);
+        assert!(html.contains("<svg"));
+        assert!(!html.contains("p`w"));
+        assert!(html.contains("TOTP: (hidden)") && !html.contains("JBSWY3DP"), "{}", html);
+        assert!(!html.contains("src=") && !html.contains("href="), "no network resources");
+    }
+
+    #[test]
+    fn test_markdown() {
+        let options = Options {
+            format: ReportFormat::Markdown,
+            passwords: true,
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        write(&mut out, &records(), &options).unwrap();
+        let md = String::from_utf8(out).unwrap();
+        assert!(md.contains("### Mail \\<work\\>\n"), "{}", md);
+        assert!(md.contains("- Password: `` p`w ``\n"), "{}", md);
+        assert!(md.contains("\n```\nline 1\nline 2\nTOTP: JBSWY3DPEHPK3PXP\n```\n"), "{}", md);
+    }
+}
@@ -1,53 +1,176 @@
-
 //! Error helper stuff.
+//!
+//! One error type for the whole library, so callers can branch on what went wrong
+//! (e.g. ask for password again on `WrongKey`) instead of parsing messages.
 
 use std;
 use std::fmt;
+use std::io;
 
 use toml;
 
-/// My error struct.
+use db::api::{GroupId, Uuid};
+
+/// Messages don't include the cause, that's in `source()`, use `report` to show both
+/// (e.g. "Error reading key file: IO error: ..."). Use `kind()` to get at the actual error.
 #[derive(Debug)]
-pub struct Error {
-    desc: String,
+pub enum Error {
+    Io(io::Error),
+    /// Not a KeePass 1.x file.
+    InvalidSignature,
+    /// KDB version (from file header) we can't read.
+    UnsupportedVersion(u32),
+    /// E.g. Twofish.
+    UnsupportedCipher(String),
+    /// Wrong password or key file, can't tell which.
+    WrongKey,
+    /// File is damaged, offset is from the start of file.
+    Corrupted { offset: u64, desc: String },
+    /// Config file can't be parsed.
+    Config(toml::de::Error),
+    /// Config can't be serialized.
+    ConfigWrite(toml::ser::Error),
+    /// Config parsed fine, but doesn't make sense (e.g. unknown version).
+    InvalidConfig(String),
+    NoSuchGroup(GroupId),
+    NoSuchEntry(Uuid),
+    EntryExists(Uuid),
+    /// Another program (or user) has the database locked, path is the lock file.
+    Locked { path: String, owner: String },
+    /// File was changed by someone else since we read it, saving would lose their changes.
+    ChangedOnDisk(String),
+    /// There are unsaved changes, which e.g. opening another file or locking would lose.
+    UnsavedChanges,
+    /// App is in a state that doesn't allow it, e.g. "lock" when "saving".
+    InvalidState { action: &'static str, state: &'static str },
+    /// Imported file (CSV, XML...) can't be parsed, format is e.g. "CSV".
+    Format { format: String, desc: String },
+    /// Valid request, but we can't do it (e.g. moving groups).
+    Unsupported(String),
+    /// Invalid data given by caller.
+    Invalid(String),
+    Other(String),
+    /// What we were doing when error happened, e.g. "Error reading key file".
+    Context { desc: String, source: Box<Error> },
 }
 
-/// My mini error chain helper, marker trait.
-/// Things that implement error chain can be converted to my error type.
-pub trait ErrorChain: std::error::Error {}
-
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.desc)
+        match *self {
+            Error::Io(_) => write!(f, "IO error"),
+            Error::InvalidSignature => write!(f, "Not a KeePass 1.x database (invalid signature)"),
+            Error::UnsupportedVersion(v) => write!(f, "Unsupported database version {}.{}", v >> 16, v & 0xFFFF),
+            Error::UnsupportedCipher(ref c) => write!(f, "Unsupported cipher: {}", c),
+            Error::WrongKey => write!(f, "Wrong password or key file"),
+            Error::Corrupted { offset, ref desc } => write!(f, "Database is corrupted at offset {}: {}", offset, desc),
+            Error::Config(_) => write!(f, "Invalid config"),
+            Error::ConfigWrite(_) => write!(f, "Failed to write config"),
+            Error::InvalidConfig(ref desc) => write!(f, "Invalid config: {}", desc),
+            Error::NoSuchGroup(id) => write!(f, "No such group: {}", id),
+            Error::NoSuchEntry(ref id) => write!(f, "No such entry: {}", id),
+            Error::EntryExists(ref id) => write!(f, "Entry already exists: {}", id),
+            Error::Locked { ref path, ref owner } => write!(
+                f,
+                "Database is in use by {} (remove \"{}\" if that's not true)",
+                owner, path
+            ),
+            Error::ChangedOnDisk(ref path) => write!(f, "\"{}\" was changed by another program", path),
+            Error::UnsavedChanges => write!(f, "Save or discard changes first"),
+            Error::InvalidState { action, state } => write!(f, "Can't {} when {}", action, state),
+            Error::Format { ref format, ref desc } => write!(f, "Invalid {} file: {}", format, desc),
+            Error::Unsupported(ref desc) | Error::Invalid(ref desc) | Error::Other(ref desc) => write!(f, "{}", desc),
+            Error::Context { ref desc, .. } => write!(f, "{}", desc),
+        }
     }
 }
 
 impl Error {
     pub fn new(desc: &str) -> Error {
-        Error {
+        Error::Other(String::from(desc))
+    }
+
+    pub fn format(format: &str, desc: &str) -> Error {
+        Error::Format {
+            format: String::from(format),
+            desc: String::from(desc),
+        }
+    }
+
+    /// Wrap error with description of what we were doing.
+    pub fn context(self, desc: &str) -> Error {
+        Error::Context {
             desc: String::from(desc),
+            source: Box::new(self),
+        }
+    }
+
+    /// The actual error, under any contexts.
+    pub fn kind(&self) -> &Error {
+        match *self {
+            Error::Context { ref source, .. } => source.kind(),
+            ref e => e,
         }
     }
 }
 
 impl std::error::Error for Error {
-    fn description(&self) -> &str {
-        &self.desc
+    fn source(&self) -> Option<&(std::error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            Error::Config(ref e) => Some(e),
+            Error::ConfigWrite(ref e) => Some(e),
+            Error::Context { ref source, .. } => Some(&**source),
+            _ => None,
+        }
     }
 }
 
-/// Generic impl of convert from things marked by my ErrorChain trait.
-/// TODO: Investigate error chain crate (again).
-/// TODO: Maybe do something with Error::cause (although for now I didn't really need it so... investigate benefits first).
-impl<E> std::convert::From<E> for Error
-where
-    E: std::marker::Sized + std::error::Error + ErrorChain,
-{
-    fn from(e: E) -> Error {
-        Error::new(&format!("{}", e))
+/// Message for users: error along with its causes, e.g. "Error reading key file: IO error: ...".
+pub fn report(e: &std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message.push_str(": ");
+        message.push_str(&e.to_string());
+        source = e.source();
     }
+    message
 }
 
-impl ErrorChain for std::io::Error {}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
 
-impl ErrorChain for toml::de::Error {}
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(e: toml::ser::Error) -> Error {
+        Error::ConfigWrite(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+
+    use super::*;
+
+    #[test]
+    fn test_cause_printed_once() {
+        let e = Error::from(io::Error::new(io::ErrorKind::NotFound, "no such file")).context("Error reading key file");
+        assert_eq!(e.to_string(), "Error reading key file");
+        assert!(!e.to_string().contains("no such file"));
+        assert!(e.source().is_some());
+        assert_eq!(report(&e), "Error reading key file: IO error: no such file");
+        match *e.kind() {
+            Error::Io(ref e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            ref e => panic!("{:?}", e),
+        }
+    }
+}
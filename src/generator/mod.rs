@@ -0,0 +1,373 @@
+//! Password generator: random characters, KeePass style patterns, or diceware passphrases.
+//!
+//! Everything comes from the OS random number generator (`rand::OsRng`), with unbiased picks.
+//!
+//! Patterns are what KeePass calls "generate using pattern", each placeholder is one character:
+//!
+//! - `d` digit, `l` lower case, `u` upper case, `L` mixed case letter,
+//! - `a` lower case or digit, `U` upper case or digit, `A` mixed case or digit,
+//! - `h`/`H` lower/upper case hex digit, `s` special, `S` printable ASCII, `b` bracket,
+//! - `v`/`Z`/`V` lower/upper/mixed vowel, `c`/`z`/`C` lower/upper/mixed consonant,
+//! - `p` punctuation, `x` high Latin-1 character,
+//! - `\` makes the next character literal, other characters are literal too,
+//! - `{n}` repeats the previous character (or set) n times in all,
+//! - `[...]` is one character from the placeholders and literals inside, with `^` all
+//!   after it are removed, e.g. `[dl^c]{8}` is 8 digits or vowels.
+
+use std::collections::BTreeSet;
+
+use rand::{OsRng, Rng};
+
+use errors::Error;
+
+pub const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+pub const DIGITS: &str = "0123456789";
+pub const SYMBOLS: &str = "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+/// Characters easily confused with each other in some fonts.
+pub const LOOKALIKES: &str = "Il1|O0";
+
+const BRACKETS: &str = "()[]{}<>";
+const PUNCTUATION: &str = ",.;:";
+const LOWER_VOWELS: &str = "aeiou";
+const UPPER_VOWELS: &str = "AEIOU";
+const LOWER_CONSONANTS: &str = "bcdfghjklmnpqrstvwxyz";
+const UPPER_CONSONANTS: &str = "BCDFGHJKLMNPQRSTVWXYZ";
+
+/// Wordlist for passphrases, the 2048 word BIP-39 English list (CC0): short, common words,
+/// none of them a prefix of another. Each word adds 11 bits.
+const WORDS: &str = include_str!("words.txt");
+
+/// Options for random characters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CharOptions {
+    pub length: usize,
+    pub upper: bool,
+    pub lower: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    /// Additional characters, a class of their own.
+    pub custom: String,
+    /// Leave out `LOOKALIKES`.
+    pub exclude_lookalikes: bool,
+    /// At least one character from each class.
+    pub each_class: bool,
+}
+
+impl Default for CharOptions {
+    fn default() -> CharOptions {
+        CharOptions {
+            length: 20,
+            upper: true,
+            lower: true,
+            digits: true,
+            symbols: false,
+            custom: String::new(),
+            exclude_lookalikes: false,
+            each_class: true,
+        }
+    }
+}
+
+impl CharOptions {
+    /// Character classes selected, without duplicates or empty ones.
+    fn classes(&self) -> Vec<Vec<char>> {
+        let mut classes: Vec<Vec<char>> = Vec::new();
+        let mut seen = BTreeSet::new();
+        let selected = [
+            (self.upper, UPPER),
+            (self.lower, LOWER),
+            (self.digits, DIGITS),
+            (self.symbols, SYMBOLS),
+            (true, self.custom.as_str()),
+        ];
+        for &(wanted, chars) in &selected {
+            if !wanted {
+                continue;
+            }
+            let class: Vec<char> = chars
+                .chars()
+                .filter(|&c| !(self.exclude_lookalikes && LOOKALIKES.contains(c)))
+                .filter(|&c| seen.insert(c))
+                .collect();
+            if !class.is_empty() {
+                classes.push(class);
+            }
+        }
+        classes
+    }
+}
+
+/// How to generate passwords.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Generator {
+    Chars(CharOptions),
+    /// KeePass style pattern, see module docs.
+    Pattern { pattern: String, exclude_lookalikes: bool },
+    /// Random words from embedded wordlist.
+    Passphrase { words: usize, separator: String },
+}
+
+impl Default for Generator {
+    fn default() -> Generator {
+        Generator::Chars(CharOptions::default())
+    }
+}
+
+impl Generator {
+    /// New random password.
+    pub fn generate(&self) -> Result<String, Error> {
+        let mut rng = OsRng::new()?;
+        match *self {
+            Generator::Chars(ref options) => generate_chars(&mut rng, options),
+            Generator::Pattern {
+                ref pattern,
+                exclude_lookalikes,
+            } => Ok(parse_pattern(pattern, exclude_lookalikes)?
+                .iter()
+                .map(|set| pick(&mut rng, set))
+                .collect()),
+            Generator::Passphrase { words, ref separator } => {
+                if words == 0 {
+                    return Err(Error::Invalid(String::from("Passphrase needs at least one word")));
+                }
+                let list = wordlist();
+                Ok((0..words)
+                    .map(|_| pick(&mut rng, &list))
+                    .collect::<Vec<_>>()
+                    .join(separator))
+            }
+        }
+    }
+
+    /// Strength of generated passwords in bits, i.e. log2 of the number of possible passwords
+    /// (a bit less when each class is required).
+    pub fn entropy_bits(&self) -> Result<f64, Error> {
+        Ok(match *self {
+            Generator::Chars(ref options) => {
+                let count: usize = options.classes().iter().map(|c| c.len()).sum();
+                options.length as f64 * (count as f64).log2()
+            }
+            Generator::Pattern {
+                ref pattern,
+                exclude_lookalikes,
+            } => parse_pattern(pattern, exclude_lookalikes)?
+                .iter()
+                .map(|set| (set.len() as f64).log2())
+                .sum(),
+            Generator::Passphrase { words, .. } => words as f64 * (wordlist().len() as f64).log2(),
+        })
+    }
+}
+
+/// Words of embedded wordlist.
+pub fn wordlist() -> Vec<&'static str> {
+    WORDS.lines().filter(|w| !w.is_empty()).collect()
+}
+
+fn pick<T: Clone>(rng: &mut OsRng, items: &[T]) -> T {
+    items[rng.gen_range(0, items.len())].clone()
+}
+
+fn generate_chars(rng: &mut OsRng, options: &CharOptions) -> Result<String, Error> {
+    let classes = options.classes();
+    if classes.is_empty() {
+        return Err(Error::Invalid(String::from("No characters to generate password from")));
+    }
+    if options.length == 0 {
+        return Err(Error::Invalid(String::from("Password length must be at least 1")));
+    }
+    let mut password = Vec::with_capacity(options.length);
+    if options.each_class {
+        if options.length < classes.len() {
+            return Err(Error::Invalid(format!(
+                "Password of {} characters can't have one of each {} character classes",
+                options.length,
+                classes.len()
+            )));
+        }
+        for class in &classes {
+            password.push(pick(rng, class));
+        }
+    }
+    let all: Vec<char> = classes.concat();
+    while password.len() < options.length {
+        password.push(pick(rng, &all));
+    }
+    rng.shuffle(&mut password);
+    Ok(password.into_iter().collect())
+}
+
+/// Characters of pattern placeholder.
+fn placeholder(c: char) -> Option<String> {
+    let chars = match c {
+        'a' => [LOWER, DIGITS].concat(),
+        'A' => [UPPER, LOWER, DIGITS].concat(),
+        'U' => [UPPER, DIGITS].concat(),
+        'd' => DIGITS.to_string(),
+        'h' => "0123456789abcdef".to_string(),
+        'H' => "0123456789ABCDEF".to_string(),
+        'l' => LOWER.to_string(),
+        'L' => [UPPER, LOWER].concat(),
+        'u' => UPPER.to_string(),
+        's' => SYMBOLS.to_string(),
+        'S' => [UPPER, LOWER, DIGITS, SYMBOLS].concat(),
+        'b' => BRACKETS.to_string(),
+        'v' => LOWER_VOWELS.to_string(),
+        'V' => [UPPER_VOWELS, LOWER_VOWELS].concat(),
+        'Z' => UPPER_VOWELS.to_string(),
+        'c' => LOWER_CONSONANTS.to_string(),
+        'C' => [UPPER_CONSONANTS, LOWER_CONSONANTS].concat(),
+        'z' => UPPER_CONSONANTS.to_string(),
+        'p' => PUNCTUATION.to_string(),
+        // Latin-1 from inverted exclamation mark, without soft hyphen.
+        'x' => (0xA1u8..=0xFF)
+            .filter(|&b| b != 0xAD)
+            .map(|b| b as char)
+            .collect(),
+        _ => return None,
+    };
+    Some(chars)
+}
+
+fn invalid_pattern(pattern: &str, desc: &str) -> Error {
+    Error::Invalid(format!("Invalid pattern \"{}\": {}", pattern, desc))
+}
+
+/// Characters to pick from for each position of pattern.
+fn parse_pattern(pattern: &str, exclude_lookalikes: bool) -> Result<Vec<Vec<char>>, Error> {
+    let mut sets: Vec<Vec<char>> = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let set: Vec<char> = match c {
+            '\\' => match chars.next() {
+                Some(literal) => vec![literal],
+                None => return Err(invalid_pattern(pattern, "\\ at the end")),
+            },
+            '{' => {
+                let count: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let count: usize = count
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid_pattern(pattern, &format!("\"{{{}}}\" is not a count", count)))?;
+                let previous = match sets.pop() {
+                    Some(p) => p,
+                    None => return Err(invalid_pattern(pattern, "nothing to repeat")),
+                };
+                for _ in 0..count {
+                    sets.push(previous.clone());
+                }
+                continue;
+            }
+            '[' => {
+                let mut included = BTreeSet::new();
+                let mut excluded = BTreeSet::new();
+                let mut excluding = false;
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    let chars_in = match c {
+                        ']' => {
+                            closed = true;
+                            break;
+                        }
+                        '^' => {
+                            excluding = true;
+                            continue;
+                        }
+                        '\\' => chars.next().map(String::from).unwrap_or_default(),
+                        c => placeholder(c).unwrap_or_else(|| c.to_string()),
+                    };
+                    if excluding {
+                        excluded.extend(chars_in.chars());
+                    } else {
+                        included.extend(chars_in.chars());
+                    }
+                }
+                if !closed {
+                    return Err(invalid_pattern(pattern, "[ without ]"));
+                }
+                included
+                    .difference(&excluded)
+                    .cloned()
+                    .filter(|&c| !(exclude_lookalikes && LOOKALIKES.contains(c)))
+                    .collect()
+            }
+            c => match placeholder(c) {
+                Some(chars) => chars
+                    .chars()
+                    .filter(|&c| !(exclude_lookalikes && LOOKALIKES.contains(c)))
+                    .collect(),
+                None => vec![c],
+            },
+        };
+        if set.is_empty() {
+            return Err(invalid_pattern(pattern, "no characters to pick from"));
+        }
+        sets.push(set);
+    }
+    if sets.is_empty() {
+        return Err(invalid_pattern(pattern, "nothing to generate"));
+    }
+    Ok(sets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chars() {
+        let options = CharOptions {
+            length: 4,
+            symbols: true,
+            exclude_lookalikes: true,
+            ..CharOptions::default()
+        };
+        for _ in 0..50 {
+            let pw = Generator::Chars(options.clone()).generate().unwrap();
+            assert_eq!(pw.chars().count(), 4);
+            assert!(pw.chars().any(|c| UPPER.contains(c)), "{}", pw);
+            assert!(pw.chars().any(|c| LOWER.contains(c)), "{}", pw);
+            assert!(pw.chars().any(|c| DIGITS.contains(c)), "{}", pw);
+            assert!(pw.chars().any(|c| SYMBOLS.contains(c)), "{}", pw);
+            assert!(!pw.chars().any(|c| LOOKALIKES.contains(c)), "{}", pw);
+        }
+        let too_short = CharOptions {
+            length: 2,
+            ..options
+        };
+        assert!(Generator::Chars(too_short).generate().is_err());
+    }
+
+    #[test]
+    fn test_pattern() {
+        let sets = parse_pattern(r"u\d{3}[la^c]-", false).unwrap();
+        assert_eq!(sets.len(), 6);
+        assert_eq!(sets[0].len(), 26);
+        assert_eq!(sets[1..4], [vec!['d'], vec!['d'], vec!['d']]);
+        assert_eq!(sets[4], "0123456789aeiou".chars().collect::<Vec<_>>());
+        assert_eq!(sets[5], vec!['-']);
+        assert!(!parse_pattern("A", true).unwrap()[0].contains(&'0'));
+        for bad in &["{2}", "d{x}", "[ab", "[^a]", "\\", ""] {
+            assert!(parse_pattern(bad, false).is_err(), "{}", bad);
+        }
+        let pw = Generator::Pattern {
+            pattern: String::from("hhhh-dd"),
+            exclude_lookalikes: false,
+        }.generate()
+            .unwrap();
+        assert_eq!(pw.len(), 7);
+        assert_eq!(&pw[4..5], "-");
+    }
+
+    #[test]
+    fn test_passphrase() {
+        assert_eq!(wordlist().len(), 2048);
+        let generator = Generator::Passphrase {
+            words: 5,
+            separator: String::from(" "),
+        };
+        assert_eq!(generator.generate().unwrap().split(' ').count(), 5);
+        assert_eq!(generator.entropy_bits().unwrap(), 55.0);
+    }
+}
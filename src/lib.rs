@@ -0,0 +1,64 @@
+//! mpkdb library: password database support shared by the mpkdb apps.
+//!
+//! Public API:
+//!
+//! - `db::api`: the `PasswordDatabase` trait, along with `Entry` and `Group`,
+//!   this is what apps should use to work with databases,
+//! - `db::kdb`: KeePass 1.x (KDB) implementation, `db::kdb::open` and `db::kdb::create`,
+//! - `db::file`: atomic saving with backups, and KeePass compatible lock files,
+//! - `db::formats`: import and export of plaintext formats (CSV, KeePass XML...),
+//! - `db::audit`: password strength and weak, reused or old passwords,
+//! - `model`: app config and state,
+//! - `controller`: app logic shared by frontends (open, lock, search, copy, save...),
+//! - `config`: loading and saving config,
+//! - `generator`: random passwords and passphrases,
+//! - `otp`: TOTP and HOTP one-time codes of entries,
+//! - `errors`: `Error`, used by all of the above,
+//! - `tui`: terminal UI (with `tui` feature).
+//!
+//! Example:
+//!
+//! ```no_run
+//! use mpkdb::db::api::PasswordDatabase;
+//!
+//! let db = mpkdb::db::kdb::open("passwords.kdb", "password", None).unwrap();
+//! for entry in db.search("mail") {
+//!     println!("{}", db.get_entry_path(&entry));
+//! }
+//! ```
+
+extern crate base64;
+extern crate crypto;
+extern crate csv;
+extern crate env_logger;
+extern crate flate2;
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate nom;
+extern crate qrcode;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+#[cfg(feature = "tui")]
+extern crate termion;
+extern crate time;
+extern crate toml;
+extern crate uuid;
+extern crate xml;
+
+pub mod config;
+pub mod controller;
+pub mod db;
+pub mod errors;
+pub mod generator;
+pub mod otp;
+pub mod logging;
+pub mod model;
+#[cfg(feature = "tui")]
+pub mod tui;
+
+pub use db::api::{Entry, Group, GroupId, PasswordDatabase};
+pub use model::{Config, RecentFile, State};
@@ -11,6 +11,11 @@ use time;
 /// Does not return error, instead just panics on error,
 /// since logging is pretty essential.
 pub fn env_logger_init() {
+    env_logger_init_with_level(LogLevelFilter::Debug);
+}
+
+/// Same as env_logger_init, but logs only given level and up unless RUST_LOG says otherwise.
+pub fn env_logger_init_with_level(level: LogLevelFilter) {
     let format = |record: &LogRecord| {
         let t = time::now();
         let ts = time::strftime("%Y-%m-%d %H:%M:%S", &t).expect("Failed to format time");
@@ -23,7 +28,7 @@ pub fn env_logger_init() {
         )
     };
     let mut builder = LogBuilder::new();
-    builder.format(format).filter(None, LogLevelFilter::Debug);
+    builder.format(format).filter(None, level);
     if env::var("RUST_LOG").is_ok() {
         builder.parse(&env::var("RUST_LOG").unwrap());
     }
@@ -1,69 +1,140 @@
 
-extern crate crypto;
-extern crate env_logger;
+extern crate gdk;
 extern crate gtk;
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate nom;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate time;
-extern crate toml;
-extern crate uuid;
+extern crate mpkdb;
+extern crate notify;
 
-mod config;
-mod db;
-mod errors;
-mod logging;
-mod model;
 mod ui;
 
-use std::sync::Arc;
-use std::sync::RwLock;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use gtk::{Window, WindowType};
 use gtk::prelude::*;
 
+use mpkdb::config;
+use mpkdb::controller::{Command, Controller, Event};
+use mpkdb::errors;
+use mpkdb::logging;
+
+/// Config file path given with `--config <path>` or `--config=<path>`, if any.
+fn parse_config_arg() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+        if arg.starts_with("--config=") {
+            return Some(String::from(&arg["--config=".len()..]));
+        }
+    }
+    None
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(config_path: &std::path::Path) {
+    if let Err(e) = mpkdb::tui::run(None, None, config_path) {
+        println!("Error: {}", errors::report(&e));
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_tui(_config_path: &std::path::Path) {
+    println!("Terminal UI is not available, mpkdb was built without the \"tui\" feature.");
+}
+
+/// Show main view if controller opened database, or error if it failed.
+fn handle_open_events(window: &gtk::Window, controller: &Rc<RefCell<Controller>>, events: Vec<Event>) {
+    for event in events {
+        match event {
+            Event::Unlocked => {
+                // DB opened successfully, show main UI.
+                ui::main::init_main_ui(window, Rc::clone(controller), show_open_ui);
+            }
+            Event::OpenFailed(desc) => {
+                error!("Failed to open DB: {}", desc);
+                ui::show_error(window, &format!("Failed to open database: {}", desc));
+            }
+            Event::LockedByOther(owner) => {
+                if ui::confirm_take_over_lock(window, &owner) {
+                    let events = controller.borrow_mut().handle(Command::TakeOverLock);
+                    handle_open_events(window, controller, events);
+                }
+            }
+            Event::Error(desc) => {
+                warn!("{}", desc);
+                if !controller.borrow().is_unlocked() {
+                    ui::show_error(window, &desc);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Show open file view, then main view once database is opened or created.
+fn show_open_ui(window: &gtk::Window, controller: Rc<RefCell<Controller>>) {
+    let recent_files = controller
+        .borrow()
+        .get_config()
+        .map(|c| c.recent_files.clone())
+        .unwrap_or_default();
+    let window_clone = window.clone(); // moved to closure
+    let controller_clone = Rc::clone(&controller);
+    ui::open::init_open_file_ui(
+        window,
+        &recent_files,
+        move |r| {
+            let events = controller_clone.borrow_mut().handle(Command::Open {
+                path: r.filename,
+                password: r.password,
+                key_file: r.key_file,
+            });
+            handle_open_events(&window_clone, &controller_clone, events);
+        },
+        {
+            let window_clone = window.clone();
+            move |r| {
+                let events = controller.borrow_mut().handle(Command::Create {
+                    path: r.filename,
+                    password: r.password,
+                    key_file: r.key_file,
+                    rounds: r.rounds,
+                });
+                handle_open_events(&window_clone, &controller, events);
+            }
+        },
+    );
+}
+
 /// Main entry.
-/// TODO: Split in smaller fns, also split app into binary and lib.
+/// TODO: Split in smaller fns.
 fn main() {
+    let config_path = config::config_file_path(parse_config_arg().as_ref().map(|p| p.as_str()));
+    if std::env::args().any(|a| a == "--tui") {
+        // Debug logs would end up all over the screen.
+        logging::env_logger_init_with_level(log::LogLevelFilter::Warn);
+        run_tui(&config_path);
+        return;
+    }
     logging::env_logger_init();
     debug!("hello, world");
     if gtk::init().is_err() {
         println!("Failed to initialize GTK.");
+        if cfg!(feature = "tui") {
+            println!("Use --tui for terminal UI.");
+        }
         return;
     }
-    let _conf = config::load_config().expect("Error loading config");
-    let state = Arc::new(RwLock::new(model::State { db: None }));
-    let state_clone = Arc::clone(&state);
+    let conf = config::load_config(&config_path).expect("Error loading config");
+    let mut controller = Controller::new();
+    controller.set_config(conf, config_path);
+    let controller = Rc::new(RefCell::new(controller));
     let window = Window::new(WindowType::Toplevel);
-    let window_clone = window.clone(); // moved to closure
     window.set_title("mpkdb");
-    ui::open::init_open_file_ui(&window, None, move |s| {
-        let password = "test";
-        let db = match db::kdb::db::open(&s, password) {
-            Ok(db) => Arc::new(RwLock::new(db)),
-            Err(e) => {
-                error!("Failed to open DB: {}", e);
-                // TODO: UI
-                return;
-            }
-        };
-        // TODO: Is failure to lock something we should expect? Maybe I should use antidote?
-        let mut state_guard = match state_clone.write() {
-            Ok(g) => g,
-            Err(e) => {
-                // TODO: UI
-                error!("Failed to lock state: {}", e);
-                return;
-            }
-        };
-        state_guard.db = Some(db);
-        // DB opened successfully, show main UI.
-        ui::main::init_main_ui(&window_clone);
-    });
+    show_open_ui(&window, controller);
     window.connect_delete_event(|_, _| {
         gtk::main_quit();
         Inhibit(false)
@@ -1,23 +1,393 @@
-
 //! Some global helper stucts.
 
+use std;
 use std::sync::Arc;
 use std::sync::RwLock;
 
+use config;
 use db::api::PasswordDatabase;
+use errors;
+
+/// How many recently opened files we remember.
+pub const MAX_RECENT_FILES: usize = 10;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct Config {
+    /// Config schema version, see config::CONFIG_VERSION.
+    pub version: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub last_file: Option<String>,
+    /// Most recently used first.
+    #[serde(default)]
+    pub recent_files: Vec<RecentFile>,
+}
+
+/// Recently opened database, along with the key file used to open it (if any).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RecentFile {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_file: Option<String>,
 }
 
 impl Default for Config {
     fn default() -> Config {
-        Config { last_file: None }
+        Config {
+            version: config::CONFIG_VERSION,
+            last_file: None,
+            recent_files: Vec::new(),
+        }
     }
 }
 
+impl Config {
+    /// Move (or add) given file to the top of the recent files list.
+    pub fn remember_file(&mut self, path: &str, key_file: Option<&str>) {
+        let mut recent_files = self.recent_files.clone();
+        recent_files.retain(|f| f.path != path);
+        recent_files.insert(
+            0,
+            RecentFile {
+                path: String::from(path),
+                key_file: key_file.map(String::from),
+            },
+        );
+        recent_files.truncate(MAX_RECENT_FILES);
+        self.recent_files = recent_files;
+        self.last_file = Some(String::from(path));
+    }
+}
+
+/// Database as kept in the app state.
+pub type SharedDatabase = Arc<RwLock<PasswordDatabase>>;
+
 /// Main "global" app state.
-pub struct State {
-    pub db: Option<Arc<RwLock<PasswordDatabase>>>,
+/// Only `State::apply` changes state, so e.g. there is no unlocked state without database.
+pub enum State {
+    /// Nothing opened yet.
+    NoFile,
+    /// Database was opened and then closed, it can be unlocked again.
+    Locked {
+        path: String,
+        key_file: Option<String>,
+    },
+    Unlocked {
+        path: String,
+        key_file: Option<String>,
+        db: SharedDatabase,
+        /// Database has unsaved changes.
+        dirty: bool,
+    },
+    /// Unlocked database being written.
+    Saving {
+        path: String,
+        key_file: Option<String>,
+        db: SharedDatabase,
+        dirty: bool,
+    },
+    /// Opening failed, or database can't be used anymore.
+    Error {
+        path: Option<String>,
+        key_file: Option<String>,
+        desc: String,
+    },
+}
+
+/// State changes, see `State::apply` for which are allowed when.
+pub enum Transition {
+    Opened {
+        path: String,
+        key_file: Option<String>,
+        db: SharedDatabase,
+    },
+    /// Opening failed, doesn't affect already unlocked database.
+    OpenFailed {
+        path: String,
+        key_file: Option<String>,
+        desc: String,
+    },
+    Lock,
+    /// Database was changed.
+    Modified,
+    /// Master key was changed, possibly to different key file.
+    KeyChanged { key_file: Option<String> },
+    StartSave,
+    SaveDone,
+    /// Changes are still in memory, so we go back to unlocked.
+    SaveFailed,
+    /// Database was read again from file, unsaved changes are gone.
+    Reloaded,
+    /// Something went badly wrong, database is closed.
+    Fail(String),
+}
+
+impl Transition {
+    fn name(&self) -> &'static str {
+        match *self {
+            Transition::Opened { .. } => "open",
+            Transition::OpenFailed { .. } => "fail to open",
+            Transition::Lock => "lock",
+            Transition::Modified => "modify",
+            Transition::KeyChanged { .. } => "change key",
+            Transition::StartSave => "save",
+            Transition::SaveDone | Transition::SaveFailed => "finish saving",
+            Transition::Reloaded => "reload",
+            Transition::Fail(_) => "fail",
+        }
+    }
+}
+
+impl State {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            State::NoFile => "no file",
+            State::Locked { .. } => "locked",
+            State::Unlocked { .. } => "unlocked",
+            State::Saving { .. } => "saving",
+            State::Error { .. } => "error",
+        }
+    }
+
+    /// Database, when unlocked (or being saved).
+    pub fn db(&self) -> Option<&SharedDatabase> {
+        match *self {
+            State::Unlocked { ref db, .. } | State::Saving { ref db, .. } => Some(db),
+            _ => None,
+        }
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.db().is_some()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        match *self {
+            State::Unlocked { dirty, .. } | State::Saving { dirty, .. } => dirty,
+            _ => false,
+        }
+    }
+
+    /// Last opened (or attempted) file.
+    pub fn path(&self) -> Option<&str> {
+        match *self {
+            State::NoFile => None,
+            State::Locked { ref path, .. } | State::Unlocked { ref path, .. } | State::Saving { ref path, .. } => {
+                Some(path)
+            }
+            State::Error { ref path, .. } => path.as_ref().map(|p| p.as_str()),
+        }
+    }
+
+    pub fn key_file(&self) -> Option<&str> {
+        match *self {
+            State::NoFile => None,
+            State::Locked { ref key_file, .. } |
+            State::Unlocked { ref key_file, .. } |
+            State::Saving { ref key_file, .. } |
+            State::Error { ref key_file, .. } => key_file.as_ref().map(|k| k.as_str()),
+        }
+    }
+
+    /// Change state, if transition is allowed in current state. Otherwise state is left as it was.
+    pub fn apply(&mut self, transition: Transition) -> Result<(), errors::Error> {
+        let current = std::mem::replace(self, State::NoFile);
+        debug!("State::apply: {} when {}", transition.name(), current.name());
+        let (next, result) = match (current, transition) {
+            // Changes in current database would be lost.
+            (s @ State::Unlocked { dirty: true, .. }, Transition::Opened { .. }) => {
+                (s, Err(errors::Error::UnsavedChanges))
+            }
+            (s @ State::Saving { .. }, t @ Transition::Opened { .. }) => {
+                let err = errors::Error::InvalidState {
+                    action: t.name(),
+                    state: s.name(),
+                };
+                (s, Err(err))
+            }
+            (_, Transition::Opened { path, key_file, db }) => (
+                State::Unlocked {
+                    path: path,
+                    key_file: key_file,
+                    db: db,
+                    dirty: false,
+                },
+                Ok(()),
+            ),
+            (s @ State::Unlocked { .. }, Transition::OpenFailed { .. }) |
+            (s @ State::Saving { .. }, Transition::OpenFailed { .. }) => (s, Ok(())),
+            (_, Transition::OpenFailed { path, key_file, desc }) => (
+                State::Error {
+                    path: Some(path),
+                    key_file: key_file,
+                    desc: desc,
+                },
+                Ok(()),
+            ),
+            (State::Unlocked { path, key_file, dirty: false, .. }, Transition::Lock) => (
+                State::Locked {
+                    path: path,
+                    key_file: key_file,
+                },
+                Ok(()),
+            ),
+            (s @ State::Unlocked { dirty: true, .. }, Transition::Lock) => (s, Err(errors::Error::UnsavedChanges)),
+            (State::Unlocked { path, key_file, db, .. }, Transition::Modified) => (
+                State::Unlocked {
+                    path: path,
+                    key_file: key_file,
+                    db: db,
+                    dirty: true,
+                },
+                Ok(()),
+            ),
+            (State::Unlocked { path, db, .. }, Transition::KeyChanged { key_file }) => (
+                State::Unlocked {
+                    path: path,
+                    key_file: key_file,
+                    db: db,
+                    dirty: true,
+                },
+                Ok(()),
+            ),
+            (State::Unlocked { path, key_file, db, dirty }, Transition::StartSave) => (
+                State::Saving {
+                    path: path,
+                    key_file: key_file,
+                    db: db,
+                    dirty: dirty,
+                },
+                Ok(()),
+            ),
+            (State::Saving { path, key_file, db, .. }, Transition::SaveDone) => (
+                State::Unlocked {
+                    path: path,
+                    key_file: key_file,
+                    db: db,
+                    dirty: false,
+                },
+                Ok(()),
+            ),
+            (State::Saving { path, key_file, db, dirty }, Transition::SaveFailed) => (
+                State::Unlocked {
+                    path: path,
+                    key_file: key_file,
+                    db: db,
+                    dirty: dirty,
+                },
+                Ok(()),
+            ),
+            (State::Unlocked { path, key_file, db, .. }, Transition::Reloaded) => (
+                State::Unlocked {
+                    path: path,
+                    key_file: key_file,
+                    db: db,
+                    dirty: false,
+                },
+                Ok(()),
+            ),
+            (s, Transition::Fail(desc)) => (
+                State::Error {
+                    path: s.path().map(String::from),
+                    key_file: s.key_file().map(String::from),
+                    desc: desc,
+                },
+                Ok(()),
+            ),
+            (s, t) => {
+                let err = errors::Error::InvalidState {
+                    action: t.name(),
+                    state: s.name(),
+                };
+                (s, Err(err))
+            }
+        };
+        *self = next;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use db::kdb;
+
+    use super::*;
+
+    fn opened(path: &str) -> Transition {
+        let db = kdb::create("unused.kdb", "secret", None).unwrap();
+        Transition::Opened {
+            path: String::from(path),
+            key_file: None,
+            db: Arc::new(RwLock::new(db)),
+        }
+    }
+
+    fn invalid_state(r: Result<(), errors::Error>) -> (&'static str, &'static str) {
+        match r {
+            Err(errors::Error::InvalidState { action, state }) => (action, state),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_full_cycle() {
+        let mut s = State::NoFile;
+        s.apply(opened("a.kdb")).unwrap();
+        assert_eq!((s.name(), s.is_dirty(), s.path()), ("unlocked", false, Some("a.kdb")));
+        s.apply(Transition::Modified).unwrap();
+        assert!(s.is_dirty());
+        s.apply(Transition::StartSave).unwrap();
+        assert_eq!((s.name(), s.is_dirty()), ("saving", true));
+        s.apply(Transition::SaveDone).unwrap();
+        assert_eq!((s.name(), s.is_dirty()), ("unlocked", false));
+        s.apply(Transition::Lock).unwrap();
+        assert_eq!((s.name(), s.path()), ("locked", Some("a.kdb")));
+        assert!(s.db().is_none());
+        s.apply(opened("a.kdb")).unwrap();
+        assert!(s.is_unlocked());
+    }
+
+    #[test]
+    fn test_rejected_transitions() {
+        let mut s = State::NoFile;
+        s.apply(opened("a.kdb")).unwrap();
+        assert_eq!(invalid_state(s.apply(Transition::SaveDone)), ("finish saving", "unlocked"));
+        s.apply(Transition::Modified).unwrap();
+        match s.apply(Transition::Lock) {
+            Err(errors::Error::UnsavedChanges) => {}
+            r => panic!("{:?}", r),
+        }
+        match s.apply(opened("b.kdb")) {
+            Err(errors::Error::UnsavedChanges) => {}
+            r => panic!("{:?}", r),
+        }
+        // Rejected transitions leave state as it was.
+        assert_eq!((s.name(), s.is_dirty(), s.path()), ("unlocked", true, Some("a.kdb")));
+        s.apply(Transition::StartSave).unwrap();
+        assert_eq!(invalid_state(s.apply(Transition::Lock)), ("lock", "saving"));
+        assert_eq!(invalid_state(s.apply(opened("b.kdb"))), ("open", "saving"));
+        assert_eq!(invalid_state(s.apply(Transition::StartSave)), ("save", "saving"));
+        assert_eq!((s.name(), s.path()), ("saving", Some("a.kdb")));
+        s.apply(Transition::SaveFailed).unwrap();
+        assert_eq!((s.name(), s.is_dirty()), ("unlocked", true));
+    }
+
+    #[test]
+    fn test_recover_from_error() {
+        let mut s = State::NoFile;
+        s.apply(Transition::OpenFailed {
+            path: String::from("a.kdb"),
+            key_file: Some(String::from("a.key")),
+            desc: String::from("Wrong password or key file"),
+        }).unwrap();
+        assert_eq!((s.name(), s.path(), s.key_file()), ("error", Some("a.kdb"), Some("a.key")));
+        assert_eq!(invalid_state(s.apply(Transition::Modified)), ("modify", "error"));
+        s.apply(opened("a.kdb")).unwrap();
+        s.apply(Transition::Modified).unwrap();
+        // E.g. poisoned lock, unsaved changes are gone.
+        s.apply(Transition::Fail(String::from("Database lock poisoned"))).unwrap();
+        assert_eq!((s.name(), s.is_dirty(), s.path()), ("error", false, Some("a.kdb")));
+        assert!(s.db().is_none());
+        s.apply(opened("a.kdb")).unwrap();
+        assert_eq!((s.name(), s.is_dirty()), ("unlocked", false));
+    }
 }
@@ -0,0 +1,507 @@
+//! One-time passwords for two-factor login: HOTP (RFC 4226) and TOTP (RFC 6238).
+//!
+//! KDB files have no custom fields, so the secret is kept in entry notes, on a line like
+//! "TOTP: otpauth://totp/Example:bob?secret=JBSWY3DPEHPK3PXP&issuer=Example", or just
+//! "TOTP: JBSWY3DPEHPK3PXP" for the usual 6 digits every 30 seconds with SHA-1.
+//! Lines other apps write are read too:
+//!
+//! - "otp: otpauth://..." (KeePassXC `otp` field, from KeePass XML import),
+//! - "TOTP Seed: ..." with optional "TOTP Settings: 30;6" (older KeePassXC),
+//! - bare "otpauth://..." lines (pass-otp).
+
+use std;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+use crypto::sha2::{Sha256, Sha512};
+
+use db::formats::TOTP_FIELD;
+use errors::Error;
+
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+const DEFAULT_DIGITS: u32 = 6;
+const DEFAULT_PERIOD: u64 = 30;
+
+/// Note line keys of OTP secrets, lower case, ours first.
+const NOTE_KEYS: &[&str] = &["totp", "otp", "totp seed"];
+const SETTINGS_KEY: &str = "totp settings";
+
+/// Shown instead of secrets, e.g. by `hide_in_notes`.
+pub const HIDDEN_SECRET: &str = "(hidden)";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_uppercase().replace('-', "").as_str() {
+            "SHA1" => Some(Algorithm::Sha1),
+            "SHA256" => Some(Algorithm::Sha256),
+            "SHA512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// New code every period seconds.
+    Totp { period: u64 },
+    /// New code each time, counter is increased after use.
+    Hotp { counter: u64 },
+}
+
+/// OTP configuration of an entry.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Otp {
+    pub secret: Vec<u8>,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub kind: Kind,
+    /// Service and account, from otpauth URI label, may be empty.
+    pub issuer: String,
+    pub account: String,
+}
+
+fn invalid(desc: &str) -> Error {
+    Error::Invalid(format!("Invalid OTP secret: {}", desc))
+}
+
+/// Base32 (RFC 4648) text as bytes, case, spaces, dashes and padding don't matter.
+pub fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for c in text.chars().filter(|&c| !c.is_whitespace() && c != '-' && c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a as char == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Bytes as base32, without padding.
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut text = String::new();
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+    for &b in data {
+        buffer = (buffer << 8) | b as u64;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            text.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        text.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    text
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+        } else {
+            None
+        };
+        match hex {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for &b in text.as_bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// HOTP value of counter, the code is this with leading zeros to digits.
+pub fn hotp(key: &[u8], counter: u64, algorithm: Algorithm, digits: u32) -> u64 {
+    let message = counter.to_be_bytes();
+    let hash = match algorithm {
+        Algorithm::Sha1 => {
+            let mut mac = Hmac::new(Sha1::new(), key);
+            mac.input(&message);
+            mac.result().code().to_vec()
+        }
+        Algorithm::Sha256 => {
+            let mut mac = Hmac::new(Sha256::new(), key);
+            mac.input(&message);
+            mac.result().code().to_vec()
+        }
+        Algorithm::Sha512 => {
+            let mut mac = Hmac::new(Sha512::new(), key);
+            mac.input(&message);
+            mac.result().code().to_vec()
+        }
+    };
+    // Dynamic truncation.
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let value = (u64::from(hash[offset]) & 0x7f) << 24 | u64::from(hash[offset + 1]) << 16 |
+        u64::from(hash[offset + 2]) << 8 | u64::from(hash[offset + 3]);
+    value % 10u64.pow(digits)
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Otp {
+    /// TOTP with default settings.
+    pub fn new(secret: Vec<u8>) -> Otp {
+        Otp {
+            secret: secret,
+            algorithm: Algorithm::Sha1,
+            digits: DEFAULT_DIGITS,
+            kind: Kind::Totp {
+                period: DEFAULT_PERIOD,
+            },
+            issuer: String::new(),
+            account: String::new(),
+        }
+    }
+
+    /// otpauth:// URI, or base32 secret.
+    pub fn parse(text: &str) -> Result<Otp, Error> {
+        let text = text.trim();
+        if !text.to_lowercase().starts_with("otpauth://") {
+            return match base32_decode(text) {
+                Some(ref s) if !s.is_empty() => Ok(Otp::new(s.clone())),
+                _ => Err(invalid("not an otpauth:// URI or base32")),
+            };
+        }
+        let rest = &text["otpauth://".len()..];
+        let (path, query) = match rest.find('?') {
+            Some(pos) => (&rest[..pos], &rest[pos + 1..]),
+            None => (rest, ""),
+        };
+        let (kind, label) = match path.find('/') {
+            Some(pos) => (path[..pos].to_lowercase(), percent_decode(&path[pos + 1..])),
+            None => (path.to_lowercase(), String::new()),
+        };
+        let mut otp = Otp::new(Vec::new());
+        match label.find(':') {
+            Some(pos) => {
+                otp.issuer = label[..pos].trim().to_string();
+                otp.account = label[pos + 1..].trim().to_string();
+            }
+            None => otp.account = label,
+        }
+        let mut counter = None;
+        let mut period = DEFAULT_PERIOD;
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(pos) => (&param[..pos], percent_decode(&param[pos + 1..])),
+                None => (param, String::new()),
+            };
+            match key.to_lowercase().as_str() {
+                "secret" => otp.secret = base32_decode(&value).ok_or_else(|| invalid("secret is not base32"))?,
+                "issuer" => otp.issuer = value,
+                "algorithm" => {
+                    otp.algorithm =
+                        Algorithm::from_name(&value).ok_or_else(|| invalid(&format!("unknown algorithm {}", value)))?
+                }
+                "digits" => otp.digits = value.parse().map_err(|_| invalid(&format!("digits {}", value)))?,
+                "period" => period = value.parse().map_err(|_| invalid(&format!("period {}", value)))?,
+                "counter" => counter = Some(value.parse().map_err(|_| invalid(&format!("counter {}", value)))?),
+                "encoder" if value != "" => {
+                    return Err(Error::Unsupported(format!("Unsupported OTP encoder: {}", value)))
+                }
+                _ => {}
+            }
+        }
+        otp.kind = match kind.as_str() {
+            "totp" => Kind::Totp { period: period },
+            "hotp" => Kind::Hotp {
+                counter: counter.ok_or_else(|| invalid("HOTP without counter"))?,
+            },
+            other => return Err(invalid(&format!("unknown type {}", other))),
+        };
+        otp.check()?;
+        Ok(otp)
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.secret.is_empty() {
+            return Err(invalid("no secret"));
+        }
+        if self.digits < 1 || self.digits > 10 {
+            return Err(invalid(&format!("{} digits", self.digits)));
+        }
+        if self.kind == (Kind::Totp { period: 0 }) {
+            return Err(invalid("period 0"));
+        }
+        Ok(())
+    }
+
+    /// OTP configuration in notes, None if there is none.
+    pub fn from_notes(notes: &str) -> Option<Result<Otp, Error>> {
+        let mut found: Option<(usize, &str)> = None;
+        let mut settings = None;
+        for line in notes.lines().map(|l| l.trim()) {
+            if line.to_lowercase().starts_with("otpauth://") {
+                found = found.or(Some((NOTE_KEYS.len(), line)));
+                continue;
+            }
+            let pos = match line.find(':') {
+                Some(p) => p,
+                None => continue,
+            };
+            let key = line[..pos].trim().to_lowercase();
+            let value = line[pos + 1..].trim();
+            if key == SETTINGS_KEY {
+                settings = Some(value);
+            } else if let Some(rank) = NOTE_KEYS.iter().position(|k| *k == key) {
+                if found.map_or(true, |(r, _)| rank < r) {
+                    found = Some((rank, value));
+                }
+            }
+        }
+        let (_, value) = found?;
+        let mut otp = match Otp::parse(value) {
+            Ok(o) => o,
+            Err(e) => return Some(Err(e)),
+        };
+        // "30;6", or "30;S" for Steam, which we can't do.
+        if let Some(s) = settings.filter(|_| !value.to_lowercase().starts_with("otpauth://")) {
+            let mut parts = s.split(';').map(|p| p.trim());
+            if let Some(period) = parts.next().and_then(|p| p.parse().ok()) {
+                otp.kind = Kind::Totp { period: period };
+            }
+            match parts.next() {
+                Some("S") => return Some(Err(Error::Unsupported(String::from("Steam codes are not supported")))),
+                Some(d) => otp.digits = d.parse().unwrap_or(otp.digits),
+                None => {}
+            }
+            if let Err(e) = otp.check() {
+                return Some(Err(e));
+            }
+        }
+        Some(Ok(otp))
+    }
+
+    /// otpauth:// URI, e.g. for QR codes and `set_in_notes`.
+    pub fn to_uri(&self) -> String {
+        let (kind, counter_or_period) = match self.kind {
+            Kind::Totp { period } => ("totp", format!("period={}", period)),
+            Kind::Hotp { counter } => ("hotp", format!("counter={}", counter)),
+        };
+        let label = if self.issuer.is_empty() {
+            percent_encode(&self.account)
+        } else {
+            format!("{}:{}", percent_encode(&self.issuer), percent_encode(&self.account))
+        };
+        let mut uri = format!(
+            "otpauth://{}/{}?secret={}&algorithm={}&digits={}&{}",
+            kind,
+            label,
+            base32_encode(&self.secret),
+            self.algorithm.name(),
+            self.digits,
+            counter_or_period
+        );
+        if !self.issuer.is_empty() {
+            uri.push_str(&format!("&issuer={}", percent_encode(&self.issuer)));
+        }
+        uri
+    }
+
+    /// Code for HOTP counter or TOTP time step.
+    pub fn code_at(&self, counter: u64) -> String {
+        format!(
+            "{:0width$}",
+            hotp(&self.secret, counter, self.algorithm, self.digits),
+            width = self.digits as usize
+        )
+    }
+
+    /// TOTP code at unix time, and seconds it's still valid. For HOTP, the code of current
+    /// counter, which stays valid until used.
+    pub fn code_at_time(&self, time: u64) -> (String, u64) {
+        match self.kind {
+            Kind::Totp { period } => (self.code_at(time / period), period - time % period),
+            Kind::Hotp { counter } => (self.code_at(counter), 0),
+        }
+    }
+
+    /// Current code, and for how many seconds it's valid (0 for HOTP).
+    pub fn current(&self) -> (String, u64) {
+        self.code_at_time(unix_time())
+    }
+
+    /// Code to log in with. HOTP counter is increased, entry notes should be updated with
+    /// `set_in_notes` then, or the same code comes again.
+    pub fn next_code(&mut self) -> String {
+        let (code, _) = self.current();
+        if let Kind::Hotp { ref mut counter } = self.kind {
+            *counter += 1;
+        }
+        code
+    }
+}
+
+/// Key of notes line, as written, if it's "Key: value".
+fn line_key(line: &str) -> Option<&str> {
+    line.find(':').map(|pos| line[..pos].trim())
+}
+
+/// Key of notes line holding an OTP secret, as written, "" for bare otpauth:// URIs.
+fn secret_key(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.to_lowercase().starts_with("otpauth://") {
+        return Some("");
+    }
+    line_key(line).filter(|k| NOTE_KEYS.contains(&k.to_lowercase().as_str()))
+}
+
+/// Whether notes hold an OTP secret, which is as secret as a password.
+pub fn has_secret(notes: &str) -> bool {
+    notes.lines().any(|l| secret_key(l).is_some())
+}
+
+/// Notes with OTP secrets hidden, for output that leaves passwords out.
+pub fn hide_in_notes(notes: &str) -> String {
+    let lines: Vec<String> = notes
+        .lines()
+        .map(|line| match secret_key(line) {
+            Some("") => String::from(HIDDEN_SECRET),
+            Some(key) => format!("{}: {}", key, HIDDEN_SECRET),
+            None => String::from(line),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Notes with OTP configuration (URI or base32 secret) on a "TOTP: " line, replacing the
+/// configuration already there, if any.
+pub fn set_in_notes(notes: &str, value: &str) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut replaced = false;
+    for line in notes.lines() {
+        let is_otp = secret_key(line).is_some() ||
+            line_key(line).map_or(false, |k| k.to_lowercase() == SETTINGS_KEY);
+        if !is_otp {
+            lines.push(String::from(line));
+        } else if !replaced {
+            lines.push(format!("{}: {}", TOTP_FIELD, value));
+            replaced = true;
+        }
+    }
+    if !replaced {
+        lines.push(format!("{}: {}", TOTP_FIELD, value));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_vectors() {
+        let key = b"12345678901234567890";
+        // RFC 4226 appendix D.
+        let hotp_codes = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, &code) in hotp_codes.iter().enumerate() {
+            assert_eq!(hotp(key, counter as u64, Algorithm::Sha1, 6), code);
+        }
+        // RFC 6238 appendix B.
+        let keys: [(Algorithm, &[u8]); 3] = [
+            (Algorithm::Sha1, key),
+            (Algorithm::Sha256, b"12345678901234567890123456789012"),
+            (
+                Algorithm::Sha512,
+                b"1234567890123456789012345678901234567890123456789012345678901234",
+            ),
+        ];
+        let totp_codes = [
+            (59, ["94287082", "46119246", "90693936"]),
+            (1111111109, ["07081804", "68084774", "25091201"]),
+            (20000000000, ["65353130", "77737706", "47863826"]),
+        ];
+        for &(time, codes) in &totp_codes {
+            for (&(algorithm, key), code) in keys.iter().zip(codes.iter()) {
+                let mut otp = Otp::new(key.to_vec());
+                otp.algorithm = algorithm;
+                otp.digits = 8;
+                assert_eq!(otp.code_at_time(time).0, *code, "{} {:?}", time, algorithm);
+            }
+        }
+        assert_eq!(Otp::new(key.to_vec()).code_at_time(59), (String::from("287082"), 1));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(base32_encode(b"12345678901234567890"), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_decode("gezd gnbv gy3t qojq GEZDGNBVGY3TQOJQ====").unwrap(), b"12345678901234567890");
+        let otp = Otp::parse(
+            "otpauth://hotp/ACME%20Co:bob@example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1&digits=8",
+        ).unwrap();
+        assert_eq!((otp.issuer.as_str(), otp.account.as_str()), ("ACME Co", "bob@example.com"));
+        assert_eq!(otp.kind, Kind::Hotp { counter: 1 });
+        assert_eq!(Otp::parse(&otp.to_uri()).unwrap(), otp);
+        let mut used = otp.clone();
+        assert_eq!(used.next_code(), "94287082");
+        assert_eq!(used.kind, Kind::Hotp { counter: 2 });
+        assert!(Otp::parse("otpauth://totp/x?secret=GEZDGNBV&period=0").is_err());
+        assert!(Otp::parse("otpauth://totp/x?secret=1!").is_err());
+
+        let notes = "PIN: 1234\nTOTP Seed: GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ\nTOTP Settings: 60;8";
+        let otp = Otp::from_notes(notes).unwrap().unwrap();
+        assert_eq!((otp.kind, otp.digits), (Kind::Totp { period: 60 }, 8));
+        let notes = set_in_notes(notes, "JBSWY3DPEHPK3PXP");
+        assert_eq!(notes, "PIN: 1234\nTOTP: JBSWY3DPEHPK3PXP");
+        assert_eq!(Otp::from_notes(&notes).unwrap().unwrap().secret, b"Hello!\xde\xad\xbe\xef");
+        assert!(Otp::from_notes("PIN: 1234").is_none());
+        assert!(has_secret(&notes) && !has_secret("PIN: 1234"));
+        assert_eq!(hide_in_notes(&notes), "PIN: 1234\nTOTP: (hidden)");
+        assert_eq!(hide_in_notes("a\n otpauth://totp/x?secret=JBSWY3DP\nb"), "a\n(hidden)\nb");
+    }
+}
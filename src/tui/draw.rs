@@ -0,0 +1,295 @@
+//! Rendering of the terminal UI, whole screen is redrawn on every change.
+
+use std::io;
+use std::io::Write;
+
+use termion;
+use termion::{clear, cursor, style};
+
+use otp;
+use otp::{Kind, Otp};
+
+use super::{App, Confirm, Focus, Mode};
+use super::form::{TextInput, FORM_FIELDS};
+
+/// Truncate or pad string to exactly `width` chars.
+fn fit(s: &str, width: usize) -> String {
+    let mut r: String = s.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(width)
+        .collect();
+    let len = r.chars().count();
+    for _ in len..width {
+        r.push(' ');
+    }
+    r
+}
+
+/// Start of the visible window of list, so that selected row is visible.
+fn scroll_offset(selected: usize, height: usize) -> usize {
+    if height == 0 || selected < height {
+        0
+    } else {
+        selected + 1 - height
+    }
+}
+
+struct Screen {
+    buf: String,
+    width: usize,
+    height: usize,
+    /// Where to put cursor at the end, if anywhere.
+    cursor: Option<(usize, usize)>,
+}
+
+impl Screen {
+    /// Put text at (x, y), zero based, truncated to width.
+    fn put(&mut self, x: usize, y: usize, width: usize, text: &str) {
+        if y >= self.height || x >= self.width {
+            return;
+        }
+        let width = width.min(self.width - x);
+        self.buf.push_str(&format!(
+            "{}{}",
+            cursor::Goto(x as u16 + 1, y as u16 + 1),
+            fit(text, width)
+        ));
+    }
+
+    fn put_inverted(&mut self, x: usize, y: usize, width: usize, text: &str) {
+        self.buf.push_str(&format!("{}", style::Invert));
+        self.put(x, y, width, text);
+        self.buf.push_str(&format!("{}", style::Reset));
+    }
+
+    /// Single line input with label, cursor goes to input cursor position.
+    fn put_input(&mut self, x: usize, y: usize, width: usize, label: &str, input: &TextInput) {
+        let text = input.display_text();
+        let label_len = label.chars().count();
+        let avail = width.saturating_sub(label_len + 1);
+        // Scroll long text so cursor is visible.
+        let skip = if input.cursor > avail { input.cursor - avail } else { 0 };
+        let visible: String = text.chars().skip(skip).collect();
+        self.put(x, y, width, &format!("{}{}", label, visible));
+        self.cursor = Some((x + label_len + input.cursor - skip, y));
+    }
+}
+
+fn draw_browse(s: &mut Screen, app: &App) {
+    let top = 1;
+    let bottom_rows = 2;
+    let detail_rows = 7;
+    let list_height = s.height.saturating_sub(top + bottom_rows + detail_rows + 1);
+    let tree_width = (s.width / 3).max(10).min(s.width);
+    let list_x = tree_width + 1;
+    let list_width = s.width.saturating_sub(list_x);
+    // Group tree.
+    let header = if app.focus == Focus::Groups { "[Groups]" } else { " Groups" };
+    s.put(0, top, tree_width, header);
+    let offset = scroll_offset(app.group_sel, list_height);
+    for (i, row) in app.groups.iter().enumerate().skip(offset).take(list_height) {
+        let text = format!("{}{}", "  ".repeat(row.depth), row.name);
+        let y = top + 1 + i - offset;
+        if i == app.group_sel && app.search.text.is_empty() {
+            s.put_inverted(0, y, tree_width, &text);
+        } else {
+            s.put(0, y, tree_width, &text);
+        }
+    }
+    // Entry list.
+    let header = if app.search.text.is_empty() {
+        String::from("Entries")
+    } else {
+        format!("Search results ({})", app.entries.len())
+    };
+    let header = if app.focus == Focus::Entries {
+        format!("[{}]", header)
+    } else {
+        format!(" {}", header)
+    };
+    s.put(list_x, top, list_width, &header);
+    let title_width = list_width / 2;
+    let offset = scroll_offset(app.entry_sel, list_height);
+    for (i, e) in app.entries.iter().enumerate().skip(offset).take(list_height) {
+        let text = format!("{} {}", fit(&e.title, title_width), e.username);
+        let y = top + 1 + i - offset;
+        if i == app.entry_sel && app.focus == Focus::Entries {
+            s.put_inverted(list_x, y, list_width, &text);
+        } else {
+            s.put(list_x, y, list_width, &text);
+        }
+    }
+    // Details of selected entry.
+    let detail_y = top + 1 + list_height;
+    s.put(0, detail_y, s.width, &"-".repeat(s.width));
+    if let Some(e) = app.selected_entry() {
+        let lines = vec![
+            format!("Title:    {}", e.title),
+            format!("Username: {}", e.username),
+            format!("URL:      {}", e.url),
+            format!("Modified: {}", e.modified),
+            format!("Notes:    {}", e.notes.lines().next().unwrap_or("")),
+        ];
+        for (i, l) in lines.iter().enumerate().take(detail_rows - 1) {
+            let width = s.width;
+            s.put(0, detail_y + 1 + i, width, l);
+        }
+    }
+}
+
+fn draw_detail(s: &mut Screen, app: &App) {
+    let e = match app.selected_entry() {
+        Some(e) => e,
+        None => return,
+    };
+    let password = if app.show_password {
+        e.password.clone()
+    } else {
+        String::from("********")
+    };
+    let mut lines = vec![
+        format!("Title:    {}", e.title),
+        format!("Username: {}", e.username),
+        format!("Password: {}", password),
+    ];
+    match Otp::from_notes(&e.notes) {
+        Some(Ok(ref otp)) => match otp.kind {
+            Kind::Totp { .. } => {
+                let (code, remaining) = otp.current();
+                lines.push(format!("One-time: {} ({}s)", code, remaining));
+            }
+            Kind::Hotp { .. } => lines.push(String::from("One-time: HOTP, t copies next code")),
+        },
+        Some(Err(err)) => lines.push(format!("One-time: {}", err)),
+        None => {}
+    }
+    lines.extend(vec![
+        format!("URL:      {}", e.url),
+        format!("Created:  {}", e.created),
+        format!("Modified: {}", e.modified),
+        format!("Expires:  {}", e.expires),
+        format!("UUID:     {}", e.id.hyphenated()),
+        String::from("Notes:"),
+    ]);
+    let notes = if app.show_password {
+        e.notes.clone()
+    } else {
+        otp::hide_in_notes(&e.notes)
+    };
+    lines.extend(notes.lines().map(String::from));
+    let width = s.width;
+    for (i, l) in lines.iter().enumerate().take(s.height.saturating_sub(3)) {
+        s.put(0, 1 + i, width, l);
+    }
+}
+
+fn draw_edit(s: &mut Screen, form: &super::form::EntryForm) {
+    let width = s.width;
+    let title = if form.is_new { "New entry" } else { "Edit entry" };
+    s.put(0, 1, width, title);
+    let mut y = 3;
+    for (i, name) in FORM_FIELDS.iter().enumerate() {
+        let label = format!("{:>9}: ", name);
+        let input = &form.inputs[i];
+        if input.multiline {
+            // Notes: show all lines, cursor goes to the end of the text.
+            s.put(0, y, width, &label);
+            let mut line_count = 0;
+            for (n, l) in input.text.split('\n').enumerate() {
+                s.put(label.len(), y + n, width - label.len().min(width), l);
+                line_count += 1;
+            }
+            if i == form.focused {
+                let before: String = input.text.chars().take(input.cursor).collect();
+                let row = before.matches('\n').count();
+                let col = before.rsplit('\n').next().map(|l| l.chars().count()).unwrap_or(0);
+                s.cursor = Some((label.len() + col, y + row));
+            }
+            y += line_count;
+        } else {
+            let cursor = s.cursor;
+            s.put_input(0, y, width, &label, input);
+            if i != form.focused {
+                s.cursor = cursor;
+            }
+            y += 1;
+        }
+    }
+}
+
+fn help_line(app: &App) -> &'static str {
+    match app.mode {
+        Mode::Unlock(_) => "Enter: open  Esc: quit",
+        Mode::Browse => "q:quit /:search Tab:focus Enter:details c:copy password u:copy user a:add e:edit d:delete w:save",
+        Mode::Search => "Type to search  Enter: done  Esc: clear",
+        Mode::Detail => "Esc:back p:show/hide password c:copy password u:copy user t:copy code e:edit",
+        Mode::Edit(_) => "Tab/Up/Down: next field  Ctrl-S: store  Ctrl-T: show/hide password  Ctrl-G: generate  Esc: cancel",
+        Mode::Confirm(Confirm::ChangedOnDisk) => "r: reload  o: overwrite  m: merge  Esc: cancel",
+        Mode::Confirm(_) => "y: yes  n: no",
+    }
+}
+
+/// Redraw whole screen.
+pub fn draw<W: Write>(out: &mut W, app: &App) -> io::Result<()> {
+    let (w, h) = termion::terminal_size()?;
+    let mut s = Screen {
+        buf: String::new(),
+        width: w as usize,
+        height: h as usize,
+        cursor: None,
+    };
+    s.buf.push_str(&format!("{}{}", cursor::Hide, clear::All));
+    let title = format!(
+        "mpkdb - {}{}",
+        app.path,
+        if app.controller.is_dirty() { " [modified]" } else { "" }
+    );
+    let width = s.width;
+    s.put_inverted(0, 0, width, &title);
+    match app.mode {
+        Mode::Unlock(ref input) => {
+            s.put(0, 2, width, &format!("Database: {}", app.path));
+            if let Some(ref k) = app.key_file {
+                s.put(0, 3, width, &format!("Key file: {}", k));
+            }
+            s.put_input(0, 5, width, "Master password: ", input);
+        }
+        Mode::Browse | Mode::Search | Mode::Confirm(_) => draw_browse(&mut s, app),
+        Mode::Detail => draw_detail(&mut s, app),
+        Mode::Edit(ref form) => draw_edit(&mut s, form),
+    }
+    // Status line: search input, confirmation question or last message.
+    let status_y = s.height.saturating_sub(2);
+    match app.mode {
+        Mode::Search => s.put_input(0, status_y, width, "/", &app.search),
+        Mode::Confirm(Confirm::DeleteEntry(ref e)) => {
+            s.put(0, status_y, width, &format!("Delete entry \"{}\"? (y/n)", e.title))
+        }
+        Mode::Confirm(Confirm::QuitUnsaved) => {
+            s.put(0, status_y, width, "There are unsaved changes, quit anyway? (y/n)")
+        }
+        Mode::Confirm(Confirm::ChangedOnDisk) => s.put(
+            0,
+            status_y,
+            width,
+            "File was changed by another program: reload (drops your changes), overwrite, or merge?",
+        ),
+        Mode::Confirm(Confirm::TakeOverLock(ref owner)) => s.put(
+            0,
+            status_y,
+            width,
+            &format!("Database is locked by {} (maybe left behind by a crash), take over the lock? (y/n)", owner),
+        ),
+        _ => if !app.search.text.is_empty() {
+            s.put(0, status_y, width, &format!("Search: {}  {}", app.search.text, app.status));
+        } else {
+            s.put(0, status_y, width, &app.status);
+        },
+    }
+    s.put_inverted(0, s.height.saturating_sub(1), width, help_line(app));
+    if let Some((x, y)) = s.cursor {
+        s.buf.push_str(&format!("{}{}", cursor::Goto(x as u16 + 1, y as u16 + 1), cursor::Show));
+    }
+    out.write_all(s.buf.as_bytes())?;
+    out.flush()
+}
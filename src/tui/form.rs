@@ -0,0 +1,201 @@
+//! Text input for the terminal UI: single line editor and entry edit form.
+
+use termion::event::Key;
+
+use db::api::Entry;
+
+/// Editable text with cursor, cursor is a char index.
+#[derive(Clone, Debug, Default)]
+pub struct TextInput {
+    pub text: String,
+    pub cursor: usize,
+    /// Show asterisks instead of text.
+    pub masked: bool,
+    /// Enter inserts new line instead of finishing input.
+    pub multiline: bool,
+}
+
+/// What happened to input after a key press.
+#[derive(Debug, PartialEq)]
+pub enum InputResult {
+    Changed,
+    Unchanged,
+    /// Enter pressed (on single line input).
+    Done,
+    /// Key not handled by input.
+    Ignored,
+}
+
+impl TextInput {
+    pub fn new(text: &str) -> TextInput {
+        TextInput {
+            text: String::from(text),
+            cursor: text.chars().count(),
+            masked: false,
+            multiline: false,
+        }
+    }
+
+    pub fn masked() -> TextInput {
+        TextInput {
+            masked: true,
+            ..TextInput::default()
+        }
+    }
+
+    fn byte_index(&self, char_index: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(char_index)
+            .map(|(i, _)| i)
+            .unwrap_or(self.text.len())
+    }
+
+    fn char_count(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    /// Text as it should be shown.
+    pub fn display_text(&self) -> String {
+        if self.masked {
+            self.text.chars().map(|_| '*').collect()
+        } else {
+            self.text.clone()
+        }
+    }
+
+    pub fn handle_key(&mut self, key: Key) -> InputResult {
+        match key {
+            Key::Char('\n') if !self.multiline => InputResult::Done,
+            Key::Char('\t') => InputResult::Ignored,
+            Key::Char(c) => {
+                let i = self.byte_index(self.cursor);
+                self.text.insert(i, c);
+                self.cursor += 1;
+                InputResult::Changed
+            }
+            Key::Backspace => {
+                if self.cursor == 0 {
+                    return InputResult::Unchanged;
+                }
+                self.cursor -= 1;
+                let i = self.byte_index(self.cursor);
+                self.text.remove(i);
+                InputResult::Changed
+            }
+            Key::Delete => {
+                if self.cursor >= self.char_count() {
+                    return InputResult::Unchanged;
+                }
+                let i = self.byte_index(self.cursor);
+                self.text.remove(i);
+                InputResult::Changed
+            }
+            Key::Left => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                }
+                InputResult::Unchanged
+            }
+            Key::Right => {
+                if self.cursor < self.char_count() {
+                    self.cursor += 1;
+                }
+                InputResult::Unchanged
+            }
+            Key::Home | Key::Ctrl('a') => {
+                self.cursor = 0;
+                InputResult::Unchanged
+            }
+            Key::End | Key::Ctrl('e') => {
+                self.cursor = self.char_count();
+                InputResult::Unchanged
+            }
+            Key::Ctrl('u') => {
+                let i = self.byte_index(self.cursor);
+                self.text = self.text.split_off(i);
+                self.cursor = 0;
+                InputResult::Changed
+            }
+            _ => InputResult::Ignored,
+        }
+    }
+}
+
+pub const FORM_FIELDS: &[&str] = &["Title", "Username", "URL", "Password", "Notes"];
+
+const PASSWORD_FIELD: usize = 3;
+
+/// Form to edit (or add) an entry.
+#[derive(Debug)]
+pub struct EntryForm {
+    pub entry: Entry,
+    pub is_new: bool,
+    pub inputs: Vec<TextInput>,
+    pub focused: usize,
+}
+
+impl EntryForm {
+    pub fn new(entry: Entry, is_new: bool) -> EntryForm {
+        let mut inputs = vec![
+            TextInput::new(&entry.title),
+            TextInput::new(&entry.username),
+            TextInput::new(&entry.url),
+            TextInput::new(&entry.password),
+            TextInput::new(&entry.notes),
+        ];
+        inputs[PASSWORD_FIELD].masked = true;
+        inputs[4].multiline = true;
+        EntryForm {
+            entry: entry,
+            is_new: is_new,
+            inputs: inputs,
+            focused: 0,
+        }
+    }
+
+    pub fn toggle_password_visibility(&mut self) {
+        let masked = self.inputs[PASSWORD_FIELD].masked;
+        self.inputs[PASSWORD_FIELD].masked = !masked;
+    }
+
+    /// Replace password, e.g. with a generated one.
+    pub fn set_password(&mut self, password: &str) {
+        let masked = self.inputs[PASSWORD_FIELD].masked;
+        self.inputs[PASSWORD_FIELD] = TextInput::new(password);
+        self.inputs[PASSWORD_FIELD].masked = masked;
+    }
+
+    /// Move focus between fields, returns true if key was used.
+    pub fn handle_key(&mut self, key: Key) -> bool {
+        match key {
+            Key::Char('\t') | Key::Down => {
+                self.focused = (self.focused + 1) % self.inputs.len();
+                true
+            }
+            Key::BackTab | Key::Up => {
+                self.focused = (self.focused + self.inputs.len() - 1) % self.inputs.len();
+                true
+            }
+            _ => match self.inputs[self.focused].handle_key(key) {
+                InputResult::Done => {
+                    self.focused = (self.focused + 1) % self.inputs.len();
+                    true
+                }
+                InputResult::Ignored => false,
+                _ => true,
+            },
+        }
+    }
+
+    /// Entry with values from form.
+    pub fn to_entry(&self) -> Entry {
+        let mut e = self.entry.clone();
+        e.title = self.inputs[0].text.clone();
+        e.username = self.inputs[1].text.clone();
+        e.url = self.inputs[2].text.clone();
+        e.password = self.inputs[3].text.clone();
+        e.notes = self.inputs[4].text.clone();
+        e
+    }
+}
@@ -0,0 +1,526 @@
+//! Terminal UI, for when GTK is not available (e.g. over SSH).
+//!
+//! Full screen view with group tree, entry list and entry details, incremental search,
+//! entry editing, and copying to clipboard with OSC 52 escape sequence
+//! (which works through SSH in most terminal emulators).
+
+mod draw;
+mod form;
+
+use std;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use base64;
+use termion;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+use config;
+use controller::{Command, Controller, Event, Field};
+use db::api::{Entry, GroupId};
+use errors;
+use generator::Generator;
+
+use self::form::{EntryForm, InputResult, TextInput};
+
+/// Which list has keyboard focus in browse mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Focus {
+    Groups,
+    Entries,
+}
+
+/// Things we ask user to confirm.
+#[derive(Debug)]
+pub enum Confirm {
+    DeleteEntry(Entry),
+    QuitUnsaved,
+    /// Save found file changed by someone else: reload, overwrite or merge.
+    ChangedOnDisk,
+    /// Database file is locked by someone else (owner), maybe left behind by a crash.
+    TakeOverLock(String),
+}
+
+#[derive(Debug)]
+pub enum Mode {
+    /// Asking for master password.
+    Unlock(TextInput),
+    Browse,
+    /// Typing search term.
+    Search,
+    /// Showing selected entry on whole screen.
+    Detail,
+    Edit(EntryForm),
+    Confirm(Confirm),
+}
+
+/// Row of group tree.
+#[derive(Clone, Debug)]
+pub struct TreeRow {
+    pub id: GroupId,
+    pub name: String,
+    pub depth: usize,
+}
+
+pub struct App {
+    path: String,
+    key_file: Option<String>,
+    controller: Controller,
+    mode: Mode,
+    focus: Focus,
+    groups: Vec<TreeRow>,
+    group_sel: usize,
+    /// Entries currently listed: selected group, or search results.
+    entries: Vec<Entry>,
+    entry_sel: usize,
+    search: TextInput,
+    show_password: bool,
+    status: String,
+    quit: bool,
+}
+
+/// Copy text to clipboard of the terminal emulator.
+fn osc52_copy<W: Write>(out: &mut W, text: &str) -> io::Result<()> {
+    write!(out, "\x1b]52;c;{}\x07", base64::encode(text.as_bytes()))?;
+    out.flush()
+}
+
+impl App {
+    fn new(path: &str, key_file: Option<&str>, controller: Controller) -> App {
+        App {
+            path: String::from(path),
+            key_file: key_file.map(String::from),
+            controller: controller,
+            mode: Mode::Unlock(TextInput::masked()),
+            focus: Focus::Groups,
+            groups: Vec::new(),
+            group_sel: 0,
+            entries: Vec::new(),
+            entry_sel: 0,
+            search: TextInput::default(),
+            show_password: false,
+            status: String::new(),
+            quit: false,
+        }
+    }
+
+    fn selected_entry(&self) -> Option<Entry> {
+        self.controller.get_selected_entry()
+    }
+
+    /// Run controller command and show what happened. Returns false if command failed.
+    fn run_command<W: Write>(&mut self, out: &mut W, command: Command) -> bool {
+        let mut ok = true;
+        for event in self.controller.handle(command) {
+            match event {
+                Event::Unlocked => {
+                    self.mode = Mode::Browse;
+                    self.status = String::from("Opened");
+                }
+                Event::Locked => self.mode = Mode::Unlock(TextInput::masked()),
+                Event::OpenFailed(desc) => {
+                    self.status = desc;
+                    self.mode = Mode::Unlock(TextInput::masked());
+                    ok = false;
+                }
+                Event::ListChanged => self.refresh(),
+                Event::SelectionChanged => self.sync_entry_selection(),
+                Event::CopyToClipboard { field, text } => match osc52_copy(out, &text) {
+                    Ok(()) => self.status = format!("Copied {} to clipboard", field.name()),
+                    Err(e) => self.status = format!("Failed to copy: {}", e),
+                },
+                Event::Saved => self.status = String::from("Saved"),
+                Event::DirtyChanged(_) => {}
+                Event::ChangedOnDisk => {
+                    self.mode = Mode::Confirm(Confirm::ChangedOnDisk);
+                    ok = false;
+                }
+                Event::LockedByOther(owner) => {
+                    self.mode = Mode::Confirm(Confirm::TakeOverLock(owner));
+                    ok = false;
+                }
+                Event::Imported(summary) => {
+                    self.status = format!(
+                        "Imported {} entries, {} skipped",
+                        summary.entries,
+                        summary.skipped.len()
+                    )
+                }
+                Event::Error(desc) => {
+                    self.status = desc;
+                    ok = false;
+                }
+            }
+        }
+        ok
+    }
+
+    /// Reload tree and entry list from controller.
+    fn refresh(&mut self) {
+        let groups = self.controller.get_groups();
+        self.groups = groups
+            .iter()
+            .map(|g| {
+                let mut depth = 0;
+                let mut parent = g.parent;
+                while let Some(p) = parent {
+                    depth += 1;
+                    parent = groups.iter().find(|x| x.id == p).and_then(|x| x.parent);
+                }
+                TreeRow {
+                    id: g.id,
+                    name: g.name.clone(),
+                    depth: depth,
+                }
+            })
+            .collect();
+        let selected_group = self.controller.get_selected_group();
+        self.group_sel = selected_group
+            .and_then(|id| self.groups.iter().position(|r| r.id == id))
+            .unwrap_or(0);
+        self.entries = self.controller.get_entries();
+        self.sync_entry_selection();
+    }
+
+    /// Entry cursor follows controller selection, first entry is selected if there's none.
+    fn sync_entry_selection(&mut self) {
+        let selected = self.controller.get_selected_entry().map(|e| e.id);
+        match selected.and_then(|id| self.entries.iter().position(|e| e.id == id)) {
+            Some(i) => self.entry_sel = i,
+            None => {
+                self.entry_sel = 0;
+                let first = self.entries.first().map(|e| e.id);
+                if first.is_some() {
+                    self.controller.handle(Command::SelectEntry(first));
+                }
+            }
+        }
+    }
+
+    fn unlock<W: Write>(&mut self, out: &mut W, password: &str) {
+        let command = Command::Open {
+            path: self.path.clone(),
+            password: String::from(password),
+            key_file: self.key_file.clone(),
+        };
+        self.run_command(out, command);
+    }
+
+    fn move_selection<W: Write>(&mut self, out: &mut W, delta: isize) {
+        fn moved(sel: usize, len: usize, delta: isize) -> usize {
+            if len == 0 {
+                return 0;
+            }
+            let s = sel as isize + delta;
+            if s < 0 {
+                0
+            } else if s >= len as isize {
+                len - 1
+            } else {
+                s as usize
+            }
+        }
+        match self.focus {
+            Focus::Groups => {
+                let i = moved(self.group_sel, self.groups.len(), delta);
+                let group = self.groups.get(i).map(|r| r.id);
+                self.run_command(out, Command::SelectGroup(group));
+            }
+            Focus::Entries => {
+                let i = moved(self.entry_sel, self.entries.len(), delta);
+                let entry = self.entries.get(i).map(|e| e.id);
+                self.run_command(out, Command::SelectEntry(entry));
+            }
+        }
+    }
+
+    fn set_search<W: Write>(&mut self, out: &mut W, input: TextInput) {
+        let term = input.text.clone();
+        self.search = input;
+        self.run_command(out, Command::Search(term));
+    }
+
+    fn start_add(&mut self) {
+        match self.controller.get_selected_group() {
+            Some(group_id) => self.mode = Mode::Edit(EntryForm::new(Entry::new(group_id, ""), true)),
+            None => self.status = String::from("Select a group first"),
+        }
+    }
+
+    fn start_edit(&mut self) {
+        if let Some(e) = self.selected_entry() {
+            self.mode = Mode::Edit(EntryForm::new(e, false));
+        }
+    }
+
+    fn finish_edit<W: Write>(&mut self, out: &mut W, form: &EntryForm) {
+        let mut e = form.to_entry();
+        e.touch();
+        let command = if form.is_new {
+            Command::AddEntry(e)
+        } else {
+            Command::UpdateEntry(e)
+        };
+        if self.run_command(out, command) {
+            self.status = String::from("Entry stored, press w to save");
+            self.focus = Focus::Entries;
+        }
+    }
+
+    fn delete_entry<W: Write>(&mut self, out: &mut W, e: &Entry) {
+        if self.run_command(out, Command::RemoveEntry(e.id)) {
+            self.status = format!("Deleted \"{}\", press w to save", e.title);
+        }
+    }
+
+    fn handle_browse_key<W: Write>(&mut self, out: &mut W, key: Key) {
+        match key {
+            Key::Char('q') => {
+                if self.controller.is_dirty() {
+                    self.mode = Mode::Confirm(Confirm::QuitUnsaved);
+                } else {
+                    self.quit = true;
+                }
+            }
+            Key::Char('\t') | Key::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Groups => Focus::Entries,
+                    Focus::Entries => Focus::Groups,
+                };
+            }
+            Key::Left | Key::Char('h') => self.focus = Focus::Groups,
+            Key::Right | Key::Char('l') => self.focus = Focus::Entries,
+            Key::Up | Key::Char('k') => self.move_selection(out, -1),
+            Key::Down | Key::Char('j') => self.move_selection(out, 1),
+            Key::PageUp => self.move_selection(out, -10),
+            Key::PageDown => self.move_selection(out, 10),
+            Key::Char('/') => {
+                self.mode = Mode::Search;
+                self.focus = Focus::Entries;
+            }
+            Key::Esc => {
+                if !self.search.text.is_empty() {
+                    self.set_search(out, TextInput::default());
+                }
+            }
+            Key::Char('\n') => match self.focus {
+                Focus::Groups => self.focus = Focus::Entries,
+                Focus::Entries => if self.selected_entry().is_some() {
+                    self.show_password = false;
+                    self.mode = Mode::Detail;
+                },
+            },
+            Key::Char('c') => {
+                self.run_command(out, Command::Copy(Field::Password));
+            }
+            Key::Char('u') => {
+                self.run_command(out, Command::Copy(Field::Username));
+            }
+            Key::Char('a') => self.start_add(),
+            Key::Char('e') => self.start_edit(),
+            Key::Char('d') => if let Some(e) = self.selected_entry() {
+                self.mode = Mode::Confirm(Confirm::DeleteEntry(e));
+            },
+            Key::Char('w') => {
+                self.run_command(out, Command::Save);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_key<W: Write>(&mut self, out: &mut W, key: Key) {
+        let mode = std::mem::replace(&mut self.mode, Mode::Browse);
+        self.mode = match mode {
+            Mode::Unlock(mut input) => match key {
+                Key::Esc | Key::Ctrl('c') => {
+                    self.quit = true;
+                    Mode::Unlock(input)
+                }
+                _ => match input.handle_key(key) {
+                    InputResult::Done => {
+                        self.status = String::from("Opening...");
+                        self.unlock(out, &input.text);
+                        return;
+                    }
+                    _ => Mode::Unlock(input),
+                },
+            },
+            Mode::Browse => {
+                self.handle_browse_key(out, key);
+                return;
+            }
+            Mode::Search => match key {
+                Key::Esc => {
+                    self.set_search(out, TextInput::default());
+                    Mode::Browse
+                }
+                Key::Char('\n') => Mode::Browse,
+                Key::Up | Key::Down => {
+                    self.move_selection(out, if key == Key::Up { -1 } else { 1 });
+                    Mode::Search
+                }
+                _ => {
+                    let mut input = self.search.clone();
+                    if input.handle_key(key) == InputResult::Changed {
+                        self.set_search(out, input);
+                    } else {
+                        self.search = input;
+                    }
+                    Mode::Search
+                }
+            },
+            Mode::Detail => match key {
+                Key::Esc | Key::Char('q') | Key::Char('\n') => Mode::Browse,
+                Key::Char('p') => {
+                    self.show_password = !self.show_password;
+                    Mode::Detail
+                }
+                Key::Char('c') => {
+                    self.run_command(out, Command::Copy(Field::Password));
+                    Mode::Detail
+                }
+                Key::Char('u') => {
+                    self.run_command(out, Command::Copy(Field::Username));
+                    Mode::Detail
+                }
+                Key::Char('t') => {
+                    self.run_command(out, Command::Copy(Field::Totp));
+                    Mode::Detail
+                }
+                Key::Char('e') => match self.selected_entry() {
+                    Some(e) => Mode::Edit(EntryForm::new(e, false)),
+                    None => Mode::Detail,
+                },
+                _ => Mode::Detail,
+            },
+            Mode::Edit(mut form) => match key {
+                Key::Esc => {
+                    self.status = String::from("Edit cancelled");
+                    Mode::Browse
+                }
+                Key::Ctrl('s') => {
+                    self.finish_edit(out, &form);
+                    Mode::Browse
+                }
+                Key::Ctrl('t') => {
+                    form.toggle_password_visibility();
+                    Mode::Edit(form)
+                }
+                Key::Ctrl('g') => {
+                    match Generator::default().generate() {
+                        Ok(password) => {
+                            form.set_password(&password);
+                            self.status = String::from("Generated password, Ctrl-T shows it");
+                        }
+                        Err(e) => self.status = format!("Failed to generate password: {}", e),
+                    }
+                    Mode::Edit(form)
+                }
+                _ => {
+                    form.handle_key(key);
+                    Mode::Edit(form)
+                }
+            },
+            Mode::Confirm(Confirm::ChangedOnDisk) => {
+                let command = match key {
+                    Key::Char('r') => Command::Reload,
+                    Key::Char('o') => Command::Overwrite,
+                    Key::Char('m') => Command::Merge,
+                    Key::Esc => {
+                        self.status = String::from("Not saved");
+                        self.mode = Mode::Browse;
+                        return;
+                    }
+                    _ => {
+                        self.mode = Mode::Confirm(Confirm::ChangedOnDisk);
+                        return;
+                    }
+                };
+                self.mode = Mode::Browse;
+                if self.run_command(out, command.clone()) {
+                    if let Command::Reload = command {
+                        self.status = String::from("Reloaded");
+                    }
+                }
+                return;
+            }
+            Mode::Confirm(c) => match key {
+                Key::Char('y') | Key::Char('Y') => {
+                    match c {
+                        Confirm::DeleteEntry(e) => self.delete_entry(out, &e),
+                        Confirm::QuitUnsaved => self.quit = true,
+                        Confirm::ChangedOnDisk => {}
+                        Confirm::TakeOverLock(_) => if self.run_command(out, Command::TakeOverLock) {
+                            self.status = String::from(if self.controller.is_dirty() {
+                                "Took over lock, save again to keep your changes"
+                            } else {
+                                "Took over lock"
+                            });
+                        },
+                    }
+                    Mode::Browse
+                }
+                Key::Char('n') | Key::Char('N') | Key::Esc => Mode::Browse,
+                _ => Mode::Confirm(c),
+            },
+        };
+    }
+}
+
+/// Run terminal UI until user quits.
+/// Without file, last opened file from config is used, successfully opened file is remembered in config.
+pub fn run(file: Option<&str>, key_file: Option<&str>, config_path: &Path) -> Result<(), errors::Error> {
+    let conf = config::load_config(config_path)?;
+    let (path, key_file) = match file {
+        Some(f) => (String::from(f), key_file.map(String::from)),
+        None => match conf.recent_files.first() {
+            Some(r) => (r.path.clone(), key_file.map(String::from).or_else(|| r.key_file.clone())),
+            None => return Err(errors::Error::Invalid(String::from("No database file given"))),
+        },
+    };
+    let mut controller = Controller::new();
+    controller.set_config(conf, config_path.to_path_buf());
+    let mut app = App::new(&path, key_file.as_ref().map(|k| k.as_str()), controller);
+    {
+        let stdin = io::stdin();
+        let stdout = io::stdout().into_raw_mode()?;
+        let mut screen = AlternateScreen::from(stdout);
+        draw::draw(&mut screen, &app)?;
+        // Keys come from another thread, so that one-time codes can be redrawn as time passes.
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            for key in stdin.keys() {
+                if sender.send(key).is_err() {
+                    break;
+                }
+            }
+        });
+        loop {
+            match keys.recv_timeout(Duration::from_secs(1)) {
+                Ok(key) => {
+                    app.handle_key(&mut screen, key?);
+                    if app.quit {
+                        break;
+                    }
+                }
+                // Only details show a one-time code.
+                Err(mpsc::RecvTimeoutError::Timeout) => match app.mode {
+                    Mode::Detail => {}
+                    _ => continue,
+                },
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            draw::draw(&mut screen, &app)?;
+        }
+        write!(screen, "{}", termion::cursor::Show)?;
+        screen.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Password audit view: strength of each entry and its issues, with a summary.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use gtk;
+use gtk::prelude::*;
+
+use mpkdb::db::audit;
+use mpkdb::db::formats::Record;
+use mpkdb::errors;
+
+use ui::open::choose_file;
+
+/// Default for "Not changed in", about two years.
+const DEFAULT_MAX_AGE_DAYS: f64 = 730.0;
+
+struct AuditView {
+    records: Vec<Record>,
+    check_age: gtk::CheckButton,
+    max_age: gtk::SpinButton,
+    all: gtk::CheckButton,
+    /// Local Have I Been Pwned list, if chosen.
+    pwned_list: RefCell<Option<PathBuf>>,
+    summary: gtk::Label,
+    results: gtk::ListBox,
+}
+
+impl AuditView {
+    fn options(&self) -> audit::Options {
+        audit::Options {
+            max_age_days: if self.check_age.get_active() {
+                Some(self.max_age.get_value_as_int() as i64)
+            } else {
+                None
+            },
+            pwned_list: self.pwned_list.borrow().clone(),
+            ..audit::Options::default()
+        }
+    }
+
+    fn refresh(&self) {
+        self.max_age.set_sensitive(self.check_age.get_active());
+        for c in self.results.get_children() {
+            self.results.remove(&c);
+        }
+        let result = match audit::audit(&self.records, &self.options()) {
+            Ok(r) => r,
+            Err(e) => {
+                self.summary.set_text(&format!("Audit failed: {}", errors::report(&e)));
+                return;
+            }
+        };
+        let all = self.all.get_active();
+        for e in &result.entries {
+            if e.issues.is_empty() && !all {
+                continue;
+            }
+            let issues: Vec<String> = e.issues.iter().map(|i| i.to_string()).collect();
+            let mut text = if e.issues.contains(&audit::Issue::Empty) {
+                e.path.clone()
+            } else {
+                format!("{} ({}, {:.0} bits)", e.path, e.strength.label(), e.strength.bits)
+            };
+            if !issues.is_empty() {
+                text = format!("{}\n    {}", text, issues.join("; "));
+            }
+            let label = gtk::Label::new(text.as_str());
+            label.set_xalign(0.0);
+            self.results.insert(&label, -1);
+        }
+        let summary = result.summary();
+        if summary.is_empty() {
+            self.summary.set_text(&format!("No issues in {} entries", result.entries.len()));
+        } else {
+            self.summary.set_text(&format!(
+                "{} of {} entries have issues: {}",
+                result.with_issues().len(),
+                result.entries.len(),
+                summary.join(", ")
+            ));
+        }
+        self.results.show_all();
+    }
+}
+
+/// Show audit of records until closed.
+pub fn show_audit_dialog(parent: &gtk::Window, records: Vec<Record>) {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("Password audit");
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.set_default_size(600, 500);
+    dialog.add_buttons(&[("Close", gtk::ResponseType::Close.into())]);
+    let options_box = gtk::Box::new(gtk::Orientation::Horizontal, 8);
+    let check_age = gtk::CheckButton::new_with_label("Not changed in days:");
+    let max_age = gtk::SpinButton::new_with_range(1.0, 36500.0, 30.0);
+    max_age.set_value(DEFAULT_MAX_AGE_DAYS);
+    let all = gtk::CheckButton::new_with_label("All entries");
+    let pwned_btn = gtk::Button::new_with_label("Breached passwords list...");
+    pwned_btn.set_tooltip_text(Some(
+        "Have I Been Pwned passwords file (SHA-1, ordered by hash), passwords are only looked up in it",
+    ));
+    options_box.add(&check_age);
+    options_box.add(&max_age);
+    options_box.add(&all);
+    options_box.add(&pwned_btn);
+    let summary = gtk::Label::new("");
+    summary.set_xalign(0.0);
+    let results = gtk::ListBox::new();
+    results.set_selection_mode(gtk::SelectionMode::None);
+    let scrolled = gtk::ScrolledWindow::new(None, None);
+    scrolled.add(&results);
+    let content = dialog.get_content_area();
+    content.add(&options_box);
+    content.add(&summary);
+    content.add(&scrolled);
+    content.set_child_packing(&scrolled, true, true, 0, gtk::PackType::Start);
+    let view = Rc::new(AuditView {
+        records: records,
+        check_age: check_age.clone(),
+        max_age: max_age.clone(),
+        all: all.clone(),
+        pwned_list: RefCell::new(None),
+        summary: summary,
+        results: results,
+    });
+    {
+        let view = Rc::clone(&view);
+        check_age.connect_toggled(move |_| view.refresh());
+    }
+    {
+        let view = Rc::clone(&view);
+        max_age.connect_value_changed(move |_| view.refresh());
+    }
+    {
+        let view = Rc::clone(&view);
+        all.connect_toggled(move |_| view.refresh());
+    }
+    {
+        let view = Rc::clone(&view);
+        let dialog_window = dialog.clone().upcast::<gtk::Window>();
+        pwned_btn.connect_clicked(move |b| {
+            if let Some(f) = choose_file(&dialog_window, "Choose Have I Been Pwned passwords file") {
+                b.set_label(&f);
+                *view.pwned_list.borrow_mut() = Some(PathBuf::from(f));
+                view.refresh();
+            }
+        });
+    }
+    view.refresh();
+    dialog.show_all();
+    dialog.run();
+    dialog.destroy();
+}
@@ -0,0 +1,234 @@
+//! Password generator dialog: characters, pattern or passphrase, with a preview to copy.
+
+use std::rc::Rc;
+
+use gtk;
+use gtk::prelude::*;
+
+use mpkdb::generator::{CharOptions, Generator, LOOKALIKES};
+
+/// Generator options and the preview.
+struct GeneratorFields {
+    mode: gtk::ComboBoxText,
+    length: gtk::SpinButton,
+    upper: gtk::CheckButton,
+    lower: gtk::CheckButton,
+    digits: gtk::CheckButton,
+    symbols: gtk::CheckButton,
+    custom: gtk::Entry,
+    exclude_lookalikes: gtk::CheckButton,
+    each_class: gtk::CheckButton,
+    pattern: gtk::Entry,
+    words: gtk::SpinButton,
+    separator: gtk::Entry,
+    preview: gtk::Entry,
+    strength: gtk::Label,
+}
+
+impl GeneratorFields {
+    fn new() -> GeneratorFields {
+        let defaults = CharOptions::default();
+        let mode = gtk::ComboBoxText::new();
+        mode.append(Some("chars"), "Characters");
+        mode.append(Some("pattern"), "Pattern");
+        mode.append(Some("words"), "Passphrase");
+        mode.set_active(0);
+        let length = gtk::SpinButton::new_with_range(1.0, 256.0, 1.0);
+        length.set_value(defaults.length as f64);
+        let check = |label: &str, active: bool| {
+            let c = gtk::CheckButton::new_with_label(label);
+            c.set_active(active);
+            c
+        };
+        let pattern = gtk::Entry::new();
+        pattern.set_text("uLLLLd{4}");
+        pattern.set_tooltip_text(Some(
+            "d digit, l/u/L lower/upper/mixed case, a/U/A also digits, s special, \
+             \\x literal x, {n} repeat, [...] one of",
+        ));
+        let words = gtk::SpinButton::new_with_range(1.0, 32.0, 1.0);
+        words.set_value(6.0);
+        let separator = gtk::Entry::new();
+        separator.set_text(" ");
+        let preview = gtk::Entry::new();
+        preview.set_editable(false);
+        GeneratorFields {
+            mode: mode,
+            length: length,
+            upper: check("Upper case", defaults.upper),
+            lower: check("Lower case", defaults.lower),
+            digits: check("Digits", defaults.digits),
+            symbols: check("Symbols", defaults.symbols),
+            custom: gtk::Entry::new(),
+            exclude_lookalikes: check(&format!("Leave out {}", LOOKALIKES), defaults.exclude_lookalikes),
+            each_class: check("At least one of each", defaults.each_class),
+            pattern: pattern,
+            words: words,
+            separator: separator,
+            preview: preview,
+            strength: gtk::Label::new(""),
+        }
+    }
+
+    fn attach(&self, grid: &gtk::Grid) {
+        grid.attach(&gtk::Label::new("Generate:"), 0, 0, 1, 1);
+        grid.attach(&self.mode, 1, 0, 1, 1);
+        grid.attach(&gtk::Label::new("Length:"), 0, 1, 1, 1);
+        grid.attach(&self.length, 1, 1, 1, 1);
+        grid.attach(&self.upper, 0, 2, 1, 1);
+        grid.attach(&self.lower, 1, 2, 1, 1);
+        grid.attach(&self.digits, 0, 3, 1, 1);
+        grid.attach(&self.symbols, 1, 3, 1, 1);
+        grid.attach(&gtk::Label::new("Also:"), 0, 4, 1, 1);
+        grid.attach(&self.custom, 1, 4, 1, 1);
+        grid.attach(&self.exclude_lookalikes, 0, 5, 1, 1);
+        grid.attach(&self.each_class, 1, 5, 1, 1);
+        grid.attach(&gtk::Label::new("Pattern:"), 0, 6, 1, 1);
+        grid.attach(&self.pattern, 1, 6, 1, 1);
+        grid.attach(&gtk::Label::new("Words:"), 0, 7, 1, 1);
+        grid.attach(&self.words, 1, 7, 1, 1);
+        grid.attach(&gtk::Label::new("Separator:"), 0, 8, 1, 1);
+        grid.attach(&self.separator, 1, 8, 1, 1);
+        grid.attach(&self.preview, 0, 9, 2, 1);
+        grid.attach(&self.strength, 0, 10, 2, 1);
+    }
+
+    fn get(&self) -> Generator {
+        let exclude_lookalikes = self.exclude_lookalikes.get_active();
+        match self.mode.get_active_id().as_ref().map(|id| id.as_str()) {
+            Some("pattern") => Generator::Pattern {
+                pattern: self.pattern.get_text().unwrap_or_default(),
+                exclude_lookalikes: exclude_lookalikes,
+            },
+            Some("words") => Generator::Passphrase {
+                words: self.words.get_value_as_int() as usize,
+                separator: self.separator.get_text().unwrap_or_default(),
+            },
+            _ => Generator::Chars(CharOptions {
+                length: self.length.get_value_as_int() as usize,
+                upper: self.upper.get_active(),
+                lower: self.lower.get_active(),
+                digits: self.digits.get_active(),
+                symbols: self.symbols.get_active(),
+                custom: self.custom.get_text().unwrap_or_default(),
+                exclude_lookalikes: exclude_lookalikes,
+                each_class: self.each_class.get_active(),
+            }),
+        }
+    }
+
+    /// Only fields of selected mode are sensitive.
+    fn update_sensitivity(&self) {
+        let generator = self.get();
+        let chars = match generator {
+            Generator::Chars(_) => true,
+            _ => false,
+        };
+        let passphrase = match generator {
+            Generator::Passphrase { .. } => true,
+            _ => false,
+        };
+        for c in &[&self.upper, &self.lower, &self.digits, &self.symbols, &self.each_class] {
+            c.set_sensitive(chars);
+        }
+        self.length.set_sensitive(chars);
+        self.custom.set_sensitive(chars);
+        self.exclude_lookalikes.set_sensitive(!passphrase);
+        self.pattern.set_sensitive(!chars && !passphrase);
+        self.words.set_sensitive(passphrase);
+        self.separator.set_sensitive(passphrase);
+    }
+
+    /// New password to preview, or the error in its place.
+    fn regenerate(&self) {
+        self.update_sensitivity();
+        let generator = self.get();
+        match generator.generate() {
+            Ok(password) => {
+                self.preview.set_text(&password);
+                let bits = generator.entropy_bits().unwrap_or(0.0);
+                self.strength.set_text(&format!("Strength: {:.0} bits", bits));
+            }
+            Err(e) => {
+                self.preview.set_text("");
+                self.strength.set_text(&e.to_string());
+            }
+        }
+    }
+}
+
+/// What user chose to do with generated password.
+pub enum Generated {
+    Copy(String),
+    /// Set it as password of the entry given to `show_generator_dialog`.
+    UseForEntry(String),
+}
+
+/// Run generator dialog, None if cancelled. With `entry_title` (of selected entry),
+/// generated password can be used for that entry, instead of just copied.
+pub fn show_generator_dialog(parent: &gtk::Window, entry_title: Option<&str>) -> Option<Generated> {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("Generate password");
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel.into()),
+        ("Again", gtk::ResponseType::Apply.into()),
+        ("Copy", gtk::ResponseType::Ok.into()),
+    ]);
+    if let Some(title) = entry_title {
+        dialog.add_button(&format!("Use for \"{}\"", title), gtk::ResponseType::Accept.into());
+    }
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(4);
+    grid.set_column_spacing(8);
+    let fields = Rc::new(GeneratorFields::new());
+    fields.attach(&grid);
+    {
+        let f = Rc::clone(&fields);
+        fields.mode.connect_changed(move |_| f.regenerate());
+    }
+    for spin in &[&fields.length, &fields.words] {
+        let f = Rc::clone(&fields);
+        spin.connect_value_changed(move |_| f.regenerate());
+    }
+    for check in &[
+        &fields.upper,
+        &fields.lower,
+        &fields.digits,
+        &fields.symbols,
+        &fields.exclude_lookalikes,
+        &fields.each_class,
+    ] {
+        let f = Rc::clone(&fields);
+        check.connect_toggled(move |_| f.regenerate());
+    }
+    for entry in &[&fields.custom, &fields.pattern, &fields.separator] {
+        let f = Rc::clone(&fields);
+        entry.connect_changed(move |_| f.regenerate());
+    }
+    fields.regenerate();
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+    let result = loop {
+        let response = dialog.run();
+        if response == Into::<i32>::into(gtk::ResponseType::Apply) {
+            fields.regenerate();
+            continue;
+        }
+        let use_for_entry = response == Into::<i32>::into(gtk::ResponseType::Accept);
+        if response != Into::<i32>::into(gtk::ResponseType::Ok) && !use_for_entry {
+            break None;
+        }
+        match fields.preview.get_text() {
+            Some(ref p) if !p.is_empty() => if use_for_entry {
+                break Some(Generated::UseForEntry(p.clone()));
+            } else {
+                break Some(Generated::Copy(p.clone()));
+            },
+            _ => continue,
+        }
+    };
+    dialog.destroy();
+    result
+}
@@ -0,0 +1,321 @@
+//! Import and export of plaintext files (CSV, KeePass XML, exports of other password managers).
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use gtk;
+use gtk::prelude::*;
+
+use mpkdb::db::file::write_atomic;
+use mpkdb::db::formats;
+use mpkdb::db::formats::csv::{Mapping, Options};
+use mpkdb::db::formats::{Format, Parsed, Record};
+use mpkdb::db::report;
+use mpkdb::db::report::ReportFormat;
+use mpkdb::errors;
+
+use ui;
+use ui::open::{choose_file, choose_save_file};
+
+/// Records listed in import preview, there may be thousands.
+const PREVIEW_RECORDS: usize = 100;
+
+const PREVIEW_RESPONSE: i32 = 1;
+
+/// Formats in format dropdown, in order, with their labels and file name extensions.
+const FORMATS: &[(Format, &str, &str)] = &[
+    (Format::Csv, "CSV", "csv"),
+    (Format::KeePass1Xml, "KeePass 1.x XML", "xml"),
+    (Format::KeePass2Xml, "KeePass 2.x XML", "xml"),
+    (Format::Bitwarden, "Bitwarden JSON", "json"),
+    (Format::OnePux, "1Password (1PUX)", "1pux"),
+    (Format::OnePif, "1Password (1PIF)", "1pif"),
+    (Format::LastPass, "LastPass CSV", "csv"),
+];
+
+/// Dropdown of plaintext formats (those that can be written, for export),
+/// CSV fields are only sensitive for CSV.
+fn format_combo(format: Format, fields: &CsvFields, export: bool) -> gtk::ComboBoxText {
+    let combo = gtk::ComboBoxText::new();
+    for &(f, label, _) in FORMATS.iter().filter(|&&(f, _, _)| !export || !f.is_import_only()) {
+        combo.append(Some(f.name()), label);
+    }
+    let header = fields.header.clone();
+    let columns = fields.columns.clone();
+    combo.connect_changed(move |c| {
+        let csv = get_format(c) == Format::Csv;
+        header.set_sensitive(csv);
+        columns.set_sensitive(csv);
+    });
+    if !combo.set_active_id(Some(format.name())) {
+        combo.set_active(0);
+    }
+    combo
+}
+
+fn get_format(combo: &gtk::ComboBoxText) -> Format {
+    combo
+        .get_active_id()
+        .and_then(|id| Format::from_name(&id))
+        .unwrap_or(Format::Csv)
+}
+
+/// Recognize format from start of file, or its name, CSV if neither tells.
+fn guess_format(filename: &str) -> Format {
+    let mut start = Vec::new();
+    let sniffed = File::open(filename)
+        .and_then(|f| f.take(1024).read_to_end(&mut start))
+        .ok()
+        .and_then(|_| Format::sniff(&start));
+    match sniffed.or_else(|| Format::from_path(Path::new(filename))) {
+        Some(Format::Kdb) | None => Format::Csv,
+        Some(f) => f,
+    }
+}
+
+/// Header row and column mapping fields.
+struct CsvFields {
+    header: gtk::CheckButton,
+    columns: gtk::Entry,
+}
+
+impl CsvFields {
+    fn new(columns: &str) -> CsvFields {
+        let header = gtk::CheckButton::new_with_label("First row is header");
+        header.set_active(true);
+        let entry = gtk::Entry::new();
+        entry.set_text(columns);
+        entry.set_tooltip_text(Some(
+            "Comma separated: title, username, password, url, notes, group, or - to skip a column",
+        ));
+        CsvFields {
+            header: header,
+            columns: entry,
+        }
+    }
+
+    /// Attach to grid, taking two rows from top.
+    fn attach(&self, grid: &gtk::Grid, top: i32) {
+        grid.attach(&self.header, 0, top, 2, 1);
+        grid.attach(&gtk::Label::new("Columns:"), 0, top + 1, 1, 1);
+        grid.attach(&self.columns, 1, top + 1, 1, 1);
+    }
+
+    /// CSV options, mapping is None if columns are empty.
+    fn get(&self) -> Result<Options, String> {
+        let columns = self.columns.get_text().unwrap_or_default();
+        let mapping = if columns.trim().is_empty() {
+            None
+        } else {
+            Some(Mapping::parse(&columns).map_err(|e| e.to_string())?)
+        };
+        Ok(Options {
+            mapping: mapping,
+            header: self.header.get_active(),
+        })
+    }
+}
+
+fn new_dialog(parent: &gtk::Window, title: &str, accept: &str) -> gtk::Dialog {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title(title);
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.set_default_size(500, 400);
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel.into()),
+        (accept, gtk::ResponseType::Ok.into()),
+    ]);
+    dialog
+}
+
+fn preview_text(parsed: &Parsed) -> String {
+    let mut lines: Vec<String> = parsed
+        .records
+        .iter()
+        .take(PREVIEW_RECORDS)
+        .map(|r| format!("{}    {}", r.path(), r.entry.username))
+        .collect();
+    if parsed.records.len() > PREVIEW_RECORDS {
+        lines.push(format!("... and {} more", parsed.records.len() - PREVIEW_RECORDS));
+    }
+    for s in &parsed.skipped {
+        lines.push(format!("Skipped: {}", s));
+    }
+    format!(
+        "{} entries to import, {} skipped:\n\n{}",
+        parsed.records.len(),
+        parsed.skipped.len(),
+        lines.join("\n")
+    )
+}
+
+/// Ask for file and how to read it, and show what would be imported.
+/// Returns records to import, None if cancelled.
+pub fn show_import_dialog(parent: &gtk::Window) -> Option<Parsed> {
+    let filename = choose_file(parent, "Import file")?;
+    let dialog = new_dialog(parent, "Import", "Import");
+    dialog.add_button("Preview", PREVIEW_RESPONSE);
+    let dialog_window = dialog.clone().upcast::<gtk::Window>();
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(4);
+    grid.set_column_spacing(8);
+    grid.attach(&gtk::Label::new(format!("File: {}", filename).as_str()), 0, 0, 2, 1);
+    // Empty columns are guessed from header.
+    let fields = CsvFields::new("");
+    let combo = format_combo(guess_format(&filename), &fields, false);
+    grid.attach(&gtk::Label::new("Format:"), 0, 1, 1, 1);
+    grid.attach(&combo, 1, 1, 1, 1);
+    fields.attach(&grid, 2);
+    let preview = gtk::Label::new(None);
+    preview.set_xalign(0.0);
+    preview.set_yalign(0.0);
+    preview.set_selectable(true);
+    let scrolled = gtk::ScrolledWindow::new(None, None);
+    scrolled.set_vexpand(true);
+    scrolled.set_hexpand(true);
+    scrolled.add(&preview);
+    grid.attach(&scrolled, 0, 4, 2, 1);
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+    let result = loop {
+        let response = dialog.run();
+        if response != PREVIEW_RESPONSE && response != Into::<i32>::into(gtk::ResponseType::Ok) {
+            break None;
+        }
+        let parsed = fields.get().and_then(|options| {
+            let file = File::open(&filename).map_err(|e| e.to_string())?;
+            formats::read(get_format(&combo), file, &options).map_err(|e| errors::report(&e))
+        });
+        match parsed {
+            Ok(ref p) if response == PREVIEW_RESPONSE => preview.set_text(&preview_text(p)),
+            Ok(p) => break Some(p),
+            Err(desc) => ui::show_error(&dialog_window, &desc),
+        }
+    };
+    dialog.destroy();
+    result
+}
+
+/// Warn that file is not encrypted, ask for format and file, and write groups and records to it.
+/// Returns file and number of exported entries, None if cancelled.
+pub fn export(parent: &gtk::Window, groups: &[String], records: &[Record]) -> Result<Option<(String, usize)>, String> {
+    let dialog = new_dialog(parent, "Export", "Export...");
+    let dialog_window = dialog.clone().upcast::<gtk::Window>();
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(4);
+    grid.set_column_spacing(8);
+    let warning = gtk::Label::new(
+        format!(
+            "{} entries will be written to a plaintext file. The file is NOT encrypted, anyone who can \
+             read it can read all the passwords, so delete it once it's not needed.",
+            records.len()
+        ).as_str(),
+    );
+    warning.set_line_wrap(true);
+    warning.set_max_width_chars(60);
+    grid.attach(&warning, 0, 0, 2, 1);
+    let fields = CsvFields::new(formats::csv::DEFAULT_COLUMNS);
+    let combo = format_combo(Format::Csv, &fields, true);
+    grid.attach(&gtk::Label::new("Format:"), 0, 1, 1, 1);
+    grid.attach(&combo, 1, 1, 1, 1);
+    fields.attach(&grid, 2);
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+    let options = loop {
+        if dialog.run() != Into::<i32>::into(gtk::ResponseType::Ok) {
+            break None;
+        }
+        match fields.get() {
+            Ok(options) => break Some((get_format(&combo), options)),
+            Err(desc) => ui::show_error(&dialog_window, &desc),
+        }
+    };
+    dialog.destroy();
+    let (format, options) = match options {
+        Some(o) => o,
+        None => return Ok(None),
+    };
+    let extension = FORMATS
+        .iter()
+        .find(|&&(f, _, _)| f == format)
+        .map(|&(_, _, e)| e)
+        .unwrap_or("txt");
+    let name = format!("passwords.{}", extension);
+    let filename = match choose_save_file(parent, "Export file", "Export", &name) {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    if Path::new(&filename).exists()
+        && !ui::confirm(parent, &format!("{} already exists, replace it?", filename), "Replace")
+    {
+        return Ok(None);
+    }
+    let mut contents = Vec::new();
+    let count = formats::write(format, &mut contents, groups, records, &options).map_err(|e| errors::report(&e))?;
+    write_atomic(Path::new(&filename), &contents, 0).map_err(|e| e.to_string())?;
+    Ok(Some((filename, count)))
+}
+
+/// Ask what to include in printable report and where to write it, and write records to it.
+/// Returns file and number of entries in report, None if cancelled.
+pub fn export_report(parent: &gtk::Window, records: &[Record], title: &str) -> Result<Option<(String, usize)>, String> {
+    let dialog = new_dialog(parent, "Printable report", "Save...");
+    dialog.set_default_size(400, -1);
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(4);
+    grid.set_column_spacing(8);
+    grid.attach(&gtk::Label::new(format!("{} entries", records.len()).as_str()), 0, 0, 2, 1);
+    let combo = gtk::ComboBoxText::new();
+    combo.append(Some("html"), "HTML");
+    combo.append(Some("markdown"), "Markdown");
+    combo.set_active(0);
+    grid.attach(&gtk::Label::new("Format:"), 0, 1, 1, 1);
+    grid.attach(&combo, 1, 1, 1, 1);
+    let passwords = gtk::CheckButton::new_with_label("Include passwords");
+    grid.attach(&passwords, 0, 2, 2, 1);
+    let qr_codes = gtk::CheckButton::new_with_label("QR code for each entry");
+    grid.attach(&qr_codes, 0, 3, 2, 1);
+    let warning = gtk::Label::new(
+        "The report is NOT encrypted, anyone who can read it (or the paper) can read the passwords.",
+    );
+    warning.set_line_wrap(true);
+    warning.set_max_width_chars(50);
+    grid.attach(&warning, 0, 4, 2, 1);
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+    warning.hide();
+    {
+        let warning = warning.clone();
+        passwords.connect_toggled(move |p| warning.set_visible(p.get_active()));
+    }
+    let accepted = dialog.run() == Into::<i32>::into(gtk::ResponseType::Ok);
+    let options = report::Options {
+        format: combo
+            .get_active_id()
+            .and_then(|id| ReportFormat::from_name(&id))
+            .unwrap_or(ReportFormat::Html),
+        title: String::from(title),
+        passwords: passwords.get_active(),
+        qr_codes: qr_codes.get_active(),
+    };
+    dialog.destroy();
+    if !accepted {
+        return Ok(None);
+    }
+    let name = format!("report.{}", options.format.extension());
+    let filename = match choose_save_file(parent, "Save report", "Save", &name) {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    if Path::new(&filename).exists()
+        && !ui::confirm(parent, &format!("{} already exists, replace it?", filename), "Replace")
+    {
+        return Ok(None);
+    }
+    let mut contents = Vec::new();
+    let count = report::write(&mut contents, records, &options).map_err(|e| errors::report(&e))?;
+    write_atomic(Path::new(&filename), &contents, 0).map_err(|e| e.to_string())?;
+    Ok(Some((filename, count)))
+}
@@ -1,23 +1,290 @@
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::Duration;
 
-use gtk::BinExt;
-use gtk::BoxExt;
-use gtk::ContainerExt;
-use gtk::StatusbarExt;
-use gtk::WidgetExt;
+use gdk;
 use gtk;
+use gtk::prelude::*;
+use notify;
+use notify::Watcher;
 
-fn create_results_box() -> gtk::Box {
-    let results_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
-    let tmp = gtk::Label::new("hello");
-    results_box.add(&tmp);
-    return results_box;
+use mpkdb::controller::{Command, Controller, Event, Field};
+use mpkdb::db::api::Uuid;
+use mpkdb::otp::{Kind, Otp};
+
+use ui;
+use ui::audit;
+use ui::generator;
+use ui::generator::Generated;
+use ui::import_export;
+use ui::settings;
+
+/// Called when database gets locked, to show the open view again.
+pub type OnLocked = fn(&gtk::Window, Rc<RefCell<Controller>>);
+
+/// Widgets of the main view, which are updated on controller events.
+#[derive(Clone)]
+struct MainView {
+    window: gtk::Window,
+    controller: Rc<RefCell<Controller>>,
+    on_locked: OnLocked,
+    results: gtk::ListBox,
+    /// Entry ids, in the same order as result rows.
+    result_ids: Rc<RefCell<Vec<Uuid>>>,
+    /// Set while results are rebuilt, so row selection signals are not sent to controller.
+    refreshing: Rc<Cell<bool>>,
+    /// One-time code of selected entry, hidden if it has none.
+    otp_label: gtk::Label,
+    status_bar: gtk::Statusbar,
+    status_context_id: u32,
+    /// Set while user is asked what to do about file changed on disk, so we ask only once.
+    asking: Rc<Cell<bool>>,
+}
+
+impl MainView {
+    /// Send command to controller, update widgets according to events.
+    fn handle(&self, command: Command) {
+        // Controller must not be borrowed while widgets are updated, that may emit signals.
+        let events = self.controller.borrow_mut().handle(command);
+        for event in events {
+            match event {
+                Event::ListChanged => {
+                    self.refresh_results();
+                    self.refresh_otp();
+                }
+                Event::SelectionChanged => self.refresh_otp(),
+                Event::Unlocked | Event::OpenFailed(_) => {}
+                Event::Locked => {
+                    (self.on_locked)(&self.window, Rc::clone(&self.controller));
+                    return;
+                }
+                Event::CopyToClipboard { field, text } => {
+                    let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+                    clipboard.set_text(&text);
+                    self.set_status(&format!("Copied {} to clipboard", field.name()));
+                }
+                Event::Saved => self.set_status("Saved"),
+                Event::DirtyChanged(dirty) => {
+                    self.window
+                        .set_title(if dirty { "mpkdb [modified]" } else { "mpkdb" });
+                }
+                Event::ChangedOnDisk => self.ask_changed_on_disk(),
+                Event::LockedByOther(owner) => if ui::confirm_take_over_lock(&self.window, &owner) {
+                    // Replaced by error message if it fails.
+                    self.set_status("Took over lock, save again to keep your changes");
+                    self.handle(Command::TakeOverLock);
+                } else {
+                    self.set_status(&format!("Database is in use by {}, can't save", owner));
+                },
+                Event::Imported(summary) => {
+                    self.set_status(&format!("Imported {} entries, save to keep them", summary.entries));
+                    if !summary.skipped.is_empty() {
+                        ui::show_info(&self.window, &format!("Imported {}", summary));
+                    }
+                }
+                Event::Error(desc) => self.set_status(&desc),
+            }
+        }
+    }
+
+    /// Database file was changed by someone else, ask whether to reload, overwrite or merge.
+    fn ask_changed_on_disk(&self) {
+        if self.asking.get() {
+            return;
+        }
+        self.asking.set(true);
+        let dirty = self.controller.borrow().is_dirty();
+        let message = if dirty {
+            "Database file was changed by another program.\n\
+             Reload it (your unsaved changes are lost), overwrite it with your version, \
+             or merge both and save?"
+        } else {
+            "Database file was changed by another program. Reload it?"
+        };
+        let dialog = gtk::MessageDialog::new(
+            Some(&self.window),
+            gtk::DialogFlags::MODAL,
+            gtk::MessageType::Question,
+            gtk::ButtonsType::None,
+            message,
+        );
+        dialog.add_button("Cancel", gtk::ResponseType::Cancel.into());
+        dialog.add_button("Reload", 1);
+        if dirty {
+            dialog.add_button("Overwrite", 2);
+            dialog.add_button("Merge", 3);
+        }
+        let response = dialog.run();
+        dialog.destroy();
+        self.asking.set(false);
+        match response {
+            1 => {
+                self.handle(Command::Reload);
+                self.set_status("Reloaded");
+            }
+            2 => self.handle(Command::Overwrite),
+            3 => self.handle(Command::Merge),
+            _ => self.set_status("Database file was changed by another program"),
+        }
+    }
+
+    fn change_settings(&self) {
+        let rounds = match self.controller.borrow().get_key_transform_rounds() {
+            Some(r) => r,
+            None => return,
+        };
+        let change = match settings::show_settings_dialog(&self.window, rounds) {
+            Some(c) => c,
+            None => return,
+        };
+        if let Some((password, key_file)) = change.master_key {
+            self.handle(Command::ChangeMasterKey {
+                password: password,
+                key_file: key_file,
+            });
+        }
+        if change.rounds != rounds {
+            self.handle(Command::SetKeyTransformRounds(change.rounds));
+        }
+        if self.controller.borrow().is_dirty() {
+            self.set_status("Settings changed, save to apply them");
+        }
+    }
+
+    fn import(&self) {
+        if let Some(parsed) = import_export::show_import_dialog(&self.window) {
+            self.handle(Command::Import(parsed));
+        }
+    }
+
+    /// Export all entries, or those of selected group.
+    fn export(&self) {
+        let (groups, records) = {
+            let controller = self.controller.borrow();
+            let group = controller.get_selected_group();
+            (controller.get_group_paths(group), controller.get_records(group))
+        };
+        match import_export::export(&self.window, &groups, &records) {
+            Ok(Some((filename, count))) => self.set_status(&format!("Exported {} entries to {}", count, filename)),
+            Ok(None) => {}
+            Err(desc) => ui::show_error(&self.window, &format!("Export failed: {}", desc)),
+        }
+    }
+
+    /// Printable report of all entries, or those of selected group.
+    fn report(&self) {
+        let (records, title) = {
+            let controller = self.controller.borrow();
+            let title = controller
+                .get_path()
+                .and_then(|p| Path::new(p).file_name())
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            (controller.get_records(controller.get_selected_group()), title)
+        };
+        match import_export::export_report(&self.window, &records, &title) {
+            Ok(Some((filename, count))) => self.set_status(&format!("Wrote report of {} entries to {}", count, filename)),
+            Ok(None) => {}
+            Err(desc) => ui::show_error(&self.window, &format!("Report failed: {}", desc)),
+        }
+    }
+
+    /// Check passwords of all entries, or those of selected group.
+    fn audit(&self) {
+        let records = {
+            let controller = self.controller.borrow();
+            controller.get_records(controller.get_selected_group())
+        };
+        audit::show_audit_dialog(&self.window, records);
+    }
+
+    /// Generate password, and copy it to clipboard, or set it as password of selected entry.
+    fn generate(&self) {
+        let selected = self.controller.borrow().get_selected_entry();
+        let generated = generator::show_generator_dialog(&self.window, selected.as_ref().map(|e| e.title.as_str()));
+        match generated {
+            Some(Generated::Copy(password)) => {
+                let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+                clipboard.set_text(&password);
+                self.set_status("Copied generated password to clipboard");
+            }
+            Some(Generated::UseForEntry(password)) => if let Some(mut e) = selected {
+                // Replaced by error message if it fails.
+                self.set_status(&format!("Changed password of {}, save to keep it", e.title));
+                e.password = password;
+                e.touch();
+                self.handle(Command::UpdateEntry(e));
+            },
+            None => {}
+        }
+    }
+
+    /// Show current one-time code of selected entry, if it has one.
+    fn refresh_otp(&self) {
+        let notes = match self.controller.borrow().get_selected_entry() {
+            Some(e) => e.notes,
+            None => String::new(),
+        };
+        let text = match Otp::from_notes(&notes) {
+            Some(Ok(ref otp)) => match otp.kind {
+                Kind::Totp { .. } => {
+                    let (code, remaining) = otp.current();
+                    format!("One-time code: {} ({}s left)", code, remaining)
+                }
+                Kind::Hotp { .. } => String::from("One-time code: HOTP, copy to get the next code"),
+            },
+            Some(Err(e)) => format!("One-time code: {}", e),
+            None => {
+                self.otp_label.hide();
+                return;
+            }
+        };
+        self.otp_label.set_text(&text);
+        self.otp_label.show();
+    }
+
+    fn set_status(&self, text: &str) {
+        self.status_bar.pop(self.status_context_id);
+        self.status_bar.push(self.status_context_id, text);
+    }
+
+    fn refresh_results(&self) {
+        self.refreshing.set(true);
+        for c in self.results.get_children() {
+            self.results.remove(&c);
+        }
+        let (entries, selected) = {
+            let controller = self.controller.borrow();
+            (
+                controller.get_entries(),
+                controller.get_selected_entry().map(|e| e.id),
+            )
+        };
+        let mut ids = self.result_ids.borrow_mut();
+        ids.clear();
+        for e in entries {
+            let label = gtk::Label::new(format!("{}    {}", e.title, e.username).as_str());
+            label.set_xalign(0.0);
+            self.results.insert(&label, -1);
+            ids.push(e.id);
+        }
+        if let Some(i) = selected.and_then(|id| ids.iter().position(|x| *x == id)) {
+            let row = self.results.get_row_at_index(i as i32);
+            self.results.select_row(row.as_ref());
+        }
+        self.results.show_all();
+        self.refreshing.set(false);
+    }
 }
 
 /// Init main app view - with Password Database visible, searchable etc.
 /// Param window is the top level main app window.
 /// We do our best to clean the window and get it into usable state, laying
 /// out widgets and controls, binding handlers etc.
-pub fn init_main_ui(window: &gtk::Window) {
+pub fn init_main_ui(window: &gtk::Window, controller: Rc<RefCell<Controller>>, on_locked: OnLocked) {
     {
         if let Some(ref c) = window.get_child() {
             window.remove(c);
@@ -26,13 +293,193 @@ pub fn init_main_ui(window: &gtk::Window) {
     let main_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
     let search_field = gtk::Entry::new();
     main_box.add(&search_field);
-    let results_box = create_results_box();
-    main_box.add(&results_box);
+    let results = gtk::ListBox::new();
+    let scrolled = gtk::ScrolledWindow::new(None, None);
+    scrolled.add(&results);
+    main_box.add(&scrolled);
+    let otp_label = gtk::Label::new("");
+    otp_label.set_xalign(0.0);
+    otp_label.set_selectable(true);
+    // Shown by refresh_otp only.
+    otp_label.set_no_show_all(true);
+    main_box.add(&otp_label);
+    let button_box = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+    let copy_password_btn = gtk::Button::new_with_label("Copy password");
+    let copy_username_btn = gtk::Button::new_with_label("Copy username");
+    let copy_code_btn = gtk::Button::new_with_label("Copy code");
+    let save_btn = gtk::Button::new_with_label("Save");
+    let import_btn = gtk::Button::new_with_label("Import...");
+    let export_btn = gtk::Button::new_with_label("Export...");
+    let report_btn = gtk::Button::new_with_label("Report...");
+    let audit_btn = gtk::Button::new_with_label("Audit...");
+    let generate_btn = gtk::Button::new_with_label("Generate...");
+    let settings_btn = gtk::Button::new_with_label("Settings...");
+    let lock_btn = gtk::Button::new_with_label("Lock");
+    for b in &[
+        &copy_password_btn,
+        &copy_username_btn,
+        &copy_code_btn,
+        &save_btn,
+        &import_btn,
+        &export_btn,
+        &report_btn,
+        &audit_btn,
+        &generate_btn,
+        &settings_btn,
+        &lock_btn,
+    ] {
+        button_box.add(*b);
+    }
+    main_box.add(&button_box);
     let status_bar = gtk::Statusbar::new();
     main_box.add(&status_bar);
-    main_box.set_child_packing(&results_box, true, true, 0, gtk::PackType::Start);
+    main_box.set_child_packing(&scrolled, true, true, 0, gtk::PackType::Start);
+    let view = MainView {
+        window: window.clone(),
+        controller: controller,
+        on_locked: on_locked,
+        results: results.clone(),
+        result_ids: Rc::new(RefCell::new(Vec::new())),
+        refreshing: Rc::new(Cell::new(false)),
+        otp_label: otp_label,
+        status_context_id: status_bar.get_context_id("main"),
+        status_bar: status_bar,
+        asking: Rc::new(Cell::new(false)),
+    };
+    {
+        let view = view.clone();
+        search_field.connect_changed(move |field| {
+            view.handle(Command::Search(field.get_text().unwrap_or_default()));
+        });
+    }
+    {
+        let view = view.clone();
+        results.connect_row_selected(move |_, row| {
+            if view.refreshing.get() {
+                return;
+            }
+            let id = row.as_ref()
+                .and_then(|r| view.result_ids.borrow().get(r.get_index() as usize).cloned());
+            view.handle(Command::SelectEntry(id));
+        });
+    }
+    {
+        let view = view.clone();
+        copy_password_btn.connect_clicked(move |_| view.handle(Command::Copy(Field::Password)));
+    }
+    {
+        let view = view.clone();
+        copy_username_btn.connect_clicked(move |_| view.handle(Command::Copy(Field::Username)));
+    }
+    {
+        let view = view.clone();
+        copy_code_btn.connect_clicked(move |_| view.handle(Command::Copy(Field::Totp)));
+    }
+    {
+        let view = view.clone();
+        save_btn.connect_clicked(move |_| view.handle(Command::Save));
+    }
+    {
+        let view = view.clone();
+        import_btn.connect_clicked(move |_| view.import());
+    }
+    {
+        let view = view.clone();
+        export_btn.connect_clicked(move |_| view.export());
+    }
+    {
+        let view = view.clone();
+        report_btn.connect_clicked(move |_| view.report());
+    }
+    {
+        let view = view.clone();
+        audit_btn.connect_clicked(move |_| view.audit());
+    }
+    {
+        let view = view.clone();
+        generate_btn.connect_clicked(move |_| view.generate());
+    }
+    {
+        let view = view.clone();
+        settings_btn.connect_clicked(move |_| view.change_settings());
+    }
+    {
+        let view = view.clone();
+        lock_btn.connect_clicked(move |_| view.handle(Command::Lock));
+    }
     window.add(&main_box);
     window.show_all();
-    let status_bar_context_id = status_bar.get_context_id("main");
-    status_bar.push(status_bar_context_id, "hello, world");
+    view.refresh_results();
+    view.refresh_otp();
+    view.set_status("Opened");
+    watch_file(&view);
+    tick_otp(&view);
+}
+
+/// Keep one-time code current, until database is locked.
+fn tick_otp(view: &MainView) {
+    let view = view.clone();
+    gtk::timeout_add(1000, move || {
+        if !view.controller.borrow().is_unlocked() {
+            return gtk::Continue(false);
+        }
+        view.refresh_otp();
+        gtk::Continue(true)
+    });
+}
+
+/// Check database file whenever something changes in its directory, until database is closed.
+/// Directory is watched, not the file, since saving replaces the file with a new one.
+fn watch_file(view: &MainView) {
+    let db_path = match view.controller.borrow().get_path() {
+        Some(p) => PathBuf::from(p),
+        None => return,
+    };
+    // Watcher reports absolute paths.
+    let path = db_path.canonicalize().unwrap_or_else(|_| db_path.clone());
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = match notify::watcher(tx, Duration::from_secs(1)) {
+        Ok(w) => w,
+        Err(e) => {
+            warn!("watch_file: Can't watch for changes: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = watcher.watch(&dir, notify::RecursiveMode::NonRecursive) {
+        warn!("watch_file: Can't watch \"{}\": {}", dir.display(), e);
+        return;
+    }
+    debug!("watch_file: Watching \"{}\"", path.display());
+    let view = view.clone();
+    gtk::timeout_add(500, move || {
+        // Keeps watching while this closure is alive.
+        let _watcher = &watcher;
+        let still_open = {
+            let controller = view.controller.borrow();
+            controller.is_unlocked() && controller.get_path().map(Path::new) == Some(db_path.as_path())
+        };
+        if !still_open {
+            debug!("watch_file: Stopped watching \"{}\"", path.display());
+            return gtk::Continue(false);
+        }
+        let mut changed = false;
+        while let Ok(event) = rx.try_recv() {
+            changed |= match event {
+                notify::DebouncedEvent::Create(ref p) |
+                notify::DebouncedEvent::Write(ref p) |
+                notify::DebouncedEvent::Remove(ref p) |
+                notify::DebouncedEvent::Rename(_, ref p) => *p == path,
+                notify::DebouncedEvent::Rescan => true,
+                _ => false,
+            };
+        }
+        if changed {
+            view.handle(Command::CheckDisk);
+        }
+        gtk::Continue(true)
+    });
 }
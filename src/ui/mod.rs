@@ -3,5 +3,64 @@
 //! Currently GTK-RS, but ideally there should be more GUI-s, including native
 //! impl on MacOS.
 
+pub mod audit;
+pub mod generator;
+pub mod import_export;
 pub mod open;
 pub mod main;
+pub mod settings;
+
+use gtk;
+use gtk::prelude::*;
+
+fn show_message(window: &gtk::Window, message_type: gtk::MessageType, message: &str) {
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        message_type,
+        gtk::ButtonsType::Ok,
+        message,
+    );
+    dialog.run();
+    dialog.destroy();
+}
+
+/// Show error message in modal dialog.
+pub fn show_error(window: &gtk::Window, message: &str) {
+    show_message(window, gtk::MessageType::Error, message);
+}
+
+pub fn show_info(window: &gtk::Window, message: &str) {
+    show_message(window, gtk::MessageType::Info, message);
+}
+
+/// Ask yes/no question, with given label for yes.
+pub fn confirm(window: &gtk::Window, message: &str, yes: &str) -> bool {
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Warning,
+        gtk::ButtonsType::None,
+        message,
+    );
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel.into()),
+        (yes, gtk::ResponseType::Ok.into()),
+    ]);
+    let response = dialog.run();
+    dialog.destroy();
+    response == Into::<i32>::into(gtk::ResponseType::Ok)
+}
+
+/// Database file is locked by `owner`, ask whether to take over the lock.
+pub fn confirm_take_over_lock(window: &gtk::Window, owner: &str) -> bool {
+    confirm(
+        window,
+        &format!(
+            "Database is in use by {}, or their lock was left behind by a crash.\n\
+             Take over the lock? If they are still editing, one of you loses changes.",
+            owner
+        ),
+        "Take Over",
+    )
+}
@@ -1,45 +1,204 @@
-
 //! Part of the UI to locate and open files.
 
+use std::cell::RefCell;
+use std::rc::Rc;
+
 use gtk;
 use gtk::prelude::*;
 
-pub fn init_open_file_ui<F: Fn(String) + 'static>(window: &gtk::Window, old_filename: Option<&str>, callback: F) {
-    let current_file_label = gtk::Label::new("");
+use mpkdb::db::kdb;
+use mpkdb::model::RecentFile;
+
+use ui;
+use ui::settings::{KeyFields, RoundsFields};
+
+/// What user wants to open.
+pub struct OpenFileRequest {
+    pub filename: String,
+    pub password: String,
+    pub key_file: Option<String>,
+}
+
+/// Database user wants to create.
+pub struct NewFileRequest {
+    pub filename: String,
+    pub password: String,
+    pub key_file: Option<String>,
+    pub rounds: u32,
+}
+
+/// Run file chooser dialog, return chosen file if any.
+pub fn choose_file(parent: &gtk::Window, title: &str) -> Option<String> {
+    let dialog = gtk::FileChooserDialog::new(Some(title), Some(parent), gtk::FileChooserAction::Open);
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel.into()),
+        ("Open", gtk::ResponseType::Ok.into()),
+    ]);
+    let result = dialog.run();
+    debug!("choose_file: Result of running file chooser dialog: {:?}", result);
+    let filename = dialog.get_filename();
+    dialog.destroy();
+    debug!("choose_file: File: {:?}", filename);
+    if result != Into::<i32>::into(gtk::ResponseType::Ok) {
+        return None;
+    }
+    filename.map(|f| String::from(f.to_string_lossy()))
+}
+
+/// Run file chooser dialog for a file to create.
+pub fn choose_save_file(parent: &gtk::Window, title: &str, accept: &str, name: &str) -> Option<String> {
+    let dialog = gtk::FileChooserDialog::new(Some(title), Some(parent), gtk::FileChooserAction::Save);
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel.into()),
+        (accept, gtk::ResponseType::Ok.into()),
+    ]);
+    dialog.set_current_name(name);
+    let result = dialog.run();
+    let filename = dialog.get_filename();
+    dialog.destroy();
+    if result != Into::<i32>::into(gtk::ResponseType::Ok) {
+        return None;
+    }
+    filename.map(|f| String::from(f.to_string_lossy()))
+}
+
+/// Ask file, master key and rounds for new database, None if cancelled.
+fn show_new_database_dialog(parent: &gtk::Window) -> Option<NewFileRequest> {
+    let filename = match choose_save_file(parent, "New Database", "Create", "passwords.kdb") {
+        Some(f) => f,
+        None => return None,
+    };
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("New database");
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel.into()),
+        ("Create", gtk::ResponseType::Ok.into()),
+    ]);
+    let dialog_window = dialog.clone().upcast::<gtk::Window>();
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(4);
+    grid.set_column_spacing(8);
+    grid.attach(&gtk::Label::new(filename.as_str()), 0, 0, 3, 1);
+    let key_fields = KeyFields::new(&dialog_window);
+    key_fields.attach(&grid, 1);
+    let rounds_fields = RoundsFields::new(kdb::DEFAULT_KEY_ENC_ROUNDS);
+    rounds_fields.attach(&grid, 4);
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+    let result = loop {
+        if dialog.run() != Into::<i32>::into(gtk::ResponseType::Ok) {
+            break None;
+        }
+        match key_fields.get() {
+            Ok((password, key_file)) => {
+                break Some(NewFileRequest {
+                    filename: filename,
+                    password: password,
+                    key_file: key_file,
+                    rounds: rounds_fields.get(),
+                })
+            }
+            Err(desc) => ui::show_error(&dialog_window, desc),
+        }
+    };
+    dialog.destroy();
+    result
+}
+
+/// Show open file view.
+/// Recent files are offered in the dropdown, most recent first, and the first one is preselected.
+/// on_new is called when user wants to create new database instead.
+pub fn init_open_file_ui<F, N>(window: &gtk::Window, recent_files: &[RecentFile], callback: F, on_new: N)
+where
+    F: Fn(OpenFileRequest) + 'static,
+    N: Fn(NewFileRequest) + 'static,
+{
+    if let Some(ref c) = window.get_child() {
+        window.remove(c);
+    }
+    // Files known to the dropdown, in the same order as dropdown items.
+    let known_files: Rc<RefCell<Vec<RecentFile>>> = Rc::new(RefCell::new(recent_files.to_vec()));
+    let file_combo = gtk::ComboBoxText::new();
+    for f in recent_files {
+        file_combo.append_text(&f.path);
+    }
+    let key_file_label = gtk::Label::new("");
     let open_btn = gtk::Button::new_with_label("Open");
     let file_box = {
         // These clones will be moved to closure...
-        let current_file_label_clone = current_file_label.clone();
-        let open_btn_clone = open_btn.clone();
+        let file_combo_clone = file_combo.clone();
+        let known_files_clone = Rc::clone(&known_files);
         let button_choose_file = gtk::Button::new_with_label("Choose...");
         let parent = window.clone();
         button_choose_file.connect_clicked(move |x| {
             debug!("init_open_file_ui: Choose file button clicked: {:?}", x);
-            let dialog = gtk::FileChooserDialog::new(None, Some(&parent), gtk::FileChooserAction::Open);
-            dialog.add_buttons(&[
-                ("Cancel", gtk::ResponseType::Cancel.into()),
-                ("Open", gtk::ResponseType::Ok.into()),
-            ]);
-            let result = dialog.run();
-            debug!(
-                "init_open_file_ui: Result of running file chooser dialog: {:?}",
-                result
-            );
-            let filename = dialog.get_filename();
-            dialog.destroy();
-            debug!("init_open_file_ui: File: {:?}", filename);
-            match filename {
-                Some(filename) => {
-                    current_file_label_clone.set_text(&filename.to_string_lossy());
-                    open_btn_clone.set_sensitive(true);
+            if let Some(filename) = choose_file(&parent, "Open Database") {
+                let (pos, is_new) = {
+                    let mut known_files = known_files_clone.borrow_mut();
+                    match known_files.iter().position(|f| f.path == filename) {
+                        Some(pos) => (pos, false),
+                        None => {
+                            known_files.push(RecentFile {
+                                path: filename.clone(),
+                                key_file: None,
+                            });
+                            (known_files.len() - 1, true)
+                        }
+                    }
+                };
+                if is_new {
+                    file_combo_clone.append_text(&filename);
                 }
-                None => {}
+                file_combo_clone.set_active(pos as i32);
             }
         });
         let b = gtk::Box::new(gtk::Orientation::Horizontal, 0);
-        b.add(&current_file_label);
+        b.add(&file_combo);
         b.add(&button_choose_file);
-        b.set_child_packing(&current_file_label, true, true, 0, gtk::PackType::Start);
+        b.set_child_packing(&file_combo, true, true, 0, gtk::PackType::Start);
+        b
+    };
+    {
+        // Selecting a known file brings back the key file it was opened with.
+        let known_files_clone = Rc::clone(&known_files);
+        let key_file_label_clone = key_file_label.clone();
+        let open_btn_clone = open_btn.clone();
+        file_combo.connect_changed(move |c| {
+            let active = c.get_active();
+            open_btn_clone.set_sensitive(active >= 0);
+            if active < 0 {
+                return;
+            }
+            if let Some(f) = known_files_clone.borrow().get(active as usize) {
+                let key_file = match f.key_file {
+                    Some(ref k) => k.as_str(),
+                    None => "",
+                };
+                key_file_label_clone.set_text(key_file);
+            }
+        });
+    }
+    let key_file_box = {
+        let key_file_label_clone = key_file_label.clone();
+        let button_choose_key_file = gtk::Button::new_with_label("Key File...");
+        let parent = window.clone();
+        button_choose_key_file.connect_clicked(move |_| {
+            if let Some(filename) = choose_file(&parent, "Choose Key File") {
+                key_file_label_clone.set_text(&filename);
+            }
+        });
+        let key_file_label_clone = key_file_label.clone();
+        let button_clear_key_file = gtk::Button::new_with_label("Clear");
+        button_clear_key_file.connect_clicked(move |_| {
+            key_file_label_clone.set_text("");
+        });
+        let b = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        b.add(&key_file_label);
+        b.add(&button_choose_key_file);
+        b.add(&button_clear_key_file);
+        b.set_child_packing(&key_file_label, true, true, 0, gtk::PackType::Start);
         b
     };
     let main_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
@@ -48,23 +207,43 @@ pub fn init_open_file_ui<F: Fn(String) + 'static>(window: &gtk::Window, old_file
         e.set_visibility(false);
         e
     };
-    if let Some(ref old_filename) = old_filename {
-        current_file_label.set_text(old_filename);
-    } else {
+    if recent_files.is_empty() {
         open_btn.set_sensitive(false);
+    } else {
+        file_combo.set_active(0);
     }
     {
-        let current_file_label_clone = current_file_label.clone();
-        open_btn.connect_clicked(move |_| match current_file_label_clone.get_text() {
-            Some(p) => callback(p),
+        let file_combo_clone = file_combo.clone();
+        let key_file_label_clone = key_file_label.clone();
+        let entry_clone = entry.clone();
+        open_btn.connect_clicked(move |_| match file_combo_clone.get_active_text() {
+            Some(p) => {
+                let key_file = key_file_label_clone.get_text().and_then(|k| if k.is_empty() { None } else { Some(k) });
+                callback(OpenFileRequest {
+                    filename: p,
+                    password: entry_clone.get_text().unwrap_or_default(),
+                    key_file: key_file,
+                })
+            }
             None => {
-                warn!("No text in label, can't open file");
+                warn!("No file selected, can't open file");
+            }
+        });
+    }
+    let new_btn = gtk::Button::new_with_label("New Database...");
+    {
+        let parent = window.clone();
+        new_btn.connect_clicked(move |_| {
+            if let Some(r) = show_new_database_dialog(&parent) {
+                on_new(r);
             }
         });
     }
     main_box.add(&file_box);
+    main_box.add(&key_file_box);
     main_box.add(&entry);
     main_box.add(&open_btn);
+    main_box.add(&new_btn);
     window.add(&main_box);
     window.show_all();
     debug!("init_open_file_ui: Done");
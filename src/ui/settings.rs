@@ -0,0 +1,177 @@
+//! Database settings dialog: master key and key transformation rounds.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use gtk;
+use gtk::prelude::*;
+
+use mpkdb::db::kdb;
+
+use ui;
+use ui::open::choose_file;
+
+/// What user changed in the dialog.
+pub struct SettingsChange {
+    /// New password and key file, None to keep current master key.
+    pub master_key: Option<(String, Option<String>)>,
+    pub rounds: u32,
+}
+
+/// Password, repeated password and key file fields, also used for new databases.
+pub struct KeyFields {
+    password: gtk::Entry,
+    repeat: gtk::Entry,
+    key_file: Rc<RefCell<Option<String>>>,
+    key_file_label: gtk::Label,
+    key_file_btn: gtk::Button,
+}
+
+impl KeyFields {
+    /// Key file chooser is shown over parent.
+    pub fn new(parent: &gtk::Window) -> KeyFields {
+        let password = gtk::Entry::new();
+        password.set_visibility(false);
+        let repeat = gtk::Entry::new();
+        repeat.set_visibility(false);
+        let key_file: Rc<RefCell<Option<String>>> = Rc::new(RefCell::new(None));
+        let key_file_label = gtk::Label::new("(none)");
+        let key_file_btn = gtk::Button::new_with_label("Key File...");
+        {
+            let key_file = Rc::clone(&key_file);
+            let key_file_label = key_file_label.clone();
+            let parent = parent.clone();
+            key_file_btn.connect_clicked(move |_| {
+                if let Some(f) = choose_file(&parent, "Choose key file") {
+                    key_file_label.set_text(&f);
+                    *key_file.borrow_mut() = Some(f);
+                }
+            });
+        }
+        KeyFields {
+            password: password,
+            repeat: repeat,
+            key_file: key_file,
+            key_file_label: key_file_label,
+            key_file_btn: key_file_btn,
+        }
+    }
+
+    /// Attach to grid, taking three rows from top.
+    pub fn attach(&self, grid: &gtk::Grid, top: i32) {
+        grid.attach(&gtk::Label::new("Password:"), 0, top, 1, 1);
+        grid.attach(&self.password, 1, top, 2, 1);
+        grid.attach(&gtk::Label::new("Repeat:"), 0, top + 1, 1, 1);
+        grid.attach(&self.repeat, 1, top + 1, 2, 1);
+        grid.attach(&gtk::Label::new("Key file:"), 0, top + 2, 1, 1);
+        grid.attach(&self.key_file_label, 1, top + 2, 1, 1);
+        grid.attach(&self.key_file_btn, 2, top + 2, 1, 1);
+    }
+
+    pub fn set_sensitive(&self, sensitive: bool) {
+        self.password.set_sensitive(sensitive);
+        self.repeat.set_sensitive(sensitive);
+        self.key_file_btn.set_sensitive(sensitive);
+    }
+
+    /// Password and key file, or message for user if they are not usable.
+    pub fn get(&self) -> Result<(String, Option<String>), &'static str> {
+        let p = self.password.get_text().unwrap_or_default();
+        if p != self.repeat.get_text().unwrap_or_default() {
+            return Err("Passwords do not match");
+        }
+        let k = self.key_file.borrow().clone();
+        if p.is_empty() && k.is_none() {
+            return Err("Password or key file is needed");
+        }
+        Ok((p, k))
+    }
+}
+
+/// Rounds spin button, with a button to calibrate it for 1 second delay.
+pub struct RoundsFields {
+    spin: gtk::SpinButton,
+    calibrate_btn: gtk::Button,
+}
+
+impl RoundsFields {
+    pub fn new(rounds: u32) -> RoundsFields {
+        let spin = gtk::SpinButton::new_with_range(1.0, u32::max_value() as f64, 1000.0);
+        spin.set_value(rounds as f64);
+        let calibrate_btn = gtk::Button::new_with_label("1 second delay");
+        {
+            let spin = spin.clone();
+            calibrate_btn.connect_clicked(move |_| {
+                let rounds = kdb::calibrate_rounds(Duration::from_secs(1));
+                spin.set_value(rounds as f64);
+            });
+        }
+        RoundsFields {
+            spin: spin,
+            calibrate_btn: calibrate_btn,
+        }
+    }
+
+    /// Attach to grid, taking one row.
+    pub fn attach(&self, grid: &gtk::Grid, top: i32) {
+        grid.attach(&gtk::Label::new("Key transformation rounds:"), 0, top, 1, 1);
+        grid.attach(&self.spin, 1, top, 1, 1);
+        grid.attach(&self.calibrate_btn, 2, top, 1, 1);
+    }
+
+    pub fn get(&self) -> u32 {
+        self.spin.get_value() as u32
+    }
+}
+
+/// Run settings dialog, None if cancelled.
+pub fn show_settings_dialog(parent: &gtk::Window, rounds: u32) -> Option<SettingsChange> {
+    let dialog = gtk::Dialog::new();
+    dialog.set_title("Database settings");
+    dialog.set_transient_for(Some(parent));
+    dialog.set_modal(true);
+    dialog.add_buttons(&[
+        ("Cancel", gtk::ResponseType::Cancel.into()),
+        ("OK", gtk::ResponseType::Ok.into()),
+    ]);
+    let dialog_window = dialog.clone().upcast::<gtk::Window>();
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(4);
+    grid.set_column_spacing(8);
+    let change_key = gtk::CheckButton::new_with_label("Change master key");
+    grid.attach(&change_key, 0, 0, 3, 1);
+    let key_fields = Rc::new(KeyFields::new(&dialog_window));
+    key_fields.attach(&grid, 1);
+    key_fields.set_sensitive(false);
+    {
+        let key_fields = Rc::clone(&key_fields);
+        change_key.connect_toggled(move |c| key_fields.set_sensitive(c.get_active()));
+    }
+    let rounds_fields = RoundsFields::new(rounds);
+    rounds_fields.attach(&grid, 4);
+    dialog.get_content_area().add(&grid);
+    dialog.show_all();
+    let result = loop {
+        if dialog.run() != Into::<i32>::into(gtk::ResponseType::Ok) {
+            break None;
+        }
+        let master_key = if change_key.get_active() {
+            match key_fields.get() {
+                Ok(k) => Some(k),
+                Err(desc) => {
+                    ui::show_error(&dialog_window, desc);
+                    continue;
+                }
+            }
+        } else {
+            None
+        };
+        break Some(SettingsChange {
+            master_key: master_key,
+            rounds: rounds_fields.get(),
+        });
+    };
+    dialog.destroy();
+    result
+}